@@ -0,0 +1,380 @@
+use crate::is_wit;
+use crate::license::is_license_file;
+
+use std::path::{Component, Path, PathBuf};
+
+/// Layout of WIT definitions within `tar` archives, see [untar](crate::untar()) and
+/// [`tar_with_deps`](crate::tar_with_deps).
+///
+/// By default, WIT definitions of the package are expected in a `wit` directory, which is either
+/// at the root of the archive or nested in a single top-level directory, as is the case for
+/// archives produced by GitHub, and transitive dependencies are expected in `wit/deps/<id>`.
+/// Archives containing entries, which could escape the destination when unpacked, are rejected.
+///
+/// Layouts are constructed using [`ArchiveLayout::default`](Layout::default) and the `with_*`
+/// methods, such that new rules can be added without breaking existing consumers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Layout {
+    prefix: PathBuf,
+    depth: usize,
+    deps: String,
+    strict: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            prefix: PathBuf::from("wit"),
+            depth: 1,
+            deps: "deps".into(),
+            strict: true,
+        }
+    }
+}
+
+/// Entry of an archive matched by [`Layout::matches`]
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Match<'a> {
+    /// WIT definition of the package
+    Package(&'a str),
+    /// WIT definition of a transitive dependency
+    Dependency(&'a str, &'a str),
+    /// License file of the package, either at the root of the archive or in the prefix
+    License(&'a str),
+}
+
+/// Type of an archive entry checked by [`check_entry`]
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Entry<'a> {
+    /// Any entry, which is not a link
+    Other,
+    /// Symbolic link to a path relative to the parent of the entry
+    Symlink(&'a Path),
+    /// Hard link to a path relative to the root of the archive
+    HardLink(&'a Path),
+}
+
+/// Returns the depth of `path` resolved relative to a directory at `depth`, if it stays within
+/// the root
+fn resolve(mut depth: usize, path: &Path) -> Option<usize> {
+    for c in path.components() {
+        match c {
+            Component::Prefix(_) | Component::RootDir => return None,
+            Component::CurDir => {}
+            Component::ParentDir => depth = depth.checked_sub(1)?,
+            Component::Normal(_) => depth += 1,
+        }
+    }
+    Some(depth)
+}
+
+/// Checks that archive `entry` at `path` cannot escape the destination directory when unpacked
+/// and returns the reason if it could
+pub(crate) fn check_entry(path: &Path, entry: &Entry<'_>) -> Result<(), &'static str> {
+    let mut depth = 0usize;
+    for c in path.components() {
+        match c {
+            Component::Prefix(_) | Component::RootDir => return Err("path is absolute"),
+            Component::ParentDir => return Err("path contains `..`"),
+            Component::CurDir => {}
+            Component::Normal(_) => depth += 1,
+        }
+    }
+    match entry {
+        Entry::Other => Ok(()),
+        Entry::Symlink(target) => resolve(depth.saturating_sub(1), target)
+            .map(|_| ())
+            .ok_or("symbolic link escapes the destination"),
+        Entry::HardLink(target) => resolve(0, target)
+            .map(|_| ())
+            .ok_or("hard link escapes the destination"),
+    }
+}
+
+impl Layout {
+    /// Returns the layout, which places WIT definitions of the package in `prefix` directory of
+    /// the archive. `prefix` may consist of multiple components or be empty, in which case WIT
+    /// definitions are placed at the root of the archive.
+    #[must_use]
+    pub fn with_prefix(self, prefix: impl Into<PathBuf>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ..self
+        }
+    }
+
+    /// Returns the layout, which accepts up to `depth` arbitrary leading components preceding
+    /// the prefix when unpacking. Archives are always packaged with no leading components.
+    #[must_use]
+    pub fn with_depth(self, depth: usize) -> Self {
+        Self { depth, ..self }
+    }
+
+    /// Returns the layout, which places transitive dependencies in `deps` subdirectory of the
+    /// prefix
+    #[must_use]
+    pub fn with_deps(self, deps: impl Into<String>) -> Self {
+        Self {
+            deps: deps.into(),
+            ..self
+        }
+    }
+
+    /// Returns the layout, which rejects archives containing absolute paths, `..` components or
+    /// links escaping the destination when unpacking if `strict` is set, which is the default,
+    /// and skips such entries otherwise
+    #[must_use]
+    pub fn with_strict(self, strict: bool) -> Self {
+        Self { strict, ..self }
+    }
+
+    /// Returns the directory WIT definitions of the package are placed in
+    #[must_use]
+    pub fn prefix(&self) -> &Path {
+        &self.prefix
+    }
+
+    /// Returns the maximum number of leading components preceding the prefix
+    #[must_use]
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the name of the subdirectory of the prefix transitive dependencies are placed in
+    #[must_use]
+    pub fn deps(&self) -> &str {
+        &self.deps
+    }
+
+    /// Returns whether archives containing entries escaping the destination are rejected
+    #[must_use]
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Returns the directory WIT definitions of dependency `id` are placed in
+    pub(crate) fn dependency(&self, id: &str) -> PathBuf {
+        self.prefix.join(&self.deps).join(id)
+    }
+
+    /// Matches `path` of an archive entry against the layout
+    pub(crate) fn matches<'a>(&self, path: &'a Path) -> Option<Match<'a>> {
+        fn components(path: &Path) -> Option<Vec<&str>> {
+            path.components().map(|c| c.as_os_str().to_str()).collect()
+        }
+
+        let prefix = components(&self.prefix)?;
+        let path = components(path)?;
+        (0..=self.depth.min(path.len())).find_map(|skip| {
+            let rest = &path[skip..];
+            if let [name] = rest {
+                if is_license_file(name) {
+                    return Some(Match::License(name));
+                }
+            }
+            if rest.len() < prefix.len() || rest.iter().zip(&prefix).any(|(a, b)| a != b) {
+                return None;
+            }
+            match rest[prefix.len()..] {
+                [name] if is_wit(name) => Some(Match::Package(name)),
+                [name] if is_license_file(name) => Some(Match::License(name)),
+                [deps, id, name] if deps == self.deps && is_wit(name) => {
+                    Some(Match::Dependency(id, name))
+                }
+                _ => None,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{untar_archive, MemoryVfs};
+
+    use std::collections::BTreeSet;
+
+    use async_tar::{EntryType, Header};
+
+    /// Builds a `tar` archive of `entries`, each of which is a regular file with contents or a
+    /// link to a target. Paths are written to headers as-is, bypassing validation performed by
+    /// [`Header::set_path`], to produce hostile archives.
+    async fn archive(entries: &[(EntryType, &str, &str)]) -> std::io::Result<Vec<u8>> {
+        let mut tar = async_tar::Builder::new(Vec::default());
+        for (ty, path, data) in entries {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(*ty);
+            header.set_mode(0o644);
+            header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+            let data = if ty.is_file() {
+                data.as_bytes()
+            } else {
+                header.as_old_mut().linkname[..data.len()].copy_from_slice(data.as_bytes());
+                &[]
+            };
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            tar.append(&header, data).await?;
+        }
+        tar.into_inner().await
+    }
+
+    #[test]
+    fn matches() {
+        let layout = Layout::default();
+        assert_eq!(
+            layout.matches(Path::new("wit/foo.wit")),
+            Some(Match::Package("foo.wit"))
+        );
+        assert_eq!(
+            layout.matches(Path::new("repo-main/wit/foo.wit")),
+            Some(Match::Package("foo.wit"))
+        );
+        assert_eq!(
+            layout.matches(Path::new("repo-main/wit/deps/bar/bar.wit")),
+            Some(Match::Dependency("bar", "bar.wit"))
+        );
+        assert_eq!(
+            layout.matches(Path::new("repo-main/LICENSE-MIT")),
+            Some(Match::License("LICENSE-MIT"))
+        );
+        assert_eq!(
+            layout.matches(Path::new("wit/LICENSE")),
+            Some(Match::License("LICENSE"))
+        );
+        assert_eq!(layout.matches(Path::new("repo-main/docs/LICENSE")), None);
+        assert_eq!(layout.matches(Path::new("wit/deps/bar/LICENSE")), None);
+        assert_eq!(layout.matches(Path::new("a/b/wit/foo.wit")), None);
+        assert_eq!(layout.matches(Path::new("wit/README.md")), None);
+        assert_eq!(layout.matches(Path::new("foo.wit")), None);
+
+        let layout = Layout::default()
+            .with_prefix("share/interfaces")
+            .with_depth(0)
+            .with_deps("vendor");
+        assert_eq!(
+            layout.matches(Path::new("share/interfaces/foo.wit")),
+            Some(Match::Package("foo.wit"))
+        );
+        assert_eq!(
+            layout.matches(Path::new("share/interfaces/vendor/bar/bar.wit")),
+            Some(Match::Dependency("bar", "bar.wit"))
+        );
+        assert_eq!(
+            layout.dependency("bar"),
+            Path::new("share/interfaces/vendor/bar")
+        );
+        assert_eq!(
+            layout.matches(Path::new("repo/share/interfaces/foo.wit")),
+            None
+        );
+        assert_eq!(
+            layout.matches(Path::new("share/interfaces/deps/bar/bar.wit")),
+            None
+        );
+
+        let layout = Layout::default().with_prefix("");
+        assert_eq!(
+            layout.matches(Path::new("foo.wit")),
+            Some(Match::Package("foo.wit"))
+        );
+        assert_eq!(
+            layout.matches(Path::new("deps/bar/bar.wit")),
+            Some(Match::Dependency("bar", "bar.wit"))
+        );
+    }
+
+    #[test]
+    fn check() {
+        assert_eq!(check_entry(Path::new("wit/foo.wit"), &Entry::Other), Ok(()));
+        assert_eq!(
+            check_entry(Path::new("./repo-main/wit/foo.wit"), &Entry::Other),
+            Ok(())
+        );
+        assert!(check_entry(Path::new("/wit/foo.wit"), &Entry::Other).is_err());
+        assert!(check_entry(Path::new("wit/deps/../../foo.wit"), &Entry::Other).is_err());
+        assert!(check_entry(Path::new("../foo.wit"), &Entry::Other).is_err());
+
+        assert_eq!(
+            check_entry(
+                Path::new("repo/wit/foo.wit"),
+                &Entry::Symlink(Path::new("../../bar.wit"))
+            ),
+            Ok(())
+        );
+        assert!(check_entry(
+            Path::new("repo/wit/foo.wit"),
+            &Entry::Symlink(Path::new("../../../bar.wit"))
+        )
+        .is_err());
+        assert!(check_entry(
+            Path::new("wit/foo.wit"),
+            &Entry::Symlink(Path::new("/etc/passwd"))
+        )
+        .is_err());
+
+        assert_eq!(
+            check_entry(
+                Path::new("wit/foo.wit"),
+                &Entry::HardLink(Path::new("wit/bar.wit"))
+            ),
+            Ok(())
+        );
+        assert!(check_entry(
+            Path::new("wit/foo.wit"),
+            &Entry::HardLink(Path::new("../bar.wit"))
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn untar_hostile() -> anyhow::Result<()> {
+        const GOOD: (EntryType, &str, &str) =
+            (EntryType::Regular, "wit/foo.wit", "package foo:foo;");
+        let hostile = [
+            (
+                EntryType::Regular,
+                "wit/deps/../evil.wit",
+                "package evil:evil;",
+            ),
+            (EntryType::Regular, "/wit/evil.wit", "package evil:evil;"),
+            (EntryType::Symlink, "wit/evil.wit", "../../../etc/passwd"),
+            (EntryType::Link, "wit/evil.wit", "/etc/passwd"),
+        ];
+        for entry in hostile {
+            let tar = archive(&[GOOD, entry]).await?;
+            let res = untar_archive(
+                &MemoryVfs::default(),
+                tar.as_slice(),
+                "/deps/foo",
+                Some(Path::new("/deps")),
+                &BTreeSet::default(),
+                &Layout::default(),
+            )
+            .await;
+            let Err(e) = res else {
+                panic!("`{}` was not rejected", entry.1);
+            };
+            assert_eq!(e.kind(), std::io::ErrorKind::InvalidData, "{e}");
+        }
+
+        let tar = archive(&[&hostile[..], &[GOOD]].concat()).await?;
+        let vfs = MemoryVfs::default();
+        let untared = untar_archive(
+            &vfs,
+            tar.as_slice(),
+            "/deps/foo",
+            Some(Path::new("/deps")),
+            &BTreeSet::default(),
+            &Layout::default().with_strict(false),
+        )
+        .await?;
+        assert!(untared.deps.is_empty());
+        assert_eq!(
+            vfs.files().into_keys().collect::<Vec<_>>(),
+            [Path::new("/deps/foo/foo.wit")]
+        );
+        Ok(())
+    }
+}
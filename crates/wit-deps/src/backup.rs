@@ -0,0 +1,146 @@
+use crate::fs;
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use tracing::{debug, error, warn};
+
+/// Prefix of the name of the directory, which `deps` is backed up to, see [`Backup`]
+const DEPS_BACKUP: &str = ".wit-deps-backup-";
+
+/// Returns the path of the backup of `deps`, which is located in the parent directory of
+/// `deps`, since every entry of `deps` is expected to be a WIT package
+fn backup_path(deps: &Path) -> PathBuf {
+    let mut name = OsString::from(DEPS_BACKUP);
+    name.push(deps.file_name().unwrap_or(deps.as_os_str()));
+    deps.parent()
+        .map_or_else(|| PathBuf::from(&name), |parent| parent.join(&name))
+}
+
+/// Recursively copies the directory at `src` to `dst`
+async fn copy_dir(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        fs::create_dir_all(dst.join(&dir)).await?;
+        let mut entries = fs::read_dir(src.join(&dir)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = dir.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(path);
+            } else {
+                fs::copy(src.join(&path), dst.join(&path)).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces `deps` by `backup`. This is blocking, since it is used on drop.
+fn restore(backup: &Path, deps: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(deps) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    std::fs::rename(backup, deps)
+}
+
+/// Backup of a `deps` directory, which is restored on drop unless [committed](Backup::commit),
+/// such that `deps` is rolled back if locking fails, is cancelled or its future is dropped.
+///
+/// Backups left behind by interrupted processes are restored on creation of a new backup.
+pub(crate) struct Backup {
+    deps: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl Backup {
+    /// Backs up `deps`. If `deps` does not exist, it is rolled back to an empty directory.
+    pub(crate) async fn new(deps: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let deps = deps.into();
+        let path = backup_path(&deps);
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            warn!(
+                "restoring `{}` from backup of an interrupted lock at `{}`",
+                deps.display(),
+                path.display()
+            );
+            restore(&path, &deps)
+                .with_context(|| format!("failed to restore backup at `{}`", path.display()))?;
+        }
+        if fs::try_exists(&deps).await.unwrap_or(false) {
+            copy_dir(&deps, &path).await
+        } else {
+            fs::create_dir_all(&path).await
+        }
+        .with_context(|| format!("failed to back up `{}`", deps.display()))?;
+        debug!("backed up `{}` to `{}`", deps.display(), path.display());
+        Ok(Self {
+            deps,
+            path,
+            committed: false,
+        })
+    }
+
+    /// Removes the backup keeping `deps` as-is
+    pub(crate) async fn commit(mut self) {
+        self.committed = true;
+        if let Err(e) = fs::remove_dir_all(&self.path).await {
+            warn!("failed to remove backup at `{}`: {e}", self.path.display());
+        }
+    }
+}
+
+impl Drop for Backup {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        match restore(&self.path, &self.deps) {
+            Ok(()) => debug!("rolled back `{}`", self.deps.display()),
+            Err(e) => error!(
+                "failed to roll back `{}` from backup at `{}`: {e}",
+                self.deps.display(),
+                self.path.display()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    #[tokio::test]
+    async fn rollback() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-backup").await?;
+        let deps = dir.join("deps");
+        fs::create_dir_all(deps.join("foo")).await?;
+        fs::write(deps.join("foo").join("foo.wit"), "package foo:foo;").await?;
+
+        let backup = Backup::new(&deps).await?;
+        fs::remove_dir_all(deps.join("foo")).await?;
+        fs::create_dir_all(deps.join("bar")).await?;
+        drop(backup);
+        let rolled_back = (
+            fs::read_to_string(deps.join("foo").join("foo.wit")).await?,
+            fs::try_exists(deps.join("bar")).await?,
+        );
+
+        let backup = Backup::new(&deps).await?;
+        fs::create_dir_all(deps.join("bar")).await?;
+        backup.commit().await;
+        let committed = (
+            fs::try_exists(deps.join("bar")).await?,
+            fs::try_exists(backup_path(&deps)).await?,
+        );
+
+        assert_eq!(rolled_back, ("package foo:foo;".into(), false));
+        assert_eq!(committed, (true, false));
+        Ok(())
+    }
+}
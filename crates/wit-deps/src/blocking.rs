@@ -0,0 +1,245 @@
+//! Blocking wrappers of the asynchronous library API, which manage a private runtime and are
+//! primarily intended to be used in build scripts.
+//!
+//! Each function constructs a single-threaded runtime, which is dropped before the function
+//! returns. If called from within an asynchronous context, the runtime is driven on a dedicated
+//! thread, such that the calling runtime is not affected.
+//!
+//! ```no_run
+//! fn main() -> Result<(), wit_deps::Error> {
+//!     wit_deps::blocking::lock_dir("wit")?;
+//!     println!("cargo:rerun-if-changed=wit/deps");
+//!     println!("cargo:rerun-if-changed=wit/deps.lock");
+//!     println!("cargo:rerun-if-changed=wit/deps.toml");
+//!     Ok(())
+//! }
+//! ```
+
+use crate::{
+    manifest_path, Error, Identifier, LockDivergence, LockOptions, LockOutcome, OutOfSync,
+};
+
+use core::future::Future;
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use anyhow::Context as _;
+use tokio::runtime::{self, Handle};
+
+/// Runs the future returned by `f` to completion on a private runtime
+fn block_on<T, F>(f: impl FnOnce() -> F + Send) -> Result<T, Error>
+where
+    T: Send,
+    F: Future<Output = Result<T, Error>>,
+{
+    let run = move || {
+        runtime::Builder::new_current_thread()
+            .thread_name("wit-deps/blocking")
+            .enable_all()
+            .build()
+            .context("failed to build runtime")?
+            .block_on(f())
+    };
+    if Handle::try_current().is_err() {
+        return run();
+    }
+    // NOTE: Runtimes cannot be nested, so the private runtime is driven on a dedicated thread
+    thread::scope(|s| {
+        thread::Builder::new()
+            .name("wit-deps/blocking".into())
+            .spawn_scoped(s, run)
+            .context("failed to spawn thread")?
+            .join()
+            .unwrap_or_else(|e| std::panic::resume_unwind(e))
+    })
+}
+
+/// Blocking version of [`lock_path`](crate::lock_path) using the manifest at `deps.toml`, the
+/// lock at `deps.lock` and the `deps` directory within `dir` and default [`LockOptions`].
+/// If `deps.toml` does not exist, the manifest is read from the `Cargo.toml` next to `dir`,
+/// if it contains a `[package.metadata.wit-deps]` table, see [`manifest_path`].
+///
+/// This is the equivalent of the [`lock_sync`](crate::lock_sync) macro, except that the
+/// manifest is read at runtime.
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails
+pub fn lock_dir(dir: impl AsRef<Path>) -> Result<LockOutcome, Error> {
+    let dir = dir.as_ref();
+    lock_path(
+        manifest_path(dir),
+        dir.join("deps.lock"),
+        dir.join("deps"),
+        &LockOptions::default(),
+    )
+}
+
+/// Blocking version of [`lock_path`](crate::lock_path)
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails
+pub fn lock_path(
+    manifest_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
+    let (manifest_path, lock_path, deps) =
+        (manifest_path.as_ref(), lock_path.as_ref(), deps.as_ref());
+    block_on(|| crate::lock_path(manifest_path, lock_path, deps, options))
+}
+
+/// Blocking version of [`update_path`](crate::update_path)
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails
+pub fn update_path(
+    manifest_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
+    let (manifest_path, lock_path, deps) =
+        (manifest_path.as_ref(), lock_path.as_ref(), deps.as_ref());
+    block_on(|| crate::update_path(manifest_path, lock_path, deps, options))
+}
+
+/// Blocking version of [`regenerate_path`](crate::regenerate_path)
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails or if `options` are frozen
+pub fn regenerate_path(
+    manifest_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
+    let (manifest_path, lock_path, deps) =
+        (manifest_path.as_ref(), lock_path.as_ref(), deps.as_ref());
+    block_on(|| crate::regenerate_path(manifest_path, lock_path, deps, options))
+}
+
+/// Blocking version of [`lock_shared_path`](crate::lock_shared_path)
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails or the roots have conflicting dependencies
+pub fn lock_shared_path(
+    roots: impl IntoIterator<Item = (PathBuf, PathBuf)>,
+    lock_path: impl AsRef<Path>,
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
+    let roots: Vec<_> = roots.into_iter().collect();
+    let lock_path = lock_path.as_ref();
+    block_on(|| crate::lock_shared_path(roots, lock_path, options))
+}
+
+/// Blocking version of [`verify_path`](crate::verify_path)
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or anything in the pipeline fails
+pub fn verify_path(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+) -> Result<BTreeMap<Identifier, LockDivergence>, Error> {
+    let (lock_path, deps) = (lock_path.as_ref(), deps.as_ref());
+    block_on(|| crate::verify_path(lock_path, deps))
+}
+
+/// Blocking version of [`out_of_sync_path`](crate::out_of_sync_path)
+///
+/// # Errors
+///
+/// Returns an error if the manifest is missing or either the manifest or the lock cannot be
+/// decoded
+pub fn out_of_sync_path(
+    manifest_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+) -> Result<BTreeMap<Identifier, OutOfSync>, Error> {
+    let (manifest_path, lock_path, deps) =
+        (manifest_path.as_ref(), lock_path.as_ref(), deps.as_ref());
+    block_on(|| crate::out_of_sync_path(manifest_path, lock_path, deps))
+}
+
+/// Blocking version of [`check_path`](crate::check_path)
+///
+/// # Errors
+///
+/// Returns an error if the WIT definitions cannot be parsed or resolved
+#[cfg(feature = "check")]
+pub fn check_path(wit: impl AsRef<Path>) -> Result<String, Error> {
+    let wit = wit.as_ref();
+    block_on(|| crate::check_path(wit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    use std::fs;
+
+    #[test]
+    fn lock() -> anyhow::Result<()> {
+        let dir = TempDir::create("wit-deps-blocking")?;
+        let wit = dir.join("wit");
+        fs::create_dir_all(dir.join("foo"))?;
+        fs::write(dir.join("foo").join("foo.wit"), "package foo:foo;")?;
+        fs::create_dir_all(&wit)?;
+        fs::write(wit.join("deps.toml"), "foo = { path = \"../foo\" }\n")?;
+        let locked = lock_dir(&wit)?;
+        let unchanged = lock_dir(&wit)?;
+
+        // NOTE: Blocking functions may be called from within an asynchronous context
+        let verified = runtime::Builder::new_current_thread()
+            .build()?
+            .block_on(async { verify_path(wit.join("deps.lock"), wit.join("deps")) })?;
+
+        assert!(locked.updated);
+        assert!(locked.lock.contains_key("foo"));
+        assert!(!unchanged.updated);
+        assert_eq!(unchanged.lock, locked.lock);
+        assert!(verified.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn lock_unversioned() -> anyhow::Result<()> {
+        let dir = TempDir::create("wit-deps-blocking-unversioned")?;
+        let wit = dir.join("wit");
+        fs::create_dir_all(dir.join("foo"))?;
+        fs::write(dir.join("foo").join("foo.wit"), "package foo:foo;")?;
+        fs::create_dir_all(&wit)?;
+        fs::write(wit.join("deps.toml"), "foo = { path = \"../foo\" }\n")?;
+        let locked = lock_dir(&wit)?;
+
+        // Locks produced before the format was versioned are in-sync, if entries are unchanged
+        let lock = fs::read_to_string(wit.join("deps.lock"))?;
+        let unversioned = lock
+            .lines()
+            .filter(|line| !line.starts_with("version") && !line.starts_with("wit-deps-version"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(wit.join("deps.lock"), &unversioned)?;
+        let frozen = lock_path(
+            wit.join("deps.toml"),
+            wit.join("deps.lock"),
+            wit.join("deps"),
+            &LockOptions::default().with_frozen(true),
+        )?;
+
+        assert!(!frozen.updated);
+        assert_eq!(frozen.lock, locked.lock);
+        assert_eq!(fs::read_to_string(wit.join("deps.lock"))?, unversioned);
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+//! Helpers for build scripts, which lock dependencies and instruct Cargo to rerun the build
+//! script whenever any of the inputs or outputs of locking change.
+//!
+//! ```no_run
+//! fn main() -> Result<(), wit_deps::Error> {
+//!     wit_deps::build::lock()?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Use [`lock_out_dir`] to install dependencies into `OUT_DIR` instead of the source tree:
+//!
+//! ```no_run
+//! fn main() -> Result<(), wit_deps::Error> {
+//!     wit_deps::build::lock_out_dir("wit")?;
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The main crate can then refer to the resulting WIT directory using
+//! `env!("WIT_DEPS_DIR")`, see [`WIT_DIR_ENV`].
+
+use crate::{
+    blocking, is_wit, manifest_path, Error, Lock, LockEntrySource, LockOptions, LockOutcome,
+};
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+
+/// Name of the environment variable set for the crate being built by [`lock_out_dir`], which
+/// contains the path to the WIT directory within `OUT_DIR`
+pub const WIT_DIR_ENV: &str = "WIT_DEPS_DIR";
+
+/// Like [`lock_dir`], but locks the `wit` directory
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails
+pub fn lock() -> Result<LockOutcome, Error> {
+    lock_dir("wit")
+}
+
+/// Locks dependencies of the manifest of `dir`, which is either `deps.toml` within `dir` or the
+/// `[package.metadata.wit-deps]` table of the `Cargo.toml` next to `dir`, see
+/// [`manifest_path`], using [`blocking::lock_path`] and prints `cargo:rerun-if-changed`
+/// directives for the manifest, the lock, the `deps` directory, every installed file and the
+/// source directory of every path dependency
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails
+pub fn lock_dir(dir: impl AsRef<Path>) -> Result<LockOutcome, Error> {
+    let dir = dir.as_ref();
+    let manifest = manifest_path(dir);
+    let outcome = blocking::lock_path(
+        &manifest,
+        dir.join("deps.lock"),
+        dir.join("deps"),
+        &LockOptions::default(),
+    )?;
+    let paths = watched(&manifest, dir, &dir.join("deps"), &outcome.lock)
+        .context("failed to collect paths to watch")?;
+    rerun_if_changed(&mut io::stdout().lock(), paths)
+        .context("failed to emit `cargo:rerun-if-changed` directives")?;
+    Ok(outcome)
+}
+
+/// Returns the WIT directory within `OUT_DIR`, which [`lock_out_dir`] installs into
+///
+/// # Errors
+///
+/// Returns an error if `OUT_DIR` is not set, i.e. if not called from a build script
+pub fn out_dir() -> Result<PathBuf, Error> {
+    let out = env::var_os("OUT_DIR").context("`OUT_DIR` is not set")?;
+    Ok(PathBuf::from(out).join("wit"))
+}
+
+/// Like [`lock_dir`], but installs dependencies into the WIT directory within `OUT_DIR`, see
+/// [`out_dir`], such that the source tree is never written to.
+///
+/// WIT definitions within `dir` are copied into the WIT directory and the lock at `deps.lock`
+/// within `dir`, if any, is used as the initial lock. If the lock is out-of-sync, the updated
+/// lock is only written into the WIT directory and a warning is emitted. The path to the WIT
+/// directory is exposed to the crate being built using the [`WIT_DIR_ENV`] environment
+/// variable.
+///
+/// # Errors
+///
+/// Returns an error if `OUT_DIR` is not set or anything in the pipeline fails
+pub fn lock_out_dir(dir: impl AsRef<Path>) -> Result<LockOutcome, Error> {
+    lock_into(&mut io::stdout().lock(), dir.as_ref(), &out_dir()?)
+}
+
+/// Implementation of [`lock_out_dir`], which installs into `out` and writes directives to `w`
+fn lock_into(w: &mut impl Write, dir: &Path, out: &Path) -> Result<LockOutcome, Error> {
+    install(dir, out)?;
+    let manifest = manifest_path(dir);
+    let outcome = blocking::lock_path(
+        &manifest,
+        out.join("deps.lock"),
+        out.join("deps"),
+        &LockOptions::default(),
+    )?;
+    let mut paths = watched(&manifest, dir, &out.join("deps"), &outcome.lock)
+        .context("failed to collect paths to watch")?;
+    paths.insert(dir.into());
+
+    rerun_if_changed(w, paths).context("failed to emit `cargo:rerun-if-changed` directives")?;
+    if outcome.updated {
+        writeln!(
+            w,
+            "cargo:warning=`{}` is out-of-sync, updated lock written to `{}`",
+            dir.join("deps.lock").display(),
+            out.join("deps.lock").display(),
+        )
+        .context("failed to emit warning")?;
+    }
+    writeln!(w, "cargo:rustc-env={WIT_DIR_ENV}={}", out.display())
+        .context("failed to emit `cargo:rustc-env` directive")?;
+    Ok(outcome)
+}
+
+/// Replaces WIT definitions and the lock within `out` by the ones within `dir`
+fn install(dir: &Path, out: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(out).with_context(|| format!("failed to create `{}`", out.display()))?;
+    for (src, remove) in [(out, true), (dir, false)] {
+        let entries = fs::read_dir(src)
+            .with_context(|| format!("failed to read directory at `{}`", src.display()))?;
+        for e in entries {
+            let e = e?;
+            if e.file_type()?.is_dir() || !is_wit(e.file_name()) {
+                continue;
+            }
+            if remove {
+                fs::remove_file(e.path())?;
+            } else {
+                fs::copy(e.path(), out.join(e.file_name()))?;
+            }
+        }
+    }
+    match fs::copy(dir.join("deps.lock"), out.join("deps.lock")) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            match fs::remove_file(out.join("deps.lock")) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(()),
+            }
+        }
+        Err(e) => Err(e).context("failed to copy lock"),
+    }
+}
+
+/// Returns all paths, changes of which require the `manifest` of `dir` to be locked again,
+/// given the resulting `lock` and `deps` directory the dependencies are installed in
+fn watched(manifest: &Path, dir: &Path, deps: &Path, lock: &Lock) -> io::Result<BTreeSet<PathBuf>> {
+    let mut paths = BTreeSet::from([
+        manifest.to_path_buf(),
+        dir.join("deps.lock"),
+        deps.to_path_buf(),
+    ]);
+    let root = manifest.parent().unwrap_or(dir);
+    for (id, entry) in lock.iter() {
+        for e in fs::read_dir(lock.path(deps, id))? {
+            let e = e?;
+            if !e.file_type()?.is_dir() {
+                paths.insert(e.path());
+            }
+        }
+        if let Some(LockEntrySource::Path(path)) = &entry.source {
+            paths.insert(root.join(path));
+        }
+    }
+    Ok(paths)
+}
+
+/// Writes a `cargo:rerun-if-changed` directive for each of `paths` to `w`
+fn rerun_if_changed(w: &mut impl Write, paths: BTreeSet<PathBuf>) -> io::Result<()> {
+    for path in paths {
+        writeln!(w, "cargo:rerun-if-changed={}", path.display())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    #[test]
+    fn rerun() -> anyhow::Result<()> {
+        let dir = TempDir::create("wit-deps-build")?;
+        let wit = dir.join("wit");
+        fs::create_dir_all(dir.join("foo").join("deps").join("bar"))?;
+        fs::write(dir.join("foo").join("foo.wit"), "package foo:foo;")?;
+        fs::write(
+            dir.join("foo").join("deps").join("bar").join("bar.wit"),
+            "package foo:bar;",
+        )?;
+        fs::create_dir_all(&wit)?;
+        fs::write(wit.join("deps.toml"), "foo = { path = \"../foo\" }\n")?;
+        let outcome = blocking::lock_dir(&wit)?;
+        let mut out = Vec::default();
+        rerun_if_changed(
+            &mut out,
+            watched(
+                &wit.join("deps.toml"),
+                &wit,
+                &wit.join("deps"),
+                &outcome.lock,
+            )?,
+        )?;
+        let out = String::from_utf8(out)?;
+
+        let deps = wit.join("deps");
+        let expected: Vec<_> = [
+            wit.join("../foo"),
+            deps.clone(),
+            deps.join("bar").join("bar.wit"),
+            deps.join("foo").join("foo.wit"),
+            wit.join("deps.lock"),
+            wit.join("deps.toml"),
+        ]
+        .iter()
+        .map(|path| format!("cargo:rerun-if-changed={}", path.display()))
+        .collect();
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn rerun_cargo_metadata() -> anyhow::Result<()> {
+        let dir = TempDir::create("wit-deps-build-cargo")?;
+        let wit = dir.join("wit");
+        fs::create_dir_all(dir.join("foo"))?;
+        fs::write(dir.join("foo").join("foo.wit"), "package foo:foo;")?;
+        fs::create_dir_all(&wit)?;
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package]\nname = \"test\"\n\n[package.metadata.wit-deps]\nfoo = { path = \"foo\" }\n",
+        )?;
+        let manifest = manifest_path(&wit);
+        let outcome = blocking::lock_path(
+            &manifest,
+            wit.join("deps.lock"),
+            wit.join("deps"),
+            &LockOptions::default(),
+        )?;
+        let mut out = Vec::default();
+        rerun_if_changed(
+            &mut out,
+            watched(&manifest, &wit, &wit.join("deps"), &outcome.lock)?,
+        )?;
+        let out = String::from_utf8(out)?;
+
+        let deps = wit.join("deps");
+        let expected: Vec<_> = [
+            dir.join("Cargo.toml"),
+            dir.join("foo"),
+            deps.clone(),
+            deps.join("foo").join("foo.wit"),
+            wit.join("deps.lock"),
+        ]
+        .iter()
+        .map(|path| format!("cargo:rerun-if-changed={}", path.display()))
+        .collect();
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn lock_into_out_dir() -> anyhow::Result<()> {
+        let dir = TempDir::create("wit-deps-build-out")?;
+        let wit = dir.join("wit");
+        let out = dir.join("out").join("wit");
+        fs::create_dir_all(dir.join("foo"))?;
+        fs::write(dir.join("foo").join("foo.wit"), "package foo:foo;")?;
+        fs::create_dir_all(&wit)?;
+        fs::write(wit.join("world.wit"), "package foo:world;")?;
+        fs::write(wit.join("deps.toml"), "foo = { path = \"../foo\" }\n")?;
+        fs::create_dir_all(&out)?;
+        fs::write(out.join("stale.wit"), "package foo:stale;")?;
+
+        let mut first = Vec::default();
+        let locked = lock_into(&mut first, &wit, &out)?;
+        let installed = (
+            fs::read_to_string(out.join("world.wit"))?,
+            fs::read_to_string(out.join("deps").join("foo").join("foo.wit"))?,
+            out.join("stale.wit").exists(),
+            wit.join("deps.lock").exists(),
+            wit.join("deps").exists(),
+        );
+
+        fs::copy(out.join("deps.lock"), wit.join("deps.lock"))?;
+        let mut second = Vec::default();
+        let unchanged = lock_into(&mut second, &wit, &out)?;
+        let (first, second) = (String::from_utf8(first)?, String::from_utf8(second)?);
+
+        assert!(locked.updated);
+        assert_eq!(
+            installed,
+            (
+                "package foo:world;".into(),
+                "package foo:foo;".into(),
+                false,
+                false,
+                false
+            )
+        );
+        let env = format!("cargo:rustc-env={WIT_DIR_ENV}={}", out.display());
+        assert!(first.lines().any(|line| line == env));
+        assert!(first.lines().any(|line| line.starts_with("cargo:warning=")));
+        assert!(first
+            .lines()
+            .any(|line| line == format!("cargo:rerun-if-changed={}", wit.display())));
+        assert!(!unchanged.updated);
+        assert_eq!(unchanged.lock, locked.lock);
+        assert!(second.lines().any(|line| line == env));
+        assert!(!second
+            .lines()
+            .any(|line| line.starts_with("cargo:warning=")));
+        Ok(())
+    }
+}
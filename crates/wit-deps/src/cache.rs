@@ -1,15 +1,49 @@
+#[cfg(feature = "reqwest")]
+mod http;
+mod index;
+mod memory;
+#[cfg(feature = "s3")]
+mod s3;
+mod tee;
+
+#[cfg(feature = "reqwest")]
+pub use http::{Http, HttpWriter};
+pub use index::Entry as IndexEntry;
+use index::FailedLookup;
+pub use memory::{Memory, MemoryWriter};
+pub use tee::{tee_into, Tee};
+
+use crate::{fs, install_wits, remove_dir_all, Digest, Identifier, Lock, LockEntrySource, OsVfs};
+#[cfg(feature = "s3")]
+pub use s3::{S3Writer, S3};
+
 use core::fmt;
 use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
 use std::ffi::{OsStr, OsString};
-use std::path::{Path, PathBuf};
+use std::io;
+use std::mem;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{bail, Context as _};
 use async_trait::async_trait;
+use blocking::{unblock, Unblock};
 use directories::ProjectDirs;
-use futures::{io::BufReader, AsyncBufRead, AsyncWrite};
-use tokio::fs::{self, File, OpenOptions};
-use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use futures::future::BoxFuture;
+use futures::{
+    io::Cursor, AsyncBufRead, AsyncRead, AsyncWrite, AsyncWriteExt as _, StreamExt as _,
+    TryStreamExt as _,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+use tracing::{debug, error, trace, warn};
 use url::{Host, Url};
 
 /// Resource caching layer
@@ -23,61 +57,409 @@ pub trait Cache {
     /// Returns an read handle for the entry from the cache associated with a given url
     async fn get(&self, url: &Url) -> anyhow::Result<Option<Self::Read>>;
 
-    /// Returns a write handle for the entry associated with a given url
+    /// Returns a write handle for the entry associated with a given url. The entry must only be
+    /// committed to the cache once the handle is closed, dropping the handle without closing it
+    /// must discard the entry. An existing entry associated with the url must be replaced on
+    /// commit.
     async fn insert(&self, url: &Url) -> anyhow::Result<Self::Write>;
+
+    /// Returns the time the entry associated with a given url was inserted into the cache or last
+    /// [validated](Self::validated), if present. Caches, which do not track insertion time,
+    /// always return `None`.
+    fn inserted<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<SystemTime>>> {
+        _ = url;
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Returns the HTTP `ETag` of the upstream resource recorded for the entry associated with a
+    /// given url, if any. Caches, which do not track metadata, always return `None`.
+    fn etag<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<String>>> {
+        _ = url;
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Records that the entry associated with a given url was validated against upstream, which
+    /// returned `etag`. Caches, which do not track metadata, ignore this.
+    fn validated<'a>(
+        &'a self,
+        url: &'a Url,
+        etag: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        _ = (url, etag);
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Returns the HTTP status of a failed upstream lookup of a given url, if one was
+    /// [recorded](Self::record_failed_lookup) recently. Caches, which do not record failed
+    /// lookups, always return `None`.
+    fn failed_lookup<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<u16>>> {
+        _ = url;
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Records that upstream responded to a lookup of a given url with HTTP `status`, e.g.
+    /// `404 Not Found`, such that the lookup is not retried for a while. Caches, which do not
+    /// record failed lookups, ignore this.
+    fn record_failed_lookup<'a>(
+        &'a self,
+        url: &'a Url,
+        status: u16,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        _ = (url, status);
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Returns the path of a directory containing unpacked WIT definitions with `digest`, laid
+    /// out as a `wit` directory with transitive dependencies in `deps` subdirectory, if present.
+    /// Caches, which do not store unpacked trees, always return `None`.
+    fn get_tree<'a>(
+        &'a self,
+        digest: &'a Digest,
+    ) -> BoxFuture<'a, anyhow::Result<Option<PathBuf>>> {
+        _ = digest;
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Stores a copy of unpacked WIT definitions with `digest` at `src` along with transitive
+    /// dependencies `deps`, such that it can be looked up using [`Self::get_tree`]. Caches, which
+    /// do not store unpacked trees, ignore this.
+    fn insert_tree<'a>(
+        &'a self,
+        digest: &'a Digest,
+        src: &'a Path,
+        deps: &'a BTreeMap<Identifier, PathBuf>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        _ = (digest, src, deps);
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Stores a snapshot of WIT definitions with `digest` copied from a path dependency to `src`
+    /// along with transitive dependencies `deps`, such that it can be restored using
+    /// [`Self::get_tree`] if the path dependency goes missing. Caches, which do not store
+    /// snapshots, ignore this.
+    fn insert_snapshot<'a>(
+        &'a self,
+        digest: &'a Digest,
+        src: &'a Path,
+        deps: &'a BTreeMap<Identifier, PathBuf>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        _ = (digest, src, deps);
+        Box::pin(async { Ok(()) })
+    }
 }
 
-/// Write-only [Cache] wrapper
-pub struct Write<T>(pub T);
+/// Policy determining how the [Cache] is used when locking, see
+/// [`LockOptions::with_cache_policy`](crate::LockOptions::with_cache_policy)
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Policy {
+    /// Dependencies are restored from and inserted into the cache
+    #[default]
+    ReadWrite,
+    /// Dependencies are inserted into the cache, but never restored from it, such that all
+    /// dependencies are fetched again and the cache is refreshed
+    WriteOnly,
+    /// Dependencies are restored from the cache, but the cache is never modified
+    ReadOnly,
+    /// The cache is not used at all
+    Disabled,
+}
 
-impl<T> From<T> for Write<T> {
-    fn from(cache: T) -> Self {
-        Self(cache)
+impl Policy {
+    /// Returns `true` if dependencies may be restored from the cache
+    #[must_use]
+    pub fn reads(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadOnly)
+    }
+
+    /// Returns `true` if the cache may be modified
+    #[must_use]
+    pub fn writes(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::WriteOnly)
+    }
+
+    /// Returns the policy, which does not allow dependencies to be restored from the cache
+    #[must_use]
+    pub fn without_reads(self) -> Self {
+        match self {
+            Self::ReadWrite | Self::WriteOnly => Self::WriteOnly,
+            Self::ReadOnly | Self::Disabled => Self::Disabled,
+        }
     }
 }
 
-impl<T> Deref for Write<T> {
-    type Target = T;
+/// [Local] cache eviction policy
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct GcPolicy {
+    /// Maximum total size of the cache in bytes, least recently accessed entries are evicted
+    /// first once it is exceeded
+    pub max_size: Option<u64>,
+    /// Maximum duration since an entry was last accessed
+    pub max_age: Option<Duration>,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.0
+impl GcPolicy {
+    /// Environment variable specifying the default [`GcPolicy::max_size`], see
+    /// [`GcPolicy::parse_size`] for format
+    pub const MAX_SIZE_ENV: &'static str = "WIT_DEPS_CACHE_MAX_SIZE";
+
+    /// Environment variable specifying the default [`GcPolicy::max_age`], see
+    /// [`GcPolicy::parse_age`] for format
+    pub const MAX_AGE_ENV: &'static str = "WIT_DEPS_CACHE_MAX_AGE";
+
+    /// Returns the policy configured using [`GcPolicy::MAX_SIZE_ENV`] and
+    /// [`GcPolicy::MAX_AGE_ENV`] environment variables
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the environment variables is set to an invalid value
+    pub fn from_env() -> anyhow::Result<Self> {
+        let max_size = env::var(Self::MAX_SIZE_ENV)
+            .ok()
+            .map(|s| Self::parse_size(&s))
+            .transpose()
+            .with_context(|| format!("invalid `{}` value", Self::MAX_SIZE_ENV))?;
+        let max_age = env::var(Self::MAX_AGE_ENV)
+            .ok()
+            .map(|s| Self::parse_age(&s))
+            .transpose()
+            .with_context(|| format!("invalid `{}` value", Self::MAX_AGE_ENV))?;
+        Ok(Self { max_size, max_age })
+    }
+
+    /// Returns `true` if no limits are configured
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.max_size.is_none() && self.max_age.is_none()
+    }
+
+    /// Fill in limits missing in `self` from `other`
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            max_size: self.max_size.or(other.max_size),
+            max_age: self.max_age.or(other.max_age),
+        }
+    }
+
+    /// Parses a size in bytes with an optional binary `K`, `M` or `G` suffix, e.g. `512M`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `s` is not a valid size
+    pub fn parse_size(s: &str) -> anyhow::Result<u64> {
+        let s = s.trim();
+        let (n, shift) = match s.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&s[..i], 10),
+            Some((i, 'M' | 'm')) => (&s[..i], 20),
+            Some((i, 'G' | 'g')) => (&s[..i], 30),
+            _ => (s, 0),
+        };
+        let n: u64 = n.parse().with_context(|| format!("invalid size `{s}`"))?;
+        n.checked_mul(1 << shift)
+            .with_context(|| format!("size `{s}` is too large"))
+    }
+
+    /// Parses a duration in seconds with an optional `s`, `m`, `h`, `d` or `w` suffix, e.g. `30d`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `s` is not a valid duration
+    pub fn parse_age(s: &str) -> anyhow::Result<Duration> {
+        let s = s.trim();
+        let (n, secs) = match s.char_indices().last() {
+            Some((i, 's')) => (&s[..i], 1),
+            Some((i, 'm')) => (&s[..i], 60),
+            Some((i, 'h')) => (&s[..i], 60 * 60),
+            Some((i, 'd')) => (&s[..i], 24 * 60 * 60),
+            Some((i, 'w')) => (&s[..i], 7 * 24 * 60 * 60),
+            _ => (s, 1),
+        };
+        let n: u64 = n
+            .parse()
+            .with_context(|| format!("invalid duration `{s}`"))?;
+        n.checked_mul(secs)
+            .map(Duration::from_secs)
+            .with_context(|| format!("duration `{s}` is too large"))
     }
 }
 
-impl<T> DerefMut for Write<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+/// Result of a [`Local::gc`] run
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct GcStats {
+    /// Number of evicted entries
+    pub removed: usize,
+    /// Total size of evicted entries in bytes
+    pub freed: u64,
+    /// Total size of remaining entries in bytes
+    pub size: u64,
+}
+
+/// Result of a [`Local::verify`] run
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct VerifyStats {
+    /// Number of entries matching the digests recorded on insert
+    pub valid: usize,
+    /// Number of entries, for which no digest was recorded, e.g. inserted by an older version
+    pub unknown: usize,
+    /// Paths of corrupted entries, which were removed from the cache
+    pub corrupted: Vec<PathBuf>,
+}
+
+/// Hit and miss counters of a [Local] cache
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Counters {
+    /// Number of lookups, which found an entry
+    pub hits: u64,
+    /// Number of lookups, which did not find an entry
+    pub misses: u64,
+}
+
+/// Statistics of a [Local] cache
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Stats {
+    /// Number of entries
+    pub entries: usize,
+    /// Number of unpacked trees, see [`Local::with_trees`]
+    pub trees: usize,
+    /// Total size of entries and unpacked trees in bytes
+    pub size: u64,
+    /// Counters of the last run, which looked up entries in the cache, if recorded
+    pub last_run: Option<Counters>,
+}
+
+/// Write handle of a [Local] cache entry.
+///
+/// Contents are written to a temporary file, which is atomically renamed to the entry path on
+/// close. An advisory lock on the entry is held by the handle, such that concurrent writers of
+/// the same entry are rejected.
+pub struct LocalWriter {
+    file: Option<Unblock<std::fs::File>>,
+    hasher: Sha256,
+    size: u64,
+    root: PathBuf,
+    key: String,
+    url: Option<Url>,
+    tmp: PathBuf,
+    digest: PathBuf,
+    path: PathBuf,
+    // NOTE: The lock is released once the entry is committed or the writer is dropped
+    lock: Option<std::fs::File>,
+    // NOTE: The digest is written, the entry renamed and the index updated off the executor, once
+    // the file is closed
+    commit: Option<BoxFuture<'static, io::Result<()>>>,
+}
+
+impl fmt::Debug for LocalWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalWriter")
+            .field("tmp", &self.tmp)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
     }
 }
 
-#[async_trait]
-impl<T: Cache + Sync + Send> Cache for Write<T> {
-    type Read = T::Read;
-    type Write = T::Write;
+impl AsyncWrite for LocalWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let Some(file) = this.file.as_mut() else {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        };
+        let n = ready!(Pin::new(file).poll_write(cx, buf))?;
+        this.hasher.update(&buf[..n]);
+        this.size += n as u64;
+        Poll::Ready(Ok(n))
+    }
 
-    async fn get(&self, _: &Url) -> anyhow::Result<Option<Self::Read>> {
-        Ok(None)
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.file.as_mut() {
+            Some(file) => Pin::new(file).poll_flush(cx),
+            None => Poll::Ready(Ok(())),
+        }
     }
 
-    async fn insert(&self, url: &Url) -> anyhow::Result<Self::Write> {
-        self.0.insert(url).await
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.commit.is_none() {
+            let Some(file) = self.file.as_mut() else {
+                return Poll::Ready(Ok(()));
+            };
+            ready!(Pin::new(file).poll_close(cx))?;
+            // NOTE: The file must be closed before it is renamed on Windows
+            self.file.take();
+            let sha256 = hex::encode(self.hasher.finalize_reset());
+            let digest = self.digest.clone();
+            let tmp = self.tmp.clone();
+            let path = self.path.clone();
+            let root = self.root.clone();
+            let key = mem::take(&mut self.key);
+            let url = self.url.take();
+            let size = self.size;
+            let lock = self.lock.take();
+            self.commit = Some(Box::pin(unblock(move || {
+                std::fs::write(digest, &sha256)?;
+                std::fs::rename(tmp, &path)?;
+                let now = SystemTime::now();
+                let entry = IndexEntry {
+                    url,
+                    sha256,
+                    size,
+                    inserted: now,
+                    accessed: now,
+                    validated: now,
+                    etag: None,
+                };
+                if let Err(e) = index::update(&root, |index| {
+                    index.failed.remove(&key);
+                    index.entries.insert(key, entry);
+                }) {
+                    warn!(
+                        "failed to record `{}` in cache index: {e:#}",
+                        path.display()
+                    );
+                }
+                drop(lock);
+                trace!("committed `{}`", path.display());
+                Ok(())
+            })));
+        }
+        let Some(commit) = self.commit.as_mut() else {
+            return Poll::Ready(Ok(()));
+        };
+        let res = ready!(commit.as_mut().poll(cx));
+        self.commit.take();
+        Poll::Ready(res)
     }
 }
 
-impl<T> Write<T> {
-    /// Extracts the inner [Cache]
-    pub fn into_inner(self) -> T {
-        self.0
+impl Drop for LocalWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            trace!("discard `{}`", self.tmp.display());
+            if let Err(e) = std::fs::remove_file(&self.tmp) {
+                debug!("failed to remove `{}`: {e}", self.tmp.display());
+            }
+        }
     }
 }
 
 /// Local caching layer
 #[derive(Clone, Debug)]
-pub struct Local(PathBuf);
+pub struct Local {
+    path: PathBuf,
+    gc: GcPolicy,
+    trees: bool,
+    snapshots: bool,
+    failed_ttl: Duration,
+    hits: Arc<AtomicU64>,
+    misses: Arc<AtomicU64>,
+}
 
 impl fmt::Display for Local {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0.display())
+        write!(f, "{}", self.path.display())
     }
 }
 
@@ -85,112 +467,966 @@ impl Deref for Local {
     type Target = PathBuf;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.path
     }
 }
 
 impl DerefMut for Local {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.path
     }
 }
 
 impl Local {
-    /// Returns a [Local] cache located at the default system-specific cache directory if such
-    /// could be determined.
+    /// Environment variable overriding the default cache directory
+    pub const DIR_ENV: &'static str = "WIT_DEPS_CACHE_DIR";
+
+    /// Environment variable enabling caching of unpacked trees if set to `1` or `true`, see
+    /// [`Local::with_trees`]
+    pub const TREES_ENV: &'static str = "WIT_DEPS_CACHE_TREES";
+
+    /// Environment variable enabling snapshots of path dependencies if set to `1` or `true`, see
+    /// [`Local::with_snapshots`]
+    pub const SNAPSHOTS_ENV: &'static str = "WIT_DEPS_CACHE_SNAPSHOTS";
+
+    /// Environment variable overriding the time failed upstream lookups are cached for, e.g.
+    /// `10m`, see [`Local::with_failed_lookup_ttl`]
+    pub const FAILED_LOOKUP_TTL_ENV: &'static str = "WIT_DEPS_CACHE_FAILED_LOOKUP_TTL";
+
+    /// Default time failed upstream lookups are cached for
+    // NOTE: `Duration::from_mins` is only available since Rust 1.91
+    #[allow(clippy::duration_suboptimal_units)]
+    pub const DEFAULT_FAILED_LOOKUP_TTL: Duration = Duration::from_secs(5 * 60);
+
+    /// Returns a [Local] cache located at the directory specified by [`Local::DIR_ENV`]
+    /// environment variable or the default system-specific cache directory if such could be
+    /// determined. The cache is garbage collected on insert according to
+    /// [`GcPolicy::from_env`] and stores unpacked trees if [`Local::TREES_ENV`] is set.
+    #[must_use]
     pub fn cache_dir() -> Option<Self> {
-        ProjectDirs::from("", "", env!("CARGO_PKG_NAME"))
-            .as_ref()
-            .map(ProjectDirs::cache_dir)
-            .map(Self::from)
+        Self::default_dir().map(|dir| Self::from(dir).with_env_config())
     }
 
-    fn path(&self, url: &Url) -> impl AsRef<Path> {
-        let mut path = self.0.clone();
-        match url.host() {
-            Some(Host::Ipv4(ip)) => {
-                path.push(ip.to_string());
+    /// Returns the directory specified by [`Local::DIR_ENV`] environment variable or the default
+    /// system-specific cache directory if such could be determined
+    fn default_dir() -> Option<PathBuf> {
+        env::var_os(Self::DIR_ENV)
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| {
+                ProjectDirs::from("", "", env!("CARGO_PKG_NAME"))
+                    .as_ref()
+                    .map(ProjectDirs::cache_dir)
+                    .map(Path::to_path_buf)
+            })
+    }
+
+    /// Returns the path of the advisory lock file guarding the `deps` directories within `dir`.
+    /// Lock files are located in the default cache directory, see [`Local::cache_dir`], or in
+    /// the system temporary directory, if it cannot be determined, rather than in the source
+    /// tree and are named by the SHA-256 digest of the absolute path of `dir`.
+    pub(crate) fn deps_lock_path(dir: &Path) -> PathBuf {
+        let dir = std::path::absolute(dir).unwrap_or_else(|_| dir.to_path_buf());
+        Self::default_dir()
+            .unwrap_or_else(env::temp_dir)
+            .join(Self::DEPS_LOCKS)
+            .join(hex::encode(Sha256::digest(
+                dir.as_os_str().as_encoded_bytes(),
+            )))
+    }
+
+    /// Returns the cache with [`GcPolicy::from_env`] eviction policy, if valid,
+    /// [`Local::TREES_ENV`], [`Local::SNAPSHOTS_ENV`] and [`Local::FAILED_LOOKUP_TTL_ENV`] applied
+    pub(crate) fn with_env_config(self) -> Self {
+        let enabled =
+            |name| env::var(name).is_ok_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+        let mut cache = self
+            .with_trees(enabled(Self::TREES_ENV))
+            .with_snapshots(enabled(Self::SNAPSHOTS_ENV));
+        if let Ok(ttl) = env::var(Self::FAILED_LOOKUP_TTL_ENV) {
+            match GcPolicy::parse_age(&ttl) {
+                Ok(ttl) => cache = cache.with_failed_lookup_ttl(ttl),
+                Err(e) => error!("invalid `{}` value: {e:#}", Self::FAILED_LOOKUP_TTL_ENV),
             }
-            Some(Host::Ipv6(ip)) => {
-                path.push(ip.to_string());
+        }
+        match GcPolicy::from_env() {
+            Ok(gc) => cache.with_gc_policy(gc),
+            Err(e) => {
+                error!("failed to configure cache garbage collection: {e:#}");
+                cache
             }
-            Some(Host::Domain(domain)) => {
-                path.push(domain);
+        }
+    }
+
+    /// Returns the cache with `gc` eviction policy applied on every [insert](Cache::insert)
+    #[must_use]
+    pub fn with_gc_policy(self, gc: GcPolicy) -> Self {
+        Self { gc, ..self }
+    }
+
+    /// Returns the cache, which additionally stores digest-verified unpacked WIT trees keyed by
+    /// digest if `trees` is `true`, such that a cache hit does not require decompressing and
+    /// unpacking the archive. Unpacked trees are subject to garbage collection like entries.
+    #[must_use]
+    pub fn with_trees(self, trees: bool) -> Self {
+        Self { trees, ..self }
+    }
+
+    /// Returns the cache, which additionally stores snapshots of path dependencies keyed by
+    /// digest if `snapshots` is `true`, such that a locked path dependency can be restored if
+    /// its source directory is missing or empty, for example, if it is an uninitialized git
+    /// submodule. Snapshots are subject to garbage collection like entries.
+    #[must_use]
+    pub fn with_snapshots(self, snapshots: bool) -> Self {
+        Self { snapshots, ..self }
+    }
+
+    /// Returns the cache, which records `404 Not Found` and `410 Gone` upstream responses for
+    /// `ttl`, during which lookups of the same URL fail without contacting upstream. Zero `ttl`
+    /// disables caching of failed lookups.
+    #[must_use]
+    pub fn with_failed_lookup_ttl(self, ttl: Duration) -> Self {
+        Self {
+            failed_ttl: ttl,
+            ..self
+        }
+    }
+
+    /// Name of the file within the cache directory, which [`Counters`] of the last run are
+    /// recorded in
+    const COUNTERS: &'static str = ".counters.toml";
+
+    /// Name of the directory within the cache directory containing entry locks
+    const LOCKS: &'static str = ".locks";
+
+    /// Name of the directory within the cache directory containing advisory locks of `deps`
+    /// directories, see [`Local::deps_lock_path`]
+    const DEPS_LOCKS: &'static str = ".deps-locks";
+
+    /// Name of the directory within the cache directory containing partially written entries
+    const TMP: &'static str = ".tmp";
+
+    /// Name of the directory within the cache directory containing SHA-256 digests of entries
+    /// recorded on insert
+    const DIGESTS: &'static str = ".digests";
+
+    /// Name of the directory within the cache directory containing unpacked trees
+    const TREES: &'static str = ".trees";
+
+    /// Name of the directory within the cache directory, which corrupted entries are moved to
+    /// by [`Local::verify`]
+    const QUARANTINE: &'static str = ".quarantine";
+
+    /// Returns the name identifying the entry at `path` within the bookkeeping directories
+    fn entry_name(&self, path: &Path) -> String {
+        let path = path.strip_prefix(&self.path).unwrap_or(path);
+        hex::encode(Sha256::digest(path.as_os_str().as_encoded_bytes()))
+    }
+
+    /// Compares the SHA-256 digest of `buf` against the digest recorded on insert of the entry
+    /// identified by `name`. Returns `None` if no digest was recorded.
+    async fn matches_digest(&self, name: &str, buf: &[u8]) -> anyhow::Result<Option<bool>> {
+        let path = self.path.join(Self::DIGESTS).join(name);
+        match fs::read_to_string(&path).await {
+            Ok(expected) => Ok(Some(hex::encode(Sha256::digest(buf)) == expected.trim())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("failed to read `{}`", path.display())),
+        }
+    }
+
+    /// Acquires an exclusive lock of the entry identified by `name`. Returns `None` if the entry
+    /// is locked by another handle.
+    async fn lock_entry(&self, name: &str) -> anyhow::Result<Option<std::fs::File>> {
+        let locks = self.path.join(Self::LOCKS);
+        fs::create_dir_all(&locks)
+            .await
+            .context("failed to create lock directory")?;
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).truncate(false).write(true);
+        let lock = fs::open(locks.join(name), options)
+            .await
+            .context("failed to open lock file")?
+            .into_inner()
+            .await;
+        match lock.try_lock() {
+            Ok(()) => Ok(Some(lock)),
+            Err(std::fs::TryLockError::WouldBlock) => Ok(None),
+            Err(std::fs::TryLockError::Error(e)) => Err(e).context("failed to lock cache entry"),
+        }
+    }
+
+    /// Returns all entries in the cache as (last access time, size, path) tuples. Hidden files
+    /// and directories at the root of the cache, which are used for bookkeeping, are skipped.
+    async fn entries(&self) -> anyhow::Result<Vec<(SystemTime, u64, PathBuf)>> {
+        let mut entries = Vec::new();
+        let mut dirs = vec![self.path.clone()];
+        while let Some(dir) = dirs.pop() {
+            let mut dir = match fs::read_dir(&dir).await {
+                Ok(dir) => dir,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| format!("failed to read `{}`", dir.display()))
+                }
+            };
+            while let Some(e) = dir.next_entry().await.context("failed to read entry")? {
+                if e.path().parent() == Some(&self.path)
+                    && e.file_name().as_encoded_bytes().starts_with(b".")
+                {
+                    continue;
+                }
+                let meta = e.metadata().await.with_context(|| {
+                    format!("failed to read metadata of `{}`", e.path().display())
+                })?;
+                if meta.is_dir() {
+                    dirs.push(e.path());
+                } else {
+                    // NOTE: Access time is not updated on filesystems mounted with `noatime`,
+                    // in which case modification time is used
+                    let accessed = match (meta.accessed(), meta.modified()) {
+                        (Ok(accessed), Ok(modified)) => accessed.max(modified),
+                        (Ok(t), Err(_)) | (Err(_), Ok(t)) => t,
+                        (Err(_), Err(_)) => SystemTime::UNIX_EPOCH,
+                    };
+                    entries.push((accessed, meta.len(), e.path()));
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns all unpacked trees in the cache as (last access time, size, path) tuples
+    async fn trees(&self) -> anyhow::Result<Vec<(SystemTime, u64, PathBuf)>> {
+        let root = self.path.join(Self::TREES);
+        let mut trees = match fs::read_dir(&root).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read `{}`", root.display()))
             }
-            _ => {}
+        };
+        let mut entries = Vec::new();
+        while let Some(tree) = trees.try_next().await.context("failed to read entry")? {
+            let path = tree.path();
+            let meta = tree
+                .metadata()
+                .await
+                .with_context(|| format!("failed to read metadata of `{}`", path.display()))?;
+            let mut size = 0;
+            let mut dirs = vec![path.clone()];
+            while let Some(dir) = dirs.pop() {
+                let mut dir = fs::read_dir(&dir)
+                    .await
+                    .with_context(|| format!("failed to read `{}`", dir.display()))?;
+                while let Some(e) = dir.next_entry().await.context("failed to read entry")? {
+                    let meta = e.metadata().await.with_context(|| {
+                        format!("failed to read metadata of `{}`", e.path().display())
+                    })?;
+                    if meta.is_dir() {
+                        dirs.push(e.path());
+                    } else {
+                        size += meta.len();
+                    }
+                }
+            }
+            let accessed = match (meta.accessed(), meta.modified()) {
+                (Ok(accessed), Ok(modified)) => accessed.max(modified),
+                (Ok(t), Err(_)) | (Err(_), Ok(t)) => t,
+                (Err(_), Err(_)) => SystemTime::UNIX_EPOCH,
+            };
+            entries.push((accessed, size, path));
+        }
+        Ok(entries)
+    }
+
+    /// Removes the entry at `path` and all of its parent directories within the cache, which
+    /// become empty
+    async fn remove_entry(&self, path: &Path) -> std::io::Result<()> {
+        fs::remove_file(path).await?;
+        let key = self.index_key(path);
+        if let Err(e) = self
+            .update_index(move |index| {
+                index.entries.remove(&key);
+            })
+            .await
+        {
+            debug!(
+                "failed to remove `{}` from cache index: {e:#}",
+                path.display()
+            );
+        }
+        let digest = self.path.join(Self::DIGESTS).join(self.entry_name(path));
+        if let Err(e) = fs::remove_file(&digest).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                debug!("failed to remove `{}`: {e}", digest.display());
+            }
+        }
+        for dir in path.ancestors().skip(1) {
+            if dir == self.path || fs::remove_dir(dir).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Evicts entries, which were not accessed within [`GcPolicy::max_age`], followed by least
+    /// recently accessed entries until the total size of the cache is within
+    /// [`GcPolicy::max_size`]. Empty directories are removed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache could not be traversed or an entry could not be removed
+    pub async fn gc(&self, policy: GcPolicy) -> anyhow::Result<GcStats> {
+        let index = self.index().await?;
+        let mut entries = self.entries().await?;
+        for (accessed, _, path) in &mut entries {
+            // NOTE: Access time recorded in the index is preferred, since file access time is
+            // not updated on filesystems mounted with `noatime`
+            if let Some(entry) = index.get(&self.index_key(path)) {
+                *accessed = entry.accessed.max(*accessed);
+            }
+        }
+        entries.extend(self.trees().await?);
+        // Least recently accessed entries first
+        entries.sort_unstable();
+
+        let now = SystemTime::now();
+        let mut size: u64 = entries.iter().map(|(_, len, _)| len).sum();
+        let mut stats = GcStats::default();
+        for (accessed, len, path) in entries {
+            let expired = policy
+                .max_age
+                .is_some_and(|max_age| now.duration_since(accessed).is_ok_and(|age| age > max_age));
+            let oversized = policy.max_size.is_some_and(|max_size| size > max_size);
+            if !expired && !oversized {
+                continue;
+            }
+            trace!("evict `{}`", path.display());
+            if path.starts_with(self.path.join(Self::TREES)) {
+                remove_dir_all(&path).await?;
+            } else {
+                self.remove_entry(&path)
+                    .await
+                    .with_context(|| format!("failed to remove `{}`", path.display()))?;
+            }
+            stats.removed += 1;
+            stats.freed += len;
+            size -= len;
         }
-        if let Some(segments) = url.path_segments() {
-            for seg in segments {
-                path.push(seg);
+        stats.size = size;
+        debug!(?stats, "garbage collected cache at `{self}`");
+        Ok(stats)
+    }
+
+    /// Returns statistics of the cache
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache could not be traversed
+    pub async fn stats(&self) -> anyhow::Result<Stats> {
+        let entries = self.entries().await?;
+        let trees = self.trees().await?;
+        let path = self.path.join(Self::COUNTERS);
+        let last_run = match fs::read_to_string(&path).await {
+            Ok(counters) => toml::from_str(&counters)
+                .map(Some)
+                .with_context(|| format!("failed to decode `{}`", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read `{}`", path.display()))
             }
+        };
+        Ok(Stats {
+            entries: entries.len(),
+            trees: trees.len(),
+            size: entries.iter().chain(&trees).map(|(_, len, _)| len).sum(),
+            last_run,
+        })
+    }
+
+    /// Returns [`Counters`] of lookups performed using this handle (and its clones)
+    #[must_use]
+    pub fn counters(&self) -> Counters {
+        Counters {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Records [`Counters`] of this handle within the cache directory, such that they are
+    /// reported by [`Local::stats`]. This is a no-op if no lookups were performed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing the counters fails
+    pub async fn save_counters(&self) -> anyhow::Result<()> {
+        let counters = self.counters();
+        if counters == Counters::default() {
+            return Ok(());
+        }
+        let counters = toml::to_string(&counters).context("failed to encode counters")?;
+        fs::create_dir_all(&self.path)
+            .await
+            .context("failed to create directory")?;
+        let path = self.path.join(Self::COUNTERS);
+        fs::write(&path, counters)
+            .await
+            .with_context(|| format!("failed to write `{}`", path.display()))
+    }
+
+    /// Re-hashes all entries and compares them against the digests recorded when they were
+    /// inserted. Corrupted entries are moved to a quarantine directory within the cache if
+    /// `quarantine` is `true` and removed otherwise. Entries currently being written to are
+    /// skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache could not be traversed or a corrupted entry could not be
+    /// removed
+    pub async fn verify(&self, quarantine: bool) -> anyhow::Result<VerifyStats> {
+        let mut stats = VerifyStats::default();
+        for (_, _, path) in self.entries().await? {
+            let name = self.entry_name(&path);
+            let Some(_lock) = self.lock_entry(&name).await? else {
+                debug!("skip `{}`, which is being written to", path.display());
+                continue;
+            };
+            let buf = fs::read(&path)
+                .await
+                .with_context(|| format!("failed to read `{}`", path.display()))?;
+            match self.matches_digest(&name, &buf).await? {
+                Some(true) => {
+                    stats.valid += 1;
+                    continue;
+                }
+                Some(false) => {}
+                None => {
+                    trace!("no digest recorded for `{}`", path.display());
+                    stats.unknown += 1;
+                    continue;
+                }
+            }
+            warn!("`{}` is corrupted", path.display());
+            if quarantine {
+                let dst = self
+                    .path
+                    .join(Self::QUARANTINE)
+                    .join(path.strip_prefix(&self.path).unwrap_or(&path));
+                if let Some(parent) = dst.parent() {
+                    fs::create_dir_all(parent)
+                        .await
+                        .context("failed to create quarantine directory")?;
+                }
+                fs::copy(&path, &dst).await.with_context(|| {
+                    format!(
+                        "failed to quarantine `{}` to `{}`",
+                        path.display(),
+                        dst.display()
+                    )
+                })?;
+            }
+            self.remove_entry(&path)
+                .await
+                .with_context(|| format!("failed to remove `{}`", path.display()))?;
+            stats.corrupted.push(path);
+        }
+        debug!(?stats, "verified cache at `{self}`");
+        Ok(stats)
+    }
+
+    /// Writes a tar archive containing the entries associated with all URL sources in `lock` to
+    /// `dst`, such that they can be imported into another cache using [`Local::import`].
+    /// Returns the number of exported entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the entries is not present in the cache or writing the archive
+    /// fails
+    pub async fn export(
+        &self,
+        lock: &Lock,
+        dst: impl AsyncWrite + Sync + Send + Unpin,
+    ) -> anyhow::Result<usize> {
+        let urls: BTreeSet<_> = lock
+            .values()
+            .filter_map(|entry| match &entry.source {
+                Some(LockEntrySource::Url(url)) => Some(url),
+                _ => None,
+            })
+            .collect();
+        let mut tar = async_tar::Builder::new(dst);
+        tar.mode(async_tar::HeaderMode::Deterministic);
+        for url in &urls {
+            let path = self.path(url);
+            let path = path.as_ref();
+            if !fs::try_exists(path).await.unwrap_or(false) {
+                bail!("`{url}` is not present in cache at `{self}`")
+            }
+            let name = segments(url).collect::<Vec<_>>().join("/");
+            tar.append_path_with_name(path, &name)
+                .await
+                .with_context(|| format!("failed to export `{url}`"))?;
+            trace!("exported `{url}`");
+        }
+        tar.into_inner()
+            .await
+            .context("failed to finish archive")?
+            .close()
+            .await
+            .context("failed to close archive")?;
+        Ok(urls.len())
+    }
+
+    /// Inserts all entries contained in a tar archive produced by [`Local::export`] read from
+    /// `src` into the cache. Returns the number of imported entries.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the archive or inserting any of the entries fails
+    pub async fn import(&self, src: impl AsyncRead + Unpin) -> anyhow::Result<usize> {
+        let mut entries = async_tar::Archive::new(src)
+            .entries()
+            .context("failed to read archive")?;
+        let mut n = 0;
+        while let Some(mut e) = entries.next().await.transpose()? {
+            if !e.header().entry_type().is_file() {
+                continue;
+            }
+            let name = e.path().context("failed to read entry path")?.into_owned();
+            let mut path = self.path.clone();
+            for c in name.components() {
+                match c {
+                    // NOTE: Hidden directories at the root of the cache are used for bookkeeping
+                    Component::Normal(c)
+                        if path != self.path || !c.as_encoded_bytes().starts_with(b".") =>
+                    {
+                        path.push(c);
+                    }
+                    _ => bail!("invalid entry path `{}`", name.display()),
+                }
+            }
+            let mut w = self.insert_path(path, None).await?;
+            futures::io::copy(&mut e, &mut w)
+                .await
+                .with_context(|| format!("failed to import `{}`", name.display()))?;
+            w.close()
+                .await
+                .with_context(|| format!("failed to commit `{}`", name.display()))?;
+            trace!("imported `{}`", name.display());
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Removes the entry associated with `url` from the cache. Returns `false` if no such entry
+    /// was present.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if removing the entry fails
+    pub async fn remove(&self, url: &Url) -> anyhow::Result<bool> {
+        let path = self.path(url);
+        match self.remove_entry(path.as_ref()).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => bail!("failed to remove `{url}` from cache: {e}"),
+        }
+    }
+
+    /// Looks up the unpacked tree with `digest`, see [`Cache::get_tree`]
+    async fn lookup_tree(&self, digest: &Digest) -> anyhow::Result<Option<PathBuf>> {
+        if !self.trees && !self.snapshots {
+            return Ok(None);
+        }
+        let path = self.tree_path(digest);
+        match fs::metadata(&path).await {
+            Ok(meta) if meta.is_dir() => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(path))
+            }
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => bail!("failed to lookup `{}` in cache: {e}", path.display()),
+        }
+    }
+
+    /// Stores an unpacked tree with `digest`, see [`Cache::insert_tree`]
+    async fn store_tree(
+        &self,
+        digest: &Digest,
+        src: &Path,
+        deps: &BTreeMap<Identifier, PathBuf>,
+    ) -> anyhow::Result<()> {
+        let path = self.tree_path(digest);
+        if fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(());
+        }
+        let name = format!("tree-{}", hex::encode(digest.sha256));
+        let Some(_lock) = self.lock_entry(&name).await? else {
+            debug!("`{}` is being written by another process", path.display());
+            return Ok(());
+        };
+        let tmp = self.path.join(Self::TMP).join(name);
+        let res = async {
+            install_wits(&OsVfs, src, &tmp).await?;
+            for (id, dep) in deps {
+                install_wits(&OsVfs, dep, tmp.join("deps").join(id)).await?;
+            }
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+            fs::rename(&tmp, &path).await
+        }
+        .await;
+        if let Err(e) = res {
+            if let Err(e) = remove_dir_all(&tmp).await {
+                debug!("{e}");
+            }
+            bail!("failed to insert `{}` into cache: {e}", path.display())
+        }
+        trace!("committed `{}`", path.display());
+        Ok(())
+    }
+
+    /// Returns the key of the entry at `path` within the cache index, i.e. the `/`-separated
+    /// path of the entry relative to the cache directory
+    fn index_key(&self, path: &Path) -> String {
+        path.strip_prefix(&self.path)
+            .unwrap_or(path)
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Returns the cache index mapping entry keys to [`IndexEntry`] metadata
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the index fails
+    pub async fn index(&self) -> anyhow::Result<BTreeMap<String, IndexEntry>> {
+        let index = self.read_index().await?;
+        Ok(index.entries)
+    }
+
+    /// Reads the cache index, see [`index::read`]
+    async fn read_index(&self) -> anyhow::Result<index::Index> {
+        let root = self.path.clone();
+        unblock(move || index::read(&root)).await
+    }
+
+    /// Returns the [`IndexEntry`] metadata of the entry associated with `url`, if recorded
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the index fails
+    pub async fn metadata(&self, url: &Url) -> anyhow::Result<Option<IndexEntry>> {
+        let key = self.index_key(self.path(url).as_ref());
+        let mut index = self.index().await?;
+        Ok(index.remove(&key))
+    }
+
+    /// Applies `f` to the cache index, see [`index::update`]
+    async fn update_index<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut index::Index) -> T + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let root = self.path.clone();
+        unblock(move || index::update(&root, f)).await
+    }
+
+    /// Returns the path of the unpacked tree with `digest`
+    fn tree_path(&self, digest: &Digest) -> PathBuf {
+        self.path.join(Self::TREES).join(hex::encode(digest.sha256))
+    }
+
+    fn path(&self, url: &Url) -> impl AsRef<Path> {
+        let mut path = self.path.clone();
+        for seg in segments(url) {
+            path.push(seg);
         }
         path
     }
+
+    /// Returns a write handle for the entry at `path` within the cache
+    async fn insert_path(&self, path: PathBuf, url: Option<&Url>) -> anyhow::Result<LocalWriter> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .context("failed to create directory")?;
+        }
+        let tmp = self.path.join(Self::TMP);
+        let digests = self.path.join(Self::DIGESTS);
+        fs::create_dir_all(&tmp)
+            .await
+            .context("failed to create temporary directory")?;
+        fs::create_dir_all(&digests)
+            .await
+            .context("failed to create digest directory")?;
+
+        let name = self.entry_name(&path);
+        let Some(lock) = self.lock_entry(&name).await? else {
+            bail!("`{}` is being written by another process", path.display())
+        };
+        // NOTE: Partially written entries left over by crashed processes are truncated
+        let tmp = tmp.join(&name);
+        let mut options = std::fs::OpenOptions::new();
+        options.create(true).truncate(true).write(true);
+        let file = fs::open(&tmp, options)
+            .await
+            .context("failed to open file for writing")?;
+        Ok(LocalWriter {
+            file: Some(file),
+            hasher: Sha256::new(),
+            size: 0,
+            root: self.path.clone(),
+            key: self.index_key(&path),
+            url: url.cloned(),
+            tmp,
+            digest: digests.join(name),
+            path,
+            lock: Some(lock),
+            commit: None,
+        })
+    }
+}
+
+/// Returns the segments of the cache key of `url`, i.e. the host followed by path segments
+fn segments(url: &Url) -> impl Iterator<Item = String> + '_ {
+    let host = match url.host() {
+        Some(Host::Ipv4(ip)) => Some(ip.to_string()),
+        Some(Host::Ipv6(ip)) => Some(ip.to_string()),
+        Some(Host::Domain(domain)) => Some(domain.to_string()),
+        _ => None,
+    };
+    host.into_iter().chain(
+        url.path_segments()
+            .into_iter()
+            .flatten()
+            .map(ToString::to_string),
+    )
 }
 
 #[async_trait]
 impl Cache for Local {
-    type Read = BufReader<Compat<File>>;
-    type Write = Compat<File>;
+    type Read = Cursor<Vec<u8>>;
+    type Write = LocalWriter;
 
     async fn get(&self, url: &Url) -> anyhow::Result<Option<Self::Read>> {
-        match File::open(self.path(url)).await {
-            Ok(file) => Ok(Some(BufReader::new(file.compat()))),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        let path = self.path(url);
+        let path = path.as_ref();
+        let buf = match fs::read(path).await {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
             Err(e) => bail!("failed to lookup `{url}` in cache: {e}"),
+        };
+        // NOTE: Entries are validated against digests recorded on insert, if any, such that
+        // corrupted entries are never served
+        let name = self.entry_name(path);
+        if self.matches_digest(&name, &buf).await? == Some(false) {
+            warn!("discard corrupted cache entry of `{url}`");
+            // NOTE: The entry may be in process of being replaced, in which case it is left as-is
+            if let Some(_lock) = self.lock_entry(&name).await? {
+                if let Err(e) = self.remove_entry(path).await {
+                    warn!("failed to remove `{}`: {e}", path.display());
+                }
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(None);
         }
+        let key = self.index_key(path);
+        if let Err(e) = self
+            .update_index(move |index| {
+                if let Some(entry) = index.entries.get_mut(&key) {
+                    entry.accessed = SystemTime::now();
+                }
+            })
+            .await
+        {
+            debug!("failed to record access of `{url}` in cache index: {e:#}");
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Ok(Some(Cursor::new(buf)))
     }
 
     async fn insert(&self, url: &Url) -> anyhow::Result<Self::Write> {
-        let path = self.path(url);
-        if let Some(parent) = path.as_ref().parent() {
-            fs::create_dir_all(parent)
-                .await
-                .context("failed to create directory")?;
+        if !self.gc.is_empty() {
+            if let Err(e) = self.gc(self.gc).await {
+                error!("failed to garbage collect cache at `{self}`: {e:#}");
+            }
         }
-        OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(path)
+        self.insert_path(self.path(url).as_ref().to_path_buf(), Some(url))
+            .await
+    }
+
+    fn get_tree<'a>(
+        &'a self,
+        digest: &'a Digest,
+    ) -> BoxFuture<'a, anyhow::Result<Option<PathBuf>>> {
+        Box::pin(self.lookup_tree(digest))
+    }
+
+    fn insert_tree<'a>(
+        &'a self,
+        digest: &'a Digest,
+        src: &'a Path,
+        deps: &'a BTreeMap<Identifier, PathBuf>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            if self.trees {
+                self.store_tree(digest, src, deps).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn insert_snapshot<'a>(
+        &'a self,
+        digest: &'a Digest,
+        src: &'a Path,
+        deps: &'a BTreeMap<Identifier, PathBuf>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            if self.snapshots {
+                self.store_tree(digest, src, deps).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn inserted<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<SystemTime>>> {
+        Box::pin(async move {
+            if let Some(IndexEntry { validated, .. }) = self.metadata(url).await? {
+                return Ok(Some(validated));
+            }
+            // NOTE: Entries inserted before the index was introduced are not present in it
+            match fs::metadata(self.path(url)).await {
+                Ok(meta) => meta
+                    .modified()
+                    .map(Some)
+                    .with_context(|| format!("failed to query modification time of `{url}`")),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => bail!("failed to lookup `{url}` in cache: {e}"),
+            }
+        })
+    }
+
+    fn etag<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<String>>> {
+        Box::pin(async move {
+            let entry = self.metadata(url).await?;
+            Ok(entry.and_then(|IndexEntry { etag, .. }| etag))
+        })
+    }
+
+    fn validated<'a>(
+        &'a self,
+        url: &'a Url,
+        etag: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        let key = self.index_key(self.path(url).as_ref());
+        let etag = etag.map(String::from);
+        Box::pin(self.update_index(move |index| {
+            if let Some(entry) = index.entries.get_mut(&key) {
+                entry.validated = SystemTime::now();
+                if etag.is_some() {
+                    entry.etag = etag;
+                }
+            }
+        }))
+    }
+
+    fn failed_lookup<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<u16>>> {
+        Box::pin(async move {
+            if self.failed_ttl.is_zero() {
+                return Ok(None);
+            }
+            let key = self.index_key(self.path(url).as_ref());
+            let index = self.read_index().await?;
+            let now = SystemTime::now();
+            Ok(index
+                .failed
+                .get(&key)
+                .filter(|FailedLookup { recorded, .. }| {
+                    now.duration_since(*recorded)
+                        .is_ok_and(|age| age < self.failed_ttl)
+                })
+                .map(|FailedLookup { status, .. }| *status))
+        })
+    }
+
+    fn record_failed_lookup<'a>(
+        &'a self,
+        url: &'a Url,
+        status: u16,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        let key = self.index_key(self.path(url).as_ref());
+        let ttl = self.failed_ttl;
+        Box::pin(async move {
+            if ttl.is_zero() {
+                return Ok(());
+            }
+            self.update_index(move |index| {
+                let now = SystemTime::now();
+                // Expired records are pruned on every update to keep the index small
+                index.failed.retain(|_, FailedLookup { recorded, .. }| {
+                    now.duration_since(*recorded).is_ok_and(|age| age < ttl)
+                });
+                index.failed.insert(
+                    key,
+                    FailedLookup {
+                        status,
+                        recorded: now,
+                    },
+                );
+            })
             .await
-            .map(tokio_util::compat::TokioAsyncReadCompatExt::compat)
-            .context("failed to open file for writing")
+        })
     }
 }
 
 impl From<PathBuf> for Local {
     fn from(path: PathBuf) -> Self {
-        Self(path)
+        Self {
+            path,
+            gc: GcPolicy::default(),
+            trees: false,
+            snapshots: false,
+            failed_ttl: Self::DEFAULT_FAILED_LOOKUP_TTL,
+            hits: Arc::default(),
+            misses: Arc::default(),
+        }
     }
 }
 
 impl From<String> for Local {
     fn from(path: String) -> Self {
-        Self(path.into())
+        PathBuf::from(path).into()
     }
 }
 
 impl From<OsString> for Local {
     fn from(path: OsString) -> Self {
-        Self(path.into())
+        PathBuf::from(path).into()
     }
 }
 
 impl From<&Path> for Local {
     fn from(path: &Path) -> Self {
-        Self(path.into())
+        PathBuf::from(path).into()
     }
 }
 
 impl From<&str> for Local {
     fn from(path: &str) -> Self {
-        Self(path.into())
+        PathBuf::from(path).into()
     }
 }
 
 impl From<&OsStr> for Local {
     fn from(path: &OsStr) -> Self {
-        Self(path.into())
+        PathBuf::from(path).into()
     }
 }
 
@@ -198,6 +1434,8 @@ impl From<&OsStr> for Local {
 mod tests {
     use super::*;
 
+    use crate::tempdir::TempDir;
+
     #[test]
     fn local_path() {
         assert_eq!(
@@ -214,4 +1452,184 @@ mod tests {
                 .join("bar.tar.gz")
         );
     }
+
+    #[test]
+    fn gc_policy_parse() -> anyhow::Result<()> {
+        assert_eq!(GcPolicy::parse_size("1024")?, 1024);
+        assert_eq!(GcPolicy::parse_size("2K")?, 2048);
+        assert_eq!(GcPolicy::parse_size("512M")?, 512 << 20);
+        assert_eq!(GcPolicy::parse_size("1g")?, 1 << 30);
+        assert!(GcPolicy::parse_size("M").is_err());
+        assert!(GcPolicy::parse_size("1T").is_err());
+
+        assert_eq!(GcPolicy::parse_age("60")?.as_secs(), 60);
+        assert_eq!(GcPolicy::parse_age("5m")?.as_secs(), 300);
+        assert_eq!(GcPolicy::parse_age("2h")?.as_secs(), 7200);
+        assert_eq!(GcPolicy::parse_age("30d")?.as_secs(), 30 * 86400);
+        assert_eq!(GcPolicy::parse_age("1w")?.as_secs(), 7 * 86400);
+        assert!(GcPolicy::parse_age("d").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn verify() -> anyhow::Result<()> {
+        let foo: Url = "https://example.com/foo.tar.gz".parse()?;
+        let bar: Url = "https://example.com/bar.tar.gz".parse()?;
+
+        let dir = TempDir::new("wit-deps-cache-verify").await?;
+        let cache = Local::from(dir.path());
+        for url in [&foo, &bar] {
+            let mut w = cache.insert(url).await?;
+            w.write_all(b"contents").await?;
+            w.close().await?;
+        }
+        fs::write(cache.path(&bar), b"corrupted").await?;
+        let stats = cache.verify(false).await?;
+        let present = (
+            cache.get(&foo).await?.is_some(),
+            cache.get(&bar).await?.is_some(),
+        );
+
+        assert_eq!(stats.valid, 1);
+        assert_eq!(stats.unknown, 0);
+        assert_eq!(
+            stats.corrupted,
+            [dir.join("example.com").join("bar.tar.gz")]
+        );
+        assert_eq!(present, (true, false));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_corrupted() -> anyhow::Result<()> {
+        let foo: Url = "https://example.com/foo.tar.gz".parse()?;
+
+        let dir = TempDir::new("wit-deps-cache-corrupted").await?;
+        let cache = Local::from(dir.path());
+        let mut w = cache.insert(&foo).await?;
+        w.write_all(b"contents").await?;
+        w.close().await?;
+        let valid = cache.get(&foo).await?.is_some();
+
+        // Simulate a torn write
+        fs::write(cache.path(&foo), b"cont").await?;
+        let corrupted = cache.get(&foo).await?.is_some();
+        let removed = !fs::try_exists(cache.path(&foo)).await?;
+
+        let mut w = cache.insert(&foo).await?;
+        w.write_all(b"contents").await?;
+        w.close().await?;
+        let mut buf = Vec::new();
+        if let Some(mut r) = cache.get(&foo).await? {
+            futures::AsyncReadExt::read_to_end(&mut r, &mut buf).await?;
+        }
+
+        assert!(valid);
+        assert!(!corrupted, "corrupted entry must not be served");
+        assert!(removed, "corrupted entry must be removed");
+        assert_eq!(buf, b"contents");
+        assert_eq!(cache.counters(), Counters { hits: 2, misses: 1 });
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_import() -> anyhow::Result<()> {
+        use crate::{Digest, LockEntry};
+
+        let foo: Url = "https://example.com/foo.tar.gz".parse()?;
+        let digest = Digest {
+            sha256: [0; 32],
+            sha512: [0; 64],
+            blake3: None,
+        };
+        let lock = Lock::from([(
+            "foo".into(),
+            LockEntry::new(
+                Some(LockEntrySource::Url(foo.clone())),
+                digest,
+                BTreeSet::default(),
+            ),
+        )]);
+
+        let dir = TempDir::new("wit-deps-cache-export").await?;
+        let src = Local::from(dir.join("src"));
+        let dst = Local::from(dir.join("dst"));
+        let mut w = src.insert(&foo).await?;
+        w.write_all(b"contents").await?;
+        w.close().await?;
+
+        let mut tar = Vec::default();
+        let exported = src.export(&lock, &mut tar).await?;
+        let imported = dst.import(tar.as_slice()).await?;
+        let contents = fs::read(dst.path(&foo)).await?;
+        let stats = dst.verify(false).await?;
+        let missing = Local::from(dir.join("missing"))
+            .export(&lock, Vec::default())
+            .await
+            .is_err();
+
+        assert_eq!(exported, 1);
+        assert_eq!(imported, 1);
+        assert_eq!(contents, b"contents");
+        assert_eq!(stats.valid, 1);
+        assert!(missing, "export of missing entries must fail");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn index() -> anyhow::Result<()> {
+        let foo: Url = "https://example.com/foo.tar.gz".parse()?;
+
+        let dir = TempDir::new("wit-deps-cache-index").await?;
+        let cache = Local::from(dir.path());
+        let mut w = cache.insert(&foo).await?;
+        w.write_all(b"contents").await?;
+        w.close().await?;
+        let inserted = cache.metadata(&foo).await?;
+        let etag = cache.etag(&foo).await?;
+
+        cache.validated(&foo, Some("\"v1\"")).await?;
+        let validated = cache.etag(&foo).await?;
+
+        cache.remove(&foo).await?;
+        let removed = cache.index().await?;
+
+        let inserted = inserted.context("entry missing in index")?;
+        assert_eq!(inserted.url, Some(foo));
+        assert_eq!(inserted.size, 8);
+        assert_eq!(inserted.sha256, hex::encode(Sha256::digest(b"contents")));
+        assert_eq!(etag, None);
+        assert_eq!(validated.as_deref(), Some("\"v1\""));
+        assert!(
+            removed.is_empty(),
+            "removed entry must be removed from index"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_lookup() -> anyhow::Result<()> {
+        let foo: Url = "https://example.com/foo.tar.gz".parse()?;
+
+        let dir = TempDir::new("wit-deps-cache-failed").await?;
+        let cache = Local::from(dir.path());
+        let missing = cache.failed_lookup(&foo).await?;
+        cache.record_failed_lookup(&foo, 404).await?;
+        let recorded = cache.failed_lookup(&foo).await?;
+        let expired = cache
+            .clone()
+            .with_failed_lookup_ttl(Duration::ZERO)
+            .failed_lookup(&foo)
+            .await?;
+
+        let mut w = cache.insert(&foo).await?;
+        w.write_all(b"contents").await?;
+        w.close().await?;
+        let inserted = cache.failed_lookup(&foo).await?;
+        assert_eq!(
+            (missing, recorded, expired, inserted),
+            (None, Some(404), None, None)
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,331 @@
+use super::{segments, Cache};
+use crate::{Digest, Identifier};
+
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::{AsyncWrite, AsyncWriteExt as _, TryStreamExt as _};
+use tracing::{debug, warn};
+use url::Url;
+
+/// Remote HTTP artifact server used by [Http]
+#[derive(Clone, Debug)]
+struct Remote {
+    client: reqwest::Client,
+    base: Url,
+    token: Option<String>,
+}
+
+impl Remote {
+    fn url(&self, url: &Url) -> anyhow::Result<Url> {
+        let mut remote = self.base.clone();
+        remote
+            .path_segments_mut()
+            .map_err(|()| anyhow::anyhow!("`{}` cannot be a base URL", self.base))?
+            .pop_if_empty()
+            .extend(segments(url));
+        Ok(remote)
+    }
+
+    fn request(&self, method: reqwest::Method, url: Url) -> reqwest::RequestBuilder {
+        let req = self.client.request(method, url);
+        if let Some(token) = &self.token {
+            req.bearer_auth(token)
+        } else {
+            req
+        }
+    }
+}
+
+/// Read-through HTTP caching layer in front of another [Cache], typically [Local](super::Local).
+///
+/// Entries missing in the inner cache are fetched using `GET` requests from a remote artifact
+/// server and stored in the inner cache. Inserted entries are uploaded to the remote using `PUT`
+/// requests once committed. Requests are authenticated using an optional bearer token.
+///
+/// Requests are sent using [`reqwest`], which requires a Tokio context. Only available with the
+/// `reqwest` feature, which is enabled by default.
+#[derive(Clone, Debug)]
+pub struct Http<T> {
+    inner: T,
+    remote: Option<Remote>,
+}
+
+impl<T> Deref for Http<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for Http<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<T> Http<T> {
+    /// Environment variable specifying the base URL of the remote artifact server
+    pub const URL_ENV: &'static str = "WIT_DEPS_REMOTE_CACHE_URL";
+
+    /// Environment variable specifying the bearer token used to authenticate with the remote
+    /// artifact server
+    pub const TOKEN_ENV: &'static str = "WIT_DEPS_REMOTE_CACHE_TOKEN";
+
+    /// Returns an [Http] cache in front of `inner` using artifact server at `base`. Entries are
+    /// located at `base` extended by the host and path segments of the cached URL.
+    pub fn new(inner: T, base: Url, token: Option<String>) -> Self {
+        Self {
+            inner,
+            remote: Some(Remote {
+                client: reqwest::Client::new(),
+                base,
+                token,
+            }),
+        }
+    }
+
+    /// Returns an [Http] cache in front of `inner` configured using [`Http::URL_ENV`] and
+    /// [`Http::TOKEN_ENV`] environment variables. If [`Http::URL_ENV`] is not set, the returned
+    /// cache is equivalent to `inner`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if [`Http::URL_ENV`] is set to an invalid URL
+    pub fn from_env(inner: T) -> anyhow::Result<Self> {
+        let Some(base) = env::var(Self::URL_ENV).ok().filter(|url| !url.is_empty()) else {
+            return Ok(Self::from(inner));
+        };
+        let base = base
+            .parse()
+            .with_context(|| format!("invalid `{}` value", Self::URL_ENV))?;
+        Ok(Self::new(inner, base, env::var(Self::TOKEN_ENV).ok()))
+    }
+
+    /// Extracts the inner [Cache]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T> From<T> for Http<T> {
+    fn from(inner: T) -> Self {
+        Self {
+            inner,
+            remote: None,
+        }
+    }
+}
+
+#[async_trait]
+impl<T> Cache for Http<T>
+where
+    T: Cache + Sync + Send,
+    T::Write: Send,
+{
+    type Read = T::Read;
+    type Write = HttpWriter<T::Write>;
+
+    async fn get(&self, url: &Url) -> anyhow::Result<Option<Self::Read>> {
+        if let Some(r) = self.inner.get(url).await? {
+            return Ok(Some(r));
+        }
+        let Some(remote) = &self.remote else {
+            return Ok(None);
+        };
+        let remote_url = remote.url(url)?;
+        let res = remote
+            .request(reqwest::Method::GET, remote_url.clone())
+            .send()
+            .await
+            .with_context(|| format!("failed to GET `{remote_url}`"))?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            debug!("`{url}` not present in remote cache");
+            return Ok(None);
+        }
+        let res = res
+            .error_for_status()
+            .with_context(|| format!("failed to GET `{remote_url}`"))?;
+        let mut w = self.inner.insert(url).await?;
+        let mut body = res.bytes_stream();
+        while let Some(chunk) = body
+            .try_next()
+            .await
+            .with_context(|| format!("failed to receive `{remote_url}`"))?
+        {
+            w.write_all(&chunk)
+                .await
+                .context("failed to write chunk to cache")?;
+        }
+        w.close().await.context("failed to commit cache entry")?;
+        debug!("fetched `{url}` from remote cache");
+        self.inner.get(url).await
+    }
+
+    async fn insert(&self, url: &Url) -> anyhow::Result<Self::Write> {
+        let inner = self.inner.insert(url).await?;
+        let upload = match &self.remote {
+            Some(remote) => Some(Upload {
+                url: remote.url(url)?,
+                remote: remote.clone(),
+                buf: Vec::default(),
+            }),
+            None => None,
+        };
+        Ok(HttpWriter {
+            inner,
+            closed: false,
+            upload,
+            put: None,
+        })
+    }
+
+    fn get_tree<'a>(
+        &'a self,
+        digest: &'a Digest,
+    ) -> BoxFuture<'a, anyhow::Result<Option<PathBuf>>> {
+        self.inner.get_tree(digest)
+    }
+
+    fn insert_tree<'a>(
+        &'a self,
+        digest: &'a Digest,
+        src: &'a Path,
+        deps: &'a BTreeMap<Identifier, PathBuf>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.inner.insert_tree(digest, src, deps)
+    }
+
+    fn insert_snapshot<'a>(
+        &'a self,
+        digest: &'a Digest,
+        src: &'a Path,
+        deps: &'a BTreeMap<Identifier, PathBuf>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.inner.insert_snapshot(digest, src, deps)
+    }
+
+    fn inserted<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<SystemTime>>> {
+        self.inner.inserted(url)
+    }
+
+    fn etag<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<String>>> {
+        self.inner.etag(url)
+    }
+
+    fn validated<'a>(
+        &'a self,
+        url: &'a Url,
+        etag: Option<&'a str>,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.inner.validated(url, etag)
+    }
+
+    fn failed_lookup<'a>(&'a self, url: &'a Url) -> BoxFuture<'a, anyhow::Result<Option<u16>>> {
+        self.inner.failed_lookup(url)
+    }
+
+    fn record_failed_lookup<'a>(
+        &'a self,
+        url: &'a Url,
+        status: u16,
+    ) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.inner.record_failed_lookup(url, status)
+    }
+}
+
+struct Upload {
+    remote: Remote,
+    url: Url,
+    buf: Vec<u8>,
+}
+
+/// Write handle of an [Http] cache entry. Written contents are buffered in memory and uploaded
+/// to the remote once the inner handle is closed.
+pub struct HttpWriter<W> {
+    inner: W,
+    closed: bool,
+    upload: Option<Upload>,
+    put: Option<BoxFuture<'static, anyhow::Result<()>>>,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for HttpWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        if let Some(Upload { buf: upload, .. }) = self.upload.as_mut() {
+            upload.extend_from_slice(&buf[..n]);
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if !this.closed {
+            ready!(Pin::new(&mut this.inner).poll_close(cx))?;
+            this.closed = true;
+        }
+        if let Some(Upload { remote, url, buf }) = this.upload.take() {
+            this.put = Some(Box::pin(async move {
+                remote
+                    .request(reqwest::Method::PUT, url.clone())
+                    .body(buf)
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status)
+                    .with_context(|| format!("failed to PUT `{url}`"))?;
+                debug!("uploaded `{url}` to remote cache");
+                Ok(())
+            }));
+        }
+        if let Some(put) = this.put.as_mut() {
+            let res = ready!(put.as_mut().poll(cx));
+            this.put = None;
+            if let Err(e) = res {
+                warn!("failed to upload cache entry: {e:#}");
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::cache::Local;
+
+    #[test]
+    fn remote_url() -> anyhow::Result<()> {
+        for base in [
+            "https://cache.example.com/wit",
+            "https://cache.example.com/wit/",
+        ] {
+            let cache = Http::new(Local::from("test"), base.parse()?, None);
+            let remote = cache.remote.as_ref().context("remote missing")?;
+            assert_eq!(
+                remote.url(&"https://example.com/foo/bar.tar.gz".parse()?)?,
+                "https://cache.example.com/wit/example.com/foo/bar.tar.gz".parse()?
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::Local;
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Name of the index file within the cache directory
+const INDEX: &str = ".index.toml";
+
+/// Name of the index lock file within the lock directory of the cache
+const LOCK: &str = "index";
+
+/// Metadata of a [Local](super::Local) cache entry recorded in the cache index
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Entry {
+    /// URL the entry is associated with, if known. Entries imported from an archive are not
+    /// associated with a URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<Url>,
+    /// Hex-encoded SHA-256 digest of the entry
+    pub sha256: String,
+    /// Size of the entry in bytes
+    pub size: u64,
+    /// Time the entry was inserted
+    #[serde(with = "unix_secs")]
+    pub inserted: SystemTime,
+    /// Time the entry was last looked up
+    #[serde(with = "unix_secs")]
+    pub accessed: SystemTime,
+    /// Time the entry was last validated against upstream, which is the insertion time unless
+    /// the entry was revalidated since
+    #[serde(with = "unix_secs")]
+    pub validated: SystemTime,
+    /// HTTP `ETag` of the upstream resource, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+}
+
+/// Failed upstream lookup of a URL recorded in the cache index
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(super) struct FailedLookup {
+    /// HTTP status returned by upstream
+    pub status: u16,
+    /// Time the failed lookup was recorded
+    #[serde(with = "unix_secs")]
+    pub recorded: SystemTime,
+}
+
+/// Encodes [`SystemTime`] as seconds since UNIX epoch
+mod unix_secs {
+    use std::time::{Duration, SystemTime};
+
+    use serde::{Deserialize as _, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(t: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let secs = t
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        serializer.serialize_u64(secs)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        u64::deserialize(deserializer)
+            .map(|secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+    }
+}
+
+/// Cache index keyed by `/`-separated paths of entries relative to the cache directory
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub(super) struct Index {
+    /// [`Entry`] metadata of cached entries
+    #[serde(default)]
+    pub entries: BTreeMap<String, Entry>,
+    /// Recent failed upstream lookups of entries, which are not present in the cache
+    #[serde(default)]
+    pub failed: BTreeMap<String, FailedLookup>,
+}
+
+/// Reads the index of the cache at `root`. Missing index is treated as empty.
+pub(super) fn read(root: &Path) -> anyhow::Result<Index> {
+    let path = root.join(INDEX);
+    match fs::read_to_string(&path) {
+        Ok(index) => {
+            toml::from_str(&index).with_context(|| format!("failed to decode `{}`", path.display()))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Index::default()),
+        Err(e) => Err(e).with_context(|| format!("failed to read `{}`", path.display())),
+    }
+}
+
+/// Applies `f` to the index of the cache at `root` and atomically writes it back. An exclusive
+/// lock is held for the duration of the update, such that concurrent updates are serialized.
+///
+/// Note, that this function blocks.
+pub(super) fn update<T>(root: &Path, f: impl FnOnce(&mut Index) -> T) -> anyhow::Result<T> {
+    let locks = root.join(Local::LOCKS);
+    let tmp = root.join(Local::TMP);
+    fs::create_dir_all(&locks).context("failed to create lock directory")?;
+    fs::create_dir_all(&tmp).context("failed to create temporary directory")?;
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(locks.join(LOCK))
+        .context("failed to open index lock file")?;
+    lock.lock().context("failed to lock index")?;
+
+    let mut index = read(root)?;
+    let res = f(&mut index);
+    let buf = toml::to_string(&index).context("failed to encode index")?;
+    let tmp = tmp.join(INDEX);
+    fs::write(&tmp, buf).with_context(|| format!("failed to write `{}`", tmp.display()))?;
+    fs::rename(&tmp, root.join(INDEX)).context("failed to commit index")?;
+    Ok(res)
+}
@@ -0,0 +1,107 @@
+use super::Cache;
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use async_trait::async_trait;
+use futures::io::Cursor;
+use futures::AsyncWrite;
+use url::Url;
+
+type Entries = Arc<Mutex<HashMap<Url, Arc<[u8]>>>>;
+
+/// In-memory caching layer, primarily useful for testing. Clones share the same entries.
+#[derive(Clone, Debug, Default)]
+pub struct Memory(Entries);
+
+impl Memory {
+    /// Returns the contents of the entry associated with `url`, if present
+    #[must_use]
+    pub fn entry(&self, url: &Url) -> Option<Arc<[u8]>> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(url)
+            .cloned()
+    }
+
+    /// Returns the number of entries in the cache
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+
+    /// Returns `true` if the cache contains no entries
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Into<Arc<[u8]>>> FromIterator<(Url, T)> for Memory {
+    fn from_iter<I: IntoIterator<Item = (Url, T)>>(iter: I) -> Self {
+        let entries = iter.into_iter().map(|(url, buf)| (url, buf.into()));
+        Self(Arc::new(Mutex::new(entries.collect())))
+    }
+}
+
+#[async_trait]
+impl Cache for Memory {
+    type Read = Cursor<Arc<[u8]>>;
+    type Write = MemoryWriter;
+
+    async fn get(&self, url: &Url) -> anyhow::Result<Option<Self::Read>> {
+        Ok(self.entry(url).map(Cursor::new))
+    }
+
+    async fn insert(&self, url: &Url) -> anyhow::Result<Self::Write> {
+        Ok(MemoryWriter {
+            entries: Arc::clone(&self.0),
+            url: url.clone(),
+            buf: Some(Vec::default()),
+        })
+    }
+}
+
+/// Write handle of a [Memory] cache entry. The entry is inserted on close.
+#[derive(Debug)]
+pub struct MemoryWriter {
+    entries: Entries,
+    url: Url,
+    buf: Option<Vec<u8>>,
+}
+
+impl AsyncWrite for MemoryWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.buf.as_mut() {
+            Some(w) => {
+                w.extend_from_slice(buf);
+                Poll::Ready(Ok(buf.len()))
+            }
+            None => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(buf) = self.buf.take() {
+            let url = self.url.clone();
+            self.entries
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(url, buf.into());
+        }
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,204 @@
+use super::{segments, Cache};
+
+use core::fmt;
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+
+use std::io;
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::operation::get_object::GetObjectError;
+use aws_sdk_s3::primitives::ByteStream;
+use futures::future::BoxFuture;
+use futures::io::Cursor;
+use futures::AsyncWrite;
+use tracing::debug;
+use url::Url;
+
+/// S3 caching layer storing entries as objects in a bucket
+#[derive(Clone, Debug)]
+pub struct S3 {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3 {
+    /// Returns an [S3] cache storing entries in `bucket` under `prefix` using `client`. Entries
+    /// are stored at keys consisting of `prefix` followed by the host and path segments of the
+    /// cached URL.
+    pub fn new(
+        client: aws_sdk_s3::Client,
+        bucket: impl Into<String>,
+        prefix: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            bucket: bucket.into(),
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Like [`S3::new`], but constructs the client from the standard AWS configuration sources,
+    /// i.e. environment variables, shared config and credential files, SSO and instance metadata
+    pub async fn from_env(bucket: impl Into<String>, prefix: impl Into<String>) -> Self {
+        let config = aws_config::load_from_env().await;
+        Self::new(aws_sdk_s3::Client::new(&config), bucket, prefix)
+    }
+
+    fn key(&self, url: &Url) -> String {
+        key(&self.prefix, url)
+    }
+}
+
+fn key(prefix: &str, url: &Url) -> String {
+    let prefix = prefix.trim_matches('/');
+    let key = segments(url).collect::<Vec<_>>().join("/");
+    if prefix.is_empty() {
+        key
+    } else {
+        format!("{prefix}/{key}")
+    }
+}
+
+#[async_trait]
+impl Cache for S3 {
+    type Read = Cursor<Vec<u8>>;
+    type Write = S3Writer;
+
+    async fn get(&self, url: &Url) -> anyhow::Result<Option<Self::Read>> {
+        let key = self.key(url);
+        let res = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+        {
+            Ok(res) => res,
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(GetObjectError::is_no_such_key) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => anyhow::bail!(
+                "failed to get `{key}` from bucket `{}`: {}",
+                self.bucket,
+                DisplayErrorContext(e)
+            ),
+        };
+        let body = res
+            .body
+            .collect()
+            .await
+            .with_context(|| format!("failed to receive `{key}`"))?;
+        Ok(Some(Cursor::new(body.to_vec())))
+    }
+
+    async fn insert(&self, url: &Url) -> anyhow::Result<Self::Write> {
+        Ok(S3Writer {
+            upload: Some(Upload {
+                client: self.client.clone(),
+                bucket: self.bucket.clone(),
+                key: self.key(url),
+                buf: Vec::default(),
+            }),
+            put: None,
+        })
+    }
+}
+
+struct Upload {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    key: String,
+    buf: Vec<u8>,
+}
+
+/// Write handle of an [S3] cache entry. Written contents are buffered in memory and uploaded on
+/// close.
+pub struct S3Writer {
+    upload: Option<Upload>,
+    put: Option<BoxFuture<'static, anyhow::Result<()>>>,
+}
+
+impl fmt::Debug for S3Writer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("S3Writer")
+            .field("key", &self.upload.as_ref().map(|Upload { key, .. }| key))
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncWrite for S3Writer {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.upload.as_mut() {
+            Some(Upload { buf: upload, .. }) => {
+                upload.extend_from_slice(buf);
+                Poll::Ready(Ok(buf.len()))
+            }
+            None => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if let Some(Upload {
+            client,
+            bucket,
+            key,
+            buf,
+        }) = this.upload.take()
+        {
+            this.put = Some(Box::pin(async move {
+                client
+                    .put_object()
+                    .bucket(&bucket)
+                    .key(&key)
+                    .body(ByteStream::from(buf))
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        anyhow::anyhow!(
+                            "failed to put `{key}` into bucket `{bucket}`: {}",
+                            DisplayErrorContext(e)
+                        )
+                    })?;
+                debug!("uploaded `{key}` to bucket `{bucket}`");
+                Ok(())
+            }));
+        }
+        if let Some(put) = this.put.as_mut() {
+            let res = ready!(put.as_mut().poll(cx));
+            this.put = None;
+            res.map_err(io::Error::other)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn key() -> anyhow::Result<()> {
+        let url = "https://example.com/foo/bar.tar.gz".parse()?;
+        assert_eq!(super::key("", &url), "example.com/foo/bar.tar.gz");
+        assert_eq!(
+            super::key("/wit-deps/", &url),
+            "wit-deps/example.com/foo/bar.tar.gz"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use core::mem;
+
+use std::io;
+use std::sync::Arc;
+
+use futures::lock::Mutex;
+use futures::{AsyncWrite, AsyncWriteExt as _, Stream, StreamExt as _};
+
+/// Handle of a cache entry writer, which a stream is [teed](tee_into) into
+#[derive(Debug)]
+pub struct Tee<W>(Arc<Mutex<io::Result<W>>>);
+
+impl<W: AsyncWrite + Unpin> Tee<W> {
+    /// Commits the entry by closing the writer. Must only be called once the teed stream was
+    /// fully consumed and its contents verified.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the first failed write, in which case the writer was dropped and the
+    /// entry discarded, or an error if closing the writer fails
+    pub async fn commit(self) -> io::Result<()> {
+        let mut state = self.0.lock().await;
+        match mem::replace(&mut *state, Err(io::ErrorKind::BrokenPipe.into())) {
+            Ok(mut w) => w.close().await,
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Returns a stream, which writes every chunk of `stream` into `w` before yielding it, and a
+/// [Tee] handle used to commit the written entry once `stream` is consumed.
+///
+/// Write failures do not interrupt the returned stream. Instead, the writer is dropped, which
+/// discards the entry, and the error is returned by [`Tee::commit`].
+pub fn tee_into<S, T, W>(stream: S, w: W) -> (impl Stream<Item = io::Result<T>>, Tee<W>)
+where
+    S: Stream<Item = io::Result<T>>,
+    T: AsRef<[u8]>,
+    W: AsyncWrite + Unpin,
+{
+    let tee = Arc::new(Mutex::new(Ok(w)));
+    let stream = stream.then({
+        let tee = Arc::clone(&tee);
+        move |chunk| {
+            let tee = Arc::clone(&tee);
+            async move {
+                let chunk = chunk?;
+                let mut state = tee.lock().await;
+                if let Ok(w) = &mut *state {
+                    if let Err(e) = w.write_all(chunk.as_ref()).await {
+                        *state = Err(e);
+                    }
+                }
+                Ok(chunk)
+            }
+        }
+    });
+    (stream, Tee(tee))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{Cache as _, MemoryCache};
+
+    use core::pin::Pin;
+    use core::task::{Context, Poll};
+
+    use futures::{stream, TryStreamExt as _};
+    use url::Url;
+
+    /// Writer, which fails all writes
+    struct Failing;
+
+    impl AsyncWrite for Failing {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            Poll::Ready(Err(io::ErrorKind::StorageFull.into()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn tee() -> anyhow::Result<()> {
+        let url: Url = "https://example.com/foo.tar.gz".parse()?;
+        let chunks = || stream::iter([Ok(b"foo".as_slice()), Ok(b"bar".as_slice())]);
+
+        let cache = MemoryCache::default();
+        let (teed, tee) = tee_into(chunks(), cache.insert(&url).await?);
+        let read: Vec<_> = teed.try_collect().await?;
+        assert_eq!(read, [b"foo", b"bar"]);
+        assert!(cache.is_empty(), "entry must only be inserted on commit");
+        tee.commit().await?;
+        assert_eq!(cache.entry(&url).as_deref(), Some(b"foobar".as_slice()));
+
+        let (teed, tee) = tee_into(chunks(), Failing);
+        let read: Vec<_> = teed.try_collect().await?;
+        assert_eq!(
+            read,
+            [b"foo", b"bar"],
+            "write failure must not interrupt the stream"
+        );
+        assert_eq!(
+            tee.commit().await.map_err(|e| e.kind()),
+            Err(io::ErrorKind::StorageFull)
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,541 @@
+use crate::package::Package;
+use crate::{Error, Identifier, Lock, OsVfs};
+
+use core::fmt::{self, Write as _};
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{debug, instrument};
+
+/// File name of Cargo manifests, which may contain the dependency manifest in the
+/// `[package.metadata.wit-deps]` table
+const CARGO_MANIFEST: &str = "Cargo.toml";
+
+/// File name of the `cargo-component` lock next to the Cargo manifest
+const CARGO_COMPONENT_LOCK: &str = "Cargo-component.lock";
+
+/// Returns `true` if `path` refers to a Cargo manifest
+pub(crate) fn is_cargo_manifest(path: &Path) -> bool {
+    path.file_name().is_some_and(|name| name == CARGO_MANIFEST)
+}
+
+/// Returns the TOML-encoded dependency manifest contained in the `[package.metadata.wit-deps]`
+/// table of Cargo manifest `cargo`, if any
+pub(crate) fn cargo_metadata(cargo: &str) -> anyhow::Result<Option<String>> {
+    let cargo: toml::Table = toml::from_str(cargo).context("failed to decode Cargo manifest")?;
+    let Some(metadata) = cargo
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("wit-deps"))
+    else {
+        return Ok(None);
+    };
+    let metadata = metadata
+        .as_table()
+        .context("`package.metadata.wit-deps` is not a table")?;
+    toml::to_string(metadata)
+        .context("failed to encode manifest")
+        .map(Some)
+}
+
+/// Returns the path of the dependency manifest of the WIT directory `wit`, which is `deps.toml`
+/// within `wit`, if it exists. Otherwise, if the `Cargo.toml` next to `wit` contains a
+/// `[package.metadata.wit-deps]` table, the path of that `Cargo.toml` is returned.
+///
+/// If neither exists, the path of `deps.toml` within `wit` is returned. Note, that path
+/// dependencies are resolved relative to the parent directory of the returned manifest path.
+#[must_use]
+pub fn manifest_path(wit: impl AsRef<Path>) -> PathBuf {
+    let wit = wit.as_ref();
+    let deps = wit.join("deps.toml");
+    if deps.exists() {
+        return deps;
+    }
+    let cargo = wit.parent().unwrap_or(Path::new("")).join(CARGO_MANIFEST);
+    match std::fs::read_to_string(&cargo).map(|cargo| cargo_metadata(&cargo)) {
+        Ok(Ok(Some(_))) => cargo,
+        _ => deps,
+    }
+}
+
+/// Reason a WIT package dependency of `cargo-component` drifted from the dependencies locked by
+/// `wit-deps`, see [`cargo_component_drift_path`]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum CargoComponentDrift {
+    /// Package is a `cargo-component` dependency, but not locked by `wit-deps`
+    NotLocked,
+    /// Version of the package locked by `wit-deps` differs from the one locked by
+    /// `cargo-component` or does not satisfy the `cargo-component` version requirement
+    Version {
+        /// Identifier of the dependency declaring the package
+        id: Identifier,
+        /// Version locked by `wit-deps`, if any
+        locked: Option<String>,
+        /// Version or version requirement of `cargo-component`
+        required: String,
+    },
+    /// Contents of the `cargo-component` path dependency differ from the ones locked by `wit-deps`
+    Contents {
+        /// Identifier of the dependency declaring the package
+        id: Identifier,
+        /// Path of the `cargo-component` dependency
+        path: PathBuf,
+    },
+}
+
+impl fmt::Display for CargoComponentDrift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotLocked => write!(f, "required by `cargo-component`, but not locked"),
+            Self::Version {
+                id,
+                locked: Some(locked),
+                required,
+            } => write!(
+                f,
+                "`{id}` is locked at version `{locked}`, but `cargo-component` requires `{required}`"
+            ),
+            Self::Version {
+                id,
+                locked: None,
+                required,
+            } => write!(
+                f,
+                "`{id}` is not versioned, but `cargo-component` requires `{required}`"
+            ),
+            Self::Contents { id, path } => write!(
+                f,
+                "contents of `{}` required by `cargo-component` differ from `{id}`",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// WIT package dependency in `[package.metadata.component.target.dependencies]`
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ComponentDependency {
+    Version(String),
+    Table {
+        version: Option<String>,
+        path: Option<PathBuf>,
+    },
+}
+
+/// `cargo-component` lock, only the fields relevant to drift detection are decoded
+#[derive(Debug, Default, Deserialize)]
+struct ComponentLock {
+    #[serde(default, rename = "package")]
+    packages: Vec<ComponentLockPackage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComponentLockPackage {
+    name: String,
+    #[serde(default, rename = "version")]
+    versions: Vec<ComponentLockVersion>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComponentLockVersion {
+    version: String,
+}
+
+/// Returns the WIT package dependencies in `[package.metadata.component.target.dependencies]`
+/// of Cargo manifest `cargo`
+fn component_dependencies(cargo: &str) -> anyhow::Result<BTreeMap<String, ComponentDependency>> {
+    let cargo: toml::Table = toml::from_str(cargo).context("failed to decode Cargo manifest")?;
+    let Some(deps) = cargo
+        .get("package")
+        .and_then(|package| package.get("metadata"))
+        .and_then(|metadata| metadata.get("component"))
+        .and_then(|component| component.get("target"))
+        .and_then(|target| target.get("dependencies"))
+    else {
+        return Ok(BTreeMap::default());
+    };
+    deps.clone()
+        .try_into()
+        .context("failed to decode `package.metadata.component.target.dependencies`")
+}
+
+/// Reads WIT packages locked by the lock at `lock_path` and installed in `deps` and returns
+/// the identifier, version and normalized contents of each keyed by unversioned package name
+async fn locked_packages(
+    lock_path: impl AsRef<Path>,
+    deps: &Path,
+) -> anyhow::Result<BTreeMap<String, (Identifier, Option<String>, Package)>> {
+    let lock: Lock = match crate::read_lock_string(lock_path).await? {
+        Some(lock) => toml::from_str(&lock).context("failed to decode lock")?,
+        None => Lock::default(),
+    };
+
+    let mut locked = BTreeMap::default();
+    for id in lock.keys() {
+        let path = lock.path(deps, id);
+        match Package::read(&OsVfs, &path).await {
+            Ok(pkg) => {
+                if let Some(name) = pkg.name.clone() {
+                    let (name, version) = match name.split_once('@') {
+                        Some((name, version)) => (name.to_string(), Some(version.to_string())),
+                        None => (name, None),
+                    };
+                    locked.insert(name, (id.clone(), version, pkg));
+                }
+            }
+            Err(e) => debug!("failed to read package at `{}`: {e}", path.display()),
+        }
+    }
+    Ok(locked)
+}
+
+/// Compares the WIT package dependencies of `cargo-component` declared in the Cargo manifest at
+/// `cargo_path` to the dependencies locked by the lock at `lock_path` and installed in `deps` and
+/// returns all drifted packages keyed by name.
+///
+/// Packages are matched by the names declared by the installed WIT definitions. Versions are
+/// compared to the ones locked in `Cargo-component.lock` next to the Cargo manifest, if present,
+/// and to the version requirements otherwise. Contents of path dependencies are compared
+/// ignoring cosmetic differences, like comments and formatting.
+///
+/// # Errors
+///
+/// Returns an error if the Cargo manifest cannot be read or either the Cargo manifest or one of
+/// the locks cannot be decoded
+#[instrument(level = "trace", skip(cargo_path, lock_path, deps))]
+pub async fn cargo_component_drift_path(
+    cargo_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+) -> Result<BTreeMap<String, CargoComponentDrift>, Error> {
+    let cargo_path = cargo_path.as_ref();
+    let deps = deps.as_ref();
+    let cargo = fs::read_to_string(cargo_path)
+        .await
+        .with_context(|| format!("failed to read `{}`", cargo_path.display()))?;
+    let required = component_dependencies(&cargo)?;
+    let root = cargo_path.parent().unwrap_or(Path::new(""));
+    let component_lock: ComponentLock =
+        match fs::read_to_string(root.join(CARGO_COMPONENT_LOCK)).await {
+            Ok(lock) => toml::from_str(&lock).context("failed to decode `cargo-component` lock")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ComponentLock::default(),
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context("failed to read `cargo-component` lock")
+                    .into())
+            }
+        };
+    let locked = locked_packages(lock_path, deps).await?;
+
+    let mut drift = BTreeMap::default();
+    for (name, dep) in required {
+        let Some((id, version, pkg)) = locked.get(&name) else {
+            drift.insert(name, CargoComponentDrift::NotLocked);
+            continue;
+        };
+        let (requirement, path) = match dep {
+            ComponentDependency::Version(version) => (Some(version), None),
+            ComponentDependency::Table { version, path } => (version, path),
+        };
+        let resolved: Vec<_> = component_lock
+            .packages
+            .iter()
+            .filter(|pkg| pkg.name == name)
+            .flat_map(|pkg| pkg.versions.iter().map(|v| v.version.as_str()))
+            .collect();
+        let required = if resolved.is_empty() {
+            requirement.filter(|requirement| {
+                let matches = version.as_deref().and_then(|version| {
+                    let version = semver::Version::parse(version).ok()?;
+                    let requirement = semver::VersionReq::parse(requirement).ok()?;
+                    Some(requirement.matches(&version))
+                });
+                matches != Some(true)
+            })
+        } else if version
+            .as_deref()
+            .is_some_and(|version| resolved.contains(&version))
+        {
+            None
+        } else {
+            Some(resolved.join(", "))
+        };
+        if let Some(required) = required {
+            drift.insert(
+                name,
+                CargoComponentDrift::Version {
+                    id: id.clone(),
+                    locked: version.clone(),
+                    required,
+                },
+            );
+            continue;
+        }
+        if let Some(path) = path {
+            let path = root.join(path);
+            if path.is_dir() && Package::read(&OsVfs, &path).await.ok().as_ref() != Some(pkg) {
+                drift.insert(
+                    name,
+                    CargoComponentDrift::Contents {
+                        id: id.clone(),
+                        path,
+                    },
+                );
+            }
+        }
+    }
+    Ok(drift)
+}
+
+/// Reads the lock at `lock_path` and the WIT package declarations of locked dependencies
+/// installed in `deps` and returns the `[package.metadata.component.target.dependencies]` table
+/// of a Cargo manifest in the `root` directory, which declares a `cargo-component` path
+/// dependency on each locked package.
+///
+/// Paths are relative to `root` if `deps` is within it. If multiple versions of a package are
+/// locked, only the latest one is declared, since `cargo-component` depends on packages by name.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded
+#[instrument(level = "trace", skip(lock_path, deps, root))]
+pub async fn cargo_component_dependencies_path(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    root: impl AsRef<Path>,
+) -> Result<String, Error> {
+    let lock = crate::read_lock(lock_path).await?.migrate();
+    let deps = deps.as_ref();
+    let root = root.as_ref();
+    let mut packages: BTreeMap<String, (Option<semver::Version>, PathBuf)> = BTreeMap::default();
+    for id in lock.keys() {
+        let path = lock.path(deps, id);
+        let name = match Package::read(&OsVfs, &path).await {
+            Ok(Package {
+                name: Some(name), ..
+            }) => name,
+            Ok(_) => {
+                debug!("`{id}` does not declare a package, skip");
+                continue;
+            }
+            Err(e) => {
+                debug!("failed to read package at `{}`: {e}", path.display());
+                continue;
+            }
+        };
+        let (name, version) = match name.split_once('@') {
+            Some((name, version)) => (name.to_string(), semver::Version::parse(version).ok()),
+            None => (name, None),
+        };
+        let path = path
+            .strip_prefix(root)
+            .map_or(path.clone(), Path::to_path_buf);
+        match packages.get(&name) {
+            Some((locked, _)) if *locked >= version => {
+                debug!("`{id}` is not the latest version of package `{name}`, skip");
+            }
+            _ => {
+                packages.insert(name, (version, path));
+            }
+        }
+    }
+    let mut table = String::from("[package.metadata.component.target.dependencies]\n");
+    for (name, (_, path)) in packages {
+        let name = toml::Value::String(name);
+        let path = toml::Value::String(path.to_string_lossy().into_owned());
+        writeln!(table, "{name} = {{ path = {path} }}").context("failed to encode table")?;
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    use std::fs;
+
+    #[test]
+    fn metadata() -> anyhow::Result<()> {
+        let manifest = cargo_metadata(
+            r#"
+[package]
+name = "foo"
+
+[package.metadata.wit-deps]
+bar = "https://example.com/bar.tar.gz"
+baz = { path = "../baz" }
+
+[dependencies]
+qux = "1"
+"#,
+        )?
+        .context("metadata missing")?;
+        let manifest: crate::Manifest = toml::from_str(&manifest)?;
+        assert_eq!(
+            manifest.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["bar", "baz"]
+        );
+        assert_eq!(cargo_metadata("[package]\nname = \"foo\"\n")?, None);
+        assert!(cargo_metadata("[package.metadata]\nwit-deps = 1\n").is_err());
+
+        let dir = TempDir::create("wit-deps-cargo")?;
+        let wit = dir.join("wit");
+        fs::create_dir_all(&wit)?;
+        let missing = manifest_path(&wit);
+        fs::write(
+            dir.join("Cargo.toml"),
+            "[package.metadata.wit-deps]\nbar = \"../bar\"\n",
+        )?;
+        let cargo = manifest_path(&wit);
+        fs::write(wit.join("deps.toml"), "")?;
+        let deps = manifest_path(&wit);
+
+        assert_eq!(missing, wit.join("deps.toml"));
+        assert_eq!(cargo, dir.join("Cargo.toml"));
+        assert_eq!(deps, wit.join("deps.toml"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn component_drift() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-component").await?;
+        let deps = dir.join("wit").join("deps");
+        let lock = dir.join("wit").join("deps.lock");
+        for (path, src) in [
+            (
+                "wit/deps/io/io.wit",
+                "package wasi:io@0.2.0;\ninterface error {}\n",
+            ),
+            (
+                "wit/deps/cli/cli.wit",
+                "package wasi:cli@0.2.0;\ninterface env {}\n",
+            ),
+            (
+                "wit/deps/foo/foo.wit",
+                "package my:foo;\ninterface foo {}\n",
+            ),
+            (
+                "foo/foo.wit",
+                "package my:foo;\ninterface foo { bar: func(); }\n",
+            ),
+        ] {
+            let path = dir.join(path);
+            tokio::fs::create_dir_all(path.parent().context("parent missing")?).await?;
+            tokio::fs::write(path, src).await?;
+        }
+        let entries = ["io", "cli", "foo"].map(|id| {
+            format!(
+                "[{id}]\nsha256 = \"{}\"\nsha512 = \"{}\"\n",
+                "00".repeat(32),
+                "00".repeat(64)
+            )
+        });
+        tokio::fs::write(&lock, entries.concat()).await?;
+        tokio::fs::write(
+            dir.join("Cargo.toml"),
+            r#"
+[package]
+name = "foo"
+
+[package.metadata.component.target.dependencies]
+"wasi:io" = "0.2"
+"wasi:cli" = "0.3"
+"wasi:http" = "0.2"
+"my:foo" = { path = "foo" }
+"#,
+        )
+        .await?;
+        let required = cargo_component_drift_path(dir.join("Cargo.toml"), &lock, &deps).await?;
+        tokio::fs::write(
+            dir.join(CARGO_COMPONENT_LOCK),
+            r#"
+[[package]]
+name = "wasi:io"
+
+[[package.version]]
+requirement = "0.2"
+version = "0.2.1"
+"#,
+        )
+        .await?;
+        let resolved = cargo_component_drift_path(dir.join("Cargo.toml"), &lock, &deps).await?;
+
+        assert_eq!(
+            required.into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    "my:foo".into(),
+                    CargoComponentDrift::Contents {
+                        id: "foo".into(),
+                        path: dir.join("foo"),
+                    }
+                ),
+                (
+                    "wasi:cli".into(),
+                    CargoComponentDrift::Version {
+                        id: "cli".into(),
+                        locked: Some("0.2.0".into()),
+                        required: "0.3".into(),
+                    }
+                ),
+                ("wasi:http".into(), CargoComponentDrift::NotLocked),
+            ]
+        );
+        assert_eq!(
+            resolved.get("wasi:io"),
+            Some(&CargoComponentDrift::Version {
+                id: "io".into(),
+                locked: Some("0.2.0".into()),
+                required: "0.2.1".into(),
+            })
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn component_dependencies_export() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-component-export").await?;
+        let deps = dir.join("wit").join("deps");
+        let lock = dir.join("wit").join("deps.lock");
+        for (id, src) in [
+            ("io", "package wasi:io@0.2.1;\n"),
+            ("io-old", "package wasi:io@0.2.0;\n"),
+            ("foo", "package my:foo;\n"),
+            ("bar", "interface bar {}\n"),
+        ] {
+            tokio::fs::create_dir_all(deps.join(id)).await?;
+            tokio::fs::write(deps.join(id).join("pkg.wit"), src).await?;
+        }
+        let entries = ["io", "io-old", "foo", "bar"].map(|id| {
+            format!(
+                "[{id}]\nsha256 = \"{}\"\nsha512 = \"{}\"\n",
+                "00".repeat(32),
+                "00".repeat(64)
+            )
+        });
+        tokio::fs::write(&lock, entries.concat()).await?;
+        let deps_table = cargo_component_dependencies_path(&lock, &deps, &dir).await?;
+
+        let path = |id| {
+            let path = Path::new("wit").join("deps").join(id);
+            toml::Value::String(path.to_string_lossy().into_owned())
+        };
+        assert_eq!(
+            deps_table,
+            format!(
+                "[package.metadata.component.target.dependencies]\n\"my:foo\" = {{ path = {} }}\n\"wasi:io\" = {{ path = {} }}\n",
+                path("foo"),
+                path("io")
+            )
+        );
+        Ok(())
+    }
+}
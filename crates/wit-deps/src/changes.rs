@@ -0,0 +1,436 @@
+use crate::Error;
+
+use core::fmt;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use anyhow::Context as _;
+use blocking::unblock;
+use serde::Serialize;
+use tracing::instrument;
+use wit_parser::{
+    AstItem, Function, Handle, InterfaceId, Type, TypeDefKind, TypeId, TypeOwner,
+    UnresolvedPackage, UnresolvedPackageGroup, WorldItem, WorldKey,
+};
+
+/// Category of a changed WIT item, see [`Change`]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Category {
+    /// Package declaration, e.g. `wasi:io@0.2.0`
+    Package,
+    /// Named interface, e.g. `streams`
+    Interface,
+    /// World, e.g. `proxy`, or an item imported or exported by it, e.g. `proxy.handler`
+    World,
+    /// Type defined or used by an interface, e.g. `streams.input-stream`
+    Type,
+    /// Function of an interface, e.g. `streams.[method]input-stream.read`
+    Function,
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Package => write!(f, "package"),
+            Self::Interface => write!(f, "interface"),
+            Self::World => write!(f, "world"),
+            Self::Type => write!(f, "type"),
+            Self::Function => write!(f, "function"),
+        }
+    }
+}
+
+/// Kind of a [`Change`]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// Item is only present in the new package
+    Added,
+    /// Item is only present in the old package
+    Removed,
+    /// Item is present in both packages, but its definition differs
+    Changed,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added => write!(f, "added"),
+            Self::Removed => write!(f, "removed"),
+            Self::Changed => write!(f, "changed"),
+        }
+    }
+}
+
+/// Change of a WIT item between two versions of a package, see [`wit_changes_path`]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Change {
+    /// Category of the item
+    pub category: Category,
+    /// Name of the item qualified by the interface or world containing it
+    pub name: String,
+    /// Kind of the change
+    pub kind: Kind,
+    /// Definition of the item in the old package, if present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<String>,
+    /// Definition of the item in the new package, if present
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+    /// Whether the change may break consumers of the package, which is the case for all removed
+    /// and changed items, except for the package declaration
+    pub breaking: bool,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            category,
+            name,
+            kind,
+            old,
+            new,
+            breaking,
+        } = self;
+        write!(f, "{kind} {category} `{name}`")?;
+        if let (Kind::Changed, Some(old), Some(new)) = (kind, old, new) {
+            write!(f, ": `{old}` -> `{new}`")?;
+        }
+        if *breaking {
+            write!(f, " (breaking)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Definitions of items of a WIT package keyed by category and qualified name
+type Items = BTreeMap<(Category, String), String>;
+
+/// Renders definitions of items of an [`UnresolvedPackage`]
+struct Renderer<'a> {
+    pkg: &'a UnresolvedPackage,
+    /// Fully-qualified names of interfaces of foreign packages
+    foreign: HashMap<InterfaceId, String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(pkg: &'a UnresolvedPackage) -> Self {
+        let mut foreign = HashMap::default();
+        for (name, items) in &pkg.foreign_deps {
+            for (interface, item) in items {
+                if let AstItem::Interface(id) = item {
+                    let qualified = format!("{}:{}/{interface}", name.namespace, name.name);
+                    let qualified = match &name.version {
+                        Some(version) => format!("{qualified}@{version}"),
+                        None => qualified,
+                    };
+                    foreign.insert(*id, qualified);
+                }
+            }
+        }
+        Self { pkg, foreign }
+    }
+
+    fn interface(&self, id: InterfaceId) -> String {
+        self.foreign
+            .get(&id)
+            .cloned()
+            .or_else(|| self.pkg.interfaces[id].name.clone())
+            .unwrap_or_else(|| "interface".into())
+    }
+
+    fn ty(&self, ty: &Type) -> String {
+        match ty {
+            Type::Bool => "bool".into(),
+            Type::U8 => "u8".into(),
+            Type::U16 => "u16".into(),
+            Type::U32 => "u32".into(),
+            Type::U64 => "u64".into(),
+            Type::S8 => "s8".into(),
+            Type::S16 => "s16".into(),
+            Type::S32 => "s32".into(),
+            Type::S64 => "s64".into(),
+            Type::F32 => "f32".into(),
+            Type::F64 => "f64".into(),
+            Type::Char => "char".into(),
+            Type::String => "string".into(),
+            Type::ErrorContext => "error-context".into(),
+            Type::Id(id) => self.type_id(*id),
+        }
+    }
+
+    fn type_id(&self, id: TypeId) -> String {
+        let def = &self.pkg.types[id];
+        match (&def.name, def.owner) {
+            (Some(name), TypeOwner::Interface(interface)) => {
+                format!("{}.{name}", self.interface(interface))
+            }
+            (Some(name), _) => name.clone(),
+            (None, _) => self.kind(&def.kind),
+        }
+    }
+
+    fn types<'b>(&self, types: impl IntoIterator<Item = &'b Type>) -> String {
+        types
+            .into_iter()
+            .map(|ty| self.ty(ty))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn kind(&self, kind: &TypeDefKind) -> String {
+        let join = |items: Vec<String>| items.join(", ");
+        match kind {
+            TypeDefKind::Record(record) => format!(
+                "record {{ {} }}",
+                join(
+                    record
+                        .fields
+                        .iter()
+                        .map(|field| format!("{}: {}", field.name, self.ty(&field.ty)))
+                        .collect()
+                )
+            ),
+            TypeDefKind::Resource => "resource".into(),
+            TypeDefKind::Handle(Handle::Own(id)) => format!("own<{}>", self.type_id(*id)),
+            TypeDefKind::Handle(Handle::Borrow(id)) => format!("borrow<{}>", self.type_id(*id)),
+            TypeDefKind::Flags(flags) => format!(
+                "flags {{ {} }}",
+                join(flags.flags.iter().map(|flag| flag.name.clone()).collect())
+            ),
+            TypeDefKind::Tuple(tuple) => format!("tuple<{}>", self.types(&tuple.types)),
+            TypeDefKind::Variant(variant) => format!(
+                "variant {{ {} }}",
+                join(
+                    variant
+                        .cases
+                        .iter()
+                        .map(|case| match &case.ty {
+                            Some(ty) => format!("{}({})", case.name, self.ty(ty)),
+                            None => case.name.clone(),
+                        })
+                        .collect()
+                )
+            ),
+            TypeDefKind::Enum(enum_) => format!(
+                "enum {{ {} }}",
+                join(enum_.cases.iter().map(|case| case.name.clone()).collect())
+            ),
+            TypeDefKind::Option(ty) => format!("option<{}>", self.ty(ty)),
+            TypeDefKind::Result(result) => match (&result.ok, &result.err) {
+                (None, None) => "result".into(),
+                (Some(ok), None) => format!("result<{}>", self.ty(ok)),
+                (None, Some(err)) => format!("result<_, {}>", self.ty(err)),
+                (Some(ok), Some(err)) => format!("result<{}, {}>", self.ty(ok), self.ty(err)),
+            },
+            TypeDefKind::List(ty) => format!("list<{}>", self.ty(ty)),
+            TypeDefKind::FixedSizeList(ty, n) => format!("list<{}, {n}>", self.ty(ty)),
+            TypeDefKind::Future(None) => "future".into(),
+            TypeDefKind::Future(Some(ty)) => format!("future<{}>", self.ty(ty)),
+            TypeDefKind::Stream(None) => "stream".into(),
+            TypeDefKind::Stream(Some(ty)) => format!("stream<{}>", self.ty(ty)),
+            TypeDefKind::Type(ty) => self.ty(ty),
+            TypeDefKind::Unknown => "unknown".into(),
+        }
+    }
+
+    fn func(&self, func: &Function) -> String {
+        let params = func
+            .params
+            .iter()
+            .map(|(name, ty)| format!("{name}: {}", self.ty(ty)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match &func.result {
+            Some(ty) => format!("func({params}) -> {}", self.ty(ty)),
+            None => format!("func({params})"),
+        }
+    }
+
+    fn world_item(&self, item: &WorldItem) -> String {
+        match item {
+            WorldItem::Interface { id, .. } => format!("interface {}", self.interface(*id)),
+            WorldItem::Function(func) => self.func(func),
+            WorldItem::Type(id) => format!("type {}", self.kind(&self.pkg.types[*id].kind)),
+        }
+    }
+
+    fn items(&self) -> Items {
+        let mut items = Items::default();
+        for (id, interface) in &self.pkg.interfaces {
+            let Some(name) = interface
+                .name
+                .as_ref()
+                .filter(|_| !self.foreign.contains_key(&id))
+            else {
+                continue;
+            };
+            items.insert((Category::Interface, name.clone()), "interface".into());
+            for (ty, id) in &interface.types {
+                items.insert(
+                    (Category::Type, format!("{name}.{ty}")),
+                    self.kind(&self.pkg.types[*id].kind),
+                );
+            }
+            for (func, def) in &interface.functions {
+                items.insert(
+                    (Category::Function, format!("{name}.{func}")),
+                    self.func(def),
+                );
+            }
+        }
+        for (_, world) in &self.pkg.worlds {
+            items.insert((Category::World, world.name.clone()), "world".into());
+            for (direction, world_items) in [("import", &world.imports), ("export", &world.exports)]
+            {
+                for (key, item) in world_items {
+                    let key = match key {
+                        WorldKey::Name(name) => name.clone(),
+                        WorldKey::Interface(id) => self.interface(*id),
+                    };
+                    items.insert(
+                        (Category::World, format!("{}.{key}", world.name)),
+                        format!("{direction} {}", self.world_item(item)),
+                    );
+                }
+            }
+        }
+        items
+    }
+}
+
+/// Returns changes between `old` and `new` package [`Items`]
+fn diff(old: &UnresolvedPackage, new: &UnresolvedPackage) -> Vec<Change> {
+    let mut changes = Vec::default();
+    let (old_name, new_name) = (old.name.to_string(), new.name.to_string());
+    if old_name != new_name {
+        changes.push(Change {
+            category: Category::Package,
+            name: new_name.clone(),
+            kind: Kind::Changed,
+            old: Some(old_name),
+            new: Some(new_name),
+            breaking: false,
+        });
+    }
+    let old = Renderer::new(old).items();
+    let new = Renderer::new(new).items();
+    let keys: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+    for key @ (category, name) in keys {
+        let (old, new) = (old.get(key), new.get(key));
+        let kind = match (old, new) {
+            (None, Some(_)) => Kind::Added,
+            (Some(_), None) => Kind::Removed,
+            (Some(old), Some(new)) if old != new => Kind::Changed,
+            _ => continue,
+        };
+        changes.push(Change {
+            category: *category,
+            name: name.clone(),
+            kind,
+            old: old.cloned(),
+            new: new.cloned(),
+            breaking: kind != Kind::Added,
+        });
+    }
+    changes
+}
+
+/// Parses the WIT packages in the `old` and `new` directories and returns the changes of
+/// interfaces, worlds, types and functions between them, i.e. the items, which were added,
+/// removed or whose definitions differ. Dependencies of the packages are not resolved and types
+/// used from other packages are compared by name.
+///
+/// # Errors
+///
+/// Returns an error if either of the packages cannot be parsed
+#[instrument(level = "trace", skip(old, new))]
+pub async fn wit_changes_path(
+    old: impl AsRef<Path>,
+    new: impl AsRef<Path>,
+) -> Result<Vec<Change>, Error> {
+    let (old, new) = (old.as_ref().to_path_buf(), new.as_ref().to_path_buf());
+    unblock(move || {
+        let parse = |path: &Path| {
+            UnresolvedPackageGroup::parse_dir(path)
+                .with_context(|| format!("failed to parse WIT package in `{}`", path.display()))
+        };
+        let old = parse(&old)?;
+        let new = parse(&new)?;
+        Ok(diff(&old.main, &new.main))
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    use tokio::fs;
+
+    #[tokio::test]
+    async fn changes() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-changes").await?;
+        let (old, new) = (dir.join("old"), dir.join("new"));
+        fs::create_dir_all(&old).await?;
+        fs::create_dir_all(&new).await?;
+        fs::write(
+            old.join("streams.wit"),
+            r"package wasi:io@0.2.0;
+
+interface streams {
+    use wasi:clocks/monotonic-clock@0.2.0.{duration};
+
+    resource input-stream {
+        read: func(len: u64) -> list<u8>;
+        skip: func(len: u64) -> u64;
+    }
+    record options { timeout: duration }
+}
+",
+        )
+        .await?;
+        fs::write(
+            new.join("streams.wit"),
+            r"package wasi:io@0.3.0;
+
+interface streams {
+    use wasi:clocks/monotonic-clock@0.3.0.{duration};
+
+    resource input-stream {
+        read: func(len: u64) -> result<list<u8>>;
+        subscribe: func() -> u32;
+    }
+    record options { timeout: duration }
+}
+
+world imports {
+    import streams;
+}
+",
+        )
+        .await?;
+        let changes: Vec<_> = wit_changes_path(&old, &new)
+            .await?
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "changed package `wasi:io@0.3.0`: `wasi:io@0.2.0` -> `wasi:io@0.3.0`",
+                "added world `imports`",
+                "added world `imports.streams`",
+                "changed type `streams.duration`: `wasi:clocks/monotonic-clock@0.2.0.duration` -> `wasi:clocks/monotonic-clock@0.3.0.duration` (breaking)",
+                "changed function `streams.[method]input-stream.read`: `func(self: borrow<streams.input-stream>, len: u64) -> list<u8>` -> `func(self: borrow<streams.input-stream>, len: u64) -> result<list<u8>>` (breaking)",
+                "removed function `streams.[method]input-stream.skip` (breaking)",
+                "added function `streams.[method]input-stream.subscribe`",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,321 @@
+use crate::error::Invalid;
+use crate::{is_wit, Error, Identifier, WitDiagnostic, WitLocation};
+
+use core::iter;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use anyhow::Context as _;
+use blocking::unblock;
+use tracing::{debug, instrument};
+use wit_parser::{PackageId, Resolve, UnresolvedPackageGroup};
+
+/// Parses and resolves the WIT package in the `wit` directory along with its dependencies in
+/// the `deps` subdirectory of it using `wit-parser`. Returns the name of the resolved package.
+///
+/// The package and each of the dependencies are parsed separately first, such that parse errors,
+/// packages defined by multiple dependencies and packages not defined by any dependency are
+/// reported for each dependency individually.
+///
+/// # Errors
+///
+/// Returns [`Error::Validation`] containing a [`WitDiagnostic`] for each problem, which includes
+/// the file and line of the offending definition, if the WIT definitions cannot be parsed or
+/// resolved
+#[instrument(level = "trace", skip(wit))]
+pub async fn check_path(wit: impl AsRef<Path>) -> Result<String, Error> {
+    let wit = wit.as_ref().to_path_buf();
+    unblock(move || {
+        let (resolve, pkg) = resolve(&wit)?;
+        let name = resolve.packages[pkg].name.to_string();
+        debug!("resolved `{name}` in `{}`", wit.display());
+        Ok(name)
+    })
+    .await
+}
+
+/// Like [`check_path`], but encodes the resolved WIT package in the `wit` directory along with
+/// all of its dependencies into a single binary WIT package using `wit-component` and returns it
+///
+/// # Errors
+///
+/// Returns [`Error::Validation`] if the WIT definitions cannot be parsed or resolved and an
+/// error if the package cannot be encoded
+#[cfg(feature = "bundle")]
+#[instrument(level = "trace", skip(wit))]
+pub async fn bundle_path(wit: impl AsRef<Path>) -> Result<Vec<u8>, Error> {
+    let wit = wit.as_ref().to_path_buf();
+    unblock(move || {
+        let (resolve, pkg) = resolve(&wit)?;
+        let name = &resolve.packages[pkg].name;
+        let wasm = wit_component::encode(&resolve, pkg)
+            .with_context(|| format!("failed to encode `{name}` in `{}`", wit.display()))?;
+        debug!(len = wasm.len(), "encoded `{name}` in `{}`", wit.display());
+        Ok(wasm)
+    })
+    .await
+}
+
+/// Parses and resolves the WIT package in the `wit` directory, see [`check_path`]
+fn resolve(wit: &Path) -> Result<(Resolve, PackageId), Error> {
+    let invalid = |diagnostics| {
+        anyhow::Error::new(Invalid(diagnostics)).context(format!(
+            "failed to validate WIT definitions in `{}`",
+            wit.display()
+        ))
+    };
+    let diagnostics = diagnose(wit)?;
+    if !diagnostics.is_empty() {
+        return Err(invalid(diagnostics).into());
+    }
+    let mut resolve = Resolve::default();
+    let (pkg, _) = resolve.push_dir(wit).map_err(|e| {
+        invalid(vec![WitDiagnostic::Resolve {
+            message: format!("{e:#}"),
+        }])
+    })?;
+    Ok((resolve, pkg))
+}
+
+/// Parses the WIT package in `wit` and each dependency in `deps` subdirectory of it separately
+/// and returns all problems found
+fn diagnose(wit: &Path) -> anyhow::Result<Vec<WitDiagnostic>> {
+    let mut dirs = vec![(None, wit.to_path_buf())];
+    let deps = wit.join("deps");
+    match fs::read_dir(&deps) {
+        Ok(entries) => {
+            let mut ids = BTreeSet::new();
+            for e in entries {
+                let e = e.with_context(|| format!("failed to read `{}`", deps.display()))?;
+                if !e.file_type()?.is_dir() {
+                    continue;
+                }
+                if let Ok(id) = e.file_name().into_string() {
+                    ids.insert(id);
+                }
+            }
+            dirs.extend(ids.into_iter().map(|id| {
+                let path = deps.join(&id);
+                (Some(Identifier::from(id)), path)
+            }));
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read `{}`", deps.display()));
+        }
+    }
+
+    let mut diagnostics = vec![];
+    let mut groups = vec![];
+    for (id, path) in dirs {
+        match UnresolvedPackageGroup::parse_dir(&path) {
+            Ok(group) => groups.push((id, path, group)),
+            Err(e) => diagnostics.push(WitDiagnostic::Parse {
+                id,
+                message: format!("{e:#}"),
+            }),
+        }
+    }
+
+    let mut defined = BTreeMap::<String, Vec<Option<Identifier>>>::new();
+    for (id, _, group) in &groups {
+        for pkg in iter::once(&group.main).chain(&group.nested) {
+            defined
+                .entry(pkg.name.to_string())
+                .or_default()
+                .push(id.clone());
+        }
+    }
+    for (package, ids) in &defined {
+        if ids.len() > 1 {
+            diagnostics.push(WitDiagnostic::DuplicatePackage {
+                package: package.clone(),
+                ids: ids.clone(),
+            });
+        }
+    }
+    // NOTE: Only report missing packages if all dependencies were parsed, since the missing
+    // package may be defined by a dependency, which failed to parse
+    if diagnostics.is_empty() {
+        for (id, path, group) in &groups {
+            for pkg in iter::once(&group.main).chain(&group.nested) {
+                for name in pkg.foreign_deps.keys() {
+                    if !defined.contains_key(&name.to_string()) {
+                        diagnostics.push(WitDiagnostic::MissingPackage {
+                            id: id.clone(),
+                            package: name.to_string(),
+                            location: find_use(path, &format!("{}:{}", name.namespace, name.name)),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    Ok(diagnostics)
+}
+
+/// Returns the location of the first line in a WIT file within `dir` using `package`
+fn find_use(dir: &Path, package: &str) -> Option<WitLocation> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| Some(e.ok()?.path()))
+        .filter(|path| is_wit(path))
+        .collect();
+    paths.sort();
+    paths.into_iter().find_map(|path| {
+        let wit = fs::read_to_string(&path).ok()?;
+        let line = wit.lines().position(|line| {
+            let line = line.trim_start();
+            !line.starts_with("package ") && !line.starts_with("//") && line.contains(package)
+        })?;
+        Some(WitLocation {
+            path,
+            line: line + 1,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    use tokio::fs;
+
+    #[tokio::test]
+    async fn check() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-check").await?;
+        let bar = dir.join("deps").join("bar");
+        fs::create_dir_all(&bar).await?;
+        fs::write(
+            dir.join("foo.wit"),
+            "package test:foo;\n\ninterface foo {\n    use test:bar/bar.{t};\n}\n",
+        )
+        .await?;
+        fs::write(
+            bar.join("bar.wit"),
+            "package test:bar;\n\ninterface bar {\n    type t = u32;\n}\n",
+        )
+        .await?;
+        let valid = check_path(&dir).await?;
+
+        fs::write(
+            bar.join("bar.wit"),
+            "package test:bar;\n\ninterface bar {}\n",
+        )
+        .await?;
+        let unresolved = check_path(&dir).await;
+
+        assert_eq!(valid, "test:foo");
+        let err = format!(
+            "{:#}",
+            unresolved.err().context("unresolved use must fail")?
+        );
+        assert!(
+            err.contains("foo.wit"),
+            "error must point to the file: {err}"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "bundle")]
+    #[tokio::test]
+    async fn bundle() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-bundle").await?;
+        let bar = dir.join("deps").join("bar");
+        fs::create_dir_all(&bar).await?;
+        fs::write(
+            dir.join("foo.wit"),
+            "package test:foo;\n\ninterface foo {\n    use test:bar/bar.{t};\n}\n",
+        )
+        .await?;
+        fs::write(
+            bar.join("bar.wit"),
+            "package test:bar;\n\ninterface bar {\n    type t = u32;\n}\n",
+        )
+        .await?;
+        let bundle = bundle_path(&dir).await?;
+
+        let wit_component::DecodedWasm::WitPackage(resolve, pkg) =
+            wit_component::decode(&bundle).context("failed to decode bundle")?
+        else {
+            anyhow::bail!("bundle must be a WIT package");
+        };
+        assert_eq!(resolve.packages[pkg].name.to_string(), "test:foo");
+        assert!(resolve
+            .packages
+            .iter()
+            .any(|(_, pkg)| pkg.name.to_string() == "test:bar"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn diagnostics() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-check-diagnostics").await?;
+        let deps = dir.join("deps");
+        for id in ["bar", "baz", "broken"] {
+            fs::create_dir_all(deps.join(id)).await?;
+        }
+        fs::write(
+            dir.join("foo.wit"),
+            "package test:foo;\n\ninterface foo {\n    use test:bar/bar.{t};\n    use test:qux/qux.{u};\n}\n",
+        )
+        .await?;
+        fs::write(
+            deps.join("bar").join("bar.wit"),
+            "package test:bar;\n\ninterface bar {\n    type t = u32;\n}\n",
+        )
+        .await?;
+        fs::write(
+            deps.join("baz").join("baz.wit"),
+            "package test:bar;\n\ninterface bar {\n    type t = u32;\n}\n",
+        )
+        .await?;
+        fs::write(
+            deps.join("broken").join("broken.wit"),
+            "package test:broken;\n\ninterface {\n",
+        )
+        .await?;
+        let broken = check_path(&dir).await;
+
+        fs::remove_dir_all(deps.join("broken")).await?;
+        fs::remove_dir_all(deps.join("baz")).await?;
+        let missing = check_path(&dir).await;
+
+        let Some(Error::Validation { diagnostics, .. }) = broken.err() else {
+            anyhow::bail!("validation error expected");
+        };
+        let [WitDiagnostic::Parse { id, message }, WitDiagnostic::DuplicatePackage { package, ids }] =
+            diagnostics.as_slice()
+        else {
+            anyhow::bail!("unexpected diagnostics: {diagnostics:?}");
+        };
+        assert_eq!(id.as_deref(), Some("broken"));
+        assert!(
+            message.contains("broken.wit:3"),
+            "message must point to the line: {message}"
+        );
+        assert_eq!(package, "test:bar");
+        assert_eq!(ids, &[Some("bar".into()), Some("baz".into())]);
+
+        let Some(Error::Validation { diagnostics, .. }) = missing.err() else {
+            anyhow::bail!("validation error expected");
+        };
+        assert_eq!(
+            diagnostics,
+            [WitDiagnostic::MissingPackage {
+                id: None,
+                package: "test:qux".into(),
+                location: Some(WitLocation {
+                    path: dir.join("foo.wit"),
+                    line: 5,
+                }),
+            }]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::Identifier;
+
+use core::fmt;
+
+use std::path::PathBuf;
+
+/// Location of a WIT definition
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Location {
+    /// Path of the WIT file
+    pub path: PathBuf,
+    /// Line number within the file, starting at 1
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.line)
+    }
+}
+
+/// Problem with WIT definitions reported by [`check_path`](crate::check_path).
+///
+/// Each diagnostic carries the [Identifier] of the dependency it relates to, if any, or `None`
+/// if it relates to the WIT package depending on the dependencies.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Diagnostic {
+    /// WIT definitions could not be parsed
+    Parse {
+        /// Identifier of the dependency
+        id: Option<Identifier>,
+        /// Error message, which includes the file, line and column of the offending definition
+        message: String,
+    },
+    /// Package is defined by multiple dependencies
+    DuplicatePackage {
+        /// Name of the package
+        package: String,
+        /// Identifiers of the dependencies defining the package
+        ids: Vec<Option<Identifier>>,
+    },
+    /// Package is used, but not defined by any dependency
+    MissingPackage {
+        /// Identifier of the dependency using the package
+        id: Option<Identifier>,
+        /// Name of the package
+        package: String,
+        /// Location of the first use of the package, if known
+        location: Option<Location>,
+    },
+    /// WIT definitions could not be resolved
+    Resolve {
+        /// Error message, which includes the file, line and column of the offending definition
+        message: String,
+    },
+}
+
+impl Diagnostic {
+    /// Returns the [Identifier] of the dependency this diagnostic relates to, if known
+    #[must_use]
+    pub fn id(&self) -> Option<&Identifier> {
+        match self {
+            Self::Parse { id, .. } | Self::MissingPackage { id, .. } => id.as_ref(),
+            Self::DuplicatePackage { .. } | Self::Resolve { .. } => None,
+        }
+    }
+}
+
+/// Formats `id` as the dependency a diagnostic relates to
+fn dependency(id: Option<&Identifier>) -> String {
+    id.map_or_else(|| "root package".into(), |id| format!("dependency `{id}`"))
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse { id, message } => {
+                write!(f, "failed to parse {}: {message}", dependency(id.as_ref()))
+            }
+            Self::DuplicatePackage { package, ids } => write!(
+                f,
+                "package `{package}` is defined by {}",
+                ids.iter()
+                    .map(|id| dependency(id.as_ref()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Self::MissingPackage {
+                id,
+                package,
+                location,
+            } => {
+                write!(
+                    f,
+                    "package `{package}` used by {} is not defined by any dependency",
+                    dependency(id.as_ref())
+                )?;
+                if let Some(location) = location {
+                    write!(f, " (used at `{location}`)")?;
+                }
+                Ok(())
+            }
+            Self::Resolve { message } => write!(f, "failed to resolve: {message}"),
+        }
+    }
+}
@@ -2,11 +2,17 @@ use core::fmt;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 
+use std::path::Path;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine as _;
+use futures::io::sink;
 use futures::{AsyncRead, AsyncWrite};
 use hex::FromHex;
 use serde::ser::SerializeStruct;
 use serde::{de, Deserialize, Serialize};
 use sha2::{Digest as _, Sha256, Sha512};
+use url::Url;
 
 /// A resource digest
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -15,14 +21,179 @@ pub struct Digest {
     pub sha256: [u8; 32],
     /// Sha512 digest of a resource
     pub sha512: [u8; 64],
+    /// Optional BLAKE3 digest of a resource, see [`DigestPolicy`]
+    pub blake3: Option<[u8; 32]>,
+}
+
+impl Digest {
+    /// Returns `true` if all digests present in both `self` and `other` are equal
+    #[must_use]
+    pub fn matches(&self, other: &Self) -> bool {
+        self.sha256 == other.sha256
+            && self.sha512 == other.sha512
+            && match (self.blake3, other.blake3) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+    }
+
+    /// Returns the [Subresource Integrity](https://www.w3.org/TR/SRI/) string of the sha256 and
+    /// sha512 digests, e.g. `sha256-<base64> sha512-<base64>`
+    #[must_use]
+    pub fn integrity(&self) -> String {
+        format!(
+            "sha256-{} sha512-{}",
+            STANDARD.encode(self.sha256),
+            STANDARD.encode(self.sha512)
+        )
+    }
+
+    /// Returns the algorithm name, hex-encoded `expected` and actual digests of the first digest
+    /// present in both `self` and `expected`, which differs, if any
+    pub(crate) fn mismatch(&self, expected: &Self) -> Option<(&'static str, String, String)> {
+        if self.sha256 != expected.sha256 {
+            Some((
+                "sha256",
+                hex::encode(expected.sha256),
+                hex::encode(self.sha256),
+            ))
+        } else if self.sha512 != expected.sha512 {
+            Some((
+                "sha512",
+                hex::encode(expected.sha512),
+                hex::encode(self.sha512),
+            ))
+        } else {
+            match (self.blake3, expected.blake3) {
+                (Some(actual), Some(expected)) if actual != expected => {
+                    Some(("blake3", hex::encode(expected), hex::encode(actual)))
+                }
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Computes the [Digest] of WIT definitions within the directory at `path`, which is the digest
+/// recorded in the lock for a dependency installed at `path`.
+///
+/// The format of the digest is stable. It is the digest of a `tar` archive, which contains an
+/// entry `wit/<name>` for each file `<name>` with `wit` extension directly within `path` in
+/// lexicographic order of names. Each entry has a GNU header with zero modification time, owner
+/// and group IDs and mode `0755` if the file is executable by its owner or `0644` otherwise.
+/// All other files and subdirectories, including `deps`, do not affect the digest.
+///
+/// # Errors
+///
+/// Returns an error if reading the directory or any of the files fails
+pub async fn digest_dir(path: impl AsRef<Path>) -> std::io::Result<Digest> {
+    crate::tar(path, Writer::from(sink())).await.map(Into::into)
+}
+
+/// Computes the [Digest] of all bytes read from `reader`
+///
+/// # Errors
+///
+/// Returns an error if reading from `reader` fails
+pub async fn digest_reader(reader: impl AsyncRead + Unpin) -> std::io::Result<Digest> {
+    let mut reader = Reader::from(reader);
+    futures::io::copy(&mut reader, &mut sink()).await?;
+    Ok(reader.into())
+}
+
+/// Optional sha256 and sha512 digests specified by a Subresource Integrity string
+pub(crate) type Integrity = (Option<[u8; 32]>, Option<[u8; 64]>);
+
+/// Parses a [Subresource Integrity](https://www.w3.org/TR/SRI/) string, e.g.
+/// `sha256-<base64>`, into sha256 and sha512 digests. Multiple space-separated hashes may be
+/// specified, but at most one per algorithm. Options following `?` are ignored.
+pub(crate) fn parse_integrity(s: &str) -> Result<Integrity, String> {
+    fn decode<const N: usize>(algorithm: &str, v: &str) -> Result<[u8; N], String> {
+        let v = STANDARD
+            .decode(v)
+            .map_err(|e| format!("invalid base64 `{algorithm}` hash: {e}"))?;
+        v.try_into().map_err(|v: Vec<u8>| {
+            format!(
+                "invalid `{algorithm}` hash length {}, expected {N} bytes",
+                v.len()
+            )
+        })
+    }
+
+    let mut sha256 = None;
+    let mut sha512 = None;
+    for hash in s.split_whitespace() {
+        let hash = hash.split_once('?').map_or(hash, |(hash, _)| hash);
+        match hash.split_once('-') {
+            Some(("sha256", v)) if sha256.is_none() => sha256 = Some(decode("sha256", v)?),
+            Some(("sha512", v)) if sha512.is_none() => sha512 = Some(decode("sha512", v)?),
+            Some((algorithm @ ("sha256" | "sha512"), _)) => {
+                return Err(format!("duplicate `{algorithm}` hash"))
+            }
+            Some((algorithm, _)) => {
+                return Err(format!(
+                    "unsupported algorithm `{algorithm}`, expected `sha256` or `sha512`"
+                ))
+            }
+            None => {
+                return Err(format!(
+                    "invalid hash `{hash}`, expected `<algorithm>-<base64>`"
+                ))
+            }
+        }
+    }
+    if sha256.is_none() && sha512.is_none() {
+        return Err("no hash specified".into());
+    }
+    Ok((sha256, sha512))
+}
+
+/// Policy controlling which optional digests are recorded in the lock
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct DigestPolicy {
+    /// Record BLAKE3 digests in addition to sha256 and sha512 digests
+    pub blake3: bool,
+    /// Record digests of individual WIT files, see [`Lock::digest_files`](crate::Lock::digest_files)
+    pub files: bool,
+    /// Record [Subresource Integrity](https://www.w3.org/TR/SRI/) strings, see
+    /// [`Digest::integrity`]
+    pub integrity: bool,
 }
 
+/// Error returned if a fetched resource does not match the expected digest
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Mismatch {
+    /// URL of the resource
+    pub url: Url,
+    /// Name of the digest algorithm, e.g. `sha256`
+    pub algorithm: &'static str,
+    /// Hex-encoded expected digest
+    pub expected: String,
+    /// Hex-encoded actual digest
+    pub actual: String,
+    /// Whether the expected digest was recorded in the lock, as opposed to being specified in
+    /// the manifest
+    pub locked: bool,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hash mismatch for `{}`\ngot: {}\nexpected: {}",
+            self.algorithm, self.url, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
 impl<'de> Deserialize<'de> for Digest {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        const FIELDS: [&str; 2] = ["sha256", "sha512"];
+        const FIELDS: [&str; 3] = ["blake3", "sha256", "sha512"];
 
         struct Visitor;
         impl<'de> de::Visitor<'de> for Visitor {
@@ -36,10 +207,19 @@ impl<'de> Deserialize<'de> for Digest {
             where
                 V: de::MapAccess<'de>,
             {
+                let mut blake3 = None;
                 let mut sha256 = None;
                 let mut sha512 = None;
                 while let Some((k, v)) = map.next_entry::<String, String>()? {
                     match k.as_ref() {
+                        "blake3" => {
+                            if blake3.is_some() {
+                                return Err(de::Error::duplicate_field("blake3"));
+                            }
+                            blake3 = FromHex::from_hex(v).map(Some).map_err(|e| {
+                                de::Error::custom(format!("invalid `blake3` field value: {e}"))
+                            })?;
+                        }
                         "sha256" => {
                             if sha256.is_some() {
                                 return Err(de::Error::duplicate_field("sha256"));
@@ -61,7 +241,11 @@ impl<'de> Deserialize<'de> for Digest {
                 }
                 let sha256 = sha256.ok_or_else(|| de::Error::missing_field("sha256"))?;
                 let sha512 = sha512.ok_or_else(|| de::Error::missing_field("sha512"))?;
-                Ok(Digest { sha256, sha512 })
+                Ok(Digest {
+                    sha256,
+                    sha512,
+                    blake3,
+                })
             }
         }
         deserializer.deserialize_struct("Entry", &FIELDS, Visitor)
@@ -73,9 +257,13 @@ impl Serialize for Digest {
     where
         S: serde::Serializer,
     {
-        let mut state = serializer.serialize_struct("Digest", 2)?;
+        let mut state =
+            serializer.serialize_struct("Digest", 2 + usize::from(self.blake3.is_some()))?;
         state.serialize_field("sha256", &hex::encode(self.sha256))?;
         state.serialize_field("sha512", &hex::encode(self.sha512))?;
+        if let Some(blake3) = self.blake3 {
+            state.serialize_field("blake3", &hex::encode(blake3))?;
+        }
         state.end()
     }
 }
@@ -85,6 +273,8 @@ pub struct Reader<T> {
     reader: T,
     sha256: Sha256,
     sha512: Sha512,
+    // NOTE: The hasher state is large and is boxed to keep futures holding it small
+    blake3: Box<blake3::Hasher>,
 }
 
 impl<T: AsyncRead + Unpin> AsyncRead for Reader<T> {
@@ -96,6 +286,7 @@ impl<T: AsyncRead + Unpin> AsyncRead for Reader<T> {
         Pin::new(&mut self.reader).poll_read(cx, buf).map_ok(|n| {
             self.sha256.update(&buf[..n]);
             self.sha512.update(&buf[..n]);
+            self.blake3.update(&buf[..n]);
             n
         })
     }
@@ -107,6 +298,7 @@ impl<T> From<T> for Reader<T> {
             reader,
             sha256: Sha256::new(),
             sha512: Sha512::new(),
+            blake3: Box::default(),
         }
     }
 }
@@ -115,7 +307,12 @@ impl<T> From<Reader<T>> for Digest {
     fn from(hashed: Reader<T>) -> Self {
         let sha256 = hashed.sha256.finalize().into();
         let sha512 = hashed.sha512.finalize().into();
-        Self { sha256, sha512 }
+        let blake3 = hashed.blake3.finalize().into();
+        Self {
+            sha256,
+            sha512,
+            blake3: Some(blake3),
+        }
     }
 }
 
@@ -124,6 +321,7 @@ pub struct Writer<T> {
     writer: T,
     sha256: Sha256,
     sha512: Sha512,
+    blake3: Box<blake3::Hasher>,
 }
 
 impl<T: AsyncWrite + Unpin> AsyncWrite for Writer<T> {
@@ -135,6 +333,7 @@ impl<T: AsyncWrite + Unpin> AsyncWrite for Writer<T> {
         Pin::new(&mut self.writer).poll_write(cx, buf).map_ok(|n| {
             self.sha256.update(&buf[..n]);
             self.sha512.update(&buf[..n]);
+            self.blake3.update(&buf[..n]);
             n
         })
     }
@@ -154,6 +353,7 @@ impl<T> From<T> for Writer<T> {
             writer,
             sha256: Sha256::new(),
             sha512: Sha512::new(),
+            blake3: Box::default(),
         }
     }
 }
@@ -162,6 +362,111 @@ impl<T> From<Writer<T>> for Digest {
     fn from(hashed: Writer<T>) -> Self {
         let sha256 = hashed.sha256.finalize().into();
         let sha512 = hashed.sha512.finalize().into();
-        Self { sha256, sha512 }
+        let blake3 = hashed.blake3.finalize().into();
+        Self {
+            sha256,
+            sha512,
+            blake3: Some(blake3),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    use crate::fs;
+
+    #[tokio::test]
+    async fn stable() -> anyhow::Result<()> {
+        let digest = digest_reader(&b"test"[..]).await?;
+        assert_eq!(
+            hex::encode(digest.sha256),
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+        );
+
+        let dir = TempDir::new("wit-deps-digest-dir").await?;
+        fs::create_dir_all(dir.join("deps").join("bar")).await?;
+        fs::write(dir.join("foo.wit"), "package foo:foo;").await?;
+        fs::write(dir.join("README.md"), "# foo").await?;
+        fs::write(
+            dir.join("deps").join("bar").join("bar.wit"),
+            "package foo:bar;",
+        )
+        .await?;
+        let digest = digest_dir(&dir).await?;
+
+        fs::write(dir.join("README.md"), "# bar").await?;
+        fs::remove_dir_all(dir.join("deps")).await?;
+        let unrelated = digest_dir(&dir).await?;
+
+        // NOTE: The digest of a directory is part of the lock format and must never change
+        assert_eq!(
+            hex::encode(digest.sha256),
+            "dc63d992f96940584fd74e66fda82636a1a9adddc307d11e763e397fea3373c4"
+        );
+        assert_eq!(digest, unrelated);
+        Ok(())
+    }
+
+    #[test]
+    fn mismatch() {
+        let digest = Digest {
+            sha256: [1; 32],
+            sha512: [2; 64],
+            blake3: Some([3; 32]),
+        };
+        assert_eq!(digest.mismatch(&digest), None);
+        assert_eq!(
+            digest.mismatch(&Digest {
+                blake3: None,
+                ..digest.clone()
+            }),
+            None
+        );
+        assert_eq!(
+            digest.mismatch(&Digest {
+                sha512: [0; 64],
+                blake3: Some([0; 32]),
+                ..digest.clone()
+            }),
+            Some(("sha512", hex::encode([0; 64]), hex::encode([2; 64])))
+        );
+        assert_eq!(
+            digest.mismatch(&Digest {
+                blake3: Some([0; 32]),
+                ..digest.clone()
+            }),
+            Some(("blake3", hex::encode([0; 32]), hex::encode([3; 32])))
+        );
+    }
+
+    #[tokio::test]
+    async fn integrity() -> anyhow::Result<()> {
+        const SHA256: &str = "sha256-n4bQgYhMfWWaL+qgxVrQFaO/TxsrC4Is0V1sFbDwCgg=";
+
+        let digest = digest_reader(&b"test"[..]).await?;
+        assert_eq!(parse_integrity(SHA256), Ok((Some(digest.sha256), None)));
+        assert_eq!(
+            parse_integrity(&format!(" {SHA256}?foo ")),
+            Ok((Some(digest.sha256), None))
+        );
+        assert_eq!(
+            parse_integrity(&digest.integrity()),
+            Ok((Some(digest.sha256), Some(digest.sha512)))
+        );
+        assert!(digest.integrity().starts_with(SHA256));
+        for invalid in [
+            "",
+            "sha384-abc",
+            "sha256-!",
+            "sha256-AAAA",
+            &format!("{SHA256} {SHA256}"),
+        ] {
+            assert!(parse_integrity(invalid).is_err(), "{invalid}");
+        }
+        Ok(())
     }
 }
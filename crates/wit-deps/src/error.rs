@@ -0,0 +1,550 @@
+use crate::fetch::{Request, Status};
+use crate::{DigestMismatch, Identifier, LockFailure, WitDiagnostic};
+
+use core::fmt;
+
+use std::path::PathBuf;
+
+use url::Url;
+
+/// Context of errors returned if a dependency manifest, optionally at a path, cannot be decoded
+#[derive(Debug)]
+pub(crate) struct InvalidManifest(pub Option<PathBuf>);
+
+impl fmt::Display for InvalidManifest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.0 {
+            Some(path) => write!(f, "failed to decode manifest `{}`", path.display()),
+            None => write!(f, "failed to decode manifest"),
+        }
+    }
+}
+
+/// Error returned if the digests of a transitive dependency required by multiple dependencies
+/// conflict
+#[derive(Debug)]
+pub(crate) struct Conflict {
+    /// Identifier of the transitive dependency
+    pub id: Identifier,
+    /// Dependency, which required the transitive dependency last
+    pub direct: Identifier,
+    /// Dependencies, which required the transitive dependency before
+    pub required_by: Vec<Identifier>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            id,
+            direct,
+            required_by,
+        } = self;
+        write!(
+            f,
+            "transitive dependency conflict for `{id}` required by `{direct}` and {}, add `{id}` to dependency manifest to resolve it",
+            required_by
+                .iter()
+                .map(|id| format!("`{id}`"))
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    }
+}
+
+impl std::error::Error for Conflict {}
+
+/// Error returned if multiple dependencies declare the same WIT package
+#[derive(Debug)]
+pub(crate) struct Collision {
+    /// Name of the package
+    pub package: String,
+    /// Identifiers of the dependencies declaring the package along with the manifest entries,
+    /// which introduced them
+    pub ids: Vec<(Identifier, Vec<Identifier>)>,
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "WIT package `{}` is declared by multiple dependencies:",
+            self.package
+        )?;
+        for (id, required_by) in &self.ids {
+            if required_by.is_empty() {
+                write!(f, "\n  - `{id}` defined in the manifest")?;
+            } else {
+                write!(
+                    f,
+                    "\n  - `{id}` required by {}",
+                    required_by
+                        .iter()
+                        .map(|id| format!("`{id}`"))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Collision {}
+
+/// WIT package used, but not declared by the package in the WIT directory or any locked
+/// dependency
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Missing {
+    /// Name of the package
+    pub package: String,
+    /// WIT directory, if the package is used by the package within it
+    pub root: Option<PathBuf>,
+    /// Locked dependencies using the package
+    pub used_by: Vec<Identifier>,
+    /// Manifest entries expected to provide the package
+    pub expected: Vec<Identifier>,
+}
+
+impl fmt::Display for Missing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            package,
+            root,
+            used_by,
+            expected,
+        } = self;
+        let used_by = root
+            .iter()
+            .map(|root| format!("`{}`", root.display()))
+            .chain(used_by.iter().map(|id| format!("`{id}`")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "`{package}` used by {used_by}")?;
+        if expected.is_empty() {
+            write!(f, ", add a manifest entry providing it")
+        } else {
+            write!(
+                f,
+                ", expected to be provided by {}",
+                expected
+                    .iter()
+                    .map(|id| format!("`{id}`"))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }
+    }
+}
+
+/// Error returned if WIT packages are used, but not declared by any locked dependency
+#[derive(Debug)]
+pub(crate) struct Incomplete(pub Vec<Missing>);
+
+impl fmt::Display for Incomplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the following WIT packages are used, but not declared by any dependency:"
+        )?;
+        for missing in &self.0 {
+            write!(f, "\n  - {missing}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Incomplete {}
+
+/// Error returned if URL dependencies are not pinned by a digest, but pinning is required
+#[derive(Debug)]
+pub(crate) struct Unpinned(pub Vec<(Identifier, Url)>);
+
+impl fmt::Display for Unpinned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the following URL dependencies are not pinned by a `sha256` or `sha512` digest:"
+        )?;
+        for (id, url) in &self.0 {
+            write!(f, "\n  - `{id}` ({url})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Unpinned {}
+
+/// Error returned if resources have to be fetched in offline mode
+#[derive(Debug)]
+pub(crate) struct Offline(pub Vec<String>);
+
+impl fmt::Display for Offline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the following URLs are not present in cache and cannot be fetched in offline mode:"
+        )?;
+        for url in &self.0 {
+            write!(f, "\n  - {url}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Offline {}
+
+/// Error returned if WIT definitions are invalid
+#[derive(Debug)]
+pub(crate) struct Invalid(pub Vec<WitDiagnostic>);
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WIT definitions are invalid:")?;
+        for diagnostic in &self.0 {
+            write!(f, "\n  - {diagnostic}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Invalid {}
+
+/// Error returned if an operation was cancelled using a
+/// [`CancellationToken`](crate::CancellationToken)
+#[derive(Debug)]
+pub(crate) struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Error returned by the library API.
+///
+/// Each variant carries the [Identifier] of the dependency the error relates to, if known, and
+/// the underlying error, which is displayed and used as source of this error, such that the
+/// message and source chain are the same as the ones of the underlying error.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Dependency manifest could not be decoded
+    Manifest {
+        /// Underlying error
+        error: anyhow::Error,
+    },
+    /// Dependency could not be fetched, for example, due to a connection failure, an HTTP error
+    /// status or because it is not present in cache in offline mode.
+    /// Errors of this kind are potentially transient
+    Network {
+        /// Identifier of the dependency
+        id: Option<Identifier>,
+        /// Underlying error
+        error: anyhow::Error,
+    },
+    /// Contents of a dependency do not match the expected digest
+    DigestMismatch {
+        /// Identifier of the dependency
+        id: Option<Identifier>,
+        /// Expected and actual digests
+        mismatch: Box<DigestMismatch>,
+        /// Underlying error
+        error: anyhow::Error,
+    },
+    /// Digests of a transitive dependency required by multiple dependencies conflict
+    Conflict {
+        /// Identifier of the transitive dependency
+        id: Identifier,
+        /// Underlying error
+        error: anyhow::Error,
+    },
+    /// I/O operation failed
+    Io {
+        /// Identifier of the dependency
+        id: Option<Identifier>,
+        /// Kind of the I/O error
+        kind: std::io::ErrorKind,
+        /// Underlying error
+        error: anyhow::Error,
+    },
+    /// WIT definitions are invalid, see [`check_path`](crate::check_path)
+    Validation {
+        /// All problems found
+        diagnostics: Vec<WitDiagnostic>,
+        /// Underlying error
+        error: anyhow::Error,
+    },
+    /// Operation was cancelled, see [`LockOptions::with_cancellation`](crate::LockOptions::with_cancellation)
+    Cancelled {
+        /// Underlying error
+        error: anyhow::Error,
+    },
+    /// Any other error
+    Other {
+        /// Identifier of the dependency
+        id: Option<Identifier>,
+        /// Underlying error
+        error: anyhow::Error,
+    },
+}
+
+impl Error {
+    /// Returns the [Identifier] of the dependency this error relates to, if known
+    #[must_use]
+    pub fn id(&self) -> Option<&Identifier> {
+        match self {
+            Self::Manifest { .. } | Self::Validation { .. } | Self::Cancelled { .. } => None,
+            Self::Conflict { id, .. } => Some(id),
+            Self::Network { id, .. }
+            | Self::DigestMismatch { id, .. }
+            | Self::Io { id, .. }
+            | Self::Other { id, .. } => id.as_ref(),
+        }
+    }
+
+    /// Returns `true` if the operation, which returned this error, may succeed if retried
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Network { .. })
+    }
+
+    /// Returns the underlying error
+    #[must_use]
+    pub fn inner(&self) -> &anyhow::Error {
+        match self {
+            Self::Manifest { error }
+            | Self::Network { error, .. }
+            | Self::DigestMismatch { error, .. }
+            | Self::Conflict { error, .. }
+            | Self::Io { error, .. }
+            | Self::Validation { error, .. }
+            | Self::Cancelled { error }
+            | Self::Other { error, .. } => error,
+        }
+    }
+
+    /// Returns the underlying error
+    #[must_use]
+    pub fn into_inner(self) -> anyhow::Error {
+        match self {
+            Self::Manifest { error }
+            | Self::Network { error, .. }
+            | Self::DigestMismatch { error, .. }
+            | Self::Conflict { error, .. }
+            | Self::Io { error, .. }
+            | Self::Validation { error, .. }
+            | Self::Cancelled { error }
+            | Self::Other { error, .. } => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+/// Kind of an [Error] without the underlying error
+enum Kind {
+    Manifest,
+    Network,
+    DigestMismatch(Box<DigestMismatch>),
+    Conflict(Identifier),
+    Io(std::io::ErrorKind),
+    Validation(Vec<WitDiagnostic>),
+    Cancelled,
+    Other,
+}
+
+impl Error {
+    fn new(kind: Kind, id: Option<Identifier>, error: anyhow::Error) -> Self {
+        match kind {
+            Kind::Manifest => Self::Manifest { error },
+            Kind::Network => Self::Network { id, error },
+            Kind::DigestMismatch(mismatch) => Self::DigestMismatch {
+                id,
+                mismatch,
+                error,
+            },
+            Kind::Conflict(id) => Self::Conflict { id, error },
+            Kind::Io(kind) => Self::Io { id, kind, error },
+            Kind::Validation(diagnostics) => Self::Validation { diagnostics, error },
+            Kind::Cancelled => Self::Cancelled { error },
+            Kind::Other => Self::Other { id, error },
+        }
+    }
+
+    fn kind(&self) -> Kind {
+        match self {
+            Self::Manifest { .. } => Kind::Manifest,
+            Self::Network { .. } => Kind::Network,
+            Self::DigestMismatch { mismatch, .. } => Kind::DigestMismatch(mismatch.clone()),
+            Self::Conflict { id, .. } => Kind::Conflict(id.clone()),
+            Self::Io { kind, .. } => Kind::Io(*kind),
+            Self::Validation { diagnostics, .. } => Kind::Validation(diagnostics.clone()),
+            Self::Cancelled { .. } => Kind::Cancelled,
+            Self::Other { .. } => Kind::Other,
+        }
+    }
+}
+
+/// Returns `true` if `error` was caused by a [`reqwest::Error`]
+#[cfg(feature = "reqwest")]
+fn is_reqwest(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(|e| e.downcast_ref::<reqwest::Error>().is_some())
+}
+
+/// Returns `true` if `error` was caused by a `reqwest::Error`, which is never the case without
+/// the `reqwest` feature
+#[cfg(not(feature = "reqwest"))]
+fn is_reqwest(_: &anyhow::Error) -> bool {
+    false
+}
+
+/// Returns the [Kind] of `error` determined by the errors in its chain
+fn classify(error: &anyhow::Error) -> Kind {
+    if error.downcast_ref::<Cancelled>().is_some() {
+        Kind::Cancelled
+    } else if let Some(mismatch) = error
+        .chain()
+        .find_map(|e| e.downcast_ref::<DigestMismatch>())
+    {
+        Kind::DigestMismatch(Box::new(mismatch.clone()))
+    } else if let Some(Conflict { id, .. }) = error.downcast_ref::<Conflict>() {
+        Kind::Conflict(id.clone())
+    } else if error.downcast_ref::<Offline>().is_some()
+        || error.downcast_ref::<Request>().is_some()
+        || error.downcast_ref::<Status>().is_some()
+        || is_reqwest(error)
+    {
+        Kind::Network
+    } else if let Some(Invalid(diagnostics)) =
+        error.chain().find_map(|e| e.downcast_ref::<Invalid>())
+    {
+        Kind::Validation(diagnostics.clone())
+    } else if error.downcast_ref::<InvalidManifest>().is_some() {
+        Kind::Manifest
+    } else if let Some(e) = error
+        .chain()
+        .find_map(|e| e.downcast_ref::<std::io::Error>())
+    {
+        Kind::Io(e.kind())
+    } else {
+        Kind::Other
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        // NOTE: Errors returned by public functions called internally preserve their kind
+        let (kind, id) = if let Some(inner) = error.chain().find_map(|e| e.downcast_ref::<Self>()) {
+            (inner.kind(), inner.id().cloned())
+        } else {
+            let id = error
+                .downcast_ref::<LockFailure>()
+                .map(|LockFailure { id }| id.clone());
+            (classify(&error), id)
+        };
+        Self::new(kind, id, error)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        anyhow::Error::new(error).into()
+    }
+}
+
+impl From<Conflict> for Error {
+    fn from(error: Conflict) -> Self {
+        anyhow::Error::new(error).into()
+    }
+}
+
+impl From<Cancelled> for Error {
+    fn from(error: Cancelled) -> Self {
+        anyhow::Error::new(error).into()
+    }
+}
+
+impl From<Offline> for Error {
+    fn from(error: Offline) -> Self {
+        anyhow::Error::new(error).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Context as _;
+
+    #[test]
+    fn classify() {
+        let err = Error::from(anyhow::anyhow!("foo"));
+        assert!(matches!(err, Error::Other { id: None, .. }));
+        assert_eq!(err.to_string(), "foo");
+
+        let err = Error::from(
+            Err::<(), _>(std::io::Error::from(std::io::ErrorKind::NotFound))
+                .context("failed to read")
+                .context(LockFailure { id: "foo".into() })
+                .expect_err("error expected"),
+        );
+        assert!(matches!(
+            err,
+            Error::Io {
+                kind: std::io::ErrorKind::NotFound,
+                ..
+            }
+        ));
+        assert_eq!(err.id().map(String::as_str), Some("foo"));
+        assert_eq!(err.to_string(), "failed to lock `foo`");
+        assert_eq!(
+            std::error::Error::source(&err).map(ToString::to_string),
+            Some("failed to read".into())
+        );
+        assert!(!err.is_transient());
+
+        let err = Error::from(anyhow::Error::new(Offline(vec![
+            "https://example.com".into()
+        ])));
+        assert!(err.is_transient());
+
+        let err = Error::from(
+            anyhow::Error::new(Conflict {
+                id: "bar".into(),
+                direct: "foo".into(),
+                required_by: vec!["baz".into()],
+            })
+            .context("failed to lock"),
+        );
+        assert!(matches!(err, Error::Conflict { .. }));
+        assert_eq!(err.id().map(String::as_str), Some("bar"));
+
+        // Kind is preserved through additional context
+        let err = Error::from(anyhow::Error::new(err).context("outer"));
+        assert!(matches!(err, Error::Conflict { .. }));
+        assert_eq!(err.id().map(String::as_str), Some("bar"));
+        assert_eq!(err.to_string(), "outer");
+
+        let err = Error::from(anyhow::Error::new(Cancelled).context("failed to lock"));
+        assert!(matches!(err, Error::Cancelled { .. }));
+        assert!(!err.is_transient());
+
+        let err = Error::from(anyhow::Error::msg("TOML error").context(InvalidManifest(None)));
+        assert!(matches!(err, Error::Manifest { .. }));
+        assert_eq!(err.id(), None);
+    }
+}
@@ -0,0 +1,295 @@
+use core::fmt;
+
+#[cfg(feature = "reqwest")]
+use std::env;
+use std::sync::Arc;
+
+#[cfg(feature = "reqwest")]
+use anyhow::Context as _;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::{self, BoxStream};
+use futures::StreamExt as _;
+#[cfg(feature = "reqwest")]
+use futures::TryStreamExt as _;
+#[cfg(feature = "reqwest")]
+use reqwest::{header::ETAG, Proxy};
+use url::Url;
+#[cfg(feature = "reqwest")]
+use urlencoding::encode;
+
+/// Context of errors returned by [Fetch] for requests using a method
+#[derive(Debug)]
+pub(crate) struct Request(pub &'static str);
+
+impl fmt::Display for Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to {}", self.0)
+    }
+}
+
+/// Error returned if an HTTP request returned an unsuccessful status code
+#[derive(Debug)]
+pub(crate) struct Status {
+    /// Status code of the response
+    pub status: u16,
+    /// URL of the response
+    pub url: Url,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { status, url } = self;
+        write!(f, "HTTP status {status} for url ({url})")
+    }
+}
+
+impl std::error::Error for Status {}
+
+/// Response to an HTTP request sent by [Fetch]
+#[non_exhaustive]
+pub struct Response {
+    /// Status code of the response
+    pub status: u16,
+    /// Final URL of the response, which differs from the requested one if redirects were
+    /// followed
+    pub url: Url,
+    /// Value of the `ETag` header, if any
+    pub etag: Option<String>,
+    /// Length of the body, if known
+    pub content_length: Option<u64>,
+    /// Stream of body chunks, which is empty for `HEAD` requests
+    pub body: BoxStream<'static, std::io::Result<Bytes>>,
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status)
+            .field("url", &self.url)
+            .field("etag", &self.etag)
+            .field("content_length", &self.content_length)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Response {
+    /// Returns a response with `status` for `url` and an empty body
+    #[must_use]
+    pub fn new(status: u16, url: Url) -> Self {
+        Self {
+            status,
+            url,
+            etag: None,
+            content_length: None,
+            body: stream::empty().boxed(),
+        }
+    }
+
+    /// Returns the response with `ETag` header value `etag`
+    #[must_use]
+    pub fn with_etag(self, etag: impl Into<String>) -> Self {
+        Self {
+            etag: Some(etag.into()),
+            ..self
+        }
+    }
+
+    /// Returns the response with body streamed from `body` of length `content_length`, if known
+    #[must_use]
+    pub fn with_body(
+        self,
+        body: impl futures::Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+        content_length: Option<u64>,
+    ) -> Self {
+        Self {
+            body: body.boxed(),
+            content_length,
+            ..self
+        }
+    }
+
+    /// Returns `true` if the status code is within the `2xx` range
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Returns an error if the status code is not [successful](Self::is_success)
+    pub(crate) fn error_for_status(self) -> Result<Self, Status> {
+        if self.is_success() {
+            Ok(self)
+        } else {
+            Err(Status {
+                status: self.status,
+                url: self.url,
+            })
+        }
+    }
+}
+
+/// Transport used to fetch URL dependencies, see
+/// [`LockOptions::with_fetch`](crate::LockOptions::with_fetch).
+///
+/// Implementations are expected to follow redirects and to return responses with unsuccessful
+/// status codes as [Response] rather than as an error.
+#[async_trait]
+pub trait Fetch: Send + Sync {
+    /// Sends a `GET` request for `url`
+    async fn get(&self, url: &Url) -> anyhow::Result<Response>;
+
+    /// Sends a `HEAD` request for `url`
+    async fn head(&self, url: &Url) -> anyhow::Result<Response>;
+
+    /// Sends a `GET` request for `url` with additional request `headers`, e.g. to authenticate
+    /// with the GitHub API. The default implementation ignores `headers`.
+    async fn get_with_headers(
+        &self,
+        url: &Url,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Response> {
+        let _ = headers;
+        self.get(url).await
+    }
+}
+
+#[async_trait]
+impl<T: Fetch + ?Sized> Fetch for Arc<T> {
+    async fn get(&self, url: &Url) -> anyhow::Result<Response> {
+        (**self).get(url).await
+    }
+
+    async fn head(&self, url: &Url) -> anyhow::Result<Response> {
+        (**self).head(url).await
+    }
+
+    async fn get_with_headers(
+        &self,
+        url: &Url,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Response> {
+        (**self).get_with_headers(url, headers).await
+    }
+}
+
+/// Returns the [Fetch] implementation used if none is configured, see [`Reqwest::from_env`]
+#[cfg(feature = "reqwest")]
+pub(crate) fn from_env() -> anyhow::Result<impl Fetch> {
+    Reqwest::from_env()
+}
+
+/// Returns the [Fetch] implementation used if none is configured, which fails all requests,
+/// since no default transport is available without the `reqwest` feature
+#[cfg(not(feature = "reqwest"))]
+#[allow(clippy::unnecessary_wraps)]
+pub(crate) fn from_env() -> anyhow::Result<impl Fetch> {
+    Ok(Unavailable)
+}
+
+/// [Fetch] implementation failing all requests
+#[cfg(not(feature = "reqwest"))]
+struct Unavailable;
+
+#[cfg(not(feature = "reqwest"))]
+#[async_trait]
+impl Fetch for Unavailable {
+    async fn get(&self, url: &Url) -> anyhow::Result<Response> {
+        anyhow::bail!(
+            "cannot fetch `{url}`, enable the `reqwest` feature or configure a `Fetch` implementation using `LockOptions::with_fetch`"
+        )
+    }
+
+    async fn head(&self, url: &Url) -> anyhow::Result<Response> {
+        self.get(url).await
+    }
+}
+
+/// Default [Fetch] implementation using [`reqwest`], which requires a Tokio context. Only
+/// available with the `reqwest` feature, which is enabled by default.
+#[cfg(feature = "reqwest")]
+#[derive(Clone, Debug, Default)]
+pub struct Reqwest(reqwest::Client);
+
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Client> for Reqwest {
+    fn from(client: reqwest::Client) -> Self {
+        Self(client)
+    }
+}
+
+#[cfg(feature = "reqwest")]
+impl Reqwest {
+    /// Constructs a new [Reqwest] using a proxy configured by `PROXY_SERVER`, `PROXY_USERNAME`
+    /// and `PROXY_PASSWORD` environment variables, if all of them are set
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the proxy configuration is invalid
+    pub fn from_env() -> anyhow::Result<Self> {
+        let proxy_url = env::var("PROXY_SERVER").ok();
+        let proxy_username = env::var("PROXY_USERNAME").ok();
+        let proxy_password = env::var("PROXY_PASSWORD").ok();
+        let (Some(proxy_url), Some(proxy_username), Some(proxy_password)) =
+            (proxy_url, proxy_username, proxy_password)
+        else {
+            return Ok(Self::default());
+        };
+        let proxy_with_auth = format!(
+            "http://{}:{}@{}",
+            encode(&proxy_username),
+            encode(&proxy_password),
+            proxy_url
+        );
+        reqwest::Client::builder()
+            .proxy(Proxy::all(proxy_with_auth)?)
+            .build()
+            .context("failed to create client")
+            .map(Self)
+    }
+
+    /// Converts a [`reqwest::Response`] into a [Response]
+    fn response(res: reqwest::Response) -> Response {
+        let etag = res
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(String::from);
+        let content_length = res.content_length();
+        Response {
+            status: res.status().as_u16(),
+            url: res.url().clone(),
+            etag,
+            content_length,
+            body: res.bytes_stream().map_err(std::io::Error::other).boxed(),
+        }
+    }
+}
+
+#[cfg(feature = "reqwest")]
+#[async_trait]
+impl Fetch for Reqwest {
+    async fn get(&self, url: &Url) -> anyhow::Result<Response> {
+        let res = self.0.get(url.clone()).send().await?;
+        Ok(Self::response(res))
+    }
+
+    async fn head(&self, url: &Url) -> anyhow::Result<Response> {
+        let res = self.0.head(url.clone()).send().await?;
+        Ok(Self::response(res))
+    }
+
+    async fn get_with_headers(
+        &self,
+        url: &Url,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Response> {
+        let res = headers
+            .iter()
+            .fold(self.0.get(url.clone()), |req, (name, value)| {
+                req.header(*name, *value)
+            })
+            .send()
+            .await?;
+        Ok(Self::response(res))
+    }
+}
@@ -0,0 +1,191 @@
+//! Runtime-agnostic asynchronous filesystem operations, which execute [`std::fs`] operations on
+//! the [`blocking`] thread pool, such that they can be awaited on any executor
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use std::ffi::OsString;
+use std::fs::{FileType, Metadata, Permissions};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use blocking::{unblock, Unblock};
+use futures::{Stream, StreamExt as _};
+
+/// Asynchronous version of [`std::fs::read`]
+pub(crate) async fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::read(path)).await
+}
+
+/// Asynchronous version of [`std::fs::read_to_string`]
+///
+/// # Errors
+///
+/// Returns an error if [`std::fs::read_to_string`] fails
+pub async fn read_to_string(path: impl AsRef<Path>) -> io::Result<String> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::read_to_string(path)).await
+}
+
+/// Asynchronous version of [`std::fs::write`]
+///
+/// # Errors
+///
+/// Returns an error if [`std::fs::write`] fails
+pub async fn write(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    let contents = contents.as_ref().to_vec();
+    unblock(move || std::fs::write(path, contents)).await
+}
+
+/// Asynchronous version of [`std::fs::copy`]
+pub(crate) async fn copy(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<u64> {
+    let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+    unblock(move || std::fs::copy(from, to)).await
+}
+
+/// Asynchronous version of [`std::fs::copy`], which clones the file instead if supported by the
+/// filesystem, such that `from` and `to` share the same data until either of them is modified.
+///
+/// On Linux, this attempts a `FICLONE` (reflink) first, which is supported by e.g. btrfs and XFS.
+/// On macOS, [`std::fs::copy`] already clones files on APFS.
+pub(crate) async fn clone_file(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+    unblock(move || {
+        #[cfg(target_os = "linux")]
+        if reflink(&from, &to).is_ok() {
+            return Ok(());
+        }
+        std::fs::copy(from, to).map(|_| ())
+    })
+    .await
+}
+
+/// Clones file at `from` to `to` using `FICLONE`, failing if `from` and `to` are on different
+/// filesystems or the filesystem does not support it
+#[cfg(target_os = "linux")]
+fn reflink(from: &Path, to: &Path) -> io::Result<()> {
+    let src = std::fs::File::open(from)?;
+    let permissions = src.metadata()?.permissions();
+    let dst = std::fs::File::create(to)?;
+    rustix::fs::ioctl_ficlone(&dst, &src)?;
+    dst.set_permissions(permissions)
+}
+
+/// Asynchronous version of [`std::fs::hard_link`]
+pub(crate) async fn hard_link(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+    unblock(move || std::fs::hard_link(from, to)).await
+}
+
+/// Asynchronous version of [`std::fs::rename`]
+pub(crate) async fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let (from, to) = (from.as_ref().to_path_buf(), to.as_ref().to_path_buf());
+    unblock(move || std::fs::rename(from, to)).await
+}
+
+/// Asynchronous version of [`std::fs::create_dir_all`]
+pub(crate) async fn create_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::create_dir_all(path)).await
+}
+
+/// Asynchronous version of [`std::fs::remove_dir`]
+pub(crate) async fn remove_dir(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::remove_dir(path)).await
+}
+
+/// Asynchronous version of [`std::fs::remove_dir_all`]
+pub(crate) async fn remove_dir_all(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::remove_dir_all(path)).await
+}
+
+/// Asynchronous version of [`std::fs::remove_file`]
+pub(crate) async fn remove_file(path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::remove_file(path)).await
+}
+
+/// Asynchronous version of [`std::fs::metadata`]
+pub(crate) async fn metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::metadata(path)).await
+}
+
+/// Asynchronous version of [`std::fs::set_permissions`]
+pub(crate) async fn set_permissions(path: impl AsRef<Path>, perm: Permissions) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::set_permissions(path, perm)).await
+}
+
+/// Asynchronous version of [`std::fs::exists`]
+pub(crate) async fn try_exists(path: impl AsRef<Path>) -> io::Result<bool> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || std::fs::exists(path)).await
+}
+
+/// Opens the file at `path` using `options` and returns an asynchronous handle to it
+pub(crate) async fn open(
+    path: impl AsRef<Path>,
+    options: std::fs::OpenOptions,
+) -> io::Result<Unblock<std::fs::File>> {
+    let path = path.as_ref().to_path_buf();
+    unblock(move || options.open(path)).await.map(Unblock::new)
+}
+
+/// Asynchronous version of [`std::fs::read_dir`]
+pub(crate) async fn read_dir(path: impl AsRef<Path>) -> io::Result<ReadDir> {
+    let path = path.as_ref().to_path_buf();
+    let dir = unblock(move || std::fs::read_dir(path)).await?;
+    Ok(ReadDir(Unblock::new(dir)))
+}
+
+/// Stream of entries in a directory returned by [`read_dir`]
+pub(crate) struct ReadDir(Unblock<std::fs::ReadDir>);
+
+impl ReadDir {
+    /// Returns the next entry in the directory, if any
+    pub(crate) async fn next_entry(&mut self) -> io::Result<Option<DirEntry>> {
+        self.0.next().await.transpose().map(|e| e.map(DirEntry))
+    }
+}
+
+impl Stream for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0
+            .poll_next_unpin(cx)
+            .map(|e| e.map(|e| e.map(DirEntry)))
+    }
+}
+
+/// Entry in a directory returned by [`ReadDir`]
+pub(crate) struct DirEntry(std::fs::DirEntry);
+
+impl DirEntry {
+    /// Returns the full path of the entry
+    pub(crate) fn path(&self) -> PathBuf {
+        self.0.path()
+    }
+
+    /// Returns the file name of the entry
+    pub(crate) fn file_name(&self) -> OsString {
+        self.0.file_name()
+    }
+
+    /// Returns the file type of the entry, which does not follow symlinks. The file type is
+    /// usually known from reading the directory, so this rarely blocks.
+    pub(crate) fn file_type(&self) -> io::Result<FileType> {
+        self.0.file_type()
+    }
+
+    /// Returns the metadata of the entry, which does not follow symlinks
+    pub(crate) async fn metadata(&self) -> io::Result<Metadata> {
+        let path = self.path();
+        unblock(move || std::fs::symlink_metadata(path)).await
+    }
+}
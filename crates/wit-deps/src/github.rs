@@ -0,0 +1,493 @@
+use crate::fetch::{Request, Response};
+use crate::license::is_license_file;
+use crate::Fetch;
+
+use std::env;
+
+use anyhow::{bail, ensure, Context as _};
+use async_compression::futures::write::GzipEncoder;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::io::AsyncWriteExt as _;
+use futures::{stream, StreamExt as _, TryStreamExt as _};
+use serde::Deserialize;
+use tracing::{debug, trace};
+use url::Url;
+
+/// GitHub REST API URL used unless overridden by `GITHUB_API_URL`
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
+/// Directory containing WIT definitions fetched if not specified by the tree URL
+const DEFAULT_PATH: &str = "wit";
+
+/// Version of the GitHub REST API requests are made against
+const API_VERSION: &str = "2022-11-28";
+
+/// Value of the `User-Agent` header, which is required by the GitHub REST API
+const USER_AGENT: &str = concat!("wit-deps/", env!("CARGO_PKG_VERSION"));
+
+/// Maximum number of files fetched concurrently from a single tree
+const BLOB_CONCURRENCY: usize = 8;
+
+/// Directory within a GitHub repository at a revision, parsed from a tree URL like
+/// `https://github.com/WebAssembly/wasi-io/tree/v0.2.0/wit`
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct Tree<'a> {
+    /// Owner of the repository
+    pub owner: &'a str,
+    /// Name of the repository
+    pub repo: &'a str,
+    /// Branch, tag or commit, which cannot contain `/`
+    pub rev: &'a str,
+    /// Path of the directory within the repository
+    pub path: String,
+}
+
+impl<'a> Tree<'a> {
+    /// Parses a GitHub tree URL, the path defaults to `wit`
+    pub fn parse(url: &'a Url) -> Option<Self> {
+        if url.scheme() != "https" || url.host_str() != Some("github.com") {
+            return None;
+        }
+        let mut segments = url.path_segments()?.filter(|s| !s.is_empty());
+        let owner = segments.next()?;
+        let repo = segments.next()?;
+        if segments.next()? != "tree" {
+            return None;
+        }
+        let rev = segments.next()?;
+        let path = segments.collect::<Vec<_>>().join("/");
+        let path = if path.is_empty() {
+            DEFAULT_PATH.into()
+        } else {
+            path
+        };
+        Some(Self {
+            owner,
+            repo,
+            rev,
+            path,
+        })
+    }
+}
+
+/// Git tree returned by the GitHub REST API
+#[derive(Debug, Deserialize)]
+struct TreeResponse {
+    tree: Vec<TreeEntry>,
+    #[serde(default)]
+    truncated: bool,
+}
+
+/// Entry of a [`TreeResponse`]
+#[derive(Debug, Deserialize)]
+struct TreeEntry {
+    path: String,
+    mode: String,
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+}
+
+/// [Fetch] implementation, which fetches only the directory referenced by a GitHub tree URL
+/// using the GitHub REST API and delegates all other requests to the wrapped [Fetch].
+///
+/// Files of the directory, including transitive dependencies in `deps`, and license files at the
+/// root of the repository are returned as a deterministic gzipped tarball, which contains them
+/// in a `wit` directory and records the resolved commit in the pax global header, like archives
+/// produced by GitHub. The resolved commit is used as `ETag`.
+pub(crate) struct GitHub<'a> {
+    fetch: &'a dyn Fetch,
+    api: String,
+    token: Option<String>,
+}
+
+impl<'a> GitHub<'a> {
+    /// Wraps `fetch` using API at `api` authenticating using `token`, if specified
+    pub fn new(fetch: &'a dyn Fetch, api: impl Into<String>, token: Option<String>) -> Self {
+        Self {
+            fetch,
+            api: api.into(),
+            token,
+        }
+    }
+
+    /// Wraps `fetch` using API at `GITHUB_API_URL`, if set, authenticating using `GITHUB_TOKEN`
+    /// or `GH_TOKEN`, if set
+    pub fn from_env(fetch: &'a dyn Fetch) -> Self {
+        let var = |name| env::var(name).ok().filter(|v| !v.is_empty());
+        let api = var("GITHUB_API_URL").unwrap_or_else(|| DEFAULT_API_URL.into());
+        let token = var("GITHUB_TOKEN").or_else(|| var("GH_TOKEN"));
+        Self::new(fetch, api, token)
+    }
+
+    /// Sends a `GET` request to API endpoint `path` accepting media type `accept` and returns
+    /// the response body
+    async fn api_get(&self, path: &str, accept: &str) -> anyhow::Result<Vec<u8>> {
+        let url: Url = format!("{}/{path}", self.api.trim_end_matches('/'))
+            .parse()
+            .context("failed to construct GitHub API URL")?;
+        let authorization = self.token.as_ref().map(|token| format!("Bearer {token}"));
+        let mut headers = vec![
+            ("accept", accept),
+            ("user-agent", USER_AGENT),
+            ("x-github-api-version", API_VERSION),
+        ];
+        if let Some(authorization) = &authorization {
+            headers.push(("authorization", authorization));
+        }
+        trace!("GET `{url}`");
+        let res = self
+            .fetch
+            .get_with_headers(&url, &headers)
+            .await
+            .context(Request("GET"))?;
+        let res = match res.error_for_status() {
+            Ok(res) => res,
+            Err(e) if self.token.is_none() && matches!(e.status, 401 | 403 | 404) => {
+                return Err(anyhow::Error::new(e).context(
+                    "GitHub API request failed, set `GITHUB_TOKEN` to access private repositories or raise the rate limit",
+                ))
+            }
+            Err(e) => return Err(anyhow::Error::new(e).context("GitHub API request failed")),
+        };
+        res.body
+            .try_fold(Vec::default(), |mut buf, chunk| async move {
+                buf.extend_from_slice(&chunk);
+                Ok(buf)
+            })
+            .await
+            .context("failed to read GitHub API response")
+    }
+
+    /// Resolves the revision of `tree` to a commit
+    async fn commit(&self, tree: &Tree<'_>) -> anyhow::Result<String> {
+        let Tree {
+            owner, repo, rev, ..
+        } = tree;
+        let commit = self
+            .api_get(
+                &format!("repos/{owner}/{repo}/commits/{rev}"),
+                "application/vnd.github.sha",
+            )
+            .await
+            .with_context(|| format!("failed to resolve `{rev}` of `{owner}/{repo}`"))?;
+        let commit = String::from_utf8(commit).context("commit is not valid UTF-8")?;
+        let commit = commit.trim();
+        ensure!(
+            !commit.is_empty() && commit.chars().all(|c| c.is_ascii_hexdigit()),
+            "invalid commit `{commit}`"
+        );
+        Ok(commit.into())
+    }
+
+    /// Fetches files of `tree` and returns the resolved commit along with a gzipped tarball
+    /// containing them
+    async fn tar_gz(&self, tree: &Tree<'_>) -> anyhow::Result<(String, Vec<u8>)> {
+        let Tree {
+            owner, repo, path, ..
+        } = tree;
+        let commit = self.commit(tree).await?;
+        let res = self
+            .api_get(
+                &format!("repos/{owner}/{repo}/git/trees/{commit}?recursive=1"),
+                "application/vnd.github+json",
+            )
+            .await
+            .with_context(|| format!("failed to list tree of `{owner}/{repo}` at `{commit}`"))?;
+        let TreeResponse { tree, truncated } =
+            serde_json::from_slice(&res).context("failed to decode tree")?;
+        if truncated {
+            bail!("tree of `{owner}/{repo}` at `{commit}` is too large to be listed, use an archive URL instead")
+        }
+        let prefix = format!("{repo}-{commit}");
+        let files: Vec<_> = tree
+            .into_iter()
+            .filter(|TreeEntry { kind, mode, .. }| {
+                kind == "blob" && matches!(mode.as_str(), "100644" | "100755")
+            })
+            .filter_map(|entry| {
+                let name = match entry.path.strip_prefix(path.as_str()) {
+                    Some(rest) => format!("{prefix}/{DEFAULT_PATH}/{}", rest.strip_prefix('/')?),
+                    None if !entry.path.contains('/') && is_license_file(&entry.path) => {
+                        format!("{prefix}/{}", entry.path)
+                    }
+                    None => return None,
+                };
+                Some((name, entry))
+            })
+            .collect();
+        if files.is_empty() {
+            bail!("`{path}` of `{owner}/{repo}` at `{commit}` does not contain any files")
+        }
+        debug!(
+            "fetch {} files of `{path}` of `{owner}/{repo}` at `{commit}`",
+            files.len()
+        );
+        let files: Vec<_> = stream::iter(files)
+            .map(|(name, TreeEntry { mode, sha, .. })| async move {
+                let data = self
+                    .api_get(
+                        &format!("repos/{owner}/{repo}/git/blobs/{sha}"),
+                        "application/vnd.github.raw+json",
+                    )
+                    .await
+                    .with_context(|| format!("failed to fetch `{name}`"))?;
+                anyhow::Ok((name, mode == "100755", data))
+            })
+            .buffered(BLOB_CONCURRENCY)
+            .try_collect()
+            .await?;
+
+        let mut tar = async_tar::Builder::new(Vec::default());
+        let comment = pax_record("comment", &commit);
+        let mut header = async_tar::Header::new_ustar();
+        header.set_entry_type(async_tar::EntryType::XGlobalHeader);
+        header.set_size(comment.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+        tar.append_data(&mut header, "pax_global_header", comment.as_bytes())
+            .await
+            .context("failed to append pax global header")?;
+        for (name, executable, data) in files {
+            let mut header = async_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(if executable { 0o755 } else { 0o644 });
+            header.set_mtime(0);
+            header.set_cksum();
+            tar.append_data(&mut header, &name, data.as_slice())
+                .await
+                .with_context(|| format!("failed to append `{name}`"))?;
+        }
+        let tar = tar.into_inner().await.context("failed to finish archive")?;
+        let mut tar_gz = GzipEncoder::new(Vec::default());
+        tar_gz
+            .write_all(&tar)
+            .await
+            .context("failed to compress archive")?;
+        tar_gz.close().await.context("failed to compress archive")?;
+        Ok((commit, tar_gz.into_inner()))
+    }
+}
+
+/// Returns a pax extended header record, which is formatted as `<length> <key>=<value>\n`,
+/// where `<length>` is the length of the whole record including itself
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = format!(" {key}={value}\n");
+    let mut len = rest.len();
+    while len.to_string().len() + rest.len() != len {
+        len += 1;
+    }
+    format!("{len}{rest}")
+}
+
+#[async_trait]
+impl Fetch for GitHub<'_> {
+    async fn get(&self, url: &Url) -> anyhow::Result<Response> {
+        let Some(tree) = Tree::parse(url) else {
+            return self.fetch.get(url).await;
+        };
+        let (commit, tar_gz) = self
+            .tar_gz(&tree)
+            .await
+            .with_context(|| format!("failed to fetch `{url}` using GitHub API"))?;
+        let len = tar_gz.len().try_into().ok();
+        Ok(Response::new(200, url.clone())
+            .with_etag(commit)
+            .with_body(stream::iter([Ok(Bytes::from(tar_gz))]), len))
+    }
+
+    async fn head(&self, url: &Url) -> anyhow::Result<Response> {
+        let Some(tree) = Tree::parse(url) else {
+            return self.fetch.head(url).await;
+        };
+        let commit = self.commit(&tree).await?;
+        Ok(Response::new(200, url.clone()).with_etag(commit))
+    }
+
+    async fn get_with_headers(
+        &self,
+        url: &Url,
+        headers: &[(&str, &str)],
+    ) -> anyhow::Result<Response> {
+        if Tree::parse(url).is_some() {
+            return self.get(url).await;
+        }
+        self.fetch.get_with_headers(url, headers).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{untar_archive, ArchiveLayout, MemoryVfs};
+
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    use async_compression::futures::bufread::GzipDecoder;
+    use futures::io::BufReader;
+
+    const COMMIT: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    /// Stub of the GitHub REST API recording authorization headers of requests
+    struct Stub {
+        responses: BTreeMap<String, Vec<u8>>,
+        authorization: Mutex<BTreeSet<Option<String>>>,
+    }
+
+    #[async_trait]
+    impl Fetch for Stub {
+        async fn get(&self, url: &Url) -> anyhow::Result<Response> {
+            self.get_with_headers(url, &[]).await
+        }
+
+        async fn head(&self, url: &Url) -> anyhow::Result<Response> {
+            Ok(Response::new(405, url.clone()))
+        }
+
+        async fn get_with_headers(
+            &self,
+            url: &Url,
+            headers: &[(&str, &str)],
+        ) -> anyhow::Result<Response> {
+            ensure!(headers.contains(&("user-agent", USER_AGENT)));
+            self.authorization.lock().expect("lock poisoned").insert(
+                headers.iter().find_map(|(name, value)| {
+                    (*name == "authorization").then(|| value.to_string())
+                }),
+            );
+            let Some(body) = self.responses.get(url.as_str()) else {
+                return Ok(Response::new(404, url.clone()));
+            };
+            Ok(Response::new(200, url.clone())
+                .with_body(stream::iter([Ok(Bytes::from(body.clone()))]), None))
+        }
+    }
+
+    #[test]
+    fn tree() -> anyhow::Result<()> {
+        let url = "https://github.com/WebAssembly/wasi-io/tree/v0.2.0".parse()?;
+        assert_eq!(
+            Tree::parse(&url),
+            Some(Tree {
+                owner: "WebAssembly",
+                repo: "wasi-io",
+                rev: "v0.2.0",
+                path: "wit".into(),
+            })
+        );
+        let url = "https://github.com/WebAssembly/wasi/tree/main/proposals/io/wit/".parse()?;
+        assert_eq!(
+            Tree::parse(&url).map(|tree| tree.path),
+            Some("proposals/io/wit".into())
+        );
+        for url in [
+            "https://github.com/WebAssembly/wasi-io/archive/refs/tags/v0.2.0.tar.gz",
+            "https://github.com/WebAssembly/wasi-io",
+            "https://example.com/WebAssembly/wasi-io/tree/v0.2.0",
+        ] {
+            assert_eq!(Tree::parse(&url.parse()?), None, "{url}");
+        }
+        assert_eq!(
+            pax_record("comment", COMMIT),
+            format!("52 comment={COMMIT}\n")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fetch_tree() -> anyhow::Result<()> {
+        let api = "https://api.example.com/repos/WebAssembly/wasi-io";
+        let tree = serde_json::json!({
+            "sha": "tree",
+            "truncated": false,
+            "tree": [
+                { "path": "LICENSE", "mode": "100644", "type": "blob", "sha": "license" },
+                { "path": "README.md", "mode": "100644", "type": "blob", "sha": "readme" },
+                { "path": "wit", "mode": "040000", "type": "tree", "sha": "wit" },
+                { "path": "wit/streams.wit", "mode": "100644", "type": "blob", "sha": "streams" },
+                { "path": "wit/deps/poll/poll.wit", "mode": "100644", "type": "blob", "sha": "poll" },
+                { "path": "wit-extra/extra.wit", "mode": "100644", "type": "blob", "sha": "extra" },
+            ],
+        });
+        let stub = Stub {
+            responses: BTreeMap::from([
+                (
+                    format!("{api}/commits/v0.2.0"),
+                    format!("{COMMIT}\n").into(),
+                ),
+                (
+                    format!("{api}/git/trees/{COMMIT}?recursive=1"),
+                    serde_json::to_vec(&tree)?,
+                ),
+                (
+                    format!("{api}/git/blobs/license"),
+                    b"SPDX-License-Identifier: MIT".to_vec(),
+                ),
+                (
+                    format!("{api}/git/blobs/streams"),
+                    b"package wasi:io@0.2.0;".to_vec(),
+                ),
+                (
+                    format!("{api}/git/blobs/poll"),
+                    b"package wasi:poll@0.2.0;".to_vec(),
+                ),
+            ]),
+            authorization: Mutex::default(),
+        };
+        let github = GitHub::new(&stub, "https://api.example.com/", Some("secret".into()));
+
+        let url: Url = "https://github.com/WebAssembly/wasi-io/tree/v0.2.0/wit".parse()?;
+        let res = github.get(&url).await?;
+        assert_eq!(res.etag.as_deref(), Some(COMMIT));
+        let tar_gz: Vec<u8> = res
+            .body
+            .try_fold(Vec::default(), |mut buf, chunk| async move {
+                buf.extend_from_slice(&chunk);
+                Ok(buf)
+            })
+            .await?;
+        let vfs = MemoryVfs::default();
+        let untared = untar_archive(
+            &vfs,
+            GzipDecoder::new(BufReader::new(tar_gz.as_slice())),
+            "/deps/io",
+            Some(Path::new("/deps")),
+            &BTreeSet::default(),
+            &ArchiveLayout::default(),
+        )
+        .await?;
+        assert_eq!(untared.commit.as_deref(), Some(COMMIT));
+        assert_eq!(
+            untared.deps.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["poll"]
+        );
+        assert_eq!(untared.licenses.into_iter().collect::<Vec<_>>(), ["MIT"]);
+        assert_eq!(
+            vfs.file("/deps/io/streams.wit").as_deref(),
+            Some(b"package wasi:io@0.2.0;".as_slice())
+        );
+        assert_eq!(
+            vfs.file("/deps/poll/poll.wit").as_deref(),
+            Some(b"package wasi:poll@0.2.0;".as_slice())
+        );
+        assert_eq!(github.head(&url).await?.etag.as_deref(), Some(COMMIT));
+        assert_eq!(
+            *stub.authorization.lock().expect("lock poisoned"),
+            BTreeSet::from([Some("Bearer secret".into())])
+        );
+
+        let missing: Url = "https://github.com/WebAssembly/wasi-io/tree/v0.3.0/wit".parse()?;
+        let err = GitHub::new(&stub, "https://api.example.com", None)
+            .get(&missing)
+            .await
+            .err()
+            .context("missing revision fetched")?;
+        assert!(format!("{err:#}").contains("GITHUB_TOKEN"), "{err:#}");
+        Ok(())
+    }
+}
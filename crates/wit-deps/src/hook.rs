@@ -0,0 +1,60 @@
+use crate::{Identifier, LockEntrySource};
+
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+/// Asynchronous callbacks invoked before and after each dependency is installed into `deps`,
+/// see [`LockOptions::with_hook`](crate::LockOptions::with_hook).
+///
+/// Hooks are only invoked for dependencies, which are fetched, copied or restored from cache,
+/// i.e. not for up-to-date ones. An error returned by a hook fails locking of the dependency.
+#[async_trait]
+pub trait Hook: Send + Sync {
+    /// Called before dependency `id` is installed from `source` into directory at `path`.
+    /// Note, that installation may still fail afterwards.
+    async fn before_install(
+        &self,
+        id: &Identifier,
+        source: &LockEntrySource,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        let _ = (id, source, path);
+        Ok(())
+    }
+
+    /// Called after dependency `id` is installed from `source` into directory at `path` and
+    /// before its digest is computed, such that changes made by the hook are reflected in the
+    /// lock
+    async fn after_install(
+        &self,
+        id: &Identifier,
+        source: &LockEntrySource,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        let _ = (id, source, path);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Hook + ?Sized> Hook for Arc<T> {
+    async fn before_install(
+        &self,
+        id: &Identifier,
+        source: &LockEntrySource,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        (**self).before_install(id, source, path).await
+    }
+
+    async fn after_install(
+        &self,
+        id: &Identifier,
+        source: &LockEntrySource,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        (**self).after_install(id, source, path).await
+    }
+}
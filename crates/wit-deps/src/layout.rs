@@ -0,0 +1,106 @@
+use crate::{recreate_dir, remove_dir_all_in, Vfs, VfsFile};
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::trace;
+
+/// Name of the directory within `deps`, which transitive dependencies are unpacked into before
+/// being moved to their final location according to a non-[flat](Layout::Flat) [Layout]
+pub(crate) const STAGING: &str = ".wit-deps-staging";
+
+/// Naming of dependency directories within `deps`, see
+/// [`Manifest::with_layout`](crate::Manifest::with_layout)
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Direct dependencies are installed in directories named by their identifiers, transitive
+    /// dependencies in directories named like the directory they are shipped in, e.g. `io`
+    #[default]
+    Flat,
+    /// Dependencies are installed in directories named by the namespace, name and version of
+    /// the WIT package they declare, e.g. `wasi-io-0.2.0`
+    Versioned,
+    /// Dependencies are installed in directories named by the fully-qualified WIT package they
+    /// declare, e.g. `wasi:io@0.2.0`. Such directory names are not valid on Windows
+    Package,
+}
+
+impl Layout {
+    /// Returns the name of the directory dependency `id` declaring WIT package `package`, if
+    /// known, is installed in
+    #[must_use]
+    pub fn dir_name(self, id: &str, package: Option<&str>) -> String {
+        match (self, package) {
+            (Self::Flat, _) | (_, None) => id.into(),
+            (Self::Versioned, Some(package)) => package.replace([':', '/', '@'], "-"),
+            (Self::Package, Some(package)) => package.replace('/', "-"),
+        }
+    }
+}
+
+/// Reads all files, which are not directories, within directory at `path` in `vfs`
+async fn read_files(vfs: &dyn Vfs, path: &Path) -> std::io::Result<Vec<(OsString, VfsFile)>> {
+    let mut files = Vec::default();
+    for name in vfs.read_dir(path).await? {
+        let file = vfs.read(&path.join(&name)).await?;
+        files.push((name, file));
+    }
+    Ok(files)
+}
+
+/// Moves directories within `vfs` from source to destination paths in `moves`. All sources are
+/// read before any of them is removed, such that a source may be the destination of another
+/// move.
+pub(crate) async fn move_dirs(vfs: &dyn Vfs, moves: &[(PathBuf, PathBuf)]) -> std::io::Result<()> {
+    let mut dirs = Vec::with_capacity(moves.len());
+    for (src, dst) in moves {
+        let files = read_files(vfs, src).await.map_err(|e| {
+            std::io::Error::new(e.kind(), format!("failed to read `{}`: {e}", src.display()))
+        })?;
+        dirs.push((src, dst, files));
+    }
+    for (src, ..) in &dirs {
+        match remove_dir_all_in(vfs, src).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    for (src, dst, files) in dirs {
+        recreate_dir(vfs, dst).await?;
+        for (name, file) in files {
+            let path = dst.join(name);
+            vfs.write(&path, file).await.map_err(|e| {
+                std::io::Error::new(
+                    e.kind(),
+                    format!("failed to write `{}`: {e}", path.display()),
+                )
+            })?;
+        }
+        trace!("moved `{}` to `{}`", src.display(), dst.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dir_name() {
+        let id = "io";
+        assert_eq!(Layout::Flat.dir_name(id, Some("wasi:io@0.2.0")), "io");
+        assert_eq!(
+            Layout::Versioned.dir_name(id, Some("wasi:io@0.2.0")),
+            "wasi-io-0.2.0"
+        );
+        assert_eq!(Layout::Versioned.dir_name(id, Some("wasi:io")), "wasi-io");
+        assert_eq!(
+            Layout::Package.dir_name(id, Some("wasi:io@0.2.0")),
+            "wasi:io@0.2.0"
+        );
+        assert_eq!(Layout::Package.dir_name(id, None), "io");
+    }
+}
@@ -4,28 +4,187 @@
 #![warn(clippy::pedantic)]
 #![warn(missing_docs)]
 
+mod archive;
+mod backup;
+#[cfg(feature = "sync")]
+pub mod blocking;
+#[cfg(feature = "sync")]
+pub mod build;
 mod cache;
+mod cargo;
+#[cfg(feature = "check")]
+mod changes;
+#[cfg(feature = "check")]
+mod check;
+mod diagnostic;
 mod digest;
+mod error;
+mod fetch;
+mod fs;
+mod github;
+mod hook;
+mod layout;
+mod license;
 mod lock;
 mod manifest;
+mod observer;
+mod options;
+mod package;
+mod registry;
+mod resolver;
+mod sbom;
+#[cfg(feature = "signing")]
+mod signature;
+pub mod telemetry;
+mod tempdir;
+mod vfs;
+mod wkg;
+mod workspace;
 
-pub use cache::{Cache, Local as LocalCache, Write as WriteCache};
-pub use digest::{Digest, Reader as DigestReader, Writer as DigestWriter};
-pub use lock::{Entry as LockEntry, EntrySource as LockEntrySource, Lock};
-pub use manifest::{Entry as ManifestEntry, Manifest};
+pub use archive::Layout as ArchiveLayout;
+pub use cache::{
+    tee_into, Cache, Counters as CacheCounters, GcPolicy as CacheGcPolicy, GcStats as CacheGcStats,
+    IndexEntry as CacheIndexEntry, Local as LocalCache, LocalWriter as LocalCacheWriter,
+    Memory as MemoryCache, MemoryWriter as MemoryCacheWriter, Policy as CachePolicy,
+    Stats as CacheStats, Tee as CacheTee, VerifyStats as CacheVerifyStats,
+};
+#[cfg(feature = "reqwest")]
+pub use cache::{Http as HttpCache, HttpWriter as HttpCacheWriter};
+#[cfg(feature = "s3")]
+pub use cache::{S3Writer as S3CacheWriter, S3 as S3Cache};
+pub use cargo::{
+    cargo_component_dependencies_path, cargo_component_drift_path, manifest_path,
+    CargoComponentDrift,
+};
+#[cfg(feature = "check")]
+pub use changes::{
+    wit_changes_path, Category as WitChangeCategory, Change as WitChange, Kind as WitChangeKind,
+};
+#[cfg(feature = "bundle")]
+pub use check::bundle_path;
+#[cfg(feature = "check")]
+pub use check::check_path;
+pub use diagnostic::{Diagnostic as WitDiagnostic, Location as WitLocation};
+pub use digest::{
+    digest_dir, digest_reader, Digest, DigestPolicy, Mismatch as DigestMismatch,
+    Reader as DigestReader, Writer as DigestWriter,
+};
+pub use error::Error;
+#[cfg(feature = "reqwest")]
+pub use fetch::Reqwest as ReqwestFetch;
+pub use fetch::{Fetch, Response as FetchResponse};
+pub use hook::Hook as InstallHook;
+pub use layout::Layout as DepsLayout;
+pub use license::Policy as LicensePolicy;
+pub use lock::{
+    AuditStatus as LockAuditStatus, Diff as LockDiff, Divergence as LockDivergence,
+    Entry as LockEntry, EntryChange as LockEntryChange, EntrySource as LockEntrySource,
+    EntrySourceResolution as LockEntrySourceResolution, Graph as LockGraph,
+    GraphEdge as LockGraphEdge, GraphNode as LockGraphNode, ListEntry as LockListEntry, Lock,
+    Outcome as LockOutcome, TreeNode as LockTreeNode,
+};
+pub use manifest::{Entry as ManifestEntry, LockFailure, Manifest, OutOfSync, Plan, PlannedAction};
+pub use observer::{Event as LockEvent, Observer};
+pub use options::LockOptions;
+pub use registry::Registry;
+pub use resolver::{Resolved, Resolver};
+pub use sbom::{sbom_path, Format as SbomFormat, Sbom};
+#[cfg(feature = "signing")]
+pub use signature::{
+    read_signing_key, read_verifying_key, sign_lock_path, signature_path,
+    verify_lock_signature_path,
+};
+pub use telemetry::TIMINGS as TIMINGS_TARGET;
+pub use tempdir::TempDir;
+pub use vfs::{File as VfsFile, Memory as MemoryVfs, Os as OsVfs, Vfs};
+pub use wkg::{wkg_lock_path, Config as WkgConfig};
+pub use workspace::{
+    workspace_members, Config as WorkspaceConfig, Workspace, CONFIG as WORKSPACE_CONFIG,
+};
 
+#[cfg(feature = "signing")]
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+pub use bytes;
 pub use futures;
-pub use tokio;
+pub use tokio_util::sync::CancellationToken;
+
+/// Items used by macros exported by this crate, which are not part of the public API
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::fs::{read_to_string, write};
 
-use std::collections::{BTreeSet, HashMap, HashSet};
-use std::ffi::{OsStr, OsString};
+    #[cfg(feature = "sync")]
+    pub use tokio;
+
+    use std::io::{Error, ErrorKind};
+    use std::path::Path;
+
+    /// Locks dependencies of TOML-encoded `manifest` into `deps` using the lock at `lock_path`,
+    /// which is written if it was updated and `write` is `true`, see [`lock`](crate::lock!)
+    pub async fn lock(
+        at: Option<&Path>,
+        manifest: impl AsRef<str>,
+        lock_path: &Path,
+        deps: &Path,
+        write: bool,
+    ) -> std::io::Result<()> {
+        let lock = match read_to_string(lock_path).await {
+            Ok(lock) => Some(lock),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(Error::new(
+                    e.kind(),
+                    format!("failed to read lock at `{}`: {e}", lock_path.display()),
+                ))
+            }
+        };
+        match crate::lock(at, manifest, lock, deps, &crate::LockOptions::default()).await {
+            Ok(Some(lock)) if write => crate::fs::write(lock_path, lock).await.map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("failed to write lock at `{}`: {e}", lock_path.display()),
+                )
+            }),
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::other(e)),
+        }
+    }
+
+    /// Like [lock], but reads the manifest from `manifest_path` at runtime
+    pub async fn lock_path(
+        manifest_path: &Path,
+        lock_path: &Path,
+        deps: &Path,
+        write: bool,
+    ) -> std::io::Result<()> {
+        let manifest = read_to_string(manifest_path).await.map_err(|e| {
+            Error::new(
+                e.kind(),
+                format!(
+                    "failed to read manifest at `{}`: {e}",
+                    manifest_path.display()
+                ),
+            )
+        })?;
+        lock(manifest_path.parent(), manifest, lock_path, deps, write).await
+    }
+}
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::mem;
 use std::path::{Path, PathBuf};
 
-use anyhow::Context;
-use futures::{try_join, AsyncRead, AsyncWrite, FutureExt, Stream, TryStreamExt};
-use tokio::fs;
-use tokio_stream::wrappers::ReadDirStream;
-use tracing::{debug, instrument, trace};
+use ::blocking::unblock;
+use anyhow::{anyhow, bail, Context};
+use futures::{
+    try_join, AsyncRead, AsyncReadExt, AsyncWrite, Stream, StreamExt as _, TryStreamExt,
+};
+use tracing::{debug, error, info, instrument, trace, warn};
+
+use crate::cargo::{cargo_metadata, is_cargo_manifest};
+use crate::error::InvalidManifest;
 
 /// WIT dependency identifier
 pub type Identifier = String;
@@ -41,8 +200,13 @@ fn is_wit(path: impl AsRef<Path>) -> bool {
 
 #[instrument(level = "trace", skip(path))]
 async fn remove_dir_all(path: impl AsRef<Path>) -> std::io::Result<()> {
+    remove_dir_all_in(&OsVfs, path).await
+}
+
+#[instrument(level = "trace", skip(vfs, path))]
+async fn remove_dir_all_in(vfs: &dyn Vfs, path: impl AsRef<Path>) -> std::io::Result<()> {
     let path = path.as_ref();
-    match fs::remove_dir_all(path).await {
+    match vfs.remove_dir_all(path).await {
         Ok(()) => {
             trace!("removed `{}`", path.display());
             Ok(())
@@ -54,15 +218,15 @@ async fn remove_dir_all(path: impl AsRef<Path>) -> std::io::Result<()> {
     }
 }
 
-#[instrument(level = "trace", skip(path))]
-async fn recreate_dir(path: impl AsRef<Path>) -> std::io::Result<()> {
+#[instrument(level = "trace", skip(vfs, path))]
+async fn recreate_dir(vfs: &dyn Vfs, path: impl AsRef<Path>) -> std::io::Result<()> {
     let path = path.as_ref();
-    match remove_dir_all(path).await {
+    match remove_dir_all_in(vfs, path).await {
         Ok(()) => {}
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
         Err(e) => return Err(e),
     };
-    fs::create_dir_all(path)
+    vfs.create_dir_all(path)
         .await
         .map(|()| trace!("recreated `{}`", path.display()))
         .map_err(|e| {
@@ -79,22 +243,19 @@ async fn read_wits(
     path: impl AsRef<Path>,
 ) -> std::io::Result<impl Stream<Item = std::io::Result<OsString>>> {
     let path = path.as_ref();
-    let st = fs::read_dir(path)
-        .await
-        .map(ReadDirStream::new)
-        .map_err(|e| {
-            std::io::Error::new(
-                e.kind(),
-                format!("failed to read directory at `{}`: {e}", path.display()),
-            )
-        })?;
+    let st = fs::read_dir(path).await.map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            format!("failed to read directory at `{}`: {e}", path.display()),
+        )
+    })?;
     Ok(st.try_filter_map(|e| async move {
         let name = e.file_name();
         if !is_wit(&name) {
             trace!("{} is not a WIT definition, skip", name.to_string_lossy());
             return Ok(None);
         }
-        if e.file_type().await?.is_dir() {
+        if e.file_type()?.is_dir() {
             trace!("{} is a directory, skip", name.to_string_lossy());
             return Ok(None);
         }
@@ -102,20 +263,25 @@ async fn read_wits(
     }))
 }
 
-/// Copies all WIT definitions from directory at `src` to `dst` creating `dst` directory, if it does not exist.
-#[instrument(level = "trace", skip(src, dst))]
-async fn install_wits(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::Result<()> {
+/// Copies all WIT definitions from directory at `src` to `dst` within `vfs` creating `dst`
+/// directory, if it does not exist.
+#[instrument(level = "trace", skip(vfs, src, dst))]
+async fn install_wits(
+    vfs: &dyn Vfs,
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+) -> std::io::Result<()> {
     let src = src.as_ref();
     let dst = dst.as_ref();
-    recreate_dir(dst).await?;
+    recreate_dir(vfs, dst).await?;
     read_wits(src)
         .await?
         .try_for_each_concurrent(None, |name| async {
             let src = src.join(&name);
             let dst = dst.join(name);
-            fs::copy(&src, &dst)
+            vfs.copy(&src, &dst)
                 .await
-                .map(|_| trace!("copied `{}` to `{}`", src.display(), dst.display()))
+                .map(|()| trace!("copied `{}` to `{}`", src.display(), dst.display()))
                 .map_err(|e| {
                     std::io::Error::new(
                         e.kind(),
@@ -130,47 +296,47 @@ async fn install_wits(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::
         .await
 }
 
-/// Copies all WIT files from directory at `src` to `dst` and returns a vector identifiers of all copied
+/// Copies all WIT files from directory at `src` to `dst` and transitive dependencies to
+/// `dst_deps`, if specified, within `vfs` and returns a vector identifiers of all copied
 /// transitive dependencies.
-#[instrument(level = "trace", skip(src, dst, skip_deps))]
+#[instrument(level = "trace", skip(vfs, src, dst, dst_deps, skip_deps))]
 async fn copy_wits(
+    vfs: &dyn Vfs,
     src: impl AsRef<Path>,
     dst: impl AsRef<Path>,
-    skip_deps: &HashSet<Identifier>,
-) -> std::io::Result<HashMap<Identifier, PathBuf>> {
+    dst_deps: Option<&Path>,
+    skip_deps: &BTreeSet<Identifier>,
+) -> std::io::Result<BTreeMap<Identifier, PathBuf>> {
     let src = src.as_ref();
     let deps = src.join("deps");
     let dst = dst.as_ref();
-    try_join!(install_wits(src, dst), async {
-        match (dst.parent(), fs::read_dir(&deps).await) {
+    try_join!(install_wits(vfs, src, dst), async {
+        match (dst_deps, fs::read_dir(&deps).await) {
             (Some(base), Ok(dir)) => {
-                ReadDirStream::new(dir)
-                    .try_filter_map(|e| async move {
-                        let name = e.file_name();
-                        let Some(id) = name.to_str().map(Identifier::from) else {
-                            return Ok(None);
-                        };
-                        if skip_deps.contains(&id) {
-                            return Ok(None);
-                        }
-                        let ft = e.file_type().await?;
-                        if !(ft.is_dir()
-                            || ft.is_symlink() && fs::metadata(e.path()).await?.is_dir())
-                        {
-                            return Ok(None);
-                        }
-                        Ok(Some(id))
-                    })
-                    .and_then(|id| async {
-                        let dst = base.join(&id);
-                        install_wits(deps.join(&id), &dst).await?;
-                        Ok((id, dst))
-                    })
-                    .try_collect()
-                    .await
+                dir.try_filter_map(|e| async move {
+                    let name = e.file_name();
+                    let Some(id) = name.to_str().map(Identifier::from) else {
+                        return Ok(None);
+                    };
+                    if skip_deps.contains(&id) {
+                        return Ok(None);
+                    }
+                    let ft = e.file_type()?;
+                    if !(ft.is_dir() || ft.is_symlink() && fs::metadata(e.path()).await?.is_dir()) {
+                        return Ok(None);
+                    }
+                    Ok(Some(id))
+                })
+                .and_then(|id| async {
+                    let dst = base.join(&id);
+                    install_wits(vfs, deps.join(&id), &dst).await?;
+                    Ok((id, dst))
+                })
+                .try_collect()
+                .await
             }
-            (None, _) => Ok(HashMap::default()),
-            (_, Err(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::default()),
+            (None, _) => Ok(BTreeMap::default()),
+            (_, Err(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::default()),
             (_, Err(e)) => Err(std::io::Error::new(
                 e.kind(),
                 format!("failed to read directory at `{}`: {e}", deps.display()),
@@ -180,22 +346,85 @@ async fn copy_wits(
     .map(|((), ids)| ids)
 }
 
-/// Unpacks all WIT interfaces found within `wit` subtree of a tar archive read from `tar` to
-/// `dst` and returns a [HashMap] of all unpacked transitive dependency identifiers.
+/// Unpacks all WIT interfaces found within the prefix subtree of a tar archive read from `tar`
+/// to `dst` according to `layout` and returns a [`BTreeMap`] of all unpacked transitive dependency
+/// identifiers.
 ///
 /// # Errors
 ///
-/// Returns and error if the operation fails
+/// Returns and error if the operation fails or if the archive contains entries, which could
+/// escape `dst`, and `layout` is strict, see [`ArchiveLayout::with_strict`]
 #[instrument(level = "trace", skip(tar, dst, skip_deps))]
 pub async fn untar(
     tar: impl AsyncRead + Unpin,
     dst: impl AsRef<Path>,
-    skip_deps: &HashSet<Identifier>,
-) -> std::io::Result<HashMap<Identifier, PathBuf>> {
+    skip_deps: &BTreeSet<Identifier>,
+    layout: &ArchiveLayout,
+) -> std::io::Result<BTreeMap<Identifier, PathBuf>> {
+    let dst = dst.as_ref();
+    untar_archive(&OsVfs, tar, dst, dst.parent(), skip_deps, layout)
+        .await
+        .map(|Untared { deps, .. }| deps)
+}
+
+/// Contents of an archive unpacked by [`untar_archive`]
+pub(crate) struct Untared {
+    /// Unpacked transitive dependencies
+    pub deps: BTreeMap<Identifier, PathBuf>,
+    /// VCS commit recorded in the archive, if any. GitHub archives record it in
+    /// the `comment` field of the pax global extended header
+    pub commit: Option<String>,
+    /// SPDX identifiers of licenses detected in license files of the package
+    pub licenses: BTreeSet<String>,
+}
+
+/// Parses the `comment` record of a pax extended header, each record of which is formatted as
+/// `<length> <key>=<value>\n`
+fn pax_comment(header: &str) -> Option<&str> {
+    header.lines().find_map(|record| {
+        let (_, kv) = record.split_once(' ')?;
+        let (k, v) = kv.split_once('=')?;
+        (k == "comment").then_some(v)
+    })
+}
+
+/// Like [untar], but unpacks into `vfs`, unpacks transitive dependencies to `dst_deps`, if
+/// specified, instead of the parent of `dst` and also returns metadata recorded in the archive
+#[instrument(level = "trace", skip(vfs, tar, dst, dst_deps, skip_deps))]
+pub(crate) async fn untar_archive(
+    vfs: &dyn Vfs,
+    tar: impl AsyncRead + Unpin,
+    dst: impl AsRef<Path>,
+    dst_deps: Option<&Path>,
+    skip_deps: &BTreeSet<Identifier>,
+    layout: &ArchiveLayout,
+) -> std::io::Result<Untared> {
     use std::io::{Error, Result};
 
-    async fn unpack(e: &mut async_tar::Entry<impl Unpin + AsyncRead>, dst: &Path) -> Result<()> {
-        e.unpack(dst).await.map_err(|e| {
+    async fn unpack(
+        vfs: &dyn Vfs,
+        e: &mut async_tar::Entry<impl Unpin + AsyncRead>,
+        dst: &Path,
+    ) -> Result<()> {
+        if !e.header().entry_type().is_file() {
+            trace!("`{}` is not a file, skip", dst.display());
+            return Ok(());
+        }
+        let executable = e.header().mode().is_ok_and(|mode| mode & 0o100 == 0o100);
+        let mut contents = Vec::default();
+        async {
+            e.read_to_end(&mut contents).await?;
+            vfs.write(
+                dst,
+                VfsFile {
+                    contents,
+                    executable,
+                },
+            )
+            .await
+        }
+        .await
+        .map_err(|e| {
             Error::new(
                 e.kind(),
                 format!("failed to unpack `{}`: {e}", dst.display()),
@@ -206,59 +435,96 @@ pub async fn untar(
     }
 
     let dst = dst.as_ref();
-    recreate_dir(dst).await?;
+    recreate_dir(vfs, dst).await?;
     async_tar::Archive::new(tar)
         .entries()
         .map_err(|e| Error::new(e.kind(), format!("failed to unpack archive metadata: {e}")))?
-        .try_fold(HashMap::default(), |mut untared, mut e| async move {
-            let path = e
-                .path()
-                .map_err(|e| Error::new(e.kind(), format!("failed to query entry path: {e}")))?;
-            let mut path = path.into_iter().map(OsStr::to_str);
-            match (
-                path.next(),
-                path.next(),
-                path.next(),
-                path.next(),
-                path.next(),
-            ) {
-                (Some(Some("wit")), Some(Some(name)), None, None, None)
-                | (Some(_), Some(Some("wit")), Some(Some(name)), None, None)
-                    if is_wit(name) =>
-                {
-                    let dst = dst.join(name);
-                    unpack(&mut e, &dst).await?;
-                    Ok(untared)
+        .try_fold(
+            Untared {
+                deps: BTreeMap::default(),
+                commit: None,
+                licenses: BTreeSet::default(),
+            },
+            |mut untared, mut e| async move {
+                if e.header().entry_type().is_pax_global_extensions() {
+                    let mut header = String::new();
+                    e.read_to_string(&mut header).await.map_err(|e| {
+                        Error::new(e.kind(), format!("failed to read pax global header: {e}"))
+                    })?;
+                    if let Some(commit) = pax_comment(&header) {
+                        trace!(commit, "found commit in pax global header");
+                        untared.commit = Some(commit.into());
+                    }
+                    return Ok(untared);
                 }
-                (Some(Some("wit")), Some(Some("deps")), Some(Some(id)), Some(Some(name)), None)
-                | (
-                    Some(_),
-                    Some(Some("wit")),
-                    Some(Some("deps")),
-                    Some(Some(id)),
-                    Some(Some(name)),
-                ) if !skip_deps.contains(id) && is_wit(name) => {
-                    let id = Identifier::from(id);
-                    if let Some(base) = dst.parent() {
-                        let dst = base.join(&id);
-                        if !untared.contains_key(&id) {
-                            recreate_dir(&dst).await?;
-                        }
-                        let wit = dst.join(name);
-                        unpack(&mut e, &wit).await?;
-                        untared.insert(id, dst);
+                let path = e.path().map_err(|e| {
+                    Error::new(e.kind(), format!("failed to query entry path: {e}"))
+                })?;
+                let link = e.link_name().map_err(|e| {
+                    Error::new(e.kind(), format!("failed to query entry link name: {e}"))
+                })?;
+                let entry_type = e.header().entry_type();
+                let entry = match link.as_deref().map(|link| Path::new(link.as_os_str())) {
+                    Some(target) if entry_type.is_symlink() => archive::Entry::Symlink(target),
+                    Some(target) if entry_type.is_hard_link() => archive::Entry::HardLink(target),
+                    _ => archive::Entry::Other,
+                };
+                if let Err(reason) = archive::check_entry(Path::new(path.as_os_str()), &entry) {
+                    if layout.strict() {
+                        return Err(Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("refusing to unpack `{}`: {reason}", path.display()),
+                        ));
+                    }
+                    warn!("skip unsafe archive entry `{}`: {reason}", path.display());
+                    return Ok(untared);
+                }
+                match layout.matches(Path::new(path.as_os_str())) {
+                    Some(archive::Match::Package(name)) => {
+                        let dst = dst.join(name);
+                        unpack(vfs, &mut e, &dst).await?;
                         Ok(untared)
-                    } else {
+                    }
+                    Some(archive::Match::Dependency(id, name)) if !skip_deps.contains(id) => {
+                        let id = Identifier::from(id);
+                        if let Some(base) = dst_deps {
+                            let dst = base.join(&id);
+                            if !untared.deps.contains_key(&id) {
+                                recreate_dir(vfs, &dst).await?;
+                            }
+                            let wit = dst.join(name);
+                            unpack(vfs, &mut e, &wit).await?;
+                            untared.deps.insert(id, dst);
+                            Ok(untared)
+                        } else {
+                            Ok(untared)
+                        }
+                    }
+                    Some(archive::Match::License(name)) => {
+                        let name = name.to_string();
+                        let mut text = String::new();
+                        match e.read_to_string(&mut text).await {
+                            Ok(_) => {
+                                if let Some(id) = license::detect(&text) {
+                                    trace!(id, "detected license in `{name}`");
+                                    untared.licenses.insert(id);
+                                } else {
+                                    debug!("failed to detect license in `{name}`");
+                                }
+                            }
+                            Err(e) => debug!("failed to read license file `{name}`: {e}"),
+                        }
                         Ok(untared)
                     }
+                    _ => Ok(untared),
                 }
-                _ => Ok(untared),
-            }
-        })
+            },
+        )
         .await
 }
 
-/// Packages path into a `wit` subtree in deterministic `tar` archive and writes it to `dst`.
+/// Packages path into a `wit` subtree in deterministic `tar` archive and writes it to `dst`
+/// using the default [`ArchiveLayout`], which is the format [`digest_dir`] is defined by.
 ///
 /// # Errors
 ///
@@ -268,57 +534,383 @@ pub async fn tar<T>(path: impl AsRef<Path>, dst: T) -> std::io::Result<T>
 where
     T: AsyncWrite + Sync + Send + Unpin,
 {
-    let path = path.as_ref();
+    tar_with_deps(path, [], dst, &ArchiveLayout::default()).await
+}
+
+/// Like [tar], but packages path into the prefix subtree of `layout` and additionally packages
+/// each of `deps`, which are paths to dependency directories keyed by identifier, into the
+/// dependency subtree of `layout`, e.g. `wit/deps/<id>` by default, such that the archive can be
+/// consumed standalone using [untar] with the same `layout`.
+///
+/// # Errors
+///
+/// Returns and error if the operation fails
+#[instrument(level = "trace", skip(path, deps, dst))]
+pub async fn tar_with_deps<T>(
+    path: impl AsRef<Path>,
+    deps: impl IntoIterator<Item = (Identifier, PathBuf)>,
+    dst: T,
+    layout: &ArchiveLayout,
+) -> std::io::Result<T>
+where
+    T: AsyncWrite + Sync + Send + Unpin,
+{
+    async fn append<T: AsyncWrite + Sync + Send + Unpin>(
+        tar: &mut async_tar::Builder<T>,
+        src: &Path,
+        dst: &Path,
+    ) -> std::io::Result<()> {
+        for name in read_wits(src).await?.try_collect::<BTreeSet<_>>().await? {
+            tar.append_path_with_name(src.join(&name), dst.join(name))
+                .await?;
+        }
+        Ok(())
+    }
+
     let mut tar = async_tar::Builder::new(dst);
     tar.mode(async_tar::HeaderMode::Deterministic);
-    for name in read_wits(path).await?.try_collect::<BTreeSet<_>>().await? {
-        tar.append_path_with_name(path.join(&name), Path::new("wit").join(name))
-            .await?;
+    append(&mut tar, path.as_ref(), layout.prefix()).await?;
+    let deps: BTreeMap<_, _> = deps.into_iter().collect();
+    for (id, path) in deps {
+        append(&mut tar, &path, &layout.dependency(&id)).await?;
     }
     tar.into_inner().await
 }
 
-fn cache() -> Option<impl Cache> {
-    LocalCache::cache_dir().map(|cache| {
-        debug!("using cache at `{cache}`");
-        cache
-    })
+/// Reads the lock at `lock_path` and packages the `package` dependency installed in `deps`
+/// along with all of its locked transitive dependencies, see [`Lock::transitive_deps`], into
+/// a deterministic `tar` archive written to `dst` according to `layout`.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing, `package` is not locked or the operation fails
+#[instrument(level = "trace", skip(lock_path, deps, dst))]
+pub async fn tar_package_path<T>(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    package: &Identifier,
+    dst: T,
+    layout: &ArchiveLayout,
+) -> Result<T, Error>
+where
+    T: AsyncWrite + Sync + Send + Unpin,
+{
+    let deps = deps.as_ref();
+    let lock = read_lock(lock_path).await?;
+    if !lock.contains_key(package) {
+        return Err(anyhow!("`{package}` is not present in the lock").into());
+    }
+    let transitive = lock
+        .transitive_deps(package)
+        .into_iter()
+        .map(|id| (id.clone(), lock.path(deps, &id)));
+    tar_with_deps(lock.path(deps, package), transitive, dst, layout)
+        .await
+        .with_context(|| format!("failed to archive `{package}`"))
+        .map_err(Into::into)
+}
+
+/// Reads the lock at `lock_path` and packages the whole WIT directory, i.e. the parent of
+/// `deps`, along with all locked dependencies installed in `deps` into a deterministic `tar`
+/// archive written to `dst` according to `layout`.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or the operation fails
+#[instrument(level = "trace", skip(lock_path, deps, dst))]
+pub async fn tar_all_path<T>(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    dst: T,
+    layout: &ArchiveLayout,
+) -> Result<T, Error>
+where
+    T: AsyncWrite + Sync + Send + Unpin,
+{
+    let deps = deps.as_ref();
+    let lock = read_lock(lock_path).await?;
+    let wit = deps
+        .parent()
+        .filter(|wit| !wit.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    tar_with_deps(
+        wit,
+        lock.keys().map(|id| (id.clone(), lock.path(deps, id))),
+        dst,
+        layout,
+    )
+    .await
+    .with_context(|| format!("failed to archive `{}`", wit.display()))
+    .map_err(Into::into)
+}
+
+/// Cache used by path-based functions, see [`cache`]
+#[cfg(feature = "reqwest")]
+type DefaultCache = HttpCache<LocalCache>;
+
+/// Cache used by path-based functions, see [`cache`]
+#[cfg(not(feature = "reqwest"))]
+type DefaultCache = LocalCache;
+
+/// Returns the [`LocalCache`] at `dir`, if specified, or the default one otherwise, optionally
+/// layered behind a remote [`HttpCache`] configured using [`HttpCache::from_env`] unless
+/// `offline` is `true` or the `reqwest` feature is disabled
+#[cfg(feature = "reqwest")]
+fn cache(dir: Option<&Path>, offline: bool) -> anyhow::Result<Option<DefaultCache>> {
+    dir.map(|dir| LocalCache::from(dir).with_env_config())
+        .or_else(LocalCache::cache_dir)
+        .map(|cache| {
+            debug!("using cache at `{cache}`");
+            if offline {
+                Ok(HttpCache::from(cache))
+            } else {
+                HttpCache::from_env(cache)
+            }
+        })
+        .transpose()
+}
+
+/// Returns the [`LocalCache`] at `dir`, if specified, or the default one otherwise
+#[cfg(not(feature = "reqwest"))]
+#[allow(clippy::unnecessary_wraps)]
+fn cache(dir: Option<&Path>, _offline: bool) -> anyhow::Result<Option<DefaultCache>> {
+    Ok(dir
+        .map(|dir| LocalCache::from(dir).with_env_config())
+        .or_else(LocalCache::cache_dir)
+        .inspect(|cache| debug!("using cache at `{cache}`")))
+}
+
+/// Returns the path of the advisory lock file guarding `deps`, which is shared by all `deps`
+/// directories within the same parent directory, see [`LocalCache::deps_lock_path`]
+fn deps_lock_path(deps: &Path) -> PathBuf {
+    let dir = deps
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    LocalCache::deps_lock_path(dir)
+}
+
+/// Acquires an exclusive advisory lock of `deps`, waiting for other processes holding it, such
+/// that concurrent mutations of `deps`, e.g. by build scripts of multiple crates sharing a `wit`
+/// directory, do not interleave. The lock is released once the returned file is dropped. The
+/// lock file is located outside of the source tree, see [`deps_lock_path`].
+async fn lock_deps_dir(deps: &Path) -> anyhow::Result<std::fs::File> {
+    let path = deps_lock_path(deps);
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("failed to create `{}`", parent.display()))?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true).truncate(false).write(true);
+    let lock = fs::open(&path, options)
+        .await
+        .with_context(|| format!("failed to open `{}`", path.display()))?
+        .into_inner()
+        .await;
+    match lock.try_lock() {
+        Ok(()) => return Ok(lock),
+        Err(std::fs::TryLockError::WouldBlock) => {
+            info!("waiting for file lock on `{}`", deps.display());
+        }
+        Err(std::fs::TryLockError::Error(e)) => {
+            return Err(e).with_context(|| format!("failed to lock `{}`", deps.display()));
+        }
+    }
+    unblock(move || lock.lock().map(|()| lock))
+        .await
+        .with_context(|| format!("failed to lock `{}`", deps.display()))
+}
+
+async fn save_cache_counters(cache: Option<&DefaultCache>) {
+    if let Some(cache) = cache {
+        if let Err(e) = cache.save_counters().await {
+            error!("failed to record cache counters: {e:#}");
+        }
+    }
 }
 
 /// Given a TOML-encoded manifest and optional TOML-encoded lock, ensures that the path pointed to by
 /// `deps` is in sync with the manifest and lock. This is a potentially destructive operation!
 /// Returns a TOML-encoded lock if the lock passed to this function was either `None` or out-of-sync.
 ///
+/// See [`LockOptions`] for available options.
+///
 /// # Errors
 ///
-/// Returns an error if anything in the pipeline fails
+/// Returns an error if anything in the pipeline fails or, if [frozen](LockOptions::with_frozen),
+/// if the lock is missing or out-of-sync
 #[instrument(level = "trace", skip(at, manifest, lock, deps))]
 pub async fn lock(
     at: Option<impl AsRef<Path>>,
     manifest: impl AsRef<str>,
     lock: Option<impl AsRef<str>>,
     deps: impl AsRef<Path>,
-) -> anyhow::Result<Option<String>> {
-    let manifest: Manifest =
-        toml::from_str(manifest.as_ref()).context("failed to decode manifest")?;
+    options: &LockOptions,
+) -> Result<Option<String>, Error> {
+    let deps = deps.as_ref();
+    let _lock = lock_deps_dir(deps).await?;
+    let outcome = lock_manifest(at, manifest, lock, deps, options).await?;
+    encode_updated(&outcome).map_err(Into::into)
+}
 
-    let old_lock = lock
+/// Like [lock](self::lock()), but fails if the lock is `None` or out-of-sync instead of
+/// returning an updated lock. The lock is never modified, but `deps` may still be populated
+/// from the lock.
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails or if the lock is missing or out-of-sync
+#[instrument(level = "trace", skip(at, manifest, lock, deps))]
+pub async fn lock_frozen(
+    at: Option<impl AsRef<Path>>,
+    manifest: impl AsRef<str>,
+    lock: Option<impl AsRef<str>>,
+    deps: impl AsRef<Path>,
+) -> Result<(), Error> {
+    let deps = deps.as_ref();
+    let _lock = lock_deps_dir(deps).await?;
+    lock_manifest(
+        at,
+        manifest,
+        lock,
+        deps,
+        &LockOptions::default().with_frozen(true),
+    )
+    .await
+    .map(|_| ())
+    .map_err(Into::into)
+}
+
+async fn lock_manifest(
+    at: Option<impl AsRef<Path>>,
+    manifest: impl AsRef<str>,
+    lock: Option<impl AsRef<str>>,
+    deps: impl AsRef<Path>,
+    options: &LockOptions,
+) -> anyhow::Result<LockOutcome> {
+    let manifest: Manifest = toml::from_str(manifest.as_ref()).context(InvalidManifest(None))?;
+
+    let old_lock: Option<Lock> = lock
         .as_ref()
         .map(AsRef::as_ref)
         .map(toml::from_str)
         .transpose()
         .context("failed to decode lock")?;
+    lock_decoded(at, manifest, old_lock, deps.as_ref(), options).await
+}
 
-    let deps = deps.as_ref();
+/// Like [`lock_manifest`], but takes a decoded manifest and lock
+async fn lock_decoded(
+    at: Option<impl AsRef<Path>>,
+    manifest: Manifest,
+    old_lock: Option<Lock>,
+    deps: &Path,
+    options: &LockOptions,
+) -> anyhow::Result<LockOutcome> {
+    let old_lock = old_lock.map(Lock::migrate);
+    let frozen = options.frozen();
+    if frozen {
+        let Some(old_lock) = old_lock.as_ref() else {
+            bail!("lock is missing and cannot be created in frozen mode");
+        };
+        // Fail early, before `deps` is modified
+        let unlocked = manifest.unlocked(old_lock);
+        if !unlocked.is_empty() {
+            bail!(
+                "lock is out-of-sync with the manifest and cannot be updated in frozen mode, out-of-sync entries: {}",
+                unlocked.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+
+    let cache = cache(options.cache_dir(), options.offline())?;
     let lock = manifest
-        .lock(at, deps, old_lock.as_ref(), cache().as_ref())
-        .await
-        .with_context(|| format!("failed to lock deps to `{}`", deps.display()))?;
+        .lock(at, deps, old_lock.as_ref(), cache.as_ref(), options)
+        .await;
+    save_cache_counters(cache.as_ref()).await;
+    let mut lock = lock.with_context(|| format!("failed to lock deps to `{}`", deps.display()))?;
+    lock.apply_digest_policy(
+        deps,
+        sticky_digest_policy(options.digests(), old_lock.as_ref()),
+    )
+    .await?;
+    finish(deps, &lock, options).await?;
+    outcome(old_lock, lock, frozen)
+}
+
+/// Returns the outcome of locking resulting in `lock` given the migrated `old_lock`, if any.
+/// Fails if `frozen` and the lock would be updated.
+fn outcome(old_lock: Option<Lock>, lock: Lock, frozen: bool) -> anyhow::Result<LockOutcome> {
     match old_lock {
-        Some(old_lock) if lock == old_lock => Ok(None),
-        _ => toml::to_string(&lock)
+        // NOTE: Neither the `wit-deps` version, which produced the lock, nor the format version
+        // invalidate the lock, since entries of older formats are migrated without changes
+        Some(old_lock) if *lock == *old_lock => Ok(LockOutcome {
+            lock,
+            diff: LockDiff::default(),
+            updated: false,
+        }),
+        _ if frozen => bail!("lock would be updated, but cannot be modified in frozen mode"),
+        old_lock => {
+            let diff = old_lock.unwrap_or_default().diff(&lock);
+            log_diff(&diff);
+            Ok(LockOutcome {
+                lock,
+                diff,
+                updated: true,
+            })
+        }
+    }
+}
+
+/// Returns the TOML-encoded lock of `outcome`, if it was updated
+fn encode_updated(outcome: &LockOutcome) -> anyhow::Result<Option<String>> {
+    if outcome.updated {
+        toml::to_string(&outcome.lock)
             .map(Some)
-            .context("failed to encode lock"),
+            .context("failed to encode lock")
+    } else {
+        Ok(None)
+    }
+}
+
+/// Prunes and validates `deps` locked by `lock` according to `options`
+async fn finish(deps: &Path, lock: &Lock, options: &LockOptions) -> anyhow::Result<()> {
+    if options.prune() {
+        lock.prune(deps).await?;
+    }
+    #[cfg(feature = "check")]
+    if options.validate() {
+        let wit = deps
+            .parent()
+            .filter(|wit| !wit.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let name = check_path(wit).await?;
+        debug!("validated `{name}` in `{}`", wit.display());
+    }
+    Ok(())
+}
+
+/// Returns `policy` extended by optional digests already recorded in `lock`, such that once
+/// recorded, optional digests are kept up-to-date
+fn sticky_digest_policy(policy: DigestPolicy, lock: Option<&Lock>) -> DigestPolicy {
+    let Some(lock) = lock else {
+        return policy;
+    };
+    let recorded = lock.digest_policy();
+    DigestPolicy {
+        blake3: policy.blake3 || recorded.blake3,
+        files: policy.files || recorded.files,
+        integrity: policy.integrity || recorded.integrity,
+    }
+}
+
+fn log_diff(diff: &LockDiff) {
+    if !diff.is_empty() {
+        info!("lock changes:\n{diff}");
     }
 }
 
@@ -326,6 +918,8 @@ pub async fn lock(
 /// `deps` is in sync with the manifest. This is a potentially destructive operation!
 /// Returns a TOML-encoded lock on success.
 ///
+/// See [`LockOptions`] for available options.
+///
 /// # Errors
 ///
 /// Returns an error if anything in the pipeline fails
@@ -334,26 +928,131 @@ pub async fn update(
     at: Option<impl AsRef<Path>>,
     manifest: impl AsRef<str>,
     deps: impl AsRef<Path>,
-) -> anyhow::Result<String> {
-    let manifest: Manifest =
-        toml::from_str(manifest.as_ref()).context("failed to decode manifest")?;
+    options: &LockOptions,
+) -> Result<String, Error> {
+    let deps = deps.as_ref();
+    let _lock = lock_deps_dir(deps).await?;
+    let LockOutcome { lock, .. } =
+        update_manifest(at, manifest, None::<&str>, deps, options).await?;
+    toml::to_string(&lock)
+        .context("failed to encode lock")
+        .map_err(Into::into)
+}
+
+async fn update_manifest(
+    at: Option<impl AsRef<Path>>,
+    manifest: impl AsRef<str>,
+    lock: Option<impl AsRef<str>>,
+    deps: impl AsRef<Path>,
+    options: &LockOptions,
+) -> anyhow::Result<LockOutcome> {
+    let manifest: Manifest = toml::from_str(manifest.as_ref()).context(InvalidManifest(None))?;
+
+    let old_lock: Option<Lock> = lock
+        .as_ref()
+        .map(AsRef::as_ref)
+        .map(toml::from_str)
+        .transpose()
+        .context("failed to decode lock")?;
+    let old_lock = old_lock.map(Lock::migrate).unwrap_or_default();
+
+    // Entries of selected packages are removed from the lock, which forces them to be updated,
+    // while entries of all other packages are preserved
+    let lock = options.packages().map(|packages| {
+        let mut lock = old_lock.clone();
+        lock.retain(|id, _| !packages.contains(id));
+        lock
+    });
 
     let deps = deps.as_ref();
+    let cache = cache(options.cache_dir(), options.offline())?;
+    // NOTE: Cache is the only source of dependencies in offline mode, otherwise dependencies
+    // are always fetched from upstream and only written to the cache
+    let fetch_options;
+    let options = if options.offline() {
+        options
+    } else {
+        fetch_options = options
+            .clone()
+            .with_cache_policy(options.cache_policy().without_reads());
+        &fetch_options
+    };
     let lock = manifest
-        .lock(at, deps, None, cache().map(WriteCache).as_ref())
-        .await
-        .with_context(|| format!("failed to lock deps to `{}`", deps.display()))?;
-    toml::to_string(&lock).context("failed to encode lock")
+        .lock(at, deps, lock.as_ref(), cache.as_ref(), options)
+        .await;
+    save_cache_counters(cache.as_ref()).await;
+    let mut lock = lock.with_context(|| format!("failed to lock deps to `{}`", deps.display()))?;
+    lock.apply_digest_policy(
+        deps,
+        sticky_digest_policy(options.digests(), Some(&old_lock)),
+    )
+    .await?;
+    finish(deps, &lock, options).await?;
+    let diff = old_lock.diff(&lock);
+    log_diff(&diff);
+    Ok(LockOutcome {
+        updated: *lock != *old_lock,
+        lock,
+        diff,
+    })
+}
+
+async fn read_lock(path: impl AsRef<Path>) -> anyhow::Result<Lock> {
+    let path = path.as_ref();
+    let Some(lock) = read_lock_string(path).await? else {
+        bail!("lock at `{}` is missing", path.display())
+    };
+    toml::from_str(&lock).context("failed to decode lock")
 }
 
 async fn read_manifest_string(path: impl AsRef<Path>) -> std::io::Result<String> {
     let path = path.as_ref();
-    fs::read_to_string(&path).await.map_err(|e| {
+    let manifest = fs::read_to_string(&path).await.map_err(|e| {
         std::io::Error::new(
             e.kind(),
             format!("failed to read manifest at `{}`: {e}", path.display()),
         )
-    })
+    })?;
+    if !is_cargo_manifest(path) {
+        return Ok(manifest);
+    }
+    match cargo_metadata(&manifest) {
+        Ok(Some(manifest)) => Ok(manifest),
+        Ok(None) => Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!(
+                "`{}` does not contain `[package.metadata.wit-deps]`",
+                path.display()
+            ),
+        )),
+        Err(e) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("failed to read manifest at `{}`: {e:#}", path.display()),
+        )),
+    }
+}
+
+/// Reads the TOML-encoded manifest at `path`. If `path` refers to a `Cargo.toml`, the manifest
+/// is read from its `[package.metadata.wit-deps]` table, see [`manifest_path`].
+///
+/// # Errors
+///
+/// Returns an error if the manifest cannot be read or, for `Cargo.toml`, if it does not contain
+/// the `[package.metadata.wit-deps]` table
+pub async fn read_manifest_path(path: impl AsRef<Path>) -> Result<String, Error> {
+    read_manifest_string(path).await.map_err(Into::into)
+}
+
+async fn read_lock_string(path: impl AsRef<Path>) -> std::io::Result<Option<String>> {
+    let path = path.as_ref();
+    match fs::read_to_string(&path).await {
+        Ok(lock) => Ok(Some(lock)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(std::io::Error::new(
+            e.kind(),
+            format!("failed to read lock at `{}`: {e}", path.display()),
+        )),
+    }
 }
 
 async fn write_lock(path: impl AsRef<Path>, buf: impl AsRef<[u8]>) -> std::io::Result<()> {
@@ -379,7 +1078,11 @@ async fn write_lock(path: impl AsRef<Path>, buf: impl AsRef<[u8]>) -> std::io::R
 
 /// Like [lock](self::lock()), but reads the manifest at `manifest_path` and reads/writes the lock at `lock_path`.
 ///
-/// Returns `true` if the lock was updated and `false` otherwise.
+/// Returns the resulting lock along with the changes compared to the lock previously present at
+/// `lock_path`. The lock is only written if it was [updated](LockOutcome::updated).
+///
+/// See [`LockOptions`] for available options. If [frozen](LockOptions::with_frozen), the lock at
+/// `lock_path` is never written, see [`lock_frozen`].
 ///
 /// # Errors
 ///
@@ -389,33 +1092,81 @@ pub async fn lock_path(
     manifest_path: impl AsRef<Path>,
     lock_path: impl AsRef<Path>,
     deps: impl AsRef<Path>,
-) -> anyhow::Result<bool> {
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
     let manifest_path = manifest_path.as_ref();
     let lock_path = lock_path.as_ref();
+    let deps = deps.as_ref();
+    let _lock = lock_deps_dir(deps).await?;
     let (manifest, lock) = try_join!(
         read_manifest_string(manifest_path),
-        fs::read_to_string(&lock_path).map(|res| match res {
-            Ok(lock) => Ok(Some(lock)),
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
-            Err(e) => Err(std::io::Error::new(
-                e.kind(),
-                format!("failed to read lock at `{}`: {e}", lock_path.display())
-            )),
-        }),
+        read_lock_string(lock_path),
     )?;
-    if let Some(lock) = self::lock(manifest_path.parent(), manifest, lock, deps)
+    if options.frozen() && lock.is_none() {
+        return Err(anyhow!("lock at `{}` is missing", lock_path.display()).into());
+    }
+    let outcome = lock_manifest(manifest_path.parent(), manifest, lock, deps, options)
         .await
-        .context("failed to lock dependencies")?
-    {
+        .context("failed to lock dependencies")?;
+    if let Some(lock) = encode_updated(&outcome)? {
         write_lock(lock_path, lock).await?;
-        Ok(true)
-    } else {
-        Ok(false)
     }
+    Ok(outcome)
+}
+
+/// Like [`lock_path`], but regenerates the lock at `lock_path`, which may contain VCS conflict
+/// markers, from the manifest at `manifest_path`. Both sides of a conflict are [merged](Lock::merge)
+/// and all conflicting entries are re-derived from the manifest, while unchanged entries are
+/// preserved. The lock is always written, therefore [frozen](LockOptions::with_frozen) mode is
+/// not supported.
+///
+/// Returns the resulting lock along with the changes compared to the merged lock.
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails or if `options` are frozen
+#[instrument(level = "trace", skip(manifest_path, lock_path, deps))]
+pub async fn regenerate_path(
+    manifest_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
+    if options.frozen() {
+        return Err(anyhow!("lock cannot be regenerated in frozen mode").into());
+    }
+    let manifest_path = manifest_path.as_ref();
+    let lock_path = lock_path.as_ref();
+    let deps = deps.as_ref();
+    let _lock = lock_deps_dir(deps).await?;
+    let (manifest, lock) = try_join!(
+        read_manifest_string(manifest_path),
+        read_lock_string(lock_path),
+    )?;
+    let lock = lock
+        .as_deref()
+        .map(Lock::from_conflicted)
+        .transpose()
+        .with_context(|| format!("failed to regenerate lock at `{}`", lock_path.display()))?
+        .unwrap_or_default();
+    let lock = toml::to_string(&lock).context("failed to encode lock")?;
+    let outcome = lock_manifest(manifest_path.parent(), manifest, Some(&lock), deps, options)
+        .await
+        .context("failed to lock dependencies")?;
+    write_lock(lock_path, encode_updated(&outcome)?.unwrap_or(lock)).await?;
+    Ok(outcome)
 }
 
 /// Like [update](self::update()), but reads the manifest at `manifest_path` and writes the lock at `lock_path`.
 ///
+/// See [`LockOptions`] for available options. If [offline](LockOptions::with_offline),
+/// dependencies are resolved from cache instead of upstream. If
+/// [packages](LockOptions::with_packages) are selected, only these dependencies are updated and
+/// all other entries of the lock at `lock_path` are preserved.
+///
+/// Returns the resulting lock along with the changes compared to the lock previously present at
+/// `lock_path`. The lock is always written.
+///
 /// # Errors
 ///
 /// Returns an error if anything in the pipeline fails
@@ -424,74 +1175,677 @@ pub async fn update_path(
     manifest_path: impl AsRef<Path>,
     lock_path: impl AsRef<Path>,
     deps: impl AsRef<Path>,
-) -> anyhow::Result<()> {
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
     let manifest_path = manifest_path.as_ref();
-    let manifest = read_manifest_string(manifest_path).await?;
-    let lock = self::update(manifest_path.parent(), manifest, deps)
+    let lock_path = lock_path.as_ref();
+    let deps = deps.as_ref();
+    let _lock = lock_deps_dir(deps).await?;
+    let (manifest, lock) = try_join!(
+        read_manifest_string(manifest_path),
+        read_lock_string(lock_path),
+    )?;
+    let outcome = update_manifest(manifest_path.parent(), manifest, lock, deps, options)
         .await
         .context("failed to lock dependencies")?;
+    let lock = toml::to_string(&outcome.lock).context("failed to encode lock")?;
     write_lock(lock_path, lock).await?;
-    Ok(())
+    Ok(outcome)
+}
+
+/// Returns the [Plan] containing the [`PlannedAction`] [`lock_path`], or [`update_path`] if
+/// `update` is `true`, would take for each dependency without fetching or modifying anything,
+/// see [`Manifest::plan`].
+///
+/// If `prune` is `true`, directories within `deps`, which would not be present in the lock, are
+/// planned to be deleted.
+///
+/// The local cache is consulted to determine whether URL dependencies would be fetched, but
+/// remote caches are not.
+///
+/// # Errors
+///
+/// Returns an error if the manifest is missing or either the manifest or the lock cannot be
+/// decoded
+#[instrument(level = "trace", skip(manifest_path, lock_path, deps, packages))]
+pub async fn plan_path(
+    manifest_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    update: bool,
+    prune: bool,
+    packages: impl IntoIterator<Item = Identifier>,
+    cache_dir: Option<&Path>,
+) -> Result<Plan, Error> {
+    let packages: BTreeSet<_> = packages.into_iter().collect();
+    let packages = (!packages.is_empty()).then_some(&packages);
+    let deps = deps.as_ref();
+    let (manifest, lock) = try_join!(
+        read_manifest_string(manifest_path),
+        read_lock_string(lock_path),
+    )?;
+    let manifest: Manifest = toml::from_str(&manifest).context(InvalidManifest(None))?;
+    let lock: Option<Lock> = lock
+        .as_deref()
+        .map(toml::from_str)
+        .transpose()
+        .context("failed to decode lock")?
+        .map(Lock::migrate);
+    let cache = cache(cache_dir, true)?;
+    let mut plan = manifest
+        .plan(deps, lock.as_ref(), cache.as_ref(), packages, update)
+        .await?;
+    if prune {
+        let lock = lock.unwrap_or_default();
+        for id in lock.unlocked(deps).await? {
+            if !manifest.contains_key(&id) {
+                plan.actions.insert(id, PlannedAction::Delete);
+            }
+        }
+        for (id, action) in &mut plan.actions {
+            if *action == PlannedAction::Remove && fs::try_exists(lock.path(deps, id)).await? {
+                *action = PlannedAction::Delete;
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Locks multiple WIT roots, each given by a pair of manifest path and `deps` directory,
+/// against a single shared lock at `lock_path`. Identical dependencies of different roots are
+/// deduplicated in the shared lock, while conflicting dependencies, i.e. dependencies with equal
+/// identifiers, but different sources or digests, result in an error.
+///
+/// Returns the resulting shared lock along with the changes compared to the lock previously
+/// present at `lock_path`. The lock is only written if it was [updated](LockOutcome::updated).
+///
+/// See [`LockOptions`] for available options, [packages](LockOptions::with_packages) are not
+/// supported and ignored. Note, that in frozen mode, `deps` of the roots may be populated before
+/// the shared lock is determined to be out-of-sync. If [pruning](LockOptions::with_prune) is
+/// enabled, `deps` of each root are pruned according to dependencies of that root.
+///
+/// # Errors
+///
+/// Returns an error if anything in the pipeline fails or the roots have conflicting dependencies
+#[instrument(level = "trace", skip(roots, lock_path))]
+pub async fn lock_shared_path(
+    roots: impl IntoIterator<Item = (PathBuf, PathBuf)>,
+    lock_path: impl AsRef<Path>,
+    options: &LockOptions,
+) -> Result<LockOutcome, Error> {
+    let lock_path = lock_path.as_ref();
+    let options = options.clone().with_packages([]);
+    let frozen = options.frozen();
+    let roots: Vec<_> = roots.into_iter().collect();
+    // NOTE: Locks are acquired in a consistent order to prevent deadlocks between processes
+    // and roots sharing a lock file are only locked once
+    let mut deps: Vec<_> = roots.iter().map(|(_, deps)| deps.as_path()).collect();
+    deps.sort_unstable_by_key(|deps| deps_lock_path(deps));
+    deps.dedup_by_key(|deps| deps_lock_path(deps));
+    let mut locks = Vec::with_capacity(deps.len());
+    for deps in deps {
+        locks.push(lock_deps_dir(deps).await?);
+    }
+    let old_lock = read_lock_string(lock_path).await?;
+    if frozen && old_lock.is_none() {
+        return Err(anyhow!("lock at `{}` is missing", lock_path.display()).into());
+    }
+    let old_lock: Option<Lock> = old_lock
+        .as_deref()
+        .map(toml::from_str)
+        .transpose()
+        .context("failed to decode lock")?;
+    let old_lock = old_lock.map(Lock::migrate);
+    let digests = sticky_digest_policy(options.digests(), old_lock.as_ref());
+
+    let cache = cache(options.cache_dir(), options.offline())?;
+    let mut lock = Lock::default();
+    // Manifest path of the root, which introduced the shared entry
+    let mut owners = BTreeMap::<Identifier, PathBuf>::default();
+    for (manifest_path, deps) in roots {
+        let manifest = read_manifest_string(&manifest_path).await?;
+        let manifest: Manifest = toml::from_str(&manifest)
+            .with_context(|| InvalidManifest(Some(manifest_path.clone())))?;
+        let root_lock = manifest
+            .lock(
+                manifest_path.parent(),
+                &deps,
+                old_lock.as_ref(),
+                cache.as_ref(),
+                &options,
+            )
+            .await;
+        save_cache_counters(cache.as_ref()).await;
+        let mut root_lock =
+            root_lock.with_context(|| format!("failed to lock deps to `{}`", deps.display()))?;
+        root_lock.apply_digest_policy(&deps, digests).await?;
+        finish(&deps, &root_lock, &options).await?;
+        for (id, entry) in root_lock.iter() {
+            use std::collections::btree_map::Entry::{Occupied, Vacant};
+
+            match lock.entry(id.clone()) {
+                Occupied(mut e) => {
+                    let other = e.get_mut();
+                    if !other.digest.matches(&entry.digest)
+                        || other.source.is_some()
+                            && entry.source.is_some()
+                            && other.source != entry.source
+                    {
+                        return Err(anyhow!(
+                            "shared dependency conflict for `{id}` locked by `{}` and `{}`",
+                            owners[id].display(),
+                            manifest_path.display()
+                        )
+                        .into());
+                    }
+                    // NOTE: Direct dependency entries take precedence over transitive ones
+                    if other.source.is_none() && entry.source.is_some() {
+                        let required_by = mem::take(&mut other.required_by);
+                        *other = entry.clone();
+                        other.required_by.extend(required_by);
+                    } else {
+                        other.required_by.extend(entry.required_by.iter().cloned());
+                    }
+                }
+                Vacant(e) => {
+                    e.insert(entry.clone());
+                    owners.insert(id.clone(), manifest_path.clone());
+                }
+            }
+        }
+    }
+    let outcome = outcome(old_lock, lock, frozen)?;
+    if let Some(lock) = encode_updated(&outcome)? {
+        write_lock(lock_path, lock).await?;
+    }
+    Ok(outcome)
+}
+
+/// Reads the lock at `lock_path` and verifies that the dependencies within `deps` match it,
+/// see [`Lock::verify`]. Returns all entries diverging from the lock.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or anything in the pipeline fails
+#[instrument(level = "trace", skip(lock_path, deps))]
+pub async fn verify_path(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+) -> Result<BTreeMap<Identifier, LockDivergence>, Error> {
+    let lock = read_lock(lock_path).await?;
+    lock.verify(deps).await
+}
+
+/// Reads the manifest at `manifest_path` and the lock at `lock_path` and returns all
+/// dependencies, which are out of sync, along with the reason, without fetching or modifying
+/// anything. A missing lock is treated as empty.
+///
+/// Direct dependencies are compared against the manifest, see [`Manifest::out_of_sync`], and
+/// all locked dependencies are verified against the contents of `deps`, see [`Lock::verify`].
+/// Directories in `deps`, which are not present in the lock, are not considered out of sync.
+///
+/// # Errors
+///
+/// Returns an error if the manifest is missing or either the manifest or the lock cannot be
+/// decoded
+#[instrument(level = "trace", skip(manifest_path, lock_path, deps))]
+pub async fn out_of_sync_path(
+    manifest_path: impl AsRef<Path>,
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+) -> Result<BTreeMap<Identifier, OutOfSync>, Error> {
+    let (manifest, lock) = try_join!(
+        read_manifest_string(manifest_path),
+        read_lock_string(lock_path),
+    )?;
+    let manifest: Manifest = toml::from_str(&manifest).context(InvalidManifest(None))?;
+    let lock: Lock = lock
+        .as_deref()
+        .map(toml::from_str)
+        .transpose()
+        .context("failed to decode lock")?
+        .unwrap_or_default();
+    let mut out_of_sync = manifest.out_of_sync(&lock);
+    for (id, divergence) in lock.verify(deps).await? {
+        if !matches!(divergence, LockDivergence::Unlocked) {
+            out_of_sync
+                .entry(id)
+                .or_insert(OutOfSync::Diverged { divergence });
+        }
+    }
+    Ok(out_of_sync)
+}
+
+/// Like [`out_of_sync_path`], but for multiple WIT roots, each given by a pair of manifest path
+/// and `deps` directory, sharing a single lock at `lock_path`, see [`lock_shared_path`].
+///
+/// Locked dependencies are only verified against `deps` of the roots, which depend on them,
+/// and are only considered removed if no root depends on them directly.
+///
+/// # Errors
+///
+/// Returns an error if a manifest is missing or either a manifest or the lock cannot be decoded
+#[instrument(level = "trace", skip(roots, lock_path))]
+pub async fn out_of_sync_shared_path(
+    roots: impl IntoIterator<Item = (PathBuf, PathBuf)>,
+    lock_path: impl AsRef<Path>,
+) -> Result<BTreeMap<Identifier, OutOfSync>, Error> {
+    let lock: Lock = read_lock_string(lock_path)
+        .await?
+        .as_deref()
+        .map(toml::from_str)
+        .transpose()
+        .context("failed to decode lock")?
+        .unwrap_or_default();
+    let mut out_of_sync = BTreeMap::default();
+    let mut direct = BTreeSet::default();
+    for (manifest_path, deps) in roots {
+        let manifest = read_manifest_string(&manifest_path).await?;
+        let manifest: Manifest = toml::from_str(&manifest)
+            .with_context(|| InvalidManifest(Some(manifest_path.clone())))?;
+        // NOTE: Only the entries of the shared lock the root depends on belong to it
+        let root: Lock = manifest
+            .keys()
+            .flat_map(|id| {
+                let mut ids = lock.transitive_deps(id);
+                ids.insert(id.clone());
+                ids
+            })
+            .filter_map(|id| lock.get(&id).map(|entry| (id, entry.clone())))
+            .collect();
+        for (id, reason) in manifest.out_of_sync(&root) {
+            if !matches!(reason, OutOfSync::Removed) {
+                out_of_sync.entry(id).or_insert(reason);
+            }
+        }
+        for (id, divergence) in root.verify(deps).await? {
+            if !matches!(divergence, LockDivergence::Unlocked) {
+                out_of_sync
+                    .entry(id)
+                    .or_insert(OutOfSync::Diverged { divergence });
+            }
+        }
+        direct.extend(manifest.keys().cloned());
+    }
+    for (id, entry) in lock.iter() {
+        if entry.is_direct() && !direct.contains(id) {
+            out_of_sync.entry(id.clone()).or_insert(OutOfSync::Removed);
+        }
+    }
+    Ok(out_of_sync)
+}
+
+/// Reads the lock at `lock_path` and returns a summary of all locked dependencies installed in
+/// `deps`, see [`Lock::list`]
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded
+#[instrument(level = "trace", skip(lock_path, deps))]
+pub async fn list_path(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+) -> Result<Vec<LockListEntry>, Error> {
+    let lock = read_lock(lock_path).await?;
+    Ok(lock.list(deps))
+}
+
+/// Reads the lock at `lock_path` and returns its dependency tree, see [`Lock::tree`]
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded
+#[instrument(level = "trace", skip(lock_path))]
+pub async fn tree_path(lock_path: impl AsRef<Path>) -> Result<Vec<LockTreeNode>, Error> {
+    let lock = read_lock(lock_path).await?;
+    Ok(lock.tree())
+}
+
+/// Reads the lock at `lock_path` and returns its dependency graph, see [`Lock::graph`]
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded
+#[instrument(level = "trace", skip(lock_path))]
+pub async fn graph_path(lock_path: impl AsRef<Path>) -> Result<LockGraph, Error> {
+    let lock = read_lock(lock_path).await?;
+    Ok(lock.graph())
+}
+
+/// Reads the lock at `lock_path` and removes all directories within `deps`, which are not
+/// present in it, see [`Lock::prune`]. Returns identifiers of all removed directories.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or anything in the pipeline fails
+#[instrument(level = "trace", skip(lock_path, deps))]
+pub async fn prune_path(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+) -> Result<BTreeSet<Identifier>, Error> {
+    let lock = read_lock(lock_path).await?;
+    let deps = deps.as_ref();
+    let _lock = lock_deps_dir(deps).await?;
+    lock.prune(deps).await
+}
+
+/// Reads the lock at `lock_path` and fetches every URL dependency in it into the cache without
+/// modifying any `deps` directory, for example, to populate the cache in a separate container
+/// image layer. The fetched dependencies are verified against the digests recorded in the lock.
+///
+/// If `manifest_path` is specified, URL dependencies of the manifest, which are not present in
+/// the lock, are fetched as well.
+///
+/// If `cache_dir` is specified, it is used as the [`LocalCache`] directory instead of the
+/// default one, see [`LocalCache::cache_dir`].
+///
+/// Returns the number of fetched dependencies, including transitive ones.
+///
+/// # Errors
+///
+/// Returns an error if no cache is available, the lock is missing, fetching a dependency fails
+/// or a fetched dependency does not match the lock
+#[instrument(level = "trace", skip(lock_path, manifest_path))]
+pub async fn fetch_path(
+    lock_path: impl AsRef<Path>,
+    manifest_path: Option<impl AsRef<Path>>,
+    cache_dir: Option<&Path>,
+) -> Result<usize, Error> {
+    let lock = read_lock(lock_path).await?.migrate();
+    let mut manifest: BTreeMap<_, _> = lock
+        .iter()
+        .filter_map(|(id, entry)| match &entry.source {
+            Some(LockEntrySource::Url(url)) => Some((id.clone(), ManifestEntry::from(url.clone()))),
+            _ => None,
+        })
+        .collect();
+    if let Some(manifest_path) = manifest_path {
+        let unlocked: Manifest = toml::from_str(&read_manifest_string(manifest_path).await?)
+            .context(InvalidManifest(None))?;
+        for (id, entry) in unlocked.iter() {
+            if matches!(entry, ManifestEntry::Url { .. }) && !lock.contains_key(id) {
+                manifest.insert(id.clone(), entry.clone());
+            }
+        }
+    }
+    let manifest: Manifest = manifest.into_iter().collect();
+
+    let Some(cache) = cache(cache_dir, false)? else {
+        return Err(
+            anyhow!("cache directory could not be determined, specify it explicitly").into(),
+        );
+    };
+    // NOTE: Dependencies are unpacked into a temporary directory to verify their digests, since
+    // the lock records digests of unpacked WIT definitions
+    let tmp = TempDir::new("wit-deps-fetch").await?;
+    let fetched = manifest
+        .lock(
+            None::<&Path>,
+            tmp.path(),
+            None,
+            Some(&cache),
+            &LockOptions::default(),
+        )
+        .await;
+    save_cache_counters(Some(&cache)).await;
+    tmp.remove().await;
+    let fetched = fetched.context("failed to fetch dependencies")?;
+    for (id, entry) in fetched.iter() {
+        if let Some(locked) = lock.get(id) {
+            let url = entry.source.as_ref().or_else(|| {
+                entry
+                    .required_by
+                    .iter()
+                    .find_map(|direct| fetched.get(direct)?.source.as_ref())
+            });
+            if let (Some(LockEntrySource::Url(url)), Some(mismatch)) =
+                (url, entry.digest.mismatch(&locked.digest))
+            {
+                let (algorithm, expected, actual) = mismatch;
+                return Err(DigestMismatch {
+                    url: url.clone(),
+                    algorithm,
+                    expected,
+                    actual,
+                    locked: true,
+                })
+                .with_context(|| format!("contents of `{id}` do not match the lock"))
+                .map_err(Into::into);
+            }
+            if !entry.digest.matches(&locked.digest) {
+                return Err(anyhow!("contents of `{id}` do not match the lock").into());
+            }
+        }
+    }
+    Ok(fetched.len())
+}
+
+/// Reads the lock at `lock_path`, re-downloads every URL dependency in it from upstream,
+/// bypassing all caches, and compares the contents against the digests recorded in the lock.
+/// Nothing is written to the cache or any `deps` directory.
+///
+/// Only the settings of `options` controlling how dependencies are fetched apply, i.e. the
+/// [download concurrency](LockOptions::with_download_concurrency), which also limits the number
+/// of dependencies audited concurrently, the [fetch](LockOptions::with_fetch) implementation and
+/// [cancellation](LockOptions::with_cancellation).
+///
+/// Returns the [`LockAuditStatus`] of every direct URL dependency and all transitive
+/// dependencies contained in them. Path dependencies are not audited.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded
+#[instrument(level = "trace", skip(lock_path, options))]
+pub async fn audit_path(
+    lock_path: impl AsRef<Path>,
+    options: &LockOptions,
+) -> Result<BTreeMap<Identifier, LockAuditStatus>, Error> {
+    let lock = read_lock(lock_path).await?.migrate();
+    let tmp = TempDir::new("wit-deps-audit").await?;
+    let options = options.fetch_options();
+    let audits: Vec<_> = futures::stream::iter(lock.iter().filter_map(|(id, entry)| {
+        let Some(LockEntrySource::Url(url)) = &entry.source else {
+            return None;
+        };
+        let manifest = Manifest::from_iter([(id.clone(), ManifestEntry::from(url.clone()))]);
+        let deps = tmp.join(id).join("deps");
+        let options = &options;
+        Some(async move {
+            debug!("audit `{id}` from `{url}`");
+            let upstream = manifest
+                .lock(None::<&Path>, deps, None, None::<&MemoryCache>, options)
+                .await;
+            (id, upstream)
+        })
+    }))
+    .buffer_unordered(options.download_concurrency())
+    .collect()
+    .await;
+    tmp.remove().await;
+    let mut statuses = BTreeMap::default();
+    for (id, upstream) in audits {
+        let upstream = match upstream {
+            Ok(upstream) => upstream,
+            Err(e) => {
+                statuses.insert(
+                    id.clone(),
+                    LockAuditStatus::Failed {
+                        error: format!("{e:#}"),
+                    },
+                );
+                continue;
+            }
+        };
+        // NOTE: Transitive dependencies contained in the upstream resource are audited as well
+        let locked = lock.get(id).map(|entry| &entry.deps);
+        for dep in locked.into_iter().flatten().chain([id]) {
+            let Some(expected) = lock.get(dep) else {
+                continue;
+            };
+            let status = match upstream.get(dep) {
+                Some(actual) if actual.digest.matches(&expected.digest) => {
+                    LockAuditStatus::Unchanged
+                }
+                Some(actual) => LockAuditStatus::Changed {
+                    expected: Box::new(expected.digest.clone()),
+                    actual: Box::new(actual.digest.clone()),
+                },
+                None => LockAuditStatus::Missing,
+            };
+            statuses.insert(dep.clone(), status);
+        }
+    }
+    Ok(statuses)
+}
+
+/// Reads the lock at `lock_path` and re-downloads the URL dependency `id` from upstream into
+/// `deps`, bypassing all caches. If `id` is a transitive dependency, the direct dependency
+/// requiring it is downloaded instead. Existing dependency directories within `deps` are
+/// overwritten.
+///
+/// Returns the path to the upstream WIT definitions of `id` within `deps`.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded, `id` is not a locked URL
+/// dependency, fetching fails or `id` is not present upstream
+#[instrument(level = "trace", skip(lock_path, deps))]
+pub async fn upstream_path(
+    lock_path: impl AsRef<Path>,
+    id: &Identifier,
+    deps: impl AsRef<Path>,
+) -> Result<PathBuf, Error> {
+    let deps = deps.as_ref();
+    let lock = read_lock(lock_path).await?.migrate();
+    let Some(entry) = lock.get(id) else {
+        return Err(anyhow!("`{id}` is not present in the lock").into());
+    };
+    let (direct, url) = match &entry.source {
+        Some(LockEntrySource::Url(url)) => (id, url),
+        Some(LockEntrySource::Path(path)) => {
+            return Err(anyhow!(
+                "`{id}` is a path dependency, compare it against `{}` directly",
+                path.display()
+            )
+            .into())
+        }
+        None => entry
+            .required_by
+            .iter()
+            .find_map(|direct| match lock.get(direct)?.source.as_ref()? {
+                LockEntrySource::Url(url) => Some((direct, url)),
+                LockEntrySource::Path(..) => None,
+            })
+            .with_context(|| format!("`{id}` is not required by any URL dependency"))?,
+    };
+    debug!("fetch `{direct}` from `{url}`");
+    let manifest = Manifest::from_iter([(direct.clone(), ManifestEntry::from(url.clone()))]);
+    let upstream = manifest
+        .lock(
+            None::<&Path>,
+            deps,
+            None,
+            None::<&MemoryCache>,
+            &LockOptions::default(),
+        )
+        .await
+        .with_context(|| format!("failed to fetch `{direct}` from `{url}`"))?;
+    if !upstream.contains_key(id) {
+        return Err(anyhow!("`{id}` is not present in upstream `{direct}` anymore").into());
+    }
+    Ok(deps.join(id))
 }
 
 /// Asynchronously ensure dependency manifest, lock and dependencies are in sync.
-/// This must run within a [tokio] context.
+/// This can run on any executor, but the default [`ReqwestFetch`] used to fetch URL dependencies
+/// requires a [Tokio](https://tokio.rs) context.
+///
+/// The macro accepts the following forms:
+///
+/// - `lock!()` locks the `wit` directory, equivalent to `lock!("wit")`
+/// - `lock!("wit")` locks the directory given by one or more [`concat!`]-compatible components,
+///   e.g. `lock!(env!("CARGO_MANIFEST_DIR"), "/wit")`. The manifest at `deps.toml` within the
+///   directory is embedded at compile time.
+/// - `lock!(dir = expr)` locks the directory at a path computed at runtime, e.g. a per-target or
+///   generated path. The manifest is read at runtime.
+/// - `lock!(manifest = expr, lock = expr, deps = expr)` locks using an explicit manifest, lock
+///   and dependency directory path, all of which are computed at runtime
+///
+/// Each form may be followed by `; read_only` to skip writing the lock, e.g. for consumers, which
+/// only need the dependencies to be present in `deps` and must not modify the lock.
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// let dir = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap_or_default()).join("wit");
+/// wit_deps::lock!(dir = &dir; read_only).await?;
+/// wit_deps::lock!(
+///     manifest = dir.join("deps.toml"),
+///     lock = dir.join("deps.lock"),
+///     deps = dir.join("deps"),
+/// )
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
 #[macro_export]
 macro_rules! lock {
-    () => {
-        $crate::lock!("wit")
+    (@write) => {
+        true
+    };
+    (@write read_only) => {
+        false
+    };
+    ($(; $opt:ident)?) => {
+        $crate::lock!("wit" $(; $opt)?)
     };
-    ($dir:literal $(,)?) => {
+    (dir = $dir:expr $(,)? $(; $opt:ident)?) => {
         async {
-            use $crate::tokio::fs;
-
-            use std::io::{Error, ErrorKind};
-
-            let lock = match fs::read_to_string(concat!($dir, "/deps.lock")).await {
-                Ok(lock) => Some(lock),
-                Err(e) if e.kind() == ErrorKind::NotFound => None,
-                Err(e) => {
-                    return Err(Error::new(
-                        e.kind(),
-                        format!(
-                            "failed to read lock at `{}`: {e}",
-                            concat!($dir, "/deps.lock")
-                        ),
-                    ))
+            match ::std::path::Path::new(&$dir) {
+                dir => {
+                    $crate::__private::lock_path(
+                        &dir.join("deps.toml"),
+                        &dir.join("deps.lock"),
+                        &dir.join("deps"),
+                        $crate::lock!(@write $($opt)?),
+                    )
+                    .await
                 }
-            };
-            match $crate::lock(
-                Some($dir),
-                include_str!(concat!($dir, "/deps.toml")),
-                lock,
-                concat!($dir, "/deps"),
-            )
-            .await
-            {
-                Ok(Some(lock)) => fs::write(concat!($dir, "/deps.lock"), lock)
+            }
+        }
+    };
+    (manifest = $manifest:expr, lock = $lock:expr, deps = $deps:expr $(,)? $(; $opt:ident)?) => {
+        async {
+            match (&$manifest, &$lock, &$deps) {
+                (manifest, lock, deps) => {
+                    $crate::__private::lock_path(
+                        ::std::path::Path::new(manifest),
+                        ::std::path::Path::new(lock),
+                        ::std::path::Path::new(deps),
+                        $crate::lock!(@write $($opt)?),
+                    )
                     .await
-                    .map_err(|e| {
-                        Error::new(
-                            e.kind(),
-                            format!(
-                                "failed to write lock at `{}`: {e}",
-                                concat!($dir, "/deps.lock")
-                            ),
-                        )
-                    }),
-                Ok(None) => Ok(()),
-                Err(e) => Err(Error::new(ErrorKind::Other, e)),
+                }
             }
         }
     };
+    ($($dir:expr),+ $(,)? $(; $opt:ident)?) => {
+        $crate::__private::lock(
+            Some(::std::path::Path::new(concat!($($dir),+))),
+            include_str!(concat!($($dir),+, "/deps.toml")),
+            ::std::path::Path::new(concat!($($dir),+, "/deps.lock")),
+            ::std::path::Path::new(concat!($($dir),+, "/deps")),
+            $crate::lock!(@write $($opt)?),
+        )
+    };
 }
 
 #[cfg(feature = "sync")]
 /// Synchronously ensure dependency manifest, lock and dependencies are in sync.
+#[deprecated(note = "use `wit_deps::blocking::lock_dir` instead")]
 #[macro_export]
 macro_rules! lock_sync {
     ($($args:tt)*) => {
-        $crate::tokio::runtime::Builder::new_multi_thread()
+        $crate::__private::tokio::runtime::Builder::new_multi_thread()
             .thread_name("wit-deps/lock_sync")
             .enable_io()
             .enable_time()
@@ -0,0 +1,296 @@
+use crate::Identifier;
+
+use core::fmt;
+
+use std::collections::BTreeSet;
+
+/// Returns `true` if `name` is the file name of a license file, e.g. `LICENSE`, `LICENSE.md`
+/// or `LICENSE-MIT`
+pub(crate) fn is_license_file(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    ["LICENSE", "LICENCE", "COPYING", "UNLICENSE"]
+        .iter()
+        .any(|prefix| {
+            name.strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '-', '_']))
+        })
+}
+
+/// Detects the SPDX license identifier of license file contents `text`
+pub(crate) fn detect(text: &str) -> Option<String> {
+    if let Some(id) = text.lines().find_map(|line| {
+        let (_, id) = line.split_once("SPDX-License-Identifier:")?;
+        Some(id.trim())
+    }) {
+        return (!id.is_empty()).then(|| id.into());
+    }
+    let text = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    let id = if text.contains("apache license") && text.contains("version 2.0") {
+        if text.contains("llvm exceptions") {
+            "Apache-2.0 WITH LLVM-exception"
+        } else {
+            "Apache-2.0"
+        }
+    } else if text.contains("permission is hereby granted, free of charge") {
+        "MIT"
+    } else if text.contains("mozilla public license") && text.contains("2.0") {
+        "MPL-2.0"
+    } else if text.contains("redistribution and use in source and binary forms") {
+        if text.contains("neither the name") {
+            "BSD-3-Clause"
+        } else {
+            "BSD-2-Clause"
+        }
+    } else if text.contains("permission to use, copy, modify, and/or distribute this software") {
+        "ISC"
+    } else if text.contains("free and unencumbered software released into the public domain") {
+        "Unlicense"
+    } else if text.contains("cc0 1.0 universal") {
+        "CC0-1.0"
+    } else {
+        return None;
+    };
+    Some(id.into())
+}
+
+/// Returns the SPDX license expression of a package containing license files with detected
+/// identifiers `ids`. Multiple licenses are assumed to be alternatives, as is conventional for
+/// dual-licensed packages shipping e.g. `LICENSE-APACHE` and `LICENSE-MIT`.
+pub(crate) fn expression(ids: &BTreeSet<String>) -> Option<String> {
+    match ids.len() {
+        0 => None,
+        1 => ids.first().cloned(),
+        _ => Some(
+            ids.iter()
+                .map(|id| {
+                    if id.contains(' ') {
+                        format!("({id})")
+                    } else {
+                        id.clone()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" OR "),
+        ),
+    }
+}
+
+/// Policy restricting the licenses of locked dependencies, see
+/// [`LockOptions::with_license_policy`](crate::LockOptions::with_license_policy).
+///
+/// License expressions are evaluated according to SPDX semantics, i.e. an expression is
+/// permitted if any alternative of an `OR` and all terms of an `AND` are permitted. A license
+/// is permitted if it is not denied and, if any licenses are allowed, it is allowed. Licenses
+/// with an exception, e.g. `Apache-2.0 WITH LLVM-exception`, match both the full license and the
+/// license without the exception. Identifiers are compared case-insensitively.
+///
+/// Dependencies with an unknown license are only rejected if any licenses are allowed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Policy {
+    allow: BTreeSet<String>,
+    deny: BTreeSet<String>,
+}
+
+impl Policy {
+    /// Returns the policy, which allows license `id`
+    #[must_use]
+    pub fn with_allow(mut self, id: impl Into<String>) -> Self {
+        self.allow.insert(id.into());
+        self
+    }
+
+    /// Returns the policy, which denies license `id`
+    #[must_use]
+    pub fn with_deny(mut self, id: impl Into<String>) -> Self {
+        self.deny.insert(id.into());
+        self
+    }
+
+    /// Returns allowed licenses
+    #[must_use]
+    pub fn allowed(&self) -> &BTreeSet<String> {
+        &self.allow
+    }
+
+    /// Returns denied licenses
+    #[must_use]
+    pub fn denied(&self) -> &BTreeSet<String> {
+        &self.deny
+    }
+
+    /// Returns `true` if the policy permits all licenses
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Returns `true` if the policy permits SPDX license expression `license`, [None] denotes an
+    /// unknown license
+    #[must_use]
+    pub fn permits(&self, license: Option<&str>) -> bool {
+        let Some(license) = license else {
+            return self.allow.is_empty();
+        };
+        let tokens: Vec<_> = license
+            .replace('(', " ( ")
+            .replace(')', " ) ")
+            .split_whitespace()
+            .map(str::to_string)
+            .collect();
+        let mut tokens = tokens.iter().map(String::as_str).peekable();
+        self.permits_or(&mut tokens) && tokens.next().is_none()
+    }
+
+    /// Evaluates `term (OR term)*`
+    fn permits_or<'a>(
+        &self,
+        tokens: &mut core::iter::Peekable<impl Iterator<Item = &'a str>>,
+    ) -> bool {
+        let mut permitted = self.permits_and(tokens);
+        while tokens.next_if(|t| t.eq_ignore_ascii_case("OR")).is_some() {
+            // NOTE: Both sides are evaluated to consume all tokens
+            permitted |= self.permits_and(tokens);
+        }
+        permitted
+    }
+
+    /// Evaluates `factor (AND factor)*`
+    fn permits_and<'a>(
+        &self,
+        tokens: &mut core::iter::Peekable<impl Iterator<Item = &'a str>>,
+    ) -> bool {
+        let mut permitted = self.permits_factor(tokens);
+        while tokens.next_if(|t| t.eq_ignore_ascii_case("AND")).is_some() {
+            permitted &= self.permits_factor(tokens);
+        }
+        permitted
+    }
+
+    /// Evaluates `( expression )` or `id [WITH exception]`
+    fn permits_factor<'a>(
+        &self,
+        tokens: &mut core::iter::Peekable<impl Iterator<Item = &'a str>>,
+    ) -> bool {
+        match tokens.next() {
+            Some("(") => {
+                let permitted = self.permits_or(tokens);
+                tokens.next_if_eq(&")").is_some() && permitted
+            }
+            Some(id) if !matches!(id, ")") => {
+                let id = id.trim_end_matches('+');
+                let full = tokens
+                    .next_if(|t| t.eq_ignore_ascii_case("WITH"))
+                    .and_then(|_| tokens.next())
+                    .map(|exception| format!("{id} WITH {exception}"));
+                let matches = |set: &BTreeSet<String>| {
+                    set.iter().any(|v| {
+                        v.eq_ignore_ascii_case(id)
+                            || full
+                                .as_ref()
+                                .is_some_and(|full| v.eq_ignore_ascii_case(full))
+                    })
+                };
+                !matches(&self.deny) && (self.allow.is_empty() || matches(&self.allow))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Error returned if the license of a locked dependency is not permitted by the [Policy]
+#[derive(Debug)]
+pub(crate) struct Violation {
+    /// Identifier of the dependency
+    pub id: Identifier,
+    /// License of the dependency, if known
+    pub license: Option<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.license {
+            Some(license) => write!(
+                f,
+                "license `{license}` of `{}` is not permitted by the license policy",
+                self.id
+            ),
+            None => write!(
+                f,
+                "license of `{}` is unknown, specify it using the `license` field in the manifest",
+                self.id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Violation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection() {
+        assert!(is_license_file("LICENSE"));
+        assert!(is_license_file("license.md"));
+        assert!(is_license_file("LICENSE-APACHE"));
+        assert!(is_license_file("COPYING"));
+        assert!(!is_license_file("LICENSES"));
+        assert!(!is_license_file("README.md"));
+
+        assert_eq!(
+            detect("Permission is hereby granted,\n  free of charge, to any person").as_deref(),
+            Some("MIT")
+        );
+        assert_eq!(
+            detect("Apache License\nVersion 2.0, January 2004\n--- LLVM Exceptions ---").as_deref(),
+            Some("Apache-2.0 WITH LLVM-exception")
+        );
+        assert_eq!(
+            detect("// SPDX-License-Identifier: MIT OR Apache-2.0\n").as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(detect("All rights reserved"), None);
+
+        assert_eq!(
+            expression(&BTreeSet::from([
+                "MIT".into(),
+                "Apache-2.0 WITH LLVM-exception".into()
+            ]))
+            .as_deref(),
+            Some("(Apache-2.0 WITH LLVM-exception) OR MIT")
+        );
+        assert_eq!(expression(&BTreeSet::default()), None);
+    }
+
+    #[test]
+    fn policy() {
+        let policy = Policy::default();
+        assert!(policy.permits(None));
+        assert!(policy.permits(Some("GPL-3.0-only")));
+
+        let policy = Policy::default().with_deny("GPL-3.0-only");
+        assert!(policy.permits(None));
+        assert!(policy.permits(Some("MIT")));
+        assert!(!policy.permits(Some("gpl-3.0-only")));
+        assert!(policy.permits(Some("GPL-3.0-only OR MIT")));
+        assert!(!policy.permits(Some("GPL-3.0-only AND MIT")));
+
+        let policy = Policy::default()
+            .with_allow("Apache-2.0")
+            .with_allow("MIT")
+            .with_deny("Apache-2.0 WITH LLVM-exception");
+        assert!(!policy.permits(None));
+        assert!(policy.permits(Some("MIT")));
+        assert!(policy.permits(Some("Apache-2.0")));
+        assert!(!policy.permits(Some("Apache-2.0 WITH LLVM-exception")));
+        assert!(policy.permits(Some("(Apache-2.0 WITH LLVM-exception) OR MIT")));
+        assert!(policy.permits(Some("(Apache-2.0 OR BSD-3-Clause) AND MIT")));
+        assert!(!policy.permits(Some("(BSD-2-Clause OR BSD-3-Clause) AND MIT")));
+        assert!(!policy.permits(Some("(MIT")));
+    }
+}
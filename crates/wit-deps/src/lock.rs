@@ -1,13 +1,19 @@
-use crate::{tar, Digest, DigestWriter, Identifier};
+use crate::{
+    digest_dir, digest_reader, fs, read_wits, remove_dir_all, Digest, DigestPolicy, Error,
+    Identifier,
+};
 
+use core::fmt;
 use core::ops::{Deref, DerefMut};
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
 
 use anyhow::Context;
-use futures::io::sink;
-use serde::{Deserialize, Serialize};
+use futures::{stream, StreamExt, TryStreamExt};
+use serde::ser::SerializeMap;
+use serde::{de, ser, Deserialize, Serialize};
+use tracing::debug;
 use url::Url;
 
 /// Source of this dependency
@@ -18,21 +24,151 @@ pub enum EntrySource {
     Url(Url),
     /// Local path
     #[serde(rename = "path")]
-    Path(PathBuf),
+    Path(#[serde(with = "portable_path")] PathBuf),
+}
+
+/// Encodes paths using forward slashes as separators, such that locks produced on different
+/// platforms are identical
+mod portable_path {
+    use std::path::{Path, PathBuf};
+
+    use serde::{ser, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        let path = path.to_str().ok_or_else(|| {
+            ser::Error::custom(format!("`{}` is not valid UTF-8", path.display()))
+        })?;
+        serializer.serialize_str(&path.replace('\\', "/"))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        let path = String::deserialize(deserializer)?;
+        Ok(path.replace('\\', "/").into())
+    }
+}
+
+impl fmt::Display for EntrySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Url(url) => write!(f, "{url}"),
+            Self::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+/// Upstream state an [`EntrySource`] was resolved to at the time of locking
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EntrySourceResolution {
+    /// Resolved VCS commit, e.g. one recorded in the pax header of an archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Registry record identifier of the resolved package release
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub registry_record: Option<String>,
+    /// Upstream release tag
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Final URL the resource was fetched from after following redirects, if it differs from the
+    /// source URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub final_url: Option<Url>,
+    /// SPDX license expression of the dependency, either specified in the manifest or detected
+    /// in license files of the fetched archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+}
+
+impl EntrySourceResolution {
+    /// Returns `true` if no resolution metadata is present
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        let Self {
+            commit,
+            registry_record,
+            tag,
+            final_url,
+            license,
+        } = self;
+        commit.is_none()
+            && registry_record.is_none()
+            && tag.is_none()
+            && final_url.is_none()
+            && license.is_none()
+    }
+
+    /// Parse the release tag from a URL of a tag archive, e.g.
+    /// `https://github.com/WebAssembly/wasi-io/archive/refs/tags/v0.2.0.tar.gz`
+    #[must_use]
+    pub fn tag_from_url(url: &Url) -> Option<String> {
+        let segments: Vec<_> = url.path_segments()?.collect();
+        let i = segments.windows(2).position(|w| w == ["refs", "tags"])?;
+        let tag = segments
+            .get(i + 2..)
+            .filter(|tag| !tag.is_empty())?
+            .join("/");
+        let tag = [".tar.gz", ".tgz", ".tar", ".zip"]
+            .into_iter()
+            .find_map(|ext| tag.strip_suffix(ext))
+            .unwrap_or(&tag);
+        (!tag.is_empty()).then(|| tag.into())
+    }
+
+    /// Fill in fields missing in `self` from `other`
+    #[must_use]
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            commit: self.commit.or(other.commit),
+            registry_record: self.registry_record.or(other.registry_record),
+            tag: self.tag.or(other.tag),
+            final_url: self.final_url.or(other.final_url),
+            license: self.license.or(other.license),
+        }
+    }
 }
 
 /// WIT dependency [Lock] entry
 #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub struct Entry {
-    /// Resource source, [None] if the dependency is transitive
+    /// Resource source, [None] if the dependency is transitive and contained in the source of a
+    /// direct dependency
     #[serde(flatten)]
     pub source: Option<EntrySource>,
+    /// Name of the directory the dependency is installed in within `deps`, if it differs from
+    /// its identifier, see [`Lock::path`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    /// WIT package the dependency was resolved for from the
+    /// [registry](crate::Manifest::with_registry), [None] unless it was resolved automatically.
+    /// Such dependencies are transitive despite having a source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub package: Option<String>,
+    /// Upstream state the source was resolved to
+    #[serde(
+        default,
+        flatten,
+        skip_serializing_if = "EntrySourceResolution::is_empty"
+    )]
+    pub resolution: EntrySourceResolution,
     /// Resource digest
     #[serde(flatten)]
     pub digest: Digest,
+    /// Optional Subresource Integrity string of the resource digest, see [`Digest::integrity`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<String>,
     /// Transitive dependency identifiers
     #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
     pub deps: BTreeSet<Identifier>,
+    /// Identifiers of direct dependencies, which introduced this transitive dependency
+    #[serde(
+        default,
+        rename = "required-by",
+        skip_serializing_if = "BTreeSet::is_empty"
+    )]
+    pub required_by: BTreeSet<Identifier>,
+    /// Optional digests of individual WIT files, see [`Lock::digest_files`]
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, Digest>,
 }
 
 impl Entry {
@@ -41,11 +177,23 @@ impl Entry {
     pub fn new(source: Option<EntrySource>, digest: Digest, deps: BTreeSet<Identifier>) -> Self {
         Self {
             source,
+            dir: None,
+            package: None,
+            resolution: EntrySourceResolution::default(),
             digest,
+            integrity: None,
             deps,
+            required_by: BTreeSet::default(),
+            files: BTreeMap::default(),
         }
     }
 
+    /// Returns `true` if the dependency is specified in the manifest
+    #[must_use]
+    pub fn is_direct(&self) -> bool {
+        self.source.is_some() && self.package.is_none()
+    }
+
     /// Create a new entry given a dependency url and path containing the unpacked contents of it
     ///
     /// # Errors
@@ -55,7 +203,7 @@ impl Entry {
         url: Url,
         path: impl AsRef<Path>,
         deps: BTreeSet<Identifier>,
-    ) -> anyhow::Result<Self> {
+    ) -> Result<Self, Error> {
         let digest = Self::digest(path)
             .await
             .context("failed to compute digest")?;
@@ -71,7 +219,7 @@ impl Entry {
         src: PathBuf,
         dst: impl AsRef<Path>,
         deps: BTreeSet<Identifier>,
-    ) -> anyhow::Result<Self> {
+    ) -> Result<Self, Error> {
         let digest = Self::digest(dst)
             .await
             .context("failed to compute digest")?;
@@ -83,50 +231,1000 @@ impl Entry {
     /// # Errors
     ///
     /// Returns an error if [`Self::digest`] of `path` fails
-    pub async fn from_transitive_path(dst: impl AsRef<Path>) -> anyhow::Result<Self> {
+    pub async fn from_transitive_path(dst: impl AsRef<Path>) -> Result<Self, Error> {
         let digest = Self::digest(dst)
             .await
             .context("failed to compute digest")?;
         Ok(Self::new(None, digest, BTreeSet::default()))
     }
 
-    /// Compute the digest of an entry from path
+    /// Compute the digest of an entry from path, see [`digest_dir`]
     ///
     /// # Errors
     ///
     /// Returns an error if tar-encoding the path fails
     pub async fn digest(path: impl AsRef<Path>) -> std::io::Result<Digest> {
-        tar(path, DigestWriter::from(sink())).await.map(Into::into)
+        digest_dir(path).await
+    }
+
+    /// Compute the digests of all WIT files within path
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading the files fails
+    pub async fn digest_files(path: impl AsRef<Path>) -> std::io::Result<BTreeMap<String, Digest>> {
+        let path = path.as_ref();
+        read_wits(path)
+            .await?
+            .try_filter_map(|name| async move {
+                let Some(name) = name.to_str().map(String::from) else {
+                    return Ok(None);
+                };
+                let buf = fs::read(path.join(&name)).await?;
+                Ok(Some((name, digest_reader(buf.as_slice()).await?)))
+            })
+            .try_collect()
+            .await
     }
 }
 
 /// WIT dependency lock mapping [Identifiers](Identifier) to [Entries](Entry)
-#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
-pub struct Lock(BTreeMap<Identifier, Entry>);
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Lock {
+    version: u64,
+    generator: Option<String>,
+    entries: BTreeMap<Identifier, Entry>,
+}
+
+impl Lock {
+    /// Current lock format version
+    pub const VERSION: u64 = 1;
+
+    /// Lock format version. Locks produced before versioning was introduced have version `0`
+    #[must_use]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Version of `wit-deps`, which produced the lock, if known
+    #[must_use]
+    pub fn generator(&self) -> Option<&str> {
+        self.generator.as_deref()
+    }
+
+    /// Returns the path of the directory dependency `id` is installed in within `deps`, which is
+    /// named by the identifier unless a different [directory](Entry::dir) is recorded
+    #[must_use]
+    pub fn path(&self, deps: impl AsRef<Path>, id: &str) -> PathBuf {
+        let dir = self
+            .entries
+            .get(id)
+            .and_then(|entry| entry.dir.as_deref())
+            .unwrap_or(id);
+        deps.as_ref().join(dir)
+    }
+
+    /// Returns the difference between `self` and a `new` lock
+    #[must_use]
+    pub fn diff(&self, new: &Self) -> Diff {
+        let mut diff = Diff::default();
+        for (id, old) in &self.entries {
+            match new.entries.get(id) {
+                None => {
+                    diff.removed.insert(id.clone(), old.clone());
+                }
+                Some(new) if old != new => {
+                    diff.changed.insert(
+                        id.clone(),
+                        EntryChange {
+                            old: old.clone(),
+                            new: new.clone(),
+                        },
+                    );
+                }
+                Some(_) => {}
+            }
+        }
+        for (id, new) in &new.entries {
+            if !self.entries.contains_key(id) {
+                diff.added.insert(id.clone(), new.clone());
+            }
+        }
+        diff
+    }
+
+    /// Recomputes digests of all dependencies within `deps` and returns all entries diverging
+    /// from the lock. Nothing is fetched or modified.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading `deps` or computing a digest fails
+    pub async fn verify(
+        &self,
+        deps: impl AsRef<Path>,
+    ) -> Result<BTreeMap<Identifier, Divergence>, Error> {
+        let deps = deps.as_ref();
+        let mut divergences: BTreeMap<_, _> = stream::iter(&self.entries)
+            .map(|(id, Entry { digest, files, .. })| async move {
+                let path = self.path(deps, id);
+                match fs::metadata(&path).await {
+                    Ok(md) if md.is_dir() => {}
+                    Ok(_) => return Ok(Some((id.clone(), Divergence::Missing))),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok(Some((id.clone(), Divergence::Missing)))
+                    }
+                    Err(e) => {
+                        return Err(e).with_context(|| {
+                            format!("failed to lookup `{}` metadata", path.display())
+                        })
+                    }
+                }
+                let actual = Entry::digest(&path)
+                    .await
+                    .with_context(|| format!("failed to compute digest of `{}`", path.display()))?;
+                if actual.matches(digest) {
+                    return Ok(None);
+                }
+                let files = if files.is_empty() {
+                    BTreeMap::default()
+                } else {
+                    let actual = Entry::digest_files(&path).await.with_context(|| {
+                        format!("failed to compute file digests of `{}`", path.display())
+                    })?;
+                    diverged_files(files, &actual)
+                };
+                Ok(Some((
+                    id.clone(),
+                    Divergence::Modified {
+                        expected: Box::new(digest.clone()),
+                        actual: Box::new(actual),
+                        files,
+                    },
+                )))
+            })
+            .buffered(16)
+            .try_filter_map(|divergence| async { Ok(divergence) })
+            .try_collect()
+            .await?;
+        let unlocked = self.unlocked(deps).await?;
+        divergences.extend(unlocked.into_iter().map(|id| (id, Divergence::Unlocked)));
+        Ok(divergences)
+    }
+
+    /// Returns a summary of all locked dependencies installed in `deps`
+    #[must_use]
+    pub fn list(&self, deps: impl AsRef<Path>) -> Vec<ListEntry> {
+        let deps = deps.as_ref();
+        self.entries
+            .iter()
+            .map(|(id, entry)| ListEntry {
+                id: id.clone(),
+                source: entry.source.clone(),
+                digest: entry.digest.clone(),
+                direct: entry.is_direct(),
+                path: self.path(deps, id),
+            })
+            .collect()
+    }
+
+    /// Returns the dependency tree of the lock rooted at direct dependencies, where children of
+    /// each node are the transitive dependencies it introduced. Dependency cycles are not
+    /// expanded.
+    #[must_use]
+    pub fn tree(&self) -> Vec<TreeNode> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.source.is_some())
+            .map(|(id, _)| self.tree_node(id, &mut Vec::new()))
+            .collect()
+    }
+
+    /// Returns the [`TreeNode`] of `id`, where `ancestors` are the identifiers on the path from
+    /// the root to it
+    fn tree_node(&self, id: &Identifier, ancestors: &mut Vec<Identifier>) -> TreeNode {
+        let entry = self.entries.get(id);
+        let mut deps = Vec::new();
+        if let Some(entry) = entry.filter(|_| !ancestors.contains(id)) {
+            ancestors.push(id.clone());
+            deps.extend(entry.deps.iter().map(|dep| self.tree_node(dep, ancestors)));
+            ancestors.pop();
+        }
+        TreeNode {
+            id: id.clone(),
+            source: entry.and_then(|entry| entry.source.clone()),
+            deps,
+        }
+    }
+
+    /// Returns identifiers of all locked transitive dependencies of `id`, excluding `id` itself
+    #[must_use]
+    pub fn transitive_deps(&self, id: &Identifier) -> BTreeSet<Identifier> {
+        let mut deps = BTreeSet::new();
+        let mut queue: Vec<_> = self
+            .entries
+            .get(id)
+            .map(|entry| entry.deps.iter().collect())
+            .unwrap_or_default();
+        while let Some(dep) = queue.pop() {
+            if dep == id || !deps.insert(dep.clone()) {
+                continue;
+            }
+            if let Some(entry) = self.entries.get(dep) {
+                queue.extend(&entry.deps);
+            }
+        }
+        deps
+    }
+
+    /// Returns the dependency graph of the lock, which contains a node for each locked dependency
+    /// and an edge from each dependency to every transitive dependency it introduced
+    #[must_use]
+    pub fn graph(&self) -> Graph {
+        let nodes = self
+            .entries
+            .iter()
+            .map(|(id, entry)| GraphNode {
+                id: id.clone(),
+                source: entry.source.clone(),
+                digest: entry.digest.clone(),
+                direct: entry.is_direct(),
+            })
+            .collect();
+        let edges = self
+            .entries
+            .iter()
+            .flat_map(|(id, Entry { deps, .. })| {
+                deps.iter().map(|dep| GraphEdge {
+                    from: id.clone(),
+                    to: dep.clone(),
+                })
+            })
+            .collect();
+        Graph { nodes, edges }
+    }
+
+    /// Returns the [`DigestPolicy`] the lock was produced with, i.e. which optional digests
+    /// are recorded in it
+    #[must_use]
+    pub fn digest_policy(&self) -> DigestPolicy {
+        DigestPolicy {
+            blake3: self
+                .entries
+                .values()
+                .any(|entry| entry.digest.blake3.is_some()),
+            files: self.entries.values().any(|entry| !entry.files.is_empty()),
+            integrity: self.entries.values().any(|entry| entry.integrity.is_some()),
+        }
+    }
+
+    /// Records optional digests of dependencies within `deps` according to `policy` and removes
+    /// all optional digests not required by it
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if computing a digest fails
+    pub async fn apply_digest_policy(
+        &mut self,
+        deps: impl AsRef<Path>,
+        policy: DigestPolicy,
+    ) -> Result<(), Error> {
+        if policy.files {
+            self.digest_files(deps).await?;
+        } else {
+            for entry in self.entries.values_mut() {
+                entry.files.clear();
+            }
+        }
+        for entry in self.entries.values_mut() {
+            entry.integrity = policy.integrity.then(|| entry.digest.integrity());
+        }
+        if !policy.blake3 {
+            for entry in self.entries.values_mut() {
+                entry.digest.blake3 = None;
+                for digest in entry.files.values_mut() {
+                    digest.blake3 = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Computes digests of all WIT files of each dependency within `deps` and records them in
+    /// the corresponding entries, which allows [`Lock::verify`] to report individual modified files
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if computing a digest fails
+    pub async fn digest_files(&mut self, deps: impl AsRef<Path>) -> Result<(), Error> {
+        let deps = deps.as_ref();
+        for (id, entry) in &mut self.entries {
+            let path = deps.join(entry.dir.as_deref().unwrap_or(id));
+            entry.files = Entry::digest_files(&path).await.with_context(|| {
+                format!("failed to compute file digests of `{}`", path.display())
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns names of all directories within `deps`, which no dependency present in the lock
+    /// is installed in
+    pub(crate) async fn unlocked(&self, deps: &Path) -> anyhow::Result<BTreeSet<Identifier>> {
+        let dirs: BTreeSet<_> = self
+            .entries
+            .iter()
+            .map(|(id, entry)| entry.dir.as_deref().unwrap_or(id))
+            .collect();
+        let dirs = &dirs;
+        match fs::read_dir(deps).await {
+            Ok(dir) => dir
+                .try_filter_map(|e| async move {
+                    if !e.file_type()?.is_dir() {
+                        return Ok(None);
+                    }
+                    let Some(id) = e.file_name().to_str().map(Identifier::from) else {
+                        return Ok(None);
+                    };
+                    Ok((!dirs.contains(id.as_str())).then_some(id))
+                })
+                .try_collect()
+                .await
+                .with_context(|| format!("failed to read `{}`", deps.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeSet::default()),
+            Err(e) => Err(e).with_context(|| format!("failed to read `{}`", deps.display())),
+        }
+    }
+
+    /// Removes all directories within `deps`, which are not present in the lock and returns
+    /// their identifiers
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if reading `deps` or removing a directory fails
+    pub async fn prune(&self, deps: impl AsRef<Path>) -> Result<BTreeSet<Identifier>, Error> {
+        let deps = deps.as_ref();
+        let unlocked = self.unlocked(deps).await?;
+        for id in &unlocked {
+            remove_dir_all(deps.join(id)).await?;
+            debug!("pruned `{id}`");
+        }
+        Ok(unlocked)
+    }
+
+    /// Merges `other` lock into `self`, keeping all entries, which are either equal in both
+    /// locks or present in only one of them. Conflicting entries are dropped, such that they
+    /// are re-derived from the manifest on next lock.
+    #[must_use]
+    pub fn merge(mut self, mut other: Self) -> Self {
+        self.entries
+            .retain(|id, entry| match other.entries.remove(id) {
+                Some(other) if other != *entry => {
+                    debug!("drop conflicting lock entry for `{id}`");
+                    false
+                }
+                _ => true,
+            });
+        self.entries.append(&mut other.entries);
+        self
+    }
+
+    /// Decodes a lock, which may contain VCS conflict markers, by [merging](Self::merge) both
+    /// sides of the conflict
+    pub(crate) fn from_conflicted(lock: &str) -> anyhow::Result<Self> {
+        let Some((ours, theirs)) = split_conflicts(lock) else {
+            return toml::from_str(lock)
+                .map(Self::migrate)
+                .context("failed to decode lock");
+        };
+        let ours: Self = toml::from_str(&ours).context("failed to decode lock conflict side")?;
+        let theirs: Self =
+            toml::from_str(&theirs).context("failed to decode lock conflict side")?;
+        Ok(ours.migrate().merge(theirs.migrate()))
+    }
+
+    /// Migrate the lock to the [current format version](Self::VERSION)
+    #[must_use]
+    pub fn migrate(self) -> Self {
+        let Self {
+            version, entries, ..
+        } = self;
+        if version < Self::VERSION {
+            debug!(
+                "migrate lock format from version {version} to {}",
+                Self::VERSION
+            );
+        }
+        // NOTE: Version `0` entries are a subset of version `1`, so only metadata needs updating
+        Self::from_iter(entries)
+    }
+}
+
+impl Default for Lock {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            generator: Some(env!("CARGO_PKG_VERSION").into()),
+            entries: BTreeMap::default(),
+        }
+    }
+}
+
+/// Summary of a locked dependency returned by [`Lock::list`]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ListEntry {
+    /// Dependency identifier
+    pub id: Identifier,
+    /// Dependency source, [None] if the dependency is transitive
+    #[serde(flatten)]
+    pub source: Option<EntrySource>,
+    /// Dependency digest
+    #[serde(flatten)]
+    pub digest: Digest,
+    /// Whether the dependency is declared in the manifest, as opposed to being transitive
+    pub direct: bool,
+    /// Path the dependency is installed at
+    pub path: PathBuf,
+}
+
+/// Node of the dependency tree returned by [`Lock::tree`]. Formatting it with [`fmt::Display`]
+/// renders the subtree rooted at the node.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct TreeNode {
+    /// Dependency identifier
+    pub id: Identifier,
+    /// Dependency source, [None] if the dependency is transitive
+    #[serde(flatten)]
+    pub source: Option<EntrySource>,
+    /// Transitive dependencies introduced by this dependency
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deps: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn fmt_label(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{} {source}", self.id),
+            None => write!(f, "{}", self.id),
+        }
+    }
+
+    fn fmt_deps(&self, f: &mut fmt::Formatter<'_>, prefix: &str) -> fmt::Result {
+        for (i, dep) in self.deps.iter().enumerate() {
+            let (branch, indent) = if i + 1 == self.deps.len() {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            write!(f, "\n{prefix}{branch}")?;
+            dep.fmt_label(f)?;
+            dep.fmt_deps(f, &format!("{prefix}{indent}"))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for TreeNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_label(f)?;
+        self.fmt_deps(f, "")
+    }
+}
+
+/// Dependency graph returned by [`Lock::graph`]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Graph {
+    /// Locked dependencies
+    pub nodes: Vec<GraphNode>,
+    /// Edges from dependencies to transitive dependencies they introduced
+    pub edges: Vec<GraphEdge>,
+}
+
+impl Graph {
+    /// Renders the graph in Graphviz DOT language. Direct dependencies are drawn as boxes.
+    #[must_use]
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        // NOTE: Writing to a `String` cannot fail
+        _ = self.write_dot(&mut dot);
+        dot
+    }
+
+    fn write_dot(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        fn quote(s: &str) -> String {
+            let s = s
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("\"{s}\"")
+        }
+
+        writeln!(w, "digraph deps {{")?;
+        for GraphNode {
+            id,
+            source,
+            digest,
+            direct,
+        } in &self.nodes
+        {
+            let label = match source {
+                Some(source) => quote(&format!("{id}\n{source}")),
+                None => quote(id),
+            };
+            let shape = if *direct { "box" } else { "ellipse" };
+            let tooltip = quote(&format!("sha256:{}", hex::encode(digest.sha256)));
+            writeln!(
+                w,
+                "    {} [label={label}, shape={shape}, tooltip={tooltip}];",
+                quote(id)
+            )?;
+        }
+        for GraphEdge { from, to } in &self.edges {
+            writeln!(w, "    {} -> {};", quote(from), quote(to))?;
+        }
+        write!(w, "}}")
+    }
+}
+
+/// Node of a dependency [Graph]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct GraphNode {
+    /// Dependency identifier
+    pub id: Identifier,
+    /// Dependency source, [None] if the dependency is transitive
+    #[serde(flatten)]
+    pub source: Option<EntrySource>,
+    /// Dependency digest
+    #[serde(flatten)]
+    pub digest: Digest,
+    /// Whether the dependency is declared in the manifest, as opposed to being transitive
+    pub direct: bool,
+}
+
+/// Edge of a dependency [Graph] from a dependency to a transitive dependency it introduced
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct GraphEdge {
+    /// Identifier of the dependency, which introduced `to`
+    pub from: Identifier,
+    /// Identifier of the transitive dependency
+    pub to: Identifier,
+}
+
+/// Divergence of a dependency from its [Lock] [Entry] found by [`Lock::verify`]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Divergence {
+    /// Dependency is locked, but missing
+    Missing,
+    /// Dependency contents do not match the locked digest
+    Modified {
+        /// Locked digest
+        expected: Box<Digest>,
+        /// Digest of the dependency contents
+        actual: Box<Digest>,
+        /// Diverging WIT files, empty if per-file digests are not locked
+        files: BTreeMap<String, Divergence>,
+    },
+    /// Dependency is present, but not locked
+    Unlocked,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing => write!(f, "locked, but missing"),
+            Self::Modified {
+                expected,
+                actual,
+                files,
+            } => {
+                write!(
+                    f,
+                    "sha256 mismatch, expected {}, got {}",
+                    hex::encode(expected.sha256),
+                    hex::encode(actual.sha256)
+                )?;
+                for (name, divergence) in files {
+                    write!(f, "\n  - `{name}`: {divergence}")?;
+                }
+                Ok(())
+            }
+            Self::Unlocked => write!(f, "present, but not locked"),
+        }
+    }
+}
+
+/// Status of a locked dependency audited against upstream, see
+/// [`audit_path`](crate::audit_path)
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AuditStatus {
+    /// Upstream contents match the lock
+    Unchanged,
+    /// Upstream contents do not match the lock, e.g. because a tag was moved or the resource
+    /// was tampered with
+    Changed {
+        /// Locked digest
+        expected: Box<Digest>,
+        /// Digest of the upstream contents
+        actual: Box<Digest>,
+    },
+    /// Transitive dependency is locked, but not contained in the upstream resource anymore
+    Missing,
+    /// Fetching the dependency from upstream failed
+    Failed {
+        /// Error message
+        error: String,
+    },
+}
+
+impl fmt::Display for AuditStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unchanged => write!(f, "unchanged upstream"),
+            Self::Changed { expected, actual } => write!(
+                f,
+                "changed upstream, locked sha256 {}, upstream sha256 {}",
+                hex::encode(expected.sha256),
+                hex::encode(actual.sha256)
+            ),
+            Self::Missing => write!(f, "locked, but not contained upstream anymore"),
+            Self::Failed { error } => write!(f, "failed to fetch from upstream: {error}"),
+        }
+    }
+}
+
+fn diverged_files(
+    expected: &BTreeMap<String, Digest>,
+    actual: &BTreeMap<String, Digest>,
+) -> BTreeMap<String, Divergence> {
+    let mut files = BTreeMap::default();
+    for (name, expected) in expected {
+        match actual.get(name) {
+            None => {
+                files.insert(name.clone(), Divergence::Missing);
+            }
+            Some(actual) if !actual.matches(expected) => {
+                files.insert(
+                    name.clone(),
+                    Divergence::Modified {
+                        expected: Box::new(expected.clone()),
+                        actual: Box::new(actual.clone()),
+                        files: BTreeMap::default(),
+                    },
+                );
+            }
+            Some(_) => {}
+        }
+    }
+    for name in actual.keys() {
+        if !expected.contains_key(name) {
+            files.insert(name.clone(), Divergence::Unlocked);
+        }
+    }
+    files
+}
+
+/// Splits text containing VCS conflict markers into both sides of the conflict, returns [None]
+/// if `s` contains no conflict markers
+fn split_conflicts(s: &str) -> Option<(String, String)> {
+    enum Side {
+        Both,
+        Ours,
+        Base,
+        Theirs,
+    }
+
+    let mut side = Side::Both;
+    let mut conflicted = false;
+    let mut ours = String::with_capacity(s.len());
+    let mut theirs = String::with_capacity(s.len());
+    for line in s.split_inclusive('\n') {
+        if line.starts_with("<<<<<<<") {
+            conflicted = true;
+            side = Side::Ours;
+        } else if line.starts_with("|||||||") {
+            side = Side::Base;
+        } else if line.starts_with("=======") {
+            side = Side::Theirs;
+        } else if line.starts_with(">>>>>>>") {
+            side = Side::Both;
+        } else {
+            match side {
+                Side::Both => {
+                    ours.push_str(line);
+                    theirs.push_str(line);
+                }
+                Side::Ours => ours.push_str(line),
+                Side::Base => {}
+                Side::Theirs => theirs.push_str(line),
+            }
+        }
+    }
+    conflicted.then_some((ours, theirs))
+}
+
+/// Change of a single [Lock] [Entry]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct EntryChange {
+    /// Old entry
+    pub old: Entry,
+    /// New entry
+    pub new: Entry,
+}
+
+/// Difference between two [Locks](Lock)
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct Diff {
+    /// Entries present only in the new lock
+    pub added: BTreeMap<Identifier, Entry>,
+    /// Entries present only in the old lock
+    pub removed: BTreeMap<Identifier, Entry>,
+    /// Entries present in both locks, which differ
+    pub changed: BTreeMap<Identifier, EntryChange>,
+}
+
+impl Diff {
+    /// Returns `true` if the locks contain equal entries
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Result of locking or updating dependencies, see [`lock_path`](crate::lock_path) and
+/// [`update_path`](crate::update_path)
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Outcome {
+    /// Resulting lock
+    pub lock: Lock,
+    /// Changes of the resulting lock compared to the previous one, if any
+    pub diff: Diff,
+    /// Whether the lock was created or modified
+    pub updated: bool,
+}
+
+fn fmt_source(source: Option<&EntrySource>) -> String {
+    source.map_or_else(|| "transitive".into(), |source| format!("`{source}`"))
+}
+
+fn fmt_opt(s: Option<&str>) -> String {
+    s.map_or_else(|| "none".into(), |s| format!("`{s}`"))
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines =
+            Vec::with_capacity(self.added.len() + self.removed.len() + self.changed.len());
+        for (id, Entry { source, digest, .. }) in &self.added {
+            lines.push(format!(
+                "added `{id}` ({}, sha256: {})",
+                fmt_source(source.as_ref()),
+                hex::encode(digest.sha256),
+            ));
+        }
+        for (id, Entry { source, digest, .. }) in &self.removed {
+            lines.push(format!(
+                "removed `{id}` ({}, sha256: {})",
+                fmt_source(source.as_ref()),
+                hex::encode(digest.sha256),
+            ));
+        }
+        for (id, EntryChange { old, new }) in &self.changed {
+            let mut changes = vec![];
+            if old.source != new.source {
+                changes.push(format!(
+                    "source: {} -> {}",
+                    fmt_source(old.source.as_ref()),
+                    fmt_source(new.source.as_ref()),
+                ));
+            }
+            if old.dir != new.dir {
+                changes.push(format!(
+                    "dir: {} -> {}",
+                    fmt_opt(old.dir.as_deref()),
+                    fmt_opt(new.dir.as_deref()),
+                ));
+            }
+            if old.package != new.package {
+                changes.push(format!(
+                    "package: {} -> {}",
+                    fmt_opt(old.package.as_deref()),
+                    fmt_opt(new.package.as_deref()),
+                ));
+            }
+            if old.digest.sha256 != new.digest.sha256 {
+                changes.push(format!(
+                    "sha256: {} -> {}",
+                    hex::encode(old.digest.sha256),
+                    hex::encode(new.digest.sha256),
+                ));
+            } else if old.digest != new.digest {
+                changes.push("digests".into());
+            }
+            if old.integrity != new.integrity {
+                changes.push(format!(
+                    "integrity: {} -> {}",
+                    fmt_opt(old.integrity.as_deref()),
+                    fmt_opt(new.integrity.as_deref()),
+                ));
+            }
+            if old.deps != new.deps {
+                changes.push(format!(
+                    "deps: [{}] -> [{}]",
+                    old.deps.iter().cloned().collect::<Vec<_>>().join(", "),
+                    new.deps.iter().cloned().collect::<Vec<_>>().join(", "),
+                ));
+            }
+            if old.required_by != new.required_by {
+                changes.push(format!(
+                    "required by: [{}] -> [{}]",
+                    old.required_by
+                        .iter()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    new.required_by
+                        .iter()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ));
+            }
+            if old.resolution != new.resolution {
+                changes.push("resolution metadata".into());
+            }
+            if old.files != new.files {
+                changes.push("file digests".into());
+            }
+            lines.push(format!("changed `{id}`: {}", changes.join(", ")));
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+const VERSION_KEY: &str = "version";
+const GENERATOR_KEY: &str = "wit-deps-version";
+
+/// Keys of the lock, which are reserved for metadata and therefore cannot be used as dependency
+/// identifiers
+pub(crate) const RESERVED_KEYS: [&str; 2] = [VERSION_KEY, GENERATOR_KEY];
+
+/// Returns the error message for dependency identifier `id` colliding with reserved key
+pub(crate) fn reserved_key_error(id: &str) -> String {
+    format!("`{id}` is reserved for lock metadata and cannot be used as a dependency identifier")
+}
+
+impl<'de> Deserialize<'de> for Lock {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // NOTE: Metadata keys are reserved, but locks predating versioning may contain entries
+        // keyed by them, which are rejected with a descriptive error
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Version {
+            Version(u64),
+            Entry(de::IgnoredAny),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Generator {
+            Generator(String),
+            Entry(de::IgnoredAny),
+        }
+
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Lock;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("a WIT dependency lock")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
+            where
+                V: de::MapAccess<'de>,
+            {
+                let mut version = None;
+                let mut generator = None;
+                let mut entries = BTreeMap::default();
+                while let Some(k) = map.next_key::<Identifier>()? {
+                    let entry = match k.as_ref() {
+                        VERSION_KEY => match map.next_value()? {
+                            Version::Version(v) => {
+                                if version.is_some() {
+                                    return Err(de::Error::duplicate_field(VERSION_KEY));
+                                }
+                                if v > Lock::VERSION {
+                                    return Err(de::Error::custom(format!(
+                                        "lock format version {v} is not supported by wit-deps {}, which supports versions up to {}, try upgrading wit-deps",
+                                        env!("CARGO_PKG_VERSION"),
+                                        Lock::VERSION,
+                                    )));
+                                }
+                                version = Some(v);
+                                continue;
+                            }
+                            Version::Entry(_) => {
+                                return Err(de::Error::custom(reserved_key_error(VERSION_KEY)))
+                            }
+                        },
+                        GENERATOR_KEY => match map.next_value()? {
+                            Generator::Generator(v) => {
+                                if generator.is_some() {
+                                    return Err(de::Error::duplicate_field(GENERATOR_KEY));
+                                }
+                                generator = Some(v);
+                                continue;
+                            }
+                            Generator::Entry(_) => {
+                                return Err(de::Error::custom(reserved_key_error(GENERATOR_KEY)))
+                            }
+                        },
+                        _ => map.next_value()?,
+                    };
+                    if entries.insert(k.clone(), entry).is_some() {
+                        return Err(de::Error::custom(format!("duplicate entry `{k}`")));
+                    }
+                }
+                Ok(Lock {
+                    version: version.unwrap_or_default(),
+                    generator,
+                    entries,
+                })
+            }
+        }
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl Serialize for Lock {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_map(None)?;
+        state.serialize_entry(VERSION_KEY, &self.version)?;
+        if let Some(generator) = &self.generator {
+            state.serialize_entry(GENERATOR_KEY, generator)?;
+        }
+        for (id, entry) in &self.entries {
+            if RESERVED_KEYS.contains(&id.as_str()) {
+                return Err(ser::Error::custom(reserved_key_error(id)));
+            }
+            state.serialize_entry(id, entry)?;
+        }
+        state.end()
+    }
+}
 
 impl Deref for Lock {
     type Target = BTreeMap<Identifier, Entry>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.entries
     }
 }
 
 impl DerefMut for Lock {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.entries
     }
 }
 
 impl FromIterator<(Identifier, Entry)> for Lock {
     fn from_iter<T: IntoIterator<Item = (Identifier, Entry)>>(iter: T) -> Self {
-        Self(BTreeMap::from_iter(iter))
+        Self {
+            entries: BTreeMap::from_iter(iter),
+            ..Self::default()
+        }
     }
 }
 
 impl Extend<(Identifier, Entry)> for Lock {
     fn extend<T: IntoIterator<Item = (Identifier, Entry)>>(&mut self, iter: T) {
-        self.0.extend(iter);
+        self.entries.extend(iter);
     }
 }
 
@@ -157,32 +1255,483 @@ mod tests {
                         source: Some(EntrySource::Url(
                             FOO_URL.parse().expect("failed to parse `foo` URL")
                         )),
+                        dir: None,
+                        package: None,
                         digest: Digest {
                             sha256: FromHex::from_hex(FOO_SHA256)
                                 .expect("failed to decode `foo` sha256"),
                             sha512: FromHex::from_hex(FOO_SHA512)
                                 .expect("failed to decode `foo` sha512"),
+                            blake3: None,
                         },
+                        integrity: None,
+                        resolution: EntrySourceResolution::default(),
                         deps: BTreeSet::default(),
+                        required_by: BTreeSet::default(),
+                        files: BTreeMap::default(),
                     }
                 )])
             );
             Ok(lock)
         }
 
-        let lock = toml::from_str(&format!(
+        let lock: Lock = toml::from_str(&format!(
             r#"
 foo = {{ url = "{FOO_URL}", sha256 = "{FOO_SHA256}", sha512 = "{FOO_SHA512}" }}
 "#
         ))
-        .context("failed to decode lock")
-        .and_then(assert_lock)?;
+        .context("failed to decode lock")?;
+        ensure!(lock.version() == 0);
+        ensure!(lock.generator().is_none());
+        let lock = assert_lock(lock.migrate())?;
 
         let lock = toml::to_string(&lock).context("failed to encode lock")?;
+        ensure!(lock.starts_with(&format!(
+            "version = {}\nwit-deps-version = \"{}\"\n",
+            Lock::VERSION,
+            env!("CARGO_PKG_VERSION")
+        )));
         toml::from_str(&lock)
             .context("failed to decode lock")
             .and_then(assert_lock)?;
 
         Ok(())
     }
+
+    #[test]
+    fn decode_resolution() -> anyhow::Result<()> {
+        const COMMIT: &str = "7071057e3eddae86fe7261e2eb199cf362d58a6e";
+        const FINAL_URL: &str = "https://codeload.example.com/baz";
+
+        let lock: Lock = toml::from_str(&format!(
+            r#"
+[foo]
+url = "{FOO_URL}"
+commit = "{COMMIT}"
+tag = "v0.1.0"
+final-url = "{FINAL_URL}"
+sha256 = "{FOO_SHA256}"
+sha512 = "{FOO_SHA512}"
+"#
+        ))
+        .context("failed to decode lock")?;
+        let foo = lock.get("foo").context("`foo` entry missing")?;
+        ensure!(
+            foo.resolution
+                == EntrySourceResolution {
+                    commit: Some(COMMIT.into()),
+                    registry_record: None,
+                    tag: Some("v0.1.0".into()),
+                    final_url: Some(FINAL_URL.parse().expect("failed to parse final URL")),
+                    license: None,
+                }
+        );
+        let encoded = toml::to_string(&lock).context("failed to encode lock")?;
+        ensure!(encoded.contains(&format!(r#"final-url = "{FINAL_URL}""#)));
+        ensure!(!encoded.contains("registry-record"));
+        ensure!(toml::from_str::<Lock>(&encoded).context("failed to decode lock")? == lock);
+        Ok(())
+    }
+
+    #[test]
+    fn resolution_tag() {
+        for (url, tag) in [
+            (
+                "https://github.com/WebAssembly/wasi-io/archive/refs/tags/v0.2.0.tar.gz",
+                Some("v0.2.0"),
+            ),
+            (
+                "https://codeload.github.com/WebAssembly/wasi-io/tar.gz/refs/tags/v0.2.0",
+                Some("v0.2.0"),
+            ),
+            (
+                "https://github.com/WebAssembly/wasi-io/archive/main.tar.gz",
+                None,
+            ),
+        ] {
+            assert_eq!(
+                EntrySourceResolution::tag_from_url(&url.parse().expect("failed to parse URL"))
+                    .as_deref(),
+                tag,
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let entry = |sha256: u8| {
+            Entry::new(
+                None,
+                Digest {
+                    sha256: [sha256; 32],
+                    sha512: [0; 64],
+                    blake3: None,
+                },
+                BTreeSet::default(),
+            )
+        };
+        let old = Lock::from([
+            ("foo".into(), entry(1)),
+            ("bar".into(), entry(2)),
+            ("baz".into(), entry(3)),
+        ]);
+        let new = Lock::from([
+            ("bar".into(), entry(4)),
+            ("baz".into(), entry(3)),
+            ("qux".into(), entry(5)),
+        ]);
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff,
+            Diff {
+                added: BTreeMap::from([("qux".into(), entry(5))]),
+                removed: BTreeMap::from([("foo".into(), entry(1))]),
+                changed: BTreeMap::from([(
+                    "bar".into(),
+                    EntryChange {
+                        old: entry(2),
+                        new: entry(4),
+                    }
+                )]),
+            }
+        );
+        assert_eq!(
+            diff.to_string(),
+            format!(
+                "added `qux` (transitive, sha256: {})\nremoved `foo` (transitive, sha256: {})\nchanged `bar`: sha256: {} -> {}",
+                "05".repeat(32),
+                "01".repeat(32),
+                "02".repeat(32),
+                "04".repeat(32),
+            )
+        );
+        assert!(new.diff(&new).is_empty());
+
+        let mut moved = entry(3);
+        moved.dir = Some("baz-0.1.0".into());
+        moved.package = Some("foo:baz@0.1.0".into());
+        moved.integrity = Some("sha256-AwMD".into());
+        let diff = new.diff(&Lock::from([("baz".into(), moved)]));
+        assert_eq!(
+            diff.to_string().lines().last(),
+            Some("changed `baz`: dir: none -> `baz-0.1.0`, package: none -> `foo:baz@0.1.0`, integrity: none -> `sha256-AwMD`")
+        );
+    }
+
+    #[test]
+    fn file_divergences() {
+        let digest = |sha256: u8| Digest {
+            sha256: [sha256; 32],
+            sha512: [0; 64],
+            blake3: None,
+        };
+        let expected = BTreeMap::from([
+            ("a.wit".into(), digest(1)),
+            ("b.wit".into(), digest(2)),
+            ("c.wit".into(), digest(3)),
+        ]);
+        let actual = BTreeMap::from([
+            ("b.wit".into(), digest(4)),
+            ("c.wit".into(), digest(3)),
+            ("d.wit".into(), digest(5)),
+        ]);
+        assert_eq!(
+            diverged_files(&expected, &actual),
+            BTreeMap::from([
+                ("a.wit".into(), Divergence::Missing),
+                (
+                    "b.wit".into(),
+                    Divergence::Modified {
+                        expected: Box::new(digest(2)),
+                        actual: Box::new(digest(4)),
+                        files: BTreeMap::default(),
+                    }
+                ),
+                ("d.wit".into(), Divergence::Unlocked),
+            ])
+        );
+        assert!(diverged_files(&expected, &expected).is_empty());
+    }
+
+    #[test]
+    fn merge() {
+        let entry = |sha256: u8| {
+            Entry::new(
+                None,
+                Digest {
+                    sha256: [sha256; 32],
+                    sha512: [0; 64],
+                    blake3: None,
+                },
+                BTreeSet::default(),
+            )
+        };
+        let ours = Lock::from([
+            ("foo".into(), entry(1)),
+            ("bar".into(), entry(2)),
+            ("baz".into(), entry(3)),
+        ]);
+        let theirs = Lock::from([
+            ("bar".into(), entry(4)),
+            ("baz".into(), entry(3)),
+            ("qux".into(), entry(5)),
+        ]);
+        assert_eq!(
+            ours.merge(theirs),
+            Lock::from([
+                ("foo".into(), entry(1)),
+                ("baz".into(), entry(3)),
+                ("qux".into(), entry(5)),
+            ])
+        );
+    }
+
+    #[test]
+    fn tree() -> anyhow::Result<()> {
+        let entry = |source: Option<&str>, deps: &[&str]| -> anyhow::Result<Entry> {
+            Ok(Entry::new(
+                source
+                    .map(|url| url.parse().map(EntrySource::Url))
+                    .transpose()?,
+                Digest {
+                    sha256: [0; 32],
+                    sha512: [0; 64],
+                    blake3: None,
+                },
+                deps.iter().copied().map(Identifier::from).collect(),
+            ))
+        };
+        let lock = Lock::from([
+            (
+                "foo".into(),
+                entry(Some("https://example.com/foo"), &["bar", "baz"])?,
+            ),
+            ("bar".into(), entry(None, &["qux"])?),
+            ("baz".into(), entry(None, &[])?),
+            ("qux".into(), entry(None, &["bar"])?),
+            ("quux".into(), entry(Some("https://example.com/quux"), &[])?),
+        ]);
+        let rendered: Vec<_> = lock.tree().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            rendered,
+            [
+                r"foo https://example.com/foo
+├── bar
+│   └── qux
+│       └── bar
+└── baz",
+                "quux https://example.com/quux",
+            ]
+        );
+        assert_eq!(
+            lock.transitive_deps(&"foo".into()),
+            BTreeSet::from(["bar".into(), "baz".into(), "qux".into()])
+        );
+        assert_eq!(
+            lock.transitive_deps(&"bar".into()),
+            BTreeSet::from(["qux".into()])
+        );
+        assert!(lock.transitive_deps(&"quux".into()).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn graph() -> anyhow::Result<()> {
+        let digest = Digest {
+            sha256: [0; 32],
+            sha512: [0; 64],
+            blake3: None,
+        };
+        let lock = Lock::from([
+            (
+                "foo".into(),
+                Entry::new(
+                    Some(EntrySource::Url("https://example.com/foo".parse()?)),
+                    digest.clone(),
+                    BTreeSet::from(["bar".into()]),
+                ),
+            ),
+            (
+                "bar".into(),
+                Entry::new(None, digest.clone(), BTreeSet::default()),
+            ),
+        ]);
+        let graph = lock.graph();
+        assert_eq!(
+            graph.edges,
+            [GraphEdge {
+                from: "foo".into(),
+                to: "bar".into(),
+            }]
+        );
+        let sha256 = hex::encode(digest.sha256);
+        assert_eq!(
+            graph.to_dot(),
+            format!(
+                r#"digraph deps {{
+    "bar" [label="bar", shape=ellipse, tooltip="sha256:{sha256}"];
+    "foo" [label="foo\nhttps://example.com/foo", shape=box, tooltip="sha256:{sha256}"];
+    "foo" -> "bar";
+}}"#
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn conflicted() -> anyhow::Result<()> {
+        assert_eq!(split_conflicts("foo\nbar\n"), None);
+        assert_eq!(
+            split_conflicts("a\n<<<<<<< HEAD\nb\n||||||| base\nc\n=======\nd\n>>>>>>> branch\ne\n"),
+            Some(("a\nb\ne\n".into(), "a\nd\ne\n".into()))
+        );
+        let lock = Lock::from_conflicted(&format!(
+            r#"version = 1
+
+[foo]
+url = "{FOO_URL}"
+<<<<<<< HEAD
+sha256 = "{FOO_SHA256}"
+sha512 = "{FOO_SHA512}"
+=======
+sha256 = "{}"
+sha512 = "{}"
+>>>>>>> branch
+
+[bar]
+sha256 = "{FOO_SHA256}"
+sha512 = "{FOO_SHA512}"
+"#,
+            "00".repeat(32),
+            "00".repeat(64),
+        ))?;
+        assert_eq!(lock.keys().collect::<Vec<_>>(), ["bar"]);
+        Ok(())
+    }
+
+    #[test]
+    fn portable_path() -> anyhow::Result<()> {
+        let lock: Lock = toml::from_str(&format!(
+            r#"
+foo = {{ path = "..\\path\\to\\foo", sha256 = "{FOO_SHA256}", sha512 = "{FOO_SHA512}" }}
+bar = {{ path = "../path/to/bar", sha256 = "{FOO_SHA256}", sha512 = "{FOO_SHA512}" }}
+"#
+        ))
+        .context("failed to decode lock")?;
+        ensure!(
+            lock.get("foo").and_then(|e| e.source.as_ref())
+                == Some(&EntrySource::Path("../path/to/foo".into()))
+        );
+        let lock = toml::to_string(&lock).context("failed to encode lock")?;
+        ensure!(lock.contains(r#"path = "../path/to/foo""#));
+        ensure!(lock.contains(r#"path = "../path/to/bar""#));
+        ensure!(!lock.contains('\\'));
+        Ok(())
+    }
+
+    #[test]
+    fn decode_blake3() -> anyhow::Result<()> {
+        let blake3 = "11".repeat(32);
+        let lock: Lock = toml::from_str(&format!(
+            r#"version = 1
+
+[foo]
+sha256 = "{FOO_SHA256}"
+sha512 = "{FOO_SHA512}"
+blake3 = "{blake3}"
+"#
+        ))?;
+        let digest = &lock.get("foo").context("`foo` entry missing")?.digest;
+        assert_eq!(digest.blake3, Some([0x11; 32]));
+        assert!(lock.digest_policy().blake3);
+        assert!(digest.matches(&Digest {
+            blake3: None,
+            ..digest.clone()
+        }));
+        assert!(!digest.matches(&Digest {
+            blake3: Some([0; 32]),
+            ..digest.clone()
+        }));
+        assert!(toml::to_string(&lock)?.contains(&format!("blake3 = \"{blake3}\"")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn integrity() -> anyhow::Result<()> {
+        let mut lock: Lock = toml::from_str(&format!(
+            r#"version = 1
+
+[foo]
+sha256 = "{FOO_SHA256}"
+sha512 = "{FOO_SHA512}"
+"#
+        ))?;
+        assert!(!lock.digest_policy().integrity);
+        let policy = DigestPolicy {
+            integrity: true,
+            ..DigestPolicy::default()
+        };
+        lock.apply_digest_policy("deps", policy).await?;
+        let digest = lock
+            .get("foo")
+            .context("`foo` entry missing")?
+            .digest
+            .clone();
+        let encoded = toml::to_string(&lock)?;
+        assert!(
+            encoded.contains(&format!("integrity = \"{}\"", digest.integrity())),
+            "{encoded}"
+        );
+        let mut lock: Lock = toml::from_str(&encoded)?;
+        assert!(lock.digest_policy().integrity);
+        lock.apply_digest_policy("deps", DigestPolicy::default())
+            .await?;
+        assert!(!toml::to_string(&lock)?.contains("integrity"));
+        Ok(())
+    }
+
+    #[test]
+    fn decode_version() -> anyhow::Result<()> {
+        let lock: Lock = toml::from_str(
+            r#"
+version = 1
+wit-deps-version = "0.0.0"
+"#,
+        )
+        .context("failed to decode lock")?;
+        ensure!(lock.version() == 1);
+        ensure!(lock.generator() == Some("0.0.0"));
+        ensure!(lock.is_empty());
+
+        let err = toml::from_str::<Lock>(&format!(
+            r#"
+[version]
+url = "{FOO_URL}"
+sha256 = "{FOO_SHA256}"
+sha512 = "{FOO_SHA512}"
+"#
+        ))
+        .expect_err("reserved entry identifier must be rejected");
+        ensure!(err.to_string().contains("is reserved"));
+
+        let lock = Lock::from_iter([(
+            "wit-deps-version".into(),
+            Entry::new(
+                None,
+                Digest {
+                    sha256: [0; 32],
+                    sha512: [0; 64],
+                    blake3: None,
+                },
+                BTreeSet::default(),
+            ),
+        )]);
+        ensure!(toml::to_string(&lock).is_err());
+
+        let err = toml::from_str::<Lock>(&format!("version = {}", Lock::VERSION + 1))
+            .expect_err("future lock version must be rejected");
+        ensure!(err.to_string().contains("try upgrading wit-deps"));
+        Ok(())
+    }
 }
@@ -1,6 +1,19 @@
+use crate::backup::Backup;
+use crate::digest::{parse_integrity, Integrity};
+use crate::error::{Cancelled, Collision, Conflict, Incomplete, Offline, Unpinned};
+use crate::fetch::{self, Request};
+use crate::github::GitHub;
+use crate::layout::{self, STAGING};
+use crate::license::{self, Violation as LicenseViolation};
+use crate::package::Package;
+use crate::registry::{self, Registry};
+use crate::telemetry::{self, phase, timed, CacheOutcome};
+use crate::vfs::{Limited, Linked};
 use crate::{
-    copy_wits, remove_dir_all, untar, Cache, Digest, DigestReader, Identifier, Lock, LockEntry,
-    LockEntrySource,
+    copy_wits, fs, remove_dir_all_in, tee_into, untar_archive, ArchiveLayout, Cache, CacheGcPolicy,
+    DepsLayout, Digest, DigestMismatch, DigestReader, Error, Fetch, Identifier, Lock,
+    LockDivergence, LockEntry, LockEntrySource, LockEntrySourceResolution, LockEvent, LockOptions,
+    OsVfs, Untared, Vfs, WkgConfig,
 };
 
 use core::convert::identity;
@@ -9,25 +22,25 @@ use core::fmt;
 use core::ops::Deref;
 use core::str::FromStr;
 
-use std::collections::{HashMap, HashSet};
-use std::env;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::ensure;
+use anyhow::anyhow;
 use anyhow::{bail, Context as _};
 use async_compression::futures::bufread::GzipDecoder;
-use futures::io::BufReader;
-use futures::lock::Mutex;
-use futures::{stream, AsyncWriteExt, StreamExt, TryStreamExt};
+use futures::future::Either;
+use futures::io::{AsyncReadExt as _, BufReader, Cursor};
+use futures::AsyncRead;
+use futures::{stream, StreamExt, TryStreamExt};
 use hex::FromHex;
-use reqwest::Proxy;
-use serde::{de, Deserialize};
-use tracing::{debug, error, info, instrument, trace, warn};
+use serde::{de, Deserialize, Serialize};
+use tracing::{debug, debug_span, error, field, info, instrument, trace, warn, Instrument as _};
 use url::Url;
-use urlencoding::encode;
 
 /// WIT dependency [Manifest] entry
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Entry {
     /// Dependency specification expressed as a resource (typically, a gzipped tarball) URL
@@ -38,10 +51,29 @@ pub enum Entry {
         sha256: Option<[u8; 32]>,
         /// Optional sha512 digest of this resource
         sha512: Option<[u8; 64]>,
+        /// Optional BLAKE3 digest of this resource
+        blake3: Option<[u8; 32]>,
+        /// Optional ed25519 signature of this resource, which is verified using `pubkey` before
+        /// the resource is unpacked
+        sig: Option<[u8; 64]>,
+        /// ed25519 public key `sig` is verified with
+        pubkey: Option<[u8; 32]>,
+        /// Maximum age of the cached resource, after which it is fetched again instead of
+        /// being unpacked from cache. Only applies to resources without a digest specified.
+        max_age: Option<Duration>,
+        /// Optional SPDX license expression, which takes precedence over the detected license
+        license: Option<String>,
     },
     /// Dependency specification expressed as a local path to a directory containing WIT
     /// definitions
-    Path(PathBuf),
+    Path {
+        /// Path to the directory containing WIT definitions
+        path: PathBuf,
+        /// Whether to copy the dependency on every lock, even if it is up-to-date with the lock
+        fresh: bool,
+        /// Optional SPDX license expression
+        license: Option<String>,
+    },
     // TODO: Support semver queries
 }
 
@@ -51,13 +83,22 @@ impl From<Url> for Entry {
             url,
             sha256: None,
             sha512: None,
+            blake3: None,
+            sig: None,
+            pubkey: None,
+            max_age: None,
+            license: None,
         }
     }
 }
 
 impl From<PathBuf> for Entry {
     fn from(path: PathBuf) -> Self {
-        Self::Path(path)
+        Self::Path {
+            path,
+            fresh: false,
+            license: None,
+        }
     }
 }
 
@@ -72,88 +113,335 @@ impl FromStr for Entry {
     }
 }
 
-impl<'de> Deserialize<'de> for Entry {
+/// Decodes the next hex-encoded value of `field` from `map`
+fn next_hex_value<'de, V, T>(map: &mut V, field: &str) -> Result<T, V::Error>
+where
+    V: de::MapAccess<'de>,
+    T: FromHex,
+    T::Error: fmt::Display,
+{
+    let v: String = map.next_value()?;
+    FromHex::from_hex(v)
+        .map_err(|e| de::Error::custom(format!("invalid `{field}` field value: {e}")))
+}
+
+/// Decodes the next string value of `field` from `map` using `parse`
+fn next_parsed_value<'de, V, T, E>(
+    map: &mut V,
+    field: &str,
+    parse: impl FnOnce(&str) -> Result<T, E>,
+) -> Result<T, V::Error>
+where
+    V: de::MapAccess<'de>,
+    E: fmt::Display,
+{
+    let v: String = map.next_value()?;
+    parse(&v).map_err(|e| de::Error::custom(format!("invalid `{field}` field value: {e}")))
+}
+
+/// Fields of a structured [Entry]
+const ENTRY_FIELDS: [&str; 11] = [
+    "blake3",
+    "fresh",
+    "integrity",
+    "license",
+    "max-age",
+    "path",
+    "pubkey",
+    "sha256",
+    "sha512",
+    "sig",
+    "url",
+];
+
+/// Fields of an [Entry] as decoded from the manifest, which may lack a source for entries
+/// keyed by a WIT package resolved from the [Registry]
+#[derive(Default)]
+struct EntryFields {
+    blake3: Option<[u8; 32]>,
+    fresh: Option<bool>,
+    integrity: Option<Integrity>,
+    license: Option<String>,
+    max_age: Option<Duration>,
+    path: Option<PathBuf>,
+    pubkey: Option<[u8; 32]>,
+    sha256: Option<[u8; 32]>,
+    sha512: Option<[u8; 64]>,
+    sig: Option<[u8; 64]>,
+    url: Option<Url>,
+}
+
+impl EntryFields {
+    /// Returns `true` if neither `url` nor `path` is specified
+    fn is_sourceless(&self) -> bool {
+        self.url.is_none() && self.path.is_none()
+    }
+
+    /// Returns the [Entry] specified by the fields
+    fn into_entry<E: de::Error>(self) -> Result<Entry, E> {
+        let Self {
+            blake3,
+            fresh,
+            integrity,
+            license,
+            max_age,
+            path,
+            pubkey,
+            mut sha256,
+            mut sha512,
+            sig,
+            url,
+        } = self;
+        if let Some((integrity_sha256, integrity_sha512)) = integrity {
+            if path.is_some() {
+                return Err(E::custom(
+                    "`integrity` is not supported in combination with `path`",
+                ));
+            }
+            match (sha256, integrity_sha256) {
+                (Some(a), Some(b)) if a != b => {
+                    return Err(E::custom("`integrity` and `sha256` digests differ"))
+                }
+                (None, v) => sha256 = v,
+                _ => {}
+            }
+            match (sha512, integrity_sha512) {
+                (Some(a), Some(b)) if a != b => {
+                    return Err(E::custom("`integrity` and `sha512` digests differ"))
+                }
+                (None, v) => sha512 = v,
+                _ => {}
+            }
+        }
+        if sig.is_some() != pubkey.is_some() {
+            return Err(E::custom("`sig` and `pubkey` must be specified together"));
+        }
+        if path.is_some() && sig.is_some() {
+            return Err(E::custom(
+                "`sig` and `pubkey` are not supported in combination with `path`",
+            ));
+        }
+        if path.is_some() && max_age.is_some() {
+            return Err(E::custom(
+                "`max-age` is not supported in combination with `path`",
+            ));
+        }
+        match (path, sha256, sha512, blake3, url) {
+            (Some(path), None, None, None, None) => Ok(Entry::Path {
+                path,
+                fresh: fresh.unwrap_or_default(),
+                license,
+            }),
+            (None, sha256, sha512, blake3, Some(url)) if fresh.is_none() => Ok(Entry::Url {
+                url,
+                sha256,
+                sha512,
+                blake3,
+                sig,
+                pubkey,
+                max_age,
+                license,
+            }),
+            (Some(_), _, _, _, None) => Err(E::custom(
+                "`sha256`, `sha512` and `blake3` are not supported in combination with `path`",
+            )),
+            (None, _, _, _, Some(_)) => Err(E::custom(
+                "`fresh` is not supported in combination with `url`",
+            )),
+            _ => Err(E::custom("eiter `url` or `path` must be specified")),
+        }
+    }
+}
+
+/// [`EntryFields`] deserialization visitor
+struct EntryVisitor;
+impl<'de> de::Visitor<'de> for EntryVisitor {
+    type Value = EntryFields;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a WIT dependency manifest entry")
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        match value.parse().map_err(de::Error::custom)? {
+            Entry::Url { url, .. } => Ok(EntryFields {
+                url: Some(url),
+                ..EntryFields::default()
+            }),
+            Entry::Path { path, .. } => Ok(EntryFields {
+                path: Some(path),
+                ..EntryFields::default()
+            }),
+        }
+    }
+
+    fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
+    where
+        V: de::MapAccess<'de>,
+    {
+        let mut fields = EntryFields::default();
+        while let Some(k) = map.next_key::<String>()? {
+            match k.as_ref() {
+                "blake3" => {
+                    if fields.blake3.is_some() {
+                        return Err(de::Error::duplicate_field("blake3"));
+                    }
+                    fields.blake3 = next_hex_value(&mut map, "blake3").map(Some)?;
+                }
+                "fresh" => {
+                    if fields.fresh.is_some() {
+                        return Err(de::Error::duplicate_field("fresh"));
+                    }
+                    fields.fresh = map.next_value().map(Some)?;
+                }
+                "integrity" => {
+                    if fields.integrity.is_some() {
+                        return Err(de::Error::duplicate_field("integrity"));
+                    }
+                    fields.integrity =
+                        next_parsed_value(&mut map, "integrity", parse_integrity).map(Some)?;
+                }
+                "license" => {
+                    if fields.license.is_some() {
+                        return Err(de::Error::duplicate_field("license"));
+                    }
+                    fields.license = map.next_value().map(Some)?;
+                }
+                "max-age" => {
+                    if fields.max_age.is_some() {
+                        return Err(de::Error::duplicate_field("max-age"));
+                    }
+                    fields.max_age = next_parsed_value(&mut map, "max-age", |v| {
+                        CacheGcPolicy::parse_age(v).map_err(|e| format!("{e:#}"))
+                    })
+                    .map(Some)?;
+                }
+                "path" => {
+                    if fields.path.is_some() {
+                        return Err(de::Error::duplicate_field("path"));
+                    }
+                    fields.path = next_parsed_value(&mut map, "path", str::parse).map(Some)?;
+                }
+                "pubkey" => {
+                    if fields.pubkey.is_some() {
+                        return Err(de::Error::duplicate_field("pubkey"));
+                    }
+                    fields.pubkey = next_hex_value(&mut map, "pubkey").map(Some)?;
+                }
+                "sha256" => {
+                    if fields.sha256.is_some() {
+                        return Err(de::Error::duplicate_field("sha256"));
+                    }
+                    fields.sha256 = next_hex_value(&mut map, "sha256").map(Some)?;
+                }
+                "sha512" => {
+                    if fields.sha512.is_some() {
+                        return Err(de::Error::duplicate_field("sha512"));
+                    }
+                    fields.sha512 = next_hex_value(&mut map, "sha512").map(Some)?;
+                }
+                "sig" => {
+                    if fields.sig.is_some() {
+                        return Err(de::Error::duplicate_field("sig"));
+                    }
+                    fields.sig = next_hex_value(&mut map, "sig").map(Some)?;
+                }
+                "url" => {
+                    if fields.url.is_some() {
+                        return Err(de::Error::duplicate_field("url"));
+                    }
+                    fields.url = next_parsed_value(&mut map, "url", str::parse).map(Some)?;
+                }
+                k => return Err(de::Error::unknown_field(k, &ENTRY_FIELDS)),
+            }
+        }
+        Ok(fields)
+    }
+}
+
+impl<'de> Deserialize<'de> for EntryFields {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        const FIELDS: [&str; 4] = ["path", "sha256", "sha512", "url"];
+        deserializer.deserialize_struct("Entry", &ENTRY_FIELDS, EntryVisitor)
+    }
+}
 
-        struct Visitor;
-        impl<'de> de::Visitor<'de> for Visitor {
-            type Value = Entry;
+impl<'de> Deserialize<'de> for Entry {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        EntryFields::deserialize(deserializer)?.into_entry()
+    }
+}
 
-            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
-                formatter.write_str("a WIT dependency manifest entry")
-            }
+impl Serialize for Entry {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::{Error as _, SerializeMap as _};
 
-            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
-            where
-                E: de::Error,
-            {
-                value.parse().map_err(de::Error::custom)
+        match self {
+            Self::Url {
+                url,
+                sha256,
+                sha512,
+                blake3,
+                sig,
+                pubkey,
+                max_age,
+                license,
+            } => {
+                let mut map = serializer.serialize_map(None)?;
+                map.serialize_entry("url", url.as_str())?;
+                if let Some(sha256) = sha256 {
+                    map.serialize_entry("sha256", &hex::encode(sha256))?;
+                }
+                if let Some(sha512) = sha512 {
+                    map.serialize_entry("sha512", &hex::encode(sha512))?;
+                }
+                if let Some(blake3) = blake3 {
+                    map.serialize_entry("blake3", &hex::encode(blake3))?;
+                }
+                if let Some(sig) = sig {
+                    map.serialize_entry("sig", &hex::encode(sig))?;
+                }
+                if let Some(pubkey) = pubkey {
+                    map.serialize_entry("pubkey", &hex::encode(pubkey))?;
+                }
+                if let Some(max_age) = max_age {
+                    map.serialize_entry("max-age", &format!("{}s", max_age.as_secs()))?;
+                }
+                if let Some(license) = license {
+                    map.serialize_entry("license", license)?;
+                }
+                map.end()
             }
-
-            fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
-            where
-                V: de::MapAccess<'de>,
-            {
-                let mut path = None;
-                let mut sha256 = None;
-                let mut sha512 = None;
-                let mut url = None;
-                while let Some((k, v)) = map.next_entry::<String, String>()? {
-                    match k.as_ref() {
-                        "path" => {
-                            if path.is_some() {
-                                return Err(de::Error::duplicate_field("path"));
-                            }
-                            path = v.parse().map(Some).map_err(|e| {
-                                de::Error::custom(format!("invalid `path` field value: {e}"))
-                            })?;
-                        }
-                        "sha256" => {
-                            if sha256.is_some() {
-                                return Err(de::Error::duplicate_field("sha256"));
-                            }
-                            sha256 = FromHex::from_hex(v).map(Some).map_err(|e| {
-                                de::Error::custom(format!("invalid `sha256` field value: {e}"))
-                            })?;
-                        }
-                        "sha512" => {
-                            if sha512.is_some() {
-                                return Err(de::Error::duplicate_field("sha512"));
-                            }
-                            sha512 = FromHex::from_hex(v).map(Some).map_err(|e| {
-                                de::Error::custom(format!("invalid `sha512` field value: {e}"))
-                            })?;
-                        }
-                        "url" => {
-                            if url.is_some() {
-                                return Err(de::Error::duplicate_field("url"));
-                            }
-                            url = v.parse().map(Some).map_err(|e| {
-                                de::Error::custom(format!("invalid `url` field value: {e}"))
-                            })?;
-                        }
-                        k => return Err(de::Error::unknown_field(k, &FIELDS)),
-                    }
+            Self::Path {
+                path,
+                fresh,
+                license,
+            } => {
+                let path = path
+                    .to_str()
+                    .ok_or_else(|| S::Error::custom("path is not valid UTF-8"))?;
+                let mut map = serializer.serialize_map(None)?;
+                map.serialize_entry("path", path)?;
+                if *fresh {
+                    map.serialize_entry("fresh", fresh)?;
                 }
-                match (path, sha256, sha512, url) {
-                    (Some(path), None, None, None) => Ok(Entry::Path(path)),
-                    (None, sha256, sha512, Some(url)) => Ok(Entry::Url {
-                        url,
-                        sha256,
-                        sha512,
-                    }),
-                    (Some(_), None | Some(_), None | Some(_), None) => Err(de::Error::custom(
-                        "`sha256` and `sha512` are not supported in combination with `path`",
-                    )),
-                    _ => Err(de::Error::custom("eiter `url` or `path` must be specified")),
+                if let Some(license) = license {
+                    map.serialize_entry("license", license)?;
                 }
+                map.end()
             }
         }
-        deserializer.deserialize_struct("Entry", &FIELDS, Visitor)
     }
 }
 
@@ -161,93 +449,563 @@ fn source_matches(
     digest: impl Into<Digest>,
     sha256: Option<[u8; 32]>,
     sha512: Option<[u8; 64]>,
+    blake3: Option<[u8; 32]>,
 ) -> bool {
     let digest = digest.into();
     sha256.map_or(true, |sha256| sha256 == digest.sha256)
         && sha512.map_or(true, |sha512| sha512 == digest.sha512)
+        && blake3.is_none_or(|blake3| Some(blake3) == digest.blake3)
+}
+
+/// Returns `true` if `url` refers to the same resource as `lurl` locked with `resolution`, i.e.
+/// URLs only differ cosmetically in fragment or query parameter order or `url` is the locked
+/// redirect target of `lurl`
+fn url_matches(url: &Url, lurl: &Url, resolution: &LockEntrySourceResolution) -> bool {
+    fn normalize(url: &Url) -> (Url, BTreeSet<(String, String)>) {
+        let pairs = url.query_pairs().into_owned().collect();
+        let mut url = url.clone();
+        url.set_fragment(None);
+        url.set_query(None);
+        (url, pairs)
+    }
+    url == lurl
+        || normalize(url) == normalize(lurl)
+        || resolution
+            .final_url
+            .as_ref()
+            .is_some_and(|final_url| normalize(url) == normalize(final_url))
+}
+
+/// Returns `true` if the entry associated with `url` was inserted into `cache` more than
+/// `max_age` ago
+async fn is_stale(cache: &impl Cache, url: &Url, max_age: Duration) -> bool {
+    match cache.inserted(url).await {
+        Ok(Some(inserted)) => SystemTime::now()
+            .duration_since(inserted)
+            .is_ok_and(|age| age > max_age),
+        Ok(None) => false,
+        Err(e) => {
+            warn!("failed to determine age of `{url}` in cache: {e:#}");
+            false
+        }
+    }
+}
+
+/// Reads the resource `tar_gz` into memory and verifies its ed25519 signature `sig` using
+/// `pubkey`
+async fn read_signed(
+    mut tar_gz: impl AsyncRead + Unpin,
+    sig: &[u8; 64],
+    pubkey: &[u8; 32],
+) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::default();
+    tar_gz
+        .read_to_end(&mut buf)
+        .await
+        .context("failed to read resource")?;
+    #[cfg(feature = "signing")]
+    {
+        crate::signature::verify_resource(&buf, sig, pubkey)?;
+        Ok(buf)
+    }
+    #[cfg(not(feature = "signing"))]
+    {
+        let _ = (buf, sig, pubkey);
+        bail!("verification of `sig` requires the `signing` feature")
+    }
+}
+
+/// Returns `true` if `path` does not exist or is an empty directory, for example, an
+/// uninitialized git submodule
+async fn is_missing(path: &Path) -> bool {
+    match fs::read_dir(path).await {
+        Ok(mut dir) => matches!(dir.next_entry().await, Ok(None)),
+        Err(e) => e.kind() == std::io::ErrorKind::NotFound,
+    }
+}
+
+/// Revalidates the entry associated with `url` in `cache` by comparing the `ETag` recorded for it
+/// with the one returned by upstream for a `HEAD` request. Returns `true` if the entry is
+/// unchanged upstream, in which case the validation is recorded in `cache`, if `writable`.
+async fn revalidate(fetch: &dyn Fetch, cache: &impl Cache, writable: bool, url: &Url) -> bool {
+    let etag = match cache.etag(url).await {
+        Ok(Some(etag)) => etag,
+        Ok(None) => return false,
+        Err(e) => {
+            warn!("failed to lookup `ETag` of `{url}` in cache: {e:#}");
+            return false;
+        }
+    };
+    let res = match fetch
+        .head(url)
+        .await
+        .and_then(|res| res.error_for_status().map_err(Into::into))
+    {
+        Ok(res) => res,
+        Err(e) => {
+            debug!("failed to revalidate `{url}`: {e}");
+            return false;
+        }
+    };
+    if res.etag.as_deref() != Some(etag.as_str()) {
+        return false;
+    }
+    if !writable {
+        return true;
+    }
+    if let Err(e) = cache.validated(url, Some(&etag)).await {
+        warn!("failed to record validation of `{url}` in cache: {e:#}");
+    }
+    true
+}
+
+/// Error returned if a resource has to be fetched in offline mode
+#[derive(Debug)]
+struct OfflineError(Url);
+
+impl fmt::Display for OfflineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{}` is not present in cache and cannot be fetched in offline mode",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for OfflineError {}
+
+/// Context of errors returned by [`Manifest::lock`] for a particular dependency, which can be
+/// retrieved via [`anyhow::Error::downcast_ref`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LockFailure {
+    /// Identifier of the dependency, which failed to lock
+    pub id: Identifier,
+}
+
+impl fmt::Display for LockFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to lock `{}`", self.id)
+    }
 }
 
-#[instrument(level = "trace", skip(deps))]
+/// Lock entries of transitive dependencies along with their [normalized contents](Package),
+/// if known, keyed by identifier
+type Transitive = BTreeMap<Identifier, (LockEntry, Option<Package>)>;
+
+#[instrument(level = "trace", skip(vfs, deps))]
 async fn lock_deps(
+    vfs: &dyn Vfs,
     deps: impl IntoIterator<Item = (Identifier, PathBuf)>,
-) -> anyhow::Result<HashMap<Identifier, LockEntry>> {
-    stream::iter(deps.into_iter().map(|(id, path)| async {
-        let entry = LockEntry::from_transitive_path(path).await?;
-        Ok((id, entry))
+) -> anyhow::Result<Transitive> {
+    stream::iter(deps.into_iter().map(|(id, path)| async move {
+        let digest = vfs
+            .digest(&path)
+            .await
+            .context("failed to compute digest")?;
+        let pkg = Package::read(vfs, &path)
+            .await
+            .map_err(|e| debug!("failed to read package at `{}`: {e}", path.display()))
+            .ok();
+        Ok((id, (LockEntry::new(None, digest, BTreeSet::default()), pkg)))
     }))
     .then(identity)
     .try_collect()
     .await
 }
 
+/// Returns collisions of WIT packages declared by multiple dependencies in `lock` installed
+/// in `deps` within `vfs`
+async fn collisions(vfs: &dyn Vfs, deps: &Path, lock: &Lock) -> Vec<Collision> {
+    let mut ids: BTreeMap<String, Vec<Identifier>> = BTreeMap::default();
+    for id in lock.keys() {
+        let path = lock.path(deps, id);
+        match Package::read(vfs, &path).await {
+            Ok(Package {
+                name: Some(name), ..
+            }) => ids.entry(name).or_default().push(id.clone()),
+            Ok(..) => {}
+            Err(e) => debug!("failed to read package at `{}`: {e}", path.display()),
+        }
+    }
+    ids.into_iter()
+        .filter(|(_, ids)| ids.len() > 1)
+        .map(|(package, ids)| Collision {
+            package,
+            ids: ids
+                .into_iter()
+                .map(|id| {
+                    let required_by = match lock.get(&id) {
+                        Some(LockEntry {
+                            source: None,
+                            required_by,
+                            ..
+                        }) => required_by.iter().cloned().collect(),
+                        _ => Vec::default(),
+                    };
+                    (id, required_by)
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Direct dependencies along with their lock entries and transitive dependencies
+type Locked = Vec<((Identifier, LockEntry), Transitive)>;
+
+/// Moves dependencies in `entries` to directories within `deps` within `vfs` named according to
+/// `layout` and returns `entries` with transitive dependencies identified by their directory
+/// names. Direct dependencies are expected to be installed where `lock` recorded them or, if not
+/// present in `lock`, in directories named by their identifiers. Transitive dependencies are
+/// expected to be staged or already installed in directories named by their identifiers.
+///
+/// Transitive dependencies, which would be installed in the directory of a direct dependency,
+/// are dropped.
+async fn relayout(
+    vfs: &dyn Vfs,
+    deps: &Path,
+    layout: DepsLayout,
+    lock: Option<&Lock>,
+    entries: Locked,
+) -> anyhow::Result<Locked> {
+    let staging = deps.join(STAGING);
+    let mut moves = Vec::default();
+    // Identifiers of direct dependencies keyed by the directory they are installed in
+    let mut dirs = BTreeMap::default();
+    let mut direct = Vec::with_capacity(entries.len());
+    for ((id, mut entry), transitive) in entries {
+        let src = lock.map_or_else(|| deps.join(&id), |lock| lock.path(deps, &id));
+        let pkg = Package::read(vfs, &src)
+            .await
+            .map_err(|e| debug!("failed to read package at `{}`: {e}", src.display()))
+            .ok();
+        let dir = layout.dir_name(&id, pkg.and_then(|pkg| pkg.name).as_deref());
+        let dst = deps.join(&dir);
+        if let Some(other) = dirs.insert(dir.clone(), id.clone()) {
+            bail!(
+                "`{other}` and `{id}` would both be installed in `{}`",
+                dst.display()
+            );
+        }
+        if src != dst {
+            moves.push((src, dst));
+        }
+        entry.dir = (dir != id).then_some(dir);
+        direct.push(((id, entry), transitive));
+    }
+    let mut entries = Vec::with_capacity(direct.len());
+    for ((id, mut entry), transitive) in direct {
+        entry.deps.clear();
+        let mut renamed = Transitive::default();
+        for (tid, (tentry, pkg)) in transitive {
+            let dir = layout.dir_name(&tid, pkg.as_ref().and_then(|pkg| pkg.name.as_deref()));
+            if let Some(other) = dirs.get(&dir) {
+                debug!("transitive dependency `{tid}` of `{id}` is installed as `{other}`, skip");
+                continue;
+            }
+            if dirs.values().any(|other| *other == dir) {
+                bail!("transitive dependency `{tid}` of `{id}` would be installed in `{dir}`, which is the identifier of a direct dependency");
+            }
+            let staged = staging.join(&id).join(&tid);
+            let src = if vfs.read_dir(&staged).await.is_ok() {
+                staged
+            } else {
+                deps.join(&tid)
+            };
+            let dst = deps.join(&dir);
+            if src != dst {
+                moves.push((src, dst));
+            }
+            entry.deps.insert(dir.clone());
+            renamed.insert(dir, (tentry, pkg));
+        }
+        entries.push(((id, entry), renamed));
+    }
+    layout::move_dirs(vfs, &moves).await?;
+    match remove_dir_all_in(vfs, &staging).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(entries)
+}
+
+/// Restores unpacked contents with `digest` and their transitive dependencies `deps` from an
+/// unpacked tree in `cache` to `out` and `out_deps`. Returns `None` if no such tree is present or the restored
+/// contents do not match `digest`.
+async fn restore_tree(
+    vfs: &dyn Vfs,
+    cache: &impl Cache,
+    digest: &Digest,
+    deps: &BTreeSet<Identifier>,
+    out: &Path,
+    out_deps: Option<&Path>,
+    skip_deps: &BTreeSet<Identifier>,
+) -> anyhow::Result<Option<(Digest, Transitive)>> {
+    let Some(tree) = cache.get_tree(digest).await? else {
+        return Ok(None);
+    };
+    let copied = copy_wits(vfs, &tree, out, out_deps, skip_deps).await?;
+    let restored = vfs.digest(out).await.context("failed to compute digest")?;
+    if !restored.matches(digest)
+        || deps
+            .iter()
+            .any(|id| !skip_deps.contains(id) && !copied.contains_key(id))
+    {
+        warn!(
+            "unpacked tree at `{}` does not match the lock",
+            tree.display()
+        );
+        return Ok(None);
+    }
+    let deps = lock_deps(vfs, copied).await?;
+    Ok(Some((restored, deps)))
+}
+
+/// Stores unpacked contents of `out` with `digest` and their transitive dependencies `deps` as
+/// an unpacked tree in `cache`
+async fn insert_tree(
+    cache: &impl Cache,
+    digest: &Digest,
+    out: &Path,
+    deps: &BTreeMap<Identifier, PathBuf>,
+) {
+    if let Err(e) = cache.insert_tree(digest, out, deps).await {
+        warn!(
+            "failed to insert unpacked tree of `{}` into cache: {e:#}",
+            out.display()
+        );
+    }
+}
+
 impl Entry {
-    #[instrument(level = "trace", skip(at, out, lock, cache, skip_deps))]
+    /// Returns a URL entry for `url` with no digests and no maximum age, which can be specified
+    /// using the `with_*` methods
+    #[must_use]
+    pub fn url(url: Url) -> Self {
+        Self::from(url)
+    }
+
+    /// Returns a path entry for `path`, which is not [fresh](Self::with_fresh)
+    #[must_use]
+    pub fn path(path: impl Into<PathBuf>) -> Self {
+        Self::from(path.into())
+    }
+
+    /// Returns the entry with the expected sha256 digest of the resource set to `sha256`.
+    /// Has no effect on path entries.
+    #[must_use]
+    pub fn with_sha256(mut self, sha256: [u8; 32]) -> Self {
+        if let Self::Url { sha256: v, .. } = &mut self {
+            *v = Some(sha256);
+        }
+        self
+    }
+
+    /// Returns the entry with the expected sha512 digest of the resource set to `sha512`.
+    /// Has no effect on path entries.
+    #[must_use]
+    pub fn with_sha512(mut self, sha512: [u8; 64]) -> Self {
+        if let Self::Url { sha512: v, .. } = &mut self {
+            *v = Some(sha512);
+        }
+        self
+    }
+
+    /// Returns the entry with the expected BLAKE3 digest of the resource set to `blake3`.
+    /// Has no effect on path entries.
+    #[must_use]
+    pub fn with_blake3(mut self, blake3: [u8; 32]) -> Self {
+        if let Self::Url { blake3: v, .. } = &mut self {
+            *v = Some(blake3);
+        }
+        self
+    }
+
+    /// Returns the entry with the expected ed25519 signature of the resource set to `sig`,
+    /// which is verified using `pubkey`. Has no effect on path entries.
+    #[must_use]
+    pub fn with_signature(mut self, sig: [u8; 64], pubkey: [u8; 32]) -> Self {
+        if let Self::Url {
+            sig: v, pubkey: k, ..
+        } = &mut self
+        {
+            *v = Some(sig);
+            *k = Some(pubkey);
+        }
+        self
+    }
+
+    /// Returns the entry with the maximum age of the cached resource set to `max_age`.
+    /// Has no effect on path entries.
+    #[must_use]
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        if let Self::Url { max_age: v, .. } = &mut self {
+            *v = Some(max_age);
+        }
+        self
+    }
+
+    /// Returns the entry, which is copied on every lock if `fresh` is `true`.
+    /// Has no effect on URL entries.
+    #[must_use]
+    pub fn with_fresh(mut self, fresh: bool) -> Self {
+        if let Self::Path { fresh: v, .. } = &mut self {
+            *v = fresh;
+        }
+        self
+    }
+
+    /// Returns the entry with the SPDX license expression of the dependency set to `license`,
+    /// which takes precedence over the license detected in license files of the fetched archive
+    #[must_use]
+    pub fn with_license(mut self, license: impl Into<String>) -> Self {
+        match &mut self {
+            Self::Url { license: v, .. } | Self::Path { license: v, .. } => {
+                *v = Some(license.into());
+            }
+        }
+        self
+    }
+
+    /// Returns the SPDX license expression of the dependency specified in the manifest, if any
+    #[must_use]
+    pub fn license(&self) -> Option<&str> {
+        match self {
+            Self::Url { license, .. } | Self::Path { license, .. } => license.as_deref(),
+        }
+    }
+
+    /// Returns `true` if `entry` locks this entry and the dependency would not be re-fetched
+    /// or re-copied, provided that its contents match the locked digest
+    fn is_up_to_date(&self, entry: &LockEntry) -> bool {
+        match (self, &entry.source) {
+            (Self::Url { url, .. }, Some(LockEntrySource::Url(lurl))) => {
+                url_matches(url, lurl, &entry.resolution)
+            }
+            (Self::Path { path, fresh, .. }, Some(LockEntrySource::Path(lpath))) => {
+                !*fresh && path == lpath
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if `entry` was locked from this manifest entry, i.e. sources are equal.
+    /// Note, that digests specified in the manifest are verified on fetch and are not compared
+    /// to the locked digest, which is computed over the unpacked contents.
+    fn is_locked_by(&self, entry: &LockEntry) -> bool {
+        match (self, &entry.source) {
+            (Self::Url { url, .. }, Some(LockEntrySource::Url(lurl))) => url == lurl,
+            (Self::Path { path, .. }, Some(LockEntrySource::Path(lpath))) => path == lpath,
+            _ => false,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    #[instrument(
+        level = "trace",
+        skip(at, out, lock, cache, skip_deps, fetch, vfs, options)
+    )]
     async fn lock(
         self,
         at: Option<impl AsRef<Path>>,
         out: impl AsRef<Path>,
         lock: Option<&LockEntry>,
         cache: Option<&impl Cache>,
-        skip_deps: &HashSet<Identifier>,
-    ) -> anyhow::Result<(LockEntry, HashMap<Identifier, LockEntry>)> {
+        skip_deps: &BTreeSet<Identifier>,
+        id: &Identifier,
+        layout: DepsLayout,
+        fetch: &dyn Fetch,
+        vfs: &dyn Vfs,
+        options: &LockOptions,
+    ) -> anyhow::Result<(LockEntry, Transitive)> {
         let out = out.as_ref();
-        let proxy_url = env::var("PROXY_SERVER").ok();
-        let proxy_username = env::var("PROXY_USERNAME").ok();
-        let proxy_password = env::var("PROXY_PASSWORD").ok();
-        let http_client = if let (Some(proxy_url), Some(proxy_username), Some(proxy_password)) =
-            (proxy_url, proxy_username, proxy_password)
-        {
-            let proxy_with_auth = format!(
-                "http://{}:{}@{}",
-                encode(&proxy_username),
-                encode(&proxy_password),
-                proxy_url
-            );
-            reqwest::Client::builder()
-                .proxy(Proxy::all(proxy_with_auth)?)
-                .build()
-                .expect("failed to create client")
-        } else {
-            reqwest::Client::new()
+        // NOTE: Unless installed flat, transitive dependencies are staged and moved to their
+        // final location once the packages of all dependencies are known. Their locked
+        // identifiers are the final directory names and cannot be compared to staged ones.
+        let (out_deps, unchecked) = match (layout, out.parent()) {
+            (DepsLayout::Flat, deps) => (deps.map(Path::to_path_buf), None),
+            (_, deps) => (
+                deps.map(|deps| deps.join(STAGING).join(id)),
+                Some(BTreeSet::default()),
+            ),
+        };
+        let out_deps = out_deps.as_deref();
+        // NOTE: Trees and snapshots are copied from `out`, which must be present on disk
+        let insert_trees = options.vfs().is_none();
+        let offline = options.offline();
+        // NOTE: `cache` is only read from and `writer` is only written to as allowed by the policy
+        let policy = options.cache_policy();
+        let writer = cache.filter(|_| policy.writes());
+        let cache = cache.filter(|_| policy.reads());
+        // Resolution metadata, which cannot be recovered from cache, is preserved if the URL did
+        // not change
+        let lresolution = match (&self, lock) {
+            (
+                Self::Url { url, .. },
+                Some(LockEntry {
+                    source: Some(LockEntrySource::Url(lurl)),
+                    resolution,
+                    ..
+                }),
+            ) if url_matches(url, lurl, resolution) => resolution.clone(),
+            _ => LockEntrySourceResolution::default(),
         };
-
         let entry = if let Some(LockEntry {
             source,
+            resolution: lres,
             digest: ldigest,
             deps: ldeps,
+            ..
         }) = lock
         {
             let deps = if ldeps.is_empty() {
-                Ok(HashMap::default())
+                Ok(BTreeMap::default())
             } else {
                 let base = out
                     .parent()
                     .with_context(|| format!("`{}` does not have a parent", out.display()))?;
-                lock_deps(ldeps.iter().cloned().map(|id| {
-                    let path = base.join(&id);
-                    (id, path)
-                }))
+                timed(
+                    phase::DIGEST,
+                    lock_deps(
+                        vfs,
+                        ldeps.iter().cloned().map(|id| {
+                            let path = base.join(&id);
+                            (id, path)
+                        }),
+                    ),
+                )
                 .await
             };
-            match (LockEntry::digest(out).await, source, deps) {
-                (Ok(digest), Some(source), Ok(deps)) if digest == *ldigest => {
+            match (timed(phase::DIGEST, vfs.digest(out)).await, source, deps) {
+                (Ok(digest), Some(source), Ok(deps)) if digest.matches(ldigest) => {
                     // NOTE: Manually deleting transitive dependencies of this
                     // dependency from `dst` is considered user error
                     // TODO: Check that transitive dependencies are in sync
                     match (self, source) {
-                        (Self::Url { url, .. }, LockEntrySource::Url(lurl)) if url == *lurl => {
+                        (Self::Url { url, .. }, LockEntrySource::Url(lurl))
+                            if url_matches(&url, lurl, lres) =>
+                        {
+                            // NOTE: Cosmetic URL changes are re-verified by the digest of `out`
+                            // and do not require a re-fetch
                             debug!("`{}` is already up-to-date, skip fetch", out.display());
-                            return Ok((
-                                LockEntry::new(
-                                    Some(LockEntrySource::Url(url)),
-                                    digest,
-                                    deps.keys().cloned().collect(),
-                                ),
-                                deps,
-                            ));
+                            options.report(|| LockEvent::Skipped { id: id.clone() });
+                            let mut entry = LockEntry::new(
+                                Some(LockEntrySource::Url(url)),
+                                digest,
+                                deps.keys().cloned().collect(),
+                            );
+                            entry.resolution = lresolution;
+                            return Ok((entry, deps));
                         }
-                        (Self::Path(path), LockEntrySource::Path(lpath)) if path == *lpath => {
+                        (
+                            Self::Path {
+                                path, fresh: false, ..
+                            },
+                            LockEntrySource::Path(lpath),
+                        ) if path == *lpath => {
                             debug!("`{}` is already up-to-date, skip copy", out.display());
+                            options.report(|| LockEvent::Skipped { id: id.clone() });
                             return Ok((
                                 LockEntry::new(
                                     Some(LockEntrySource::Path(path)),
@@ -286,19 +1044,86 @@ impl Entry {
         } else {
             self
         };
+        let source = match &entry {
+            Self::Path { path, .. } => LockEntrySource::Path(path.clone()),
+            Self::Url { url, .. } => LockEntrySource::Url(url.clone()),
+        };
+        options.before_install(id, &source, out).await?;
         match entry {
-            Self::Path(path) => {
+            Self::Path { path, .. } => {
                 let src = at.map(|at| at.as_ref().join(&path));
                 let src = src.as_ref().unwrap_or(&path);
-                let deps = copy_wits(src, out, skip_deps).await?;
+                if let (
+                    Some(cache),
+                    Some(LockEntry {
+                        source: Some(LockEntrySource::Path(lpath)),
+                        digest: ldigest,
+                        deps: ldeps,
+                        ..
+                    }),
+                ) = (cache, lock)
+                {
+                    if *lpath == path && is_missing(src).await {
+                        match timed(
+                            phase::CACHE,
+                            restore_tree(
+                                vfs,
+                                cache,
+                                ldigest,
+                                unchecked.as_ref().unwrap_or(ldeps),
+                                out,
+                                out_deps,
+                                skip_deps,
+                            ),
+                        )
+                        .await
+                        {
+                            Ok(Some((digest, deps))) => {
+                                warn!(
+                                    "`{}` is missing, restored `{}` from snapshot in cache",
+                                    src.display(),
+                                    out.display()
+                                );
+                                options.installed(id, &source, out).await?;
+                                return Ok((
+                                    LockEntry::new(
+                                        Some(LockEntrySource::Path(path)),
+                                        digest,
+                                        deps.keys().cloned().collect(),
+                                    ),
+                                    deps,
+                                ));
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                warn!(
+                                    "failed to restore `{}` from snapshot in cache: {e:#}",
+                                    out.display()
+                                );
+                            }
+                        }
+                    }
+                }
+                let deps =
+                    timed(phase::COPY, copy_wits(vfs, src, out, out_deps, skip_deps)).await?;
                 trace!(?deps, "copied WIT definitions to `{}`", out.display());
-                let deps = lock_deps(deps).await?;
+                options.installed(id, &source, out).await?;
+                let snapshot_deps = deps.clone();
+                let deps = timed(phase::DIGEST, lock_deps(vfs, deps)).await?;
                 trace!(
                     ?deps,
                     "locked transitive dependencies of `{}`",
                     out.display()
                 );
-                let digest = LockEntry::digest(out).await?;
+                let digest = timed(phase::DIGEST, vfs.digest(out)).await?;
+                if let (Some(cache), true) = (writer, insert_trees) {
+                    if let Err(e) = cache.insert_snapshot(&digest, out, &snapshot_deps).await {
+                        warn!(
+                            "failed to insert snapshot of `{}` into cache: {e:#}",
+                            out.display()
+                        );
+                    }
+                }
                 Ok((
                     LockEntry::new(
                         Some(LockEntrySource::Path(path)),
@@ -312,36 +1137,165 @@ impl Entry {
                 url,
                 sha256,
                 sha512,
+                blake3,
+                sig,
+                pubkey,
+                max_age,
+                ..
             } => {
-                let cache = if let Some(cache) = cache {
-                    match cache.get(&url).await {
-                        Err(e) => error!("failed to get `{url}` from cache: {e}"),
-                        Ok(None) => debug!("`{url}` not present in cache"),
-                        Ok(Some(tar_gz)) => {
-                            let mut hashed = DigestReader::from(tar_gz);
-                            match untar(
-                                GzipDecoder::new(BufReader::new(&mut hashed)),
+                let signature = match (sig, pubkey) {
+                    (Some(sig), Some(pubkey)) => Some((sig, pubkey)),
+                    (None, None) => None,
+                    _ => bail!("`sig` and `pubkey` must be specified together"),
+                };
+                if let (
+                    Some(cache),
+                    Some(LockEntry {
+                        source: Some(LockEntrySource::Url(lurl)),
+                        resolution: lres,
+                        digest: ldigest,
+                        deps: ldeps,
+                        ..
+                    }),
+                ) = (cache, lock)
+                {
+                    // NOTE: Signatures are verified over the resource, which is not available
+                    // in unpacked trees
+                    if signature.is_none() && url_matches(&url, lurl, lres) {
+                        match timed(
+                            phase::CACHE,
+                            restore_tree(
+                                vfs,
+                                cache,
+                                ldigest,
+                                unchecked.as_ref().unwrap_or(ldeps),
                                 out,
+                                out_deps,
                                 skip_deps,
+                            ),
+                        )
+                        .await
+                        {
+                            Ok(Some((digest, deps))) => {
+                                debug!("restored `{url}` from unpacked tree in cache");
+                                telemetry::cache(CacheOutcome::Hit);
+                                options.report(|| LockEvent::CacheHit {
+                                    id: id.clone(),
+                                    url: url.clone(),
+                                });
+                                options.installed(id, &source, out).await?;
+                                let mut entry = LockEntry::new(
+                                    Some(LockEntrySource::Url(url)),
+                                    digest,
+                                    deps.keys().cloned().collect(),
+                                );
+                                entry.resolution = lresolution;
+                                return Ok((entry, deps));
+                            }
+                            Ok(None) => {}
+                            Err(e) => {
+                                warn!(
+                                    "failed to restore `{url}` from unpacked tree in cache: {e:#}"
+                                );
+                            }
+                        }
+                    }
+                }
+                if let Some(cache) = cache {
+                    // NOTE: Resources without a digest specified may change upstream, therefore
+                    // they are revalidated once they exceed `max-age`
+                    let unpinned = sha256.is_none() && sha512.is_none() && blake3.is_none();
+                    let cached = match max_age {
+                        Some(max_age)
+                            if unpinned && !offline && is_stale(cache, &url, max_age).await =>
+                        {
+                            if revalidate(fetch, cache, policy.writes(), &url).await {
+                                debug!("cached `{url}` is older than `max-age`, but unchanged upstream");
+                                timed(phase::CACHE, cache.get(&url)).await
+                            } else {
+                                debug!("cached `{url}` is older than `max-age`, refetch");
+                                Ok(None)
+                            }
+                        }
+                        _ => timed(phase::CACHE, cache.get(&url)).await,
+                    };
+                    let cached = match (cached, signature) {
+                        (Ok(Some(tar_gz)), Some((sig, pubkey))) => {
+                            match read_signed(tar_gz, &sig, &pubkey).await {
+                                Ok(buf) => Ok(Some(Either::Left(Cursor::new(buf)))),
+                                Err(e) => {
+                                    warn!("cached `{url}` failed signature verification: {e:#}");
+                                    Ok(None)
+                                }
+                            }
+                        }
+                        (cached, _) => cached.map(|tar_gz| tar_gz.map(Either::Right)),
+                    };
+                    match cached {
+                        Err(e) => error!("failed to get `{url}` from cache: {e}"),
+                        Ok(None) => {
+                            debug!("`{url}` not present in cache");
+                            telemetry::cache(CacheOutcome::Miss);
+                            options.report(|| LockEvent::CacheMiss {
+                                id: id.clone(),
+                                url: url.clone(),
+                            });
+                        }
+                        Ok(Some(tar_gz)) => {
+                            let mut hashed = DigestReader::from(tar_gz);
+                            match timed(
+                                phase::CACHE,
+                                untar_archive(
+                                    vfs,
+                                    GzipDecoder::new(BufReader::new(&mut hashed)),
+                                    out,
+                                    out_deps,
+                                    skip_deps,
+                                    &ArchiveLayout::default(),
+                                ),
                             )
                             .await
                             {
-                                Ok(deps) if source_matches(hashed, sha256, sha512) => {
+                                Ok(Untared {
+                                    deps,
+                                    commit,
+                                    licenses,
+                                }) if source_matches(hashed, sha256, sha512, blake3) => {
                                     debug!("unpacked `{url}` from cache");
-                                    let deps = lock_deps(deps).await?;
-                                    let entry = LockEntry::from_url(
-                                        url,
-                                        out,
+                                    telemetry::cache(CacheOutcome::Hit);
+                                    options.report(|| LockEvent::CacheHit {
+                                        id: id.clone(),
+                                        url: url.clone(),
+                                    });
+                                    options.installed(id, &source, out).await?;
+                                    let tree_deps = deps.clone();
+                                    let deps = timed(phase::DIGEST, lock_deps(vfs, deps)).await?;
+                                    let tag = LockEntrySourceResolution::tag_from_url(&url);
+                                    let digest = timed(phase::DIGEST, vfs.digest(out))
+                                        .await
+                                        .context("failed to compute digest")?;
+                                    let mut entry = LockEntry::new(
+                                        Some(LockEntrySource::Url(url)),
+                                        digest,
                                         deps.keys().cloned().collect(),
-                                    )
-                                    .await?;
+                                    );
+                                    entry.resolution = LockEntrySourceResolution {
+                                        commit,
+                                        tag,
+                                        license: license::expression(&licenses),
+                                        ..LockEntrySourceResolution::default()
+                                    }
+                                    .or(lresolution);
+                                    if let (Some(cache), true) = (writer, insert_trees) {
+                                        insert_tree(cache, &entry.digest, out, &tree_deps).await;
+                                    }
                                     return Ok((entry, deps));
                                 }
-                                Ok(deps) => {
+                                Ok(Untared { deps, .. }) => {
                                     warn!("cache hash mismatch for `{url}`");
-                                    remove_dir_all(out).await?;
+                                    remove_dir_all_in(vfs, out).await?;
                                     for (_, dep) in deps {
-                                        remove_dir_all(&dep).await?;
+                                        remove_dir_all_in(vfs, &dep).await?;
                                     }
                                 }
                                 Err(e) => {
@@ -350,65 +1304,118 @@ impl Entry {
                             }
                         }
                     }
-                    if let Ok(cache) = cache.insert(&url).await {
-                        Some(cache)
-                    } else {
-                        None
+                }
+                if offline {
+                    return Err(OfflineError(url).into());
+                }
+                if let Some(cache) = cache {
+                    match cache.failed_lookup(&url).await {
+                        Ok(Some(status)) => bail!(
+                            "`{url}` lookup recently failed with HTTP status {status}, not retrying until the failure expires in cache"
+                        ),
+                        Ok(None) => {}
+                        Err(e) => warn!("failed to lookup failures of `{url}` in cache: {e:#}"),
+                    }
+                }
+                let cache = if let Some(cache) = writer {
+                    match cache.insert(&url).await {
+                        Ok(cache) => Some(cache),
+                        Err(e) => {
+                            warn!("failed to insert `{url}` into cache: {e:#}");
+                            None
+                        }
                     }
                 } else {
                     None
                 };
-                let cache = Arc::new(Mutex::new(cache));
-                let (digest, deps) = match url.scheme() {
+                let (
+                    digest,
+                    Untared {
+                        deps,
+                        commit,
+                        licenses,
+                    },
+                    final_url,
+                    etag,
+                    tee,
+                ) = match url.scheme() {
                     "http" | "https" => {
+                        let _permit = options
+                            .download_permits()
+                            .acquire()
+                            .await
+                            .context("failed to acquire download permit")?;
                         info!("fetch `{url}` into `{}`", out.display());
+                        options.report(|| LockEvent::FetchStarted {
+                            id: id.clone(),
+                            url: url.clone(),
+                        });
 
-                        let res = http_client
-                            .get(url.clone())
-                            .send()
+                        let res = timed(phase::DOWNLOAD, fetch.get(&url))
                             .await
-                            .context("failed to GET")
-                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?
-                            .error_for_status()
-                            .context("GET request failed")
-                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-                        let tar_gz = res
-                            .bytes_stream()
-                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
-                            .then(|chunk| async {
-                                let chunk = chunk?;
-                                let mut cache = cache.lock().await;
-                                let cache_res = if let Some(w) = cache.as_mut().map(|w| async {
-                                    if let Err(e) = w.write(&chunk).await {
-                                        error!("failed to write chunk to cache: {e}");
-                                        if let Err(e) = w.close().await {
-                                            error!("failed to close cache writer: {e}");
-                                        }
-                                        return Err(e);
-                                    }
-                                    Ok(())
-                                }) {
-                                    Some(w.await)
-                                } else {
-                                    None
-                                }
-                                .transpose();
-                                if cache_res.is_err() {
-                                    // Drop the cache writer if a failure occurs
-                                    cache.take();
-                                }
-                                Ok(chunk)
-                            })
-                            .into_async_read();
-                        let mut hashed = DigestReader::from(Box::pin(tar_gz));
-                        let deps = untar(
-                            GzipDecoder::new(BufReader::new(&mut hashed)),
-                            out,
-                            skip_deps,
+                            .context(Request("GET"))?;
+                        if let (Some(cache), 404 | 410) = (writer, res.status) {
+                            if let Err(e) = cache.record_failed_lookup(&url, res.status).await {
+                                warn!("failed to record failed lookup of `{url}` in cache: {e:#}");
+                            }
+                        }
+                        let res = res.error_for_status().context("GET request failed")?;
+                        let final_url = (res.url != url).then(|| res.url.clone());
+                        let etag = res.etag;
+                        let total = res.content_length;
+                        let downloaded = AtomicU64::new(0);
+                        let chunks = res.body.inspect_ok(|chunk| {
+                            let len = u64::try_from(chunk.len()).unwrap_or(u64::MAX);
+                            let bytes = downloaded
+                                .fetch_add(len, Ordering::Relaxed)
+                                .saturating_add(len);
+                            options.report(|| LockEvent::Downloaded {
+                                id: id.clone(),
+                                url: url.clone(),
+                                bytes,
+                                total,
+                            });
+                        });
+                        let (chunks, tee) = if let Some(w) = cache {
+                            let (chunks, tee) = tee_into(chunks, w);
+                            (chunks.left_stream(), Some(tee))
+                        } else {
+                            (chunks.right_stream(), None)
+                        };
+                        let tar_gz = Box::pin(chunks.into_async_read());
+                        let tar_gz = if let Some((sig, pubkey)) = signature {
+                            let buf =
+                                read_signed(tar_gz, &sig, &pubkey).await.with_context(|| {
+                                    format!("failed to verify signature of `{url}`")
+                                })?;
+                            Either::Left(Cursor::new(buf))
+                        } else {
+                            Either::Right(tar_gz)
+                        };
+                        let mut hashed = DigestReader::from(tar_gz);
+                        // NOTE: The response body is streamed, hence decompression includes the
+                        // time it takes to receive it
+                        let untared = timed(
+                            phase::DECOMPRESS,
+                            untar_archive(
+                                vfs,
+                                GzipDecoder::new(BufReader::new(&mut hashed)),
+                                out,
+                                out_deps,
+                                skip_deps,
+                                &ArchiveLayout::default(),
+                            ),
                         )
                         .await
                         .with_context(|| format!("failed to unpack contents of `{url}`"))?;
-                        (Digest::from(hashed), deps)
+                        let bytes = downloaded.load(Ordering::Relaxed);
+                        telemetry::downloaded(id, &url, bytes);
+                        options.report(|| LockEvent::FetchFinished {
+                            id: id.clone(),
+                            url: url.clone(),
+                            bytes,
+                        });
+                        (Digest::from(hashed), untared, final_url, etag, tee)
                     }
                     "file" => bail!(
                         r#"`file` scheme is not supported for `url` field, use `path` instead. Try:
@@ -429,70 +1436,753 @@ path = "/path/to/my/dep"
                 };
                 if let Some(sha256) = sha256 {
                     if digest.sha256 != sha256 {
-                        remove_dir_all(out).await?;
-                        bail!(
-                            r#"sha256 hash mismatch for `{url}`
-got: {}
-expected: {}"#,
-                            hex::encode(digest.sha256),
-                            hex::encode(sha256),
-                        );
+                        remove_dir_all_in(vfs, out).await?;
+                        return Err(DigestMismatch {
+                            url: url.clone(),
+                            algorithm: "sha256",
+                            expected: hex::encode(sha256),
+                            actual: hex::encode(digest.sha256),
+                            locked: false,
+                        }
+                        .into());
                     }
                 }
                 if let Some(sha512) = sha512 {
                     if digest.sha512 != sha512 {
-                        remove_dir_all(out).await?;
-                        bail!(
-                            r#"sha512 hash mismatch for `{url}`
-got: {}
-expected: {}"#,
-                            hex::encode(digest.sha512),
-                            hex::encode(sha512),
-                        );
+                        remove_dir_all_in(vfs, out).await?;
+                        return Err(DigestMismatch {
+                            url: url.clone(),
+                            algorithm: "sha512",
+                            expected: hex::encode(sha512),
+                            actual: hex::encode(digest.sha512),
+                            locked: false,
+                        }
+                        .into());
+                    }
+                }
+                if let Some(blake3) = blake3 {
+                    if digest.blake3 != Some(blake3) {
+                        remove_dir_all_in(vfs, out).await?;
+                        return Err(DigestMismatch {
+                            url: url.clone(),
+                            algorithm: "blake3",
+                            expected: hex::encode(blake3),
+                            actual: digest.blake3.map(hex::encode).unwrap_or_default(),
+                            locked: false,
+                        }
+                        .into());
+                    }
+                }
+                // NOTE: Closing the cache writer commits the entry, which is only done once the
+                // digest is verified
+                if let Some(tee) = tee {
+                    if let Err(e) = tee.commit().await {
+                        error!("failed to write `{url}` to cache: {e}");
                     }
                 }
                 trace!(?deps, "fetched contents of `{url}` to `{}`", out.display());
-                let deps = lock_deps(deps).await?;
+                options.installed(id, &source, out).await?;
+                let tree_deps = deps.clone();
+                let deps = timed(phase::DIGEST, lock_deps(vfs, deps)).await?;
                 trace!(?deps, "locked transitive dependencies of `{url}`");
-                let entry = LockEntry::from_url(url, out, deps.keys().cloned().collect()).await?;
+                let tag = LockEntrySourceResolution::tag_from_url(&url);
+                let entry_url = url.clone();
+                let digest = timed(phase::DIGEST, vfs.digest(out))
+                    .await
+                    .context("failed to compute digest")?;
+                let mut entry = LockEntry::new(
+                    Some(LockEntrySource::Url(url)),
+                    digest,
+                    deps.keys().cloned().collect(),
+                );
+                entry.resolution = LockEntrySourceResolution {
+                    commit,
+                    tag,
+                    final_url,
+                    license: license::expression(&licenses),
+                    ..LockEntrySourceResolution::default()
+                };
+                if let Some(cache) = writer {
+                    if let Err(e) = cache.validated(&entry_url, etag.as_deref()).await {
+                        warn!("failed to record validation of `{entry_url}` in cache: {e:#}");
+                    }
+                    if insert_trees {
+                        insert_tree(cache, &entry.digest, out, &tree_deps).await;
+                    }
+                }
                 Ok((entry, deps))
             }
         }
     }
 }
 
+/// Reason a dependency is out of sync with the [Manifest] or its [Lock]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum OutOfSync {
+    /// Dependency is declared in the manifest, but not locked
+    NotLocked,
+    /// Dependency is locked from a source different from the one declared in the manifest
+    ManifestChanged,
+    /// Direct dependency is locked, but no longer declared in the manifest
+    Removed,
+    /// Dependency directory diverges from the lock
+    Diverged {
+        /// Divergence of the dependency directory
+        divergence: LockDivergence,
+    },
+}
+
+impl fmt::Display for OutOfSync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotLocked => write!(f, "declared in the manifest, but not locked"),
+            Self::ManifestChanged => write!(f, "source in the manifest changed"),
+            Self::Removed => write!(f, "locked, but removed from the manifest"),
+            Self::Diverged { divergence } => divergence.fmt(f),
+        }
+    }
+}
+
+/// Action [`Manifest::lock`] would take for a dependency, see [`Manifest::plan`]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlannedAction {
+    /// Dependency is up-to-date and would be kept as-is
+    Keep,
+    /// Dependency would be unpacked from the resource cached for `url`
+    Unpack {
+        /// Resource URL
+        url: Url,
+    },
+    /// Dependency would be fetched from `url`
+    Fetch {
+        /// Resource URL
+        url: Url,
+    },
+    /// Dependency would be copied from `path`
+    Copy {
+        /// Source path
+        path: PathBuf,
+    },
+    /// Dependency is no longer required and its lock entry would be removed
+    Remove,
+    /// Directory, which is not locked, would be deleted when pruning
+    Delete,
+}
+
+impl fmt::Display for PlannedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Keep => write!(f, "keep"),
+            Self::Unpack { url } => write!(f, "unpack `{url}` from cache"),
+            Self::Fetch { url } => write!(f, "fetch `{url}`"),
+            Self::Copy { path } => write!(f, "copy `{}`", path.display()),
+            Self::Remove => write!(f, "remove lock entry"),
+            Self::Delete => write!(f, "delete directory"),
+        }
+    }
+}
+
+/// Actions required to lock a [Manifest] into `deps`, computed by [`Manifest::plan`] without
+/// touching the filesystem and executed by [`Plan::apply`].
+///
+/// Plans are serializable, such that they can be reviewed before being applied, possibly by a
+/// different process. A plan is only valid for the state of `deps` it was computed for.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Plan {
+    deps: PathBuf,
+    manifest: Manifest,
+    lock: Option<Lock>,
+    pub(crate) actions: BTreeMap<Identifier, PlannedAction>,
+}
+
+impl Plan {
+    /// Returns the `deps` directory the plan was computed for
+    #[must_use]
+    pub fn deps(&self) -> &Path {
+        &self.deps
+    }
+
+    /// Returns the [`PlannedAction`] for each dependency
+    #[must_use]
+    pub fn actions(&self) -> &BTreeMap<Identifier, PlannedAction> {
+        &self.actions
+    }
+
+    /// Returns `true` if applying the plan would not change `deps` or the lock
+    #[must_use]
+    pub fn is_noop(&self) -> bool {
+        self.actions
+            .values()
+            .all(|action| *action == PlannedAction::Keep)
+    }
+
+    /// Executes the plan, i.e. fetches, unpacks or copies all dependencies, which are not kept,
+    /// deletes directories planned to be deleted and returns the resulting lock.
+    ///
+    /// Dependencies are selected by the plan and [packages](LockOptions::with_packages) in
+    /// `options` are ignored, otherwise this behaves like [`Manifest::lock`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if anything in the pipeline fails
+    #[instrument(level = "trace", skip(self, at, cache, options))]
+    pub async fn apply(
+        self,
+        at: Option<impl AsRef<Path>>,
+        cache: Option<&impl Cache>,
+        options: &LockOptions,
+    ) -> Result<Lock, Error> {
+        let Self {
+            deps,
+            manifest,
+            lock,
+            actions,
+        } = self;
+        let mut lock = lock.unwrap_or_default();
+        let mut packages = BTreeSet::new();
+        for (id, action) in &actions {
+            match action {
+                PlannedAction::Unpack { .. }
+                | PlannedAction::Fetch { .. }
+                | PlannedAction::Copy { .. } => {
+                    // NOTE: Removing the lock entry forces the dependency to be locked again
+                    lock.remove(id);
+                    packages.insert(id.clone());
+                }
+                PlannedAction::Keep | PlannedAction::Remove | PlannedAction::Delete => {}
+            }
+        }
+        let options = options.clone().with_packages(packages);
+        let locked = manifest
+            .lock(at, &deps, Some(&lock), cache, &options)
+            .await?;
+        for (id, action) in actions {
+            if action == PlannedAction::Delete && !locked.contains_key(&id) {
+                let vfs = options.vfs().unwrap_or(&OsVfs);
+                match remove_dir_all_in(vfs, lock.path(&deps, &id)).await {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+        Ok(locked)
+    }
+}
+
+/// Manifest key of the [`DepsLayout`]
+const LAYOUT_KEY: &str = "layout";
+
+/// Manifest key of the [Registry]
+const REGISTRY_KEY: &str = "registry";
+
+/// Manifest key of the setting requiring URL entries to be pinned
+const REQUIRE_PINNED_KEY: &str = "require-pinned";
+
 /// WIT dependency manifest mapping [Identifiers](Identifier) to [Entries](Entry)
-#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
-pub struct Manifest(HashMap<Identifier, Entry>);
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Manifest {
+    layout: DepsLayout,
+    registry: Registry,
+    require_pinned: bool,
+    entries: BTreeMap<Identifier, Entry>,
+}
 
 impl Manifest {
+    /// Returns the manifest, which installs dependencies according to `layout`
+    #[must_use]
+    pub fn with_layout(mut self, layout: DepsLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Returns the [`DepsLayout`] dependencies are installed according to
+    #[must_use]
+    pub fn layout(&self) -> DepsLayout {
+        self.layout
+    }
+
+    /// Returns the manifest, which resolves WIT packages used, but not declared by the package
+    /// in the parent directory of `deps` or any locked dependency from `registry` when locking.
+    /// Such packages are locked as transitive dependencies with their source and
+    /// [package](LockEntry::package) recorded.
+    #[must_use]
+    pub fn with_registry(mut self, registry: Registry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Returns the [Registry] missing WIT packages are resolved from
+    #[must_use]
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Returns the manifest, which fails locking if `require` is `true` and any URL entry is
+    /// not pinned by a `sha256` or `sha512` digest, like
+    /// [`LockOptions::with_require_pinned`]
+    #[must_use]
+    pub fn with_require_pinned(mut self, require: bool) -> Self {
+        self.require_pinned = require;
+        self
+    }
+
+    /// Returns `true` if the manifest requires URL entries to be pinned
+    #[must_use]
+    pub fn require_pinned(&self) -> bool {
+        self.require_pinned
+    }
+
+    /// Returns identifiers and URLs of URL entries, which are not pinned by a `sha256` or
+    /// `sha512` digest
+    #[must_use]
+    pub fn unpinned(&self) -> Vec<(Identifier, Url)> {
+        self.entries
+            .iter()
+            .filter_map(|(id, entry)| match entry {
+                Entry::Url {
+                    url,
+                    sha256: None,
+                    sha512: None,
+                    ..
+                } => Some((id.clone(), url.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Inserts dependency `id` specified by `entry` into the manifest and returns the entry
+    /// previously present for `id`, if any
+    pub fn insert(&mut self, id: impl Into<Identifier>, entry: Entry) -> Option<Entry> {
+        self.entries.insert(id.into(), entry)
+    }
+
+    /// Removes dependency `id` from the manifest and returns its entry, if it was present
+    pub fn remove(&mut self, id: &str) -> Option<Entry> {
+        self.entries.remove(id)
+    }
+
+    /// Returns a mutable reference to the entry of dependency `id`, if present
+    pub fn get_mut(&mut self, id: &str) -> Option<&mut Entry> {
+        self.entries.get_mut(id)
+    }
+
+    /// Returns identifiers of direct dependencies, which are either missing in `lock`, locked
+    /// from a different source or present in `lock`, but not in the manifest
+    pub(crate) fn unlocked(&self, lock: &Lock) -> BTreeSet<Identifier> {
+        self.out_of_sync(lock).into_keys().collect()
+    }
+
+    /// Returns direct dependencies, which are either missing in `lock`, locked from a different
+    /// source or present in `lock`, but not in the manifest, along with the reason
+    #[must_use]
+    pub fn out_of_sync(&self, lock: &Lock) -> BTreeMap<Identifier, OutOfSync> {
+        let changed = self
+            .entries
+            .iter()
+            .filter_map(|(id, entry)| match lock.get(id) {
+                None => Some((id.clone(), OutOfSync::NotLocked)),
+                Some(locked) if !entry.is_locked_by(locked) => {
+                    Some((id.clone(), OutOfSync::ManifestChanged))
+                }
+                Some(_) => None,
+            });
+        let removed = lock
+            .iter()
+            .filter(|(id, entry)| entry.is_direct() && !self.entries.contains_key(*id))
+            .map(|(id, _)| (id.clone(), OutOfSync::Removed));
+        changed.chain(removed).collect()
+    }
+
+    /// Returns the [Plan] containing the [`PlannedAction`] [`Manifest::lock`] would take for each
+    /// direct dependency and each lock entry, which would be removed, without fetching or
+    /// modifying anything.
+    ///
+    /// If `update` is `true`, dependencies in `packages`, or all dependencies if `packages` is
+    /// not specified, are planned to be re-locked regardless of `lock`, as done when updating.
+    ///
+    /// Transitive dependencies of dependencies, which would be fetched or copied, are only known
+    /// once they are and are therefore not planned.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a package in `packages` is not present in the manifest
+    #[instrument(level = "trace", skip(deps, lock, cache))]
+    pub async fn plan(
+        &self,
+        deps: impl AsRef<Path>,
+        lock: Option<&Lock>,
+        cache: Option<&impl Cache>,
+        packages: Option<&BTreeSet<Identifier>>,
+        update: bool,
+    ) -> Result<Plan, Error> {
+        let deps = deps.as_ref();
+        if let Some(packages) = packages {
+            for id in packages {
+                if !self.entries.contains_key(id) {
+                    return Err(anyhow!("package `{id}` is not present in the manifest").into());
+                }
+            }
+        }
+        let mut plan = BTreeMap::default();
+        for (id, entry) in &self.entries {
+            let locked = lock.and_then(|lock| lock.get(id));
+            let action = match (packages, locked) {
+                (Some(packages), Some(_)) if !packages.contains(id) => PlannedAction::Keep,
+                (_, Some(locked))
+                    if !update
+                        && entry.is_up_to_date(locked)
+                        && LockEntry::digest(deps.join(locked.dir.as_deref().unwrap_or(id)))
+                            .await
+                            .is_ok_and(|digest| digest.matches(&locked.digest)) =>
+                {
+                    PlannedAction::Keep
+                }
+                _ => match entry {
+                    Entry::Url { url, .. } => match cache {
+                        Some(cache) if cache.inserted(url).await.is_ok_and(|t| t.is_some()) => {
+                            PlannedAction::Unpack { url: url.clone() }
+                        }
+                        _ => PlannedAction::Fetch { url: url.clone() },
+                    },
+                    Entry::Path { path, .. } => PlannedAction::Copy { path: path.clone() },
+                },
+            };
+            plan.insert(id.clone(), action);
+        }
+        if let Some(lock) = lock {
+            let removed: BTreeSet<_> = lock
+                .iter()
+                .filter(|(id, entry)| entry.is_direct() && !self.entries.contains_key(*id))
+                .map(|(id, _)| id)
+                .collect();
+            for (id, entry) in lock.iter() {
+                // NOTE: Transitive dependencies are removed along with all direct dependencies,
+                // which required them
+                if removed.contains(id)
+                    || entry.source.is_none()
+                        && !entry.required_by.is_empty()
+                        && entry.required_by.iter().all(|id| removed.contains(id))
+                {
+                    plan.insert(id.clone(), PlannedAction::Remove);
+                }
+            }
+        }
+        Ok(Plan {
+            deps: deps.to_path_buf(),
+            manifest: self.clone(),
+            lock: lock.cloned(),
+            actions: plan,
+        })
+    }
+
     /// Lock the manifest populating `deps`
-    #[instrument(level = "trace", skip(at, deps, lock, cache))]
+    ///
+    /// If [offline](LockOptions::with_offline), URL dependencies, which are neither up-to-date in
+    /// `deps` nor present in `cache` are not fetched and an error listing all of them is returned
+    /// instead.
+    ///
+    /// If [packages](LockOptions::with_packages) are selected, only the dependencies with these
+    /// identifiers and their transitive dependencies are locked. Entries of all other
+    /// dependencies present in `lock` are preserved verbatim without touching `deps`.
+    ///
+    /// Transitive dependencies shipped by multiple dependencies are compared by the WIT package
+    /// they declare, such that copies differing only in comments, formatting or order of
+    /// top-level items do not conflict. Copies of the same package shipped under different
+    /// identifiers are deduplicated into the first one locked.
+    ///
+    /// Dependencies declaring the same WIT package under different identifiers are reported as
+    /// warnings or, if [denied](LockOptions::with_deny_collisions), fail locking.
+    ///
+    /// Dependencies are installed in directories within `deps` named according to the
+    /// [layout](Self::with_layout) of the manifest. Unless installed flat, transitive
+    /// dependencies are identified by their directory names and dependencies installed according
+    /// to a different layout in `lock` are moved.
+    ///
+    /// Up to [`LockOptions::concurrency`] dependencies are locked concurrently. Options, which
+    /// apply to the lock file or `deps` as a whole, e.g. digests or pruning, are not applied.
+    ///
+    /// If a [cancellation token](LockOptions::with_cancellation) is specified, `deps` is backed
+    /// up before it is modified and rolled back if locking fails, is cancelled or the returned
+    /// future is dropped, unless a custom [VFS](LockOptions::with_vfs) is used.
+    #[instrument(level = "trace", skip(at, deps, lock, cache, options))]
     pub async fn lock(
         self,
         at: Option<impl AsRef<Path>>,
         deps: impl AsRef<Path>,
         lock: Option<&Lock>,
         cache: Option<&impl Cache>,
-    ) -> anyhow::Result<Lock> {
-        let at = at.as_ref();
+        options: &LockOptions,
+    ) -> Result<Lock, Error> {
         let deps = deps.as_ref();
-        // Dependency ids, which are pinned in the manifest
-        let pinned = self.0.keys().cloned().collect();
-        stream::iter(self.0.into_iter().map(|(id, entry)| async {
-            let out = deps.join(&id);
-            let lock = lock.and_then(|lock| lock.get(&id));
-            let (entry, deps) = entry
-                .lock(at, out, lock, cache, &pinned)
+        if let Some(id) = self
+            .entries
+            .keys()
+            .find(|id| crate::lock::RESERVED_KEYS.contains(&id.as_str()))
+        {
+            return Err(anyhow!(crate::lock::reserved_key_error(id)).into());
+        }
+        if self.require_pinned || options.require_pinned() {
+            let unpinned = self.unpinned();
+            if !unpinned.is_empty() {
+                return Err(anyhow::Error::new(Unpinned(unpinned)).into());
+            }
+        }
+        let Some(token) = options.cancellation() else {
+            return self.lock_registry(at, deps, lock, cache, options).await;
+        };
+        if token.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        // NOTE: Only `deps` present on disk can be backed up
+        let backup = match options.vfs() {
+            None => Some(Backup::new(deps).await?),
+            Some(_) => None,
+        };
+        match token
+            .run_until_cancelled(self.lock_registry(at, deps, lock, cache, options))
+            .await
+        {
+            Some(Ok(lock)) => {
+                if let Some(backup) = backup {
+                    backup.commit().await;
+                }
+                Ok(lock)
+            }
+            Some(Err(e)) => Err(e),
+            None => {
+                debug!("locking cancelled, roll back `{}`", deps.display());
+                Err(Cancelled.into())
+            }
+        }
+    }
+
+    /// Locks entries of the manifest, then repeatedly resolves WIT packages used, but not
+    /// declared by any locked dependency from the [Registry] and locks them as well until no
+    /// more packages can be resolved. Fails if packages remain missing and
+    /// [completeness is required](LockOptions::with_require_complete).
+    async fn lock_registry(
+        self,
+        at: Option<impl AsRef<Path>>,
+        deps: &Path,
+        lock: Option<&Lock>,
+        cache: Option<&impl Cache>,
+        options: &LockOptions,
+    ) -> Result<Lock, Error> {
+        if self.registry.is_empty() && !options.require_complete() {
+            return self.lock_entries(at, deps, lock, cache, options).await;
+        }
+        let at = at.as_ref().map(AsRef::as_ref);
+        let vfs = options.vfs().unwrap_or(&OsVfs);
+        let wit = deps
+            .parent()
+            .filter(|wit| !wit.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."));
+        let mut manifest = self;
+        // Packages resolved from the registry keyed by the identifiers they are locked as
+        let mut resolved = BTreeMap::default();
+        // NOTE: Dependencies locked by previous iterations are up-to-date and not fetched again
+        let mut previous: Option<Lock> = None;
+        loop {
+            let base = match previous.take() {
+                Some(previous) => {
+                    let mut base = lock.cloned().unwrap_or_default();
+                    base.extend(previous.iter().map(|(id, e)| (id.clone(), e.clone())));
+                    Some(base)
+                }
+                None => lock.cloned(),
+            };
+            let mut locked = manifest
+                .clone()
+                .lock_entries(at, deps, base.as_ref(), cache, options)
+                .await?;
+            let usage = registry::Usage::read(vfs, wit, deps, &locked)
                 .await
-                .with_context(|| format!("failed to lock `{id}`"))?;
-            Ok(((id, entry), deps))
-        }))
-        .then(identity)
-        .try_fold(Lock::default(), |mut lock, ((id, entry), deps)| async {
+                .context("failed to read used packages")?;
+            let mut added = false;
+            for package in usage.missing() {
+                let id = registry::id(package);
+                if resolved.contains_key(&id) {
+                    return Err(anyhow!(
+                        "`{id}` resolved from the registry does not declare package `{package}`"
+                    )
+                    .into());
+                }
+                if manifest.entries.contains_key(&id) {
+                    warn!("`{id}` does not declare package `{package}`, skip resolution");
+                    continue;
+                }
+                let url = manifest
+                    .registry
+                    .url(package, options.wkg_config())
+                    .with_context(|| format!("failed to construct URL of package `{package}`"))?;
+                let Some(url) = url else {
+                    debug!("no registry configured for package `{package}`, skip resolution");
+                    continue;
+                };
+                debug!("resolve package `{package}` from `{url}` as `{id}`");
+                manifest.entries.insert(id.clone(), Entry::from(url));
+                resolved.insert(id, package.to_string());
+                added = true;
+            }
+            if !added {
+                for (id, entry) in locked.iter_mut() {
+                    entry.package = resolved.get(id).cloned();
+                    if let Some(package) = &entry.package {
+                        entry.resolution.registry_record =
+                            Some(registry::record(package, options.wkg_config()));
+                        entry.required_by = usage.used_by(package);
+                        entry.required_by.remove(id);
+                    }
+                }
+                if options.require_complete() {
+                    let ids = manifest.entries.keys().cloned().collect();
+                    let missing = usage.incomplete(&ids, &locked);
+                    if !missing.is_empty() {
+                        return Err(anyhow::Error::new(Incomplete(missing)).into());
+                    }
+                }
+                return Ok(locked);
+            }
+            previous = Some(locked);
+        }
+    }
+
+    #[allow(clippy::too_many_lines)]
+    async fn lock_entries(
+        self,
+        at: Option<impl AsRef<Path>>,
+        deps: &Path,
+        lock: Option<&Lock>,
+        cache: Option<&impl Cache>,
+        options: &LockOptions,
+    ) -> Result<Lock, Error> {
+        let at = at.as_ref();
+        let packages = options.packages();
+        if let Some(packages) = packages {
+            for id in packages {
+                if !self.entries.contains_key(id) {
+                    return Err(anyhow!("package `{id}` is not present in the manifest").into());
+                }
+            }
+        }
+        let default_fetch;
+        let fetch = if let Some(fetch) = options.fetch() {
+            fetch
+        } else {
+            default_fetch = fetch::from_env()?;
+            &default_fetch
+        };
+        let github = GitHub::from_env(fetch);
+        let fetch: &dyn Fetch = &github;
+        let vfs: &dyn Vfs = match options.vfs() {
+            Some(vfs) => vfs,
+            None if options.hardlinks() => &Linked,
+            None => &OsVfs,
+        };
+        let vfs = Limited::new(vfs, options.fs_permits());
+        let vfs = &vfs;
+        let layout = self.layout;
+        // NOTE: Unless installed flat, transitive dependencies are deduplicated with direct
+        // dependencies by the directory they are installed in once locked
+        // Dependency ids, which are pinned in the manifest
+        let pinned = match layout {
+            DepsLayout::Flat => self.entries.keys().cloned().collect(),
+            _ => BTreeSet::default(),
+        };
+        let results: Vec<anyhow::Result<_>> =
+            stream::iter(self.entries.into_iter().map(|(id, entry)| async {
+                let lock = lock.and_then(|lock| lock.get(&id).map(|entry| (lock, entry)));
+                match (packages, lock) {
+                    (Some(packages), Some((lock, entry))) if !packages.contains(&id) => {
+                        debug!("`{id}` not selected, preserve lock entry");
+                        options.report(|| LockEvent::Skipped { id: id.clone() });
+                        let deps = entry
+                            .deps
+                            .iter()
+                            .filter_map(|id| Some((id.clone(), (lock.get(id)?.clone(), None))))
+                            .collect();
+                        return Ok(((id, entry.clone()), deps));
+                    }
+                    _ => {}
+                }
+                let out = lock.map_or_else(|| deps.join(&id), |(lock, _)| lock.path(deps, &id));
+                let span = debug_span!(
+                    "dependency",
+                    %id,
+                    url = field::Empty,
+                    cache = field::Empty,
+                );
+                if let Entry::Url { url, .. } = &entry {
+                    span.record(telemetry::URL, url.as_str());
+                }
+                let license = entry.license().map(str::to_string);
+                let start = Instant::now();
+                // NOTE: The future is boxed, since it is large
+                let res = Box::pin(entry.lock(
+                    at,
+                    out,
+                    lock.map(|(_, entry)| entry),
+                    cache,
+                    &pinned,
+                    &id,
+                    layout,
+                    fetch,
+                    vfs,
+                    options,
+                ))
+                .instrument(span)
+                .await;
+                telemetry::locked(&id, start, res.as_ref().map(|(_, deps)| deps.len()));
+                let (mut entry, deps) = res.context(LockFailure { id: id.clone() })?;
+                if license.is_some() {
+                    entry.resolution.license = license;
+                }
+                options.report(|| LockEvent::Locked { id: id.clone() });
+                Ok(((id, entry), deps))
+            }))
+            .buffered(options.concurrency())
+            .collect()
+            .await;
+        let mut entries = Vec::with_capacity(results.len());
+        let mut offline_urls = BTreeSet::default();
+        for res in results {
+            match res {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    if let Some(OfflineError(url)) = e.downcast_ref() {
+                        offline_urls.insert(url.to_string());
+                    } else {
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+        if !offline_urls.is_empty() {
+            return Err(Offline(offline_urls.into_iter().collect()).into());
+        }
+        // NOTE: Dependencies previously installed according to a different layout are moved
+        if layout != DepsLayout::Flat
+            || lock.is_some_and(|lock| lock.values().any(|entry| entry.dir.is_some()))
+        {
+            entries = relayout(vfs, deps, layout, lock, entries).await?;
+        }
+        // Normalized contents of locked transitive dependencies
+        let mut pkgs = BTreeMap::default();
+        // Transitive dependencies, which differ from the locked ones only cosmetically
+        let mut equivalent = BTreeSet::default();
+        // Transitive dependencies declaring the same package as a locked transitive dependency
+        let mut aliased = BTreeSet::default();
+        let mut lock = Lock::default();
+        for ((direct, entry), deps) in entries {
             use std::collections::btree_map::Entry::{Occupied, Vacant};
 
-            match lock.entry(id) {
+            // NOTE: Transitive dependencies are contained in the archive of the direct
+            // dependency and therefore inherit its license
+            let license = entry.resolution.license.clone();
+            match lock.entry(direct.clone()) {
                 Occupied(e) => {
                     error!("duplicate lock entry for direct dependency `{}`", e.key());
                 }
@@ -501,61 +2191,319 @@ impl Manifest {
                     e.insert(entry);
                 }
             }
-            for (id, entry) in deps {
+            for (id, (mut entry, pkg)) in deps {
+                let alias = pkg
+                    .as_ref()
+                    .filter(|pkg| pkg.name.is_some() && !lock.contains_key(&id))
+                    .and_then(|pkg| pkgs.iter().find(|(_, other)| *other == pkg))
+                    .map(|(other, _): (&Identifier, _)| other.clone());
+                if let Some(other) = alias {
+                    debug!("`{id}` declares the same package as `{other}`, deduplicate");
+                    if let Some(entry) = lock.get_mut(&other) {
+                        entry.required_by.insert(direct.clone());
+                    }
+                    if let Some(entry) = lock.get_mut(&direct) {
+                        entry.deps.remove(&id);
+                        entry.deps.insert(other);
+                    }
+                    aliased.insert(id);
+                    continue;
+                }
                 match lock.entry(id) {
-                    Occupied(e) => {
+                    Occupied(mut e) => {
                         let other = e.get();
                         debug_assert!(other.source.is_none());
-                        ensure!(other.digest == entry.digest, "transitive dependency conflict for `{}`, add `{}` to dependency manifest to resolve it", e.key(), e.key());
+                        if !other.digest.matches(&entry.digest) {
+                            if pkg.is_none() || pkg.as_ref() != pkgs.get(e.key()) {
+                                return Err(Conflict {
+                                    id: e.key().clone(),
+                                    direct: direct.clone(),
+                                    required_by: other.required_by.iter().cloned().collect(),
+                                }
+                                .into());
+                            }
+                            debug!(
+                                "transitive dependency on `{}` differs only cosmetically",
+                                e.key()
+                            );
+                            equivalent.insert(e.key().clone());
+                        }
                         trace!(
                             "transitive dependency on `{}` already locked, skip",
                             e.key()
                         );
+                        e.get_mut().required_by.insert(direct.clone());
                     }
                     Vacant(e) => {
                         trace!("record lock entry for transitive dependency `{}`", e.key());
+                        if let Some(pkg) = pkg {
+                            pkgs.insert(e.key().clone(), pkg);
+                        }
+                        entry.required_by = BTreeSet::from([direct.clone()]);
+                        if entry.resolution.license.is_none() {
+                            entry.resolution.license.clone_from(&license);
+                        }
                         e.insert(entry);
                     }
                 }
             }
-            Ok(lock)
-        })
-        .await
+        }
+        for id in aliased {
+            if !lock.contains_key(&id) {
+                remove_dir_all_in(vfs, deps.join(&id)).await?;
+            }
+        }
+        // NOTE: Cosmetically different transitive dependencies were installed concurrently,
+        // ensure that the installed contents are one of them and record their digest
+        for id in equivalent {
+            let path = deps.join(&id);
+            let pkg = Package::read(vfs, &path)
+                .await
+                .with_context(|| format!("failed to read package at `{}`", path.display()))?;
+            let Some(entry) = lock.get_mut(&id) else {
+                continue;
+            };
+            if pkgs.get(&id) != Some(&pkg) {
+                let mut required_by: Vec<_> = entry.required_by.iter().cloned().collect();
+                let direct = required_by.pop().unwrap_or_else(|| id.clone());
+                return Err(Conflict {
+                    id,
+                    direct,
+                    required_by,
+                }
+                .into());
+            }
+            entry.digest = vfs
+                .digest(&path)
+                .await
+                .context("failed to compute digest")?;
+        }
+        for collision in collisions(vfs, deps, &lock).await {
+            if options.deny_collisions() {
+                return Err(anyhow::Error::new(collision).into());
+            }
+            warn!("{collision}");
+        }
+        let policy = options.license_policy();
+        for (id, entry) in lock.iter() {
+            let license = entry.resolution.license.as_deref();
+            if !policy.permits(license) {
+                return Err(anyhow::Error::new(LicenseViolation {
+                    id: id.clone(),
+                    license: license.map(str::to_string),
+                })
+                .into());
+            }
+        }
+        Ok(lock)
     }
 }
 
 impl Deref for Manifest {
-    type Target = HashMap<Identifier, Entry>;
+    type Target = BTreeMap<Identifier, Entry>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl FromIterator<(Identifier, Entry)> for Manifest {
-    fn from_iter<T: IntoIterator<Item = (Identifier, Entry)>>(iter: T) -> Self {
-        Self(HashMap::from_iter(iter))
+        &self.entries
     }
 }
 
-impl<const N: usize> From<[(Identifier, Entry); N]> for Manifest {
-    fn from(entries: [(Identifier, Entry); N]) -> Self {
-        Self::from_iter(entries)
+/// Resolves entries keyed by WIT packages without a source using `registry` templates
+fn resolve_packages<E: de::Error>(
+    registry: &Registry,
+    packages: Vec<(String, EntryFields)>,
+    entries: &mut BTreeMap<Identifier, Entry>,
+) -> Result<(), E> {
+    for (package, mut fields) in packages {
+        let url = registry
+            .url(&package, &WkgConfig::default())
+            .map_err(|e| E::custom(format!("invalid URL of WIT package `{package}`: {e}")))?
+            .ok_or_else(|| {
+                E::custom(format!(
+                    "no `registry` template resolves WIT package `{package}`, add a template for its namespace or specify `url` or `path`"
+                ))
+            })?;
+        fields.url = Some(url);
+        let id = registry::id(&package);
+        if entries.insert(id.clone(), fields.into_entry()?).is_some() {
+            return Err(E::custom(format!(
+                "duplicate entry `{id}` for WIT package `{package}`"
+            )));
+        }
     }
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    const FOO_URL: &str = "https://example.com/foo.tar.gz";
+impl<'de> Deserialize<'de> for Manifest {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // NOTE: The layout key may collide with a dependency identifier, in which case the value
+        // is used to disambiguate
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Layout(DepsLayout),
+            // NOTE: Untagged enums cannot deserialize string entries as structs
+            Str(String),
+            Entry(Box<Entry>),
+            Registry(Registry),
+            Bool(bool),
+        }
 
-    const BAR_URL: &str = "https://example.com/bar";
-    const BAR_SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        struct Visitor;
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Manifest;
 
-    const BAZ_URL: &str = "http://127.0.0.1/baz";
-    const BAZ_SHA256: &str = "9f86d081884c7d658a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
-    const BAZ_SHA512: &str = "ee26b0dd4af7e749aa1a8ee3c10ae9923f618980772e473f8819a5d4940e0db27ac185f8a0e1d5f84f88bc887fd67b143732c304cc5fa9ad8e6f57f50028a8ff";
+            fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                formatter.write_str("a WIT dependency manifest")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
+            where
+                V: de::MapAccess<'de>,
+            {
+                let mut layout = None;
+                let mut registry = None;
+                let mut require_pinned = None;
+                let mut entries = BTreeMap::default();
+                // Entries keyed by WIT packages without a source, which are resolved from the
+                // registry once it is decoded
+                let mut packages = Vec::new();
+                while let Some(k) = map.next_key::<Identifier>()? {
+                    let entry = match k.as_ref() {
+                        LAYOUT_KEY => match map.next_value()? {
+                            Value::Layout(v) => {
+                                if layout.is_some() {
+                                    return Err(de::Error::duplicate_field(LAYOUT_KEY));
+                                }
+                                layout = Some(v);
+                                continue;
+                            }
+                            Value::Str(entry) => entry.parse().map_err(de::Error::custom)?,
+                            Value::Entry(entry) => *entry,
+                            Value::Registry(_) | Value::Bool(_) => {
+                                return Err(de::Error::custom("invalid layout"));
+                            }
+                        },
+                        REGISTRY_KEY => match map.next_value()? {
+                            Value::Registry(v) => {
+                                if registry.is_some() {
+                                    return Err(de::Error::duplicate_field(REGISTRY_KEY));
+                                }
+                                registry = Some(v);
+                                continue;
+                            }
+                            Value::Layout(_) | Value::Bool(_) => {
+                                return Err(de::Error::custom("invalid registry"))
+                            }
+                            Value::Str(entry) => entry.parse().map_err(de::Error::custom)?,
+                            Value::Entry(entry) => *entry,
+                        },
+                        REQUIRE_PINNED_KEY => match map.next_value()? {
+                            Value::Bool(v) => {
+                                if require_pinned.is_some() {
+                                    return Err(de::Error::duplicate_field(REQUIRE_PINNED_KEY));
+                                }
+                                require_pinned = Some(v);
+                                continue;
+                            }
+                            Value::Str(entry) => entry.parse().map_err(de::Error::custom)?,
+                            Value::Entry(entry) => *entry,
+                            Value::Layout(_) | Value::Registry(_) => {
+                                return Err(de::Error::custom("invalid `require-pinned` setting"))
+                            }
+                        },
+                        k if k.contains(':') => {
+                            let fields: EntryFields = map.next_value()?;
+                            if fields.is_sourceless() {
+                                packages.push((k.to_string(), fields));
+                                continue;
+                            }
+                            fields.into_entry()?
+                        }
+                        _ => map.next_value()?,
+                    };
+                    if entries.insert(k.clone(), entry).is_some() {
+                        return Err(de::Error::custom(format!("duplicate entry `{k}`")));
+                    }
+                }
+                let registry = registry.unwrap_or_default();
+                resolve_packages(&registry, packages, &mut entries)?;
+                Ok(Manifest {
+                    layout: layout.unwrap_or_default(),
+                    registry,
+                    require_pinned: require_pinned.unwrap_or_default(),
+                    entries,
+                })
+            }
+        }
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+impl Serialize for Manifest {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeMap as _;
+
+        let mut state = serializer.serialize_map(None)?;
+        if self.layout != DepsLayout::Flat {
+            state.serialize_entry(LAYOUT_KEY, &self.layout)?;
+        }
+        if self.require_pinned {
+            state.serialize_entry(REQUIRE_PINNED_KEY, &true)?;
+        }
+        if !self.registry.is_empty() {
+            state.serialize_entry(REGISTRY_KEY, &self.registry)?;
+        }
+        for (id, entry) in &self.entries {
+            state.serialize_entry(id, entry)?;
+        }
+        state.end()
+    }
+}
+
+impl FromIterator<(Identifier, Entry)> for Manifest {
+    fn from_iter<T: IntoIterator<Item = (Identifier, Entry)>>(iter: T) -> Self {
+        Self {
+            layout: DepsLayout::default(),
+            registry: Registry::default(),
+            require_pinned: false,
+            entries: BTreeMap::from_iter(iter),
+        }
+    }
+}
+
+impl<const N: usize> From<[(Identifier, Entry); N]> for Manifest {
+    fn from(entries: [(Identifier, Entry); N]) -> Self {
+        Self::from_iter(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+    use crate::FetchResponse;
+
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::{Arc, Mutex};
+
+    use anyhow::ensure;
+    use futures::AsyncWriteExt as _;
+
+    const FOO_URL: &str = "https://example.com/foo.tar.gz";
+
+    const BAR_URL: &str = "https://example.com/bar";
+    const BAR_SHA256: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    const BAZ_URL: &str = "http://127.0.0.1/baz";
+    const BAZ_SHA256: &str = "9f86d081884c7d658a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    const BAZ_SHA512: &str = "ee26b0dd4af7e749aa1a8ee3c10ae9923f618980772e473f8819a5d4940e0db27ac185f8a0e1d5f84f88bc887fd67b143732c304cc5fa9ad8e6f57f50028a8ff";
 
     #[test]
     fn decode_url() -> anyhow::Result<()> {
@@ -564,6 +2512,7 @@ mod tests {
 foo = "{FOO_URL}"
 bar = {{ url = "{BAR_URL}", sha256 = "{BAR_SHA256}" }}
 baz = {{ url = "{BAZ_URL}", sha256 = "{BAZ_SHA256}", sha512 = "{BAZ_SHA512}" }}
+qux = {{ url = "{FOO_URL}", max-age = "1d" }}
 "#
         ))
         .context("failed to decode manifest")?;
@@ -576,6 +2525,11 @@ baz = {{ url = "{BAZ_URL}", sha256 = "{BAZ_SHA256}", sha512 = "{BAZ_SHA512}" }}
                         url: FOO_URL.parse().expect("failed to parse `foo` URL string"),
                         sha256: None,
                         sha512: None,
+                        blake3: None,
+                        sig: None,
+                        pubkey: None,
+                        max_age: None,
+                        license: None,
                     },
                 ),
                 (
@@ -586,6 +2540,11 @@ baz = {{ url = "{BAZ_URL}", sha256 = "{BAZ_SHA256}", sha512 = "{BAZ_SHA512}" }}
                             .map(Some)
                             .expect("failed to decode `bar` sha256"),
                         sha512: None,
+                        blake3: None,
+                        sig: None,
+                        pubkey: None,
+                        max_age: None,
+                        license: None,
                     }
                 ),
                 (
@@ -597,7 +2556,27 @@ baz = {{ url = "{BAZ_URL}", sha256 = "{BAZ_SHA256}", sha512 = "{BAZ_SHA512}" }}
                             .expect("failed to decode `baz` sha256"),
                         sha512: FromHex::from_hex(BAZ_SHA512)
                             .map(Some)
-                            .expect("failed to decode `baz` sha512")
+                            .expect("failed to decode `baz` sha512"),
+                        blake3: None,
+                        sig: None,
+                        pubkey: None,
+                        max_age: None,
+                        license: None,
+                    }
+                ),
+                (
+                    "qux".parse().expect("failed to parse `qux` identifier"),
+                    Entry::Url {
+                        url: FOO_URL.parse().expect("failed to parse `qux` URL"),
+                        sha256: None,
+                        sha512: None,
+                        blake3: None,
+                        sig: None,
+                        pubkey: None,
+                        max_age: CacheGcPolicy::parse_age("1d")
+                            .map(Some)
+                            .expect("failed to parse `qux` max-age"),
+                        license: None,
                     }
                 )
             ])
@@ -605,12 +2584,279 @@ baz = {{ url = "{BAZ_URL}", sha256 = "{BAZ_SHA256}", sha512 = "{BAZ_SHA512}" }}
         Ok(())
     }
 
+    #[test]
+    fn decode_integrity() -> anyhow::Result<()> {
+        use base64::engine::general_purpose::STANDARD;
+        use base64::Engine as _;
+
+        let sha256: [u8; 32] = FromHex::from_hex(BAZ_SHA256).expect("failed to decode sha256");
+        let sha512: [u8; 64] = FromHex::from_hex(BAZ_SHA512).expect("failed to decode sha512");
+        let sri256 = format!("sha256-{}", STANDARD.encode(sha256));
+        let sri512 = format!("sha512-{}", STANDARD.encode(sha512));
+        let manifest: Manifest = toml::from_str(&format!(
+            r#"
+foo = {{ url = "{BAZ_URL}", integrity = "{sri256}" }}
+bar = {{ url = "{BAZ_URL}", integrity = "{sri256} {sri512}", sha256 = "{BAZ_SHA256}" }}
+"#
+        ))?;
+        assert_eq!(
+            manifest["foo"],
+            Entry::url(BAZ_URL.parse()?).with_sha256(sha256)
+        );
+        assert_eq!(
+            manifest["bar"],
+            Entry::url(BAZ_URL.parse()?)
+                .with_sha256(sha256)
+                .with_sha512(sha512)
+        );
+        assert!(toml::to_string(&manifest)?.contains(&format!("sha256 = \"{BAZ_SHA256}\"")));
+
+        for invalid in [
+            format!(r#"foo = {{ url = "{BAZ_URL}", integrity = "sha384-{sri256}" }}"#),
+            format!(
+                r#"foo = {{ url = "{BAZ_URL}", integrity = "{sri256}", sha256 = "{BAR_SHA256}" }}"#
+            ),
+            format!(r#"foo = {{ path = "../foo", integrity = "{sri256}" }}"#),
+        ] {
+            assert!(toml::from_str::<Manifest>(&invalid).is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn mutate() -> anyhow::Result<()> {
+        let sha256 = FromHex::from_hex(BAR_SHA256).expect("failed to decode sha256");
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.insert("foo", Entry::url(FOO_URL.parse()?)), None);
+        assert_eq!(
+            manifest.insert(
+                "bar",
+                Entry::url(BAR_URL.parse()?)
+                    .with_sha256(sha256)
+                    .with_fresh(true)
+            ),
+            None
+        );
+        manifest.insert("baz", Entry::path("../baz").with_sha256(sha256));
+        manifest.insert("qux", Entry::path("/path/to/qux"));
+        assert_eq!(manifest.remove("qux"), Some(Entry::path("/path/to/qux")));
+        assert_eq!(manifest.remove("qux"), None);
+        if let Some(baz) = manifest.get_mut("baz") {
+            *baz = baz.clone().with_fresh(true);
+        }
+        let foo = manifest
+            .get_mut("foo")
+            .context("`foo` missing in manifest")?;
+        *foo = foo.clone().with_max_age(Duration::from_secs(90));
+
+        let encoded = toml::to_string(&manifest).context("failed to encode manifest")?;
+        assert_eq!(
+            encoded,
+            format!(
+                r#"[bar]
+url = "{BAR_URL}"
+sha256 = "{BAR_SHA256}"
+
+[baz]
+path = "../baz"
+fresh = true
+
+[foo]
+url = "{FOO_URL}"
+max-age = "90s"
+"#
+            )
+        );
+        let decoded: Manifest = toml::from_str(&encoded).context("failed to decode manifest")?;
+        assert_eq!(decoded, manifest);
+        Ok(())
+    }
+
+    #[test]
+    fn unlocked() -> anyhow::Result<()> {
+        let manifest: Manifest = toml::from_str(&format!(
+            r#"
+foo = "{FOO_URL}"
+bar = {{ url = "{BAR_URL}", sha256 = "{BAR_SHA256}" }}
+baz = "/path/to/baz"
+"#
+        ))
+        .context("failed to decode manifest")?;
+        let digest = Digest {
+            sha256: FromHex::from_hex(BAZ_SHA256).expect("failed to decode sha256"),
+            sha512: FromHex::from_hex(BAZ_SHA512).expect("failed to decode sha512"),
+            blake3: None,
+        };
+        let lock = Lock::from([
+            (
+                "foo".into(),
+                LockEntry::new(
+                    Some(LockEntrySource::Url(FOO_URL.parse()?)),
+                    digest.clone(),
+                    BTreeSet::default(),
+                ),
+            ),
+            (
+                "bar".into(),
+                LockEntry::new(
+                    Some(LockEntrySource::Url("https://example.com/baz".parse()?)),
+                    digest.clone(),
+                    BTreeSet::default(),
+                ),
+            ),
+            (
+                "qux".into(),
+                LockEntry::new(
+                    Some(LockEntrySource::Path("/path/to/qux".into())),
+                    digest.clone(),
+                    BTreeSet::default(),
+                ),
+            ),
+            (
+                "transitive".into(),
+                LockEntry::new(None, digest, BTreeSet::default()),
+            ),
+        ]);
+        assert_eq!(
+            manifest.unlocked(&lock),
+            BTreeSet::from(["bar".into(), "baz".into(), "qux".into()])
+        );
+        assert_eq!(
+            manifest.out_of_sync(&lock),
+            BTreeMap::from([
+                ("bar".into(), OutOfSync::ManifestChanged),
+                ("baz".into(), OutOfSync::NotLocked),
+                ("qux".into(), OutOfSync::Removed),
+            ])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plan() -> anyhow::Result<()> {
+        let manifest: Manifest = toml::from_str(&format!(
+            r#"
+foo = "{FOO_URL}"
+bar = "/path/to/bar"
+"#
+        ))
+        .context("failed to decode manifest")?;
+        let digest = Digest {
+            sha256: FromHex::from_hex(BAZ_SHA256).expect("failed to decode sha256"),
+            sha512: FromHex::from_hex(BAZ_SHA512).expect("failed to decode sha512"),
+            blake3: None,
+        };
+        let transitive = |required_by: &[&str]| {
+            let mut entry = LockEntry::new(None, digest.clone(), BTreeSet::default());
+            entry.required_by = required_by.iter().copied().map(Identifier::from).collect();
+            entry
+        };
+        let lock = Lock::from([
+            (
+                "foo".into(),
+                LockEntry::new(
+                    Some(LockEntrySource::Url(FOO_URL.parse()?)),
+                    digest.clone(),
+                    BTreeSet::from(["quux".into()]),
+                ),
+            ),
+            (
+                "baz".into(),
+                LockEntry::new(
+                    Some(LockEntrySource::Url(BAZ_URL.parse()?)),
+                    digest.clone(),
+                    BTreeSet::from(["qux".into(), "quux".into()]),
+                ),
+            ),
+            ("qux".into(), transitive(&["baz"])),
+            ("quux".into(), transitive(&["baz", "foo"])),
+        ]);
+        let dir = TempDir::new("wit-deps-plan").await?;
+        let deps = dir.join("deps");
+        assert_eq!(
+            manifest
+                .plan(&deps, Some(&lock), None::<&crate::MemoryCache>, None, false)
+                .await?
+                .actions,
+            BTreeMap::from([
+                (
+                    "foo".into(),
+                    PlannedAction::Fetch {
+                        url: FOO_URL.parse()?
+                    }
+                ),
+                (
+                    "bar".into(),
+                    PlannedAction::Copy {
+                        path: "/path/to/bar".into()
+                    }
+                ),
+                ("baz".into(), PlannedAction::Remove),
+                ("qux".into(), PlannedAction::Remove),
+            ])
+        );
+        let packages = BTreeSet::from(["bar".into()]);
+        let plan = manifest
+            .plan(
+                &deps,
+                Some(&lock),
+                None::<&crate::MemoryCache>,
+                Some(&packages),
+                true,
+            )
+            .await?;
+        assert_eq!(plan.actions().get("foo"), Some(&PlannedAction::Keep));
+        assert!(manifest
+            .plan(
+                &deps,
+                None,
+                None::<&crate::MemoryCache>,
+                Some(&BTreeSet::from(["baz".into()])),
+                false
+            )
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn url_matches() -> anyhow::Result<()> {
+        let resolution = LockEntrySourceResolution::default();
+        let url = "https://example.com/foo.tar.gz?a=1&b=2".parse()?;
+        assert!(super::url_matches(&url, &url, &resolution));
+        assert!(super::url_matches(
+            &"https://example.com/foo.tar.gz?b=2&a=1#bar".parse()?,
+            &url,
+            &resolution
+        ));
+        assert!(!super::url_matches(
+            &"https://example.com/foo.tar.gz?a=2&b=2".parse()?,
+            &url,
+            &resolution
+        ));
+        assert!(!super::url_matches(
+            &"https://example.com/bar.tar.gz".parse()?,
+            &url,
+            &resolution
+        ));
+        assert!(super::url_matches(
+            &"https://example.com/bar.tar.gz".parse()?,
+            &url,
+            &LockEntrySourceResolution {
+                final_url: Some("https://example.com/bar.tar.gz".parse()?),
+                ..LockEntrySourceResolution::default()
+            }
+        ));
+        Ok(())
+    }
+
     #[test]
     fn decode_path() -> anyhow::Result<()> {
         let manifest: Manifest = toml::from_str(
             r#"
 foo = "/path/to/foo"
 bar = { path = "./path/to/bar" }
+baz = { path = "./path/to/baz", fresh = true }
 "#,
         )
         .context("failed to decode manifest")?;
@@ -619,14 +2865,1429 @@ bar = { path = "./path/to/bar" }
             Manifest::from([
                 (
                     "foo".parse().expect("failed to parse `foo` identifier"),
-                    Entry::Path(PathBuf::from("/path/to/foo")),
+                    Entry::from(PathBuf::from("/path/to/foo")),
                 ),
                 (
                     "bar".parse().expect("failed to parse `bar` identifier"),
-                    Entry::Path(PathBuf::from("./path/to/bar")),
+                    Entry::from(PathBuf::from("./path/to/bar")),
+                ),
+                (
+                    "baz".parse().expect("failed to parse `baz` identifier"),
+                    Entry::Path {
+                        path: PathBuf::from("./path/to/baz"),
+                        fresh: true,
+                        license: None,
+                    },
+                ),
+            ])
+        );
+        assert!(toml::from_str::<Manifest>(
+            r#"foo = { url = "https://example.com/foo.tar.gz", fresh = true }"#
+        )
+        .is_err());
+        assert!(toml::from_str::<Manifest>(r#"foo = { path = "./foo", max-age = "1d" }"#).is_err());
+        Ok(())
+    }
+
+    const FOO_WIT: &[u8] = b"package test:foo\n";
+    const BAR_WIT: &[u8] = b"package test:bar\n";
+
+    /// Returns a gzipped tarball containing `foo` package and its transitive dependency `bar`
+    async fn foo_tar_gz() -> anyhow::Result<Vec<u8>> {
+        tar_gz([
+            ("foo-main/wit/foo.wit", FOO_WIT),
+            ("foo-main/wit/deps/bar/bar.wit", BAR_WIT),
+        ])
+        .await
+    }
+
+    /// Returns a gzipped tarball containing `files`
+    async fn tar_gz(files: impl IntoIterator<Item = (&str, &[u8])>) -> anyhow::Result<Vec<u8>> {
+        use async_compression::futures::write::GzipEncoder;
+
+        let mut tar = async_tar::Builder::new(Vec::default());
+        for (path, data) in files {
+            let mut header = async_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, path, data).await?;
+        }
+        let tar = tar.into_inner().await?;
+        let mut tar_gz = GzipEncoder::new(Vec::default());
+        tar_gz.write_all(&tar).await?;
+        tar_gz.close().await?;
+        Ok(tar_gz.into_inner())
+    }
+
+    /// [Fetch] serving `tar_gz` and recording requests, `HEAD` requests are not supported
+    #[derive(Default)]
+    struct StubFetch {
+        tar_gz: Vec<u8>,
+        /// The only URL served, all other URLs are not found. All URLs are served if [None]
+        only: Option<Url>,
+        requested: Mutex<Vec<Url>>,
+        active: AtomicUsize,
+        /// Maximum number of concurrent `GET` requests
+        max: AtomicUsize,
+    }
+
+    impl StubFetch {
+        fn new(tar_gz: Vec<u8>) -> Self {
+            Self {
+                tar_gz,
+                ..Self::default()
+            }
+        }
+
+        fn requested(&self) -> Vec<Url> {
+            self.requested.lock().expect("lock poisoned").clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Fetch for StubFetch {
+        async fn get(&self, url: &Url) -> anyhow::Result<FetchResponse> {
+            self.requested
+                .lock()
+                .expect("lock poisoned")
+                .push(url.clone());
+            if self.only.as_ref().is_some_and(|only| only != url) {
+                return Ok(FetchResponse::new(404, url.clone()));
+            }
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(active, Ordering::SeqCst);
+            for _ in 0..8 {
+                tokio::task::yield_now().await;
+            }
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            let body = bytes::Bytes::from(self.tar_gz.clone());
+            let len = body.len().try_into()?;
+            Ok(FetchResponse::new(200, url.clone()).with_body(stream::iter([Ok(body)]), Some(len)))
+        }
+
+        async fn head(&self, url: &Url) -> anyhow::Result<FetchResponse> {
+            Ok(FetchResponse::new(405, url.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn lock_cached() -> anyhow::Result<()> {
+        use crate::MemoryCache;
+        use tokio::fs;
+
+        let url: Url = FOO_URL.parse()?;
+        let cache = MemoryCache::from_iter([(url.clone(), foo_tar_gz().await?)]);
+        let manifest = Manifest::from([("foo".into(), Entry::from(url.clone()))]);
+
+        let locked = Arc::new(Mutex::new(vec![]));
+        let options = LockOptions::default()
+            .with_offline(true)
+            .with_concurrency(4)
+            .with_observer({
+                let locked = Arc::clone(&locked);
+                move |event: &LockEvent| {
+                    locked.lock().expect("lock poisoned").push(event.clone());
+                }
+            });
+
+        let dir = TempDir::new("wit-deps-lock-cached").await?;
+        let deps = dir.join("deps");
+        let lock = manifest
+            .clone()
+            .lock(None::<&Path>, &deps, None, Some(&cache), &options)
+            .await;
+        let wit = fs::read(deps.join("foo").join("foo.wit")).await;
+        let empty = manifest
+            .lock(
+                None::<&Path>,
+                &deps,
+                None,
+                Some(&MemoryCache::default()),
+                &options,
+            )
+            .await;
+
+        let lock = lock?;
+        assert_eq!(
+            lock.get("foo").and_then(|e| e.source.as_ref()),
+            Some(&LockEntrySource::Url(url.clone()))
+        );
+        let path = deps.join("foo");
+        assert_eq!(
+            *locked.lock().expect("lock poisoned"),
+            [
+                LockEvent::CacheHit {
+                    id: "foo".into(),
+                    url: url.clone()
+                },
+                LockEvent::Installed {
+                    id: "foo".into(),
+                    path
+                },
+                LockEvent::Locked { id: "foo".into() },
+                LockEvent::CacheMiss {
+                    id: "foo".into(),
+                    url
+                },
+            ]
+        );
+        assert_eq!(wit?, FOO_WIT);
+        assert!(empty.is_err(), "offline lock without a cache hit must fail");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stale() -> anyhow::Result<()> {
+        use crate::LocalCache;
+        use tokio::fs;
+
+        let day = CacheGcPolicy::parse_age("1d")?;
+        let url: Url = FOO_URL.parse()?;
+        let dir = TempDir::new("wit-deps-stale").await?;
+        let cache = LocalCache::from(dir.path());
+        let missing = is_stale(&cache, &url, day).await;
+        let mut w = cache.insert(&url).await?;
+        w.write_all(b"contents").await?;
+        w.close().await?;
+        let fresh = is_stale(&cache, &url, day).await;
+
+        // NOTE: Entries missing in the index fall back to modification time
+        fs::remove_file(dir.join(".index.toml")).await?;
+        let path = dir.join("example.com").join("foo.tar.gz");
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(SystemTime::now() - 2 * day)?;
+        let stale = is_stale(&cache, &url, day).await;
+        let within = is_stale(&cache, &url, 3 * day).await;
+        assert_eq!((missing, fresh, stale, within), (false, false, true, false));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plan_apply() -> anyhow::Result<()> {
+        use crate::MemoryCache;
+        use tokio::fs;
+
+        let dir = TempDir::new("wit-deps-plan-apply").await?;
+        let src = dir.join("src");
+        let deps = dir.join("deps");
+        let manifest = Manifest::from([("foo".into(), Entry::from(src.clone()))]);
+        fs::create_dir_all(&src).await?;
+        fs::write(src.join("foo.wit"), FOO_WIT).await?;
+        let plan = manifest
+            .plan(&deps, None, None::<&MemoryCache>, None, false)
+            .await?;
+        // NOTE: The plan is applied from its encoding to ensure it is self-contained
+        let plan: Plan = toml::from_str(&toml::to_string(&plan)?)?;
+        ensure!(
+            plan.actions()
+                == &BTreeMap::from([("foo".into(), PlannedAction::Copy { path: src.clone() })]),
+            "unexpected plan: {plan:?}"
+        );
+        let lock = plan
+            .apply(None::<&Path>, None::<&MemoryCache>, &LockOptions::default())
+            .await?;
+        let wit = fs::read(deps.join("foo").join("foo.wit")).await?;
+        let replan = manifest
+            .plan(&deps, Some(&lock), None::<&MemoryCache>, None, false)
+            .await?;
+
+        assert_eq!(
+            lock.get("foo").and_then(|e| e.source.as_ref()),
+            Some(&LockEntrySource::Path(src))
+        );
+        assert_eq!(wit, FOO_WIT);
+        assert!(replan.is_noop());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_vfs() -> anyhow::Result<()> {
+        use crate::{MemoryCache, MemoryVfs};
+        use tokio::fs;
+
+        let url: Url = FOO_URL.parse()?;
+        let cache = MemoryCache::from_iter([(url.clone(), foo_tar_gz().await?)]);
+        let manifest = Manifest::from([("foo".into(), Entry::from(url))]);
+        let offline = LockOptions::default().with_offline(true);
+
+        let dir = TempDir::new("wit-deps-lock-vfs").await?;
+        let deps = dir.join("deps");
+        let vfs = MemoryVfs::default();
+        let virt = manifest
+            .clone()
+            .lock(
+                None::<&Path>,
+                &deps,
+                None,
+                Some(&cache),
+                &offline.clone().with_vfs(vfs.clone()),
+            )
+            .await?;
+        ensure!(!fs::try_exists(&deps).await?, "`deps` written to disk");
+        let lock = manifest
+            .clone()
+            .lock(None::<&Path>, &deps, None, Some(&cache), &offline)
+            .await;
+
+        // Locks are equal regardless of where dependencies are materialized
+        assert_eq!(virt, lock?);
+        assert_eq!(
+            vfs.file(deps.join("foo").join("foo.wit")).as_deref(),
+            Some(FOO_WIT)
+        );
+        assert_eq!(
+            vfs.file(deps.join("bar").join("bar.wit")).as_deref(),
+            Some(BAR_WIT)
+        );
+
+        // Up-to-date dependencies within the VFS are not unpacked again
+        let relocked = manifest
+            .lock(
+                None::<&Path>,
+                &deps,
+                Some(&virt),
+                Some(&MemoryCache::default()),
+                &offline.with_vfs(vfs),
+            )
+            .await?;
+        assert_eq!(relocked, virt);
+        Ok(())
+    }
+
+    #[test]
+    fn lock_executor() -> anyhow::Result<()> {
+        use crate::{fs, MemoryCache};
+
+        // NOTE: Locking does not require a Tokio context
+        futures::executor::block_on(async {
+            let url: Url = FOO_URL.parse()?;
+            let cache = MemoryCache::from_iter([(url.clone(), foo_tar_gz().await?)]);
+            let manifest = Manifest::from([("foo".into(), Entry::from(url))]);
+
+            let dir = TempDir::new("wit-deps-lock-executor").await?;
+            let deps = dir.join("deps");
+            let lock = manifest
+                .lock(
+                    None::<&Path>,
+                    &deps,
+                    None,
+                    Some(&cache),
+                    &LockOptions::default().with_offline(true),
+                )
+                .await;
+            let wit = fs::read(deps.join("foo").join("foo.wit")).await;
+
+            assert!(lock?.contains_key("foo"));
+            assert_eq!(wit?, FOO_WIT);
+            Ok(())
+        })
+    }
+
+    #[tokio::test]
+    async fn lock_fetch() -> anyhow::Result<()> {
+        use crate::MemoryCache;
+        use tokio::fs;
+
+        let foo: Url = FOO_URL.parse()?;
+        let bar: Url = BAR_URL.parse()?;
+        let stub = Arc::new(StubFetch {
+            only: Some(foo.clone()),
+            ..StubFetch::new(foo_tar_gz().await?)
+        });
+        let options = LockOptions::default().with_fetch(Arc::clone(&stub));
+
+        let dir = TempDir::new("wit-deps-lock-fetch").await?;
+        let deps = dir.join("deps");
+        let lock = Manifest::from([("foo".into(), Entry::from(foo.clone()))])
+            .lock(None::<&Path>, &deps, None, None::<&MemoryCache>, &options)
+            .await;
+        let wit = fs::read(deps.join("foo").join("foo.wit")).await;
+        let missing = Manifest::from([("bar".into(), Entry::from(bar.clone()))])
+            .lock(None::<&Path>, &deps, None, None::<&MemoryCache>, &options)
+            .await;
+
+        assert_eq!(
+            lock?.get("foo").and_then(|e| e.source.as_ref()),
+            Some(&LockEntrySource::Url(foo.clone()))
+        );
+        assert_eq!(wit?, FOO_WIT);
+        let err = missing.expect_err("missing dependency must fail to lock");
+        assert!(err.is_transient());
+        assert_eq!(err.id().map(String::as_str), Some("bar"));
+        assert_eq!(stub.requested(), [foo, bar]);
+        Ok(())
+    }
+
+    #[cfg(feature = "signing")]
+    #[tokio::test]
+    async fn lock_signature() -> anyhow::Result<()> {
+        use crate::MemoryCache;
+        use ed25519_dalek::{Signer as _, SigningKey};
+        use tokio::fs;
+
+        let tar_gz = foo_tar_gz().await?;
+        let key = SigningKey::from_bytes(&[0x42; 32]);
+        let sig = key.sign(&tar_gz).to_bytes();
+        let forged = key.sign(b"forged").to_bytes();
+        let pubkey = key.verifying_key().to_bytes();
+
+        let manifest: Manifest = toml::from_str(&format!(
+            r#"foo = {{ url = "{FOO_URL}", sig = "{}", pubkey = "{}" }}"#,
+            hex::encode(sig),
+            hex::encode(pubkey)
+        ))?;
+        assert_eq!(
+            manifest["foo"],
+            Entry::url(FOO_URL.parse()?).with_signature(sig, pubkey)
+        );
+        assert_eq!(
+            toml::from_str::<Manifest>(&toml::to_string(&manifest)?)?,
+            manifest
+        );
+        assert!(toml::from_str::<Manifest>(&format!(
+            r#"foo = {{ url = "{FOO_URL}", sig = "{}" }}"#,
+            hex::encode(sig)
+        ))
+        .is_err());
+
+        let signed = |sig| {
+            Manifest::from([(
+                "foo".into(),
+                Entry::url(FOO_URL.parse().expect("invalid URL")).with_signature(sig, pubkey),
+            )])
+        };
+        let cache = MemoryCache::from_iter([(FOO_URL.parse()?, tar_gz.clone())]);
+        let offline = LockOptions::default().with_offline(true);
+        let online = LockOptions::default().with_fetch(StubFetch::new(tar_gz));
+
+        let dir = TempDir::new("wit-deps-lock-signature").await?;
+        let deps = dir.join("deps");
+        let cached = signed(sig)
+            .lock(None::<&Path>, &deps, None, Some(&cache), &offline)
+            .await;
+        let cached_forged = signed(forged)
+            .lock(
+                None::<&Path>,
+                &deps.join("forged"),
+                None,
+                Some(&cache),
+                &offline,
+            )
+            .await;
+        let fetched = signed(sig)
+            .lock(
+                None::<&Path>,
+                &deps.join("fetched"),
+                None,
+                None::<&MemoryCache>,
+                &online,
+            )
+            .await;
+        let fetched_forged = signed(forged)
+            .lock(
+                None::<&Path>,
+                &deps.join("fetched-forged"),
+                None,
+                None::<&MemoryCache>,
+                &online,
+            )
+            .await;
+        let forged_installed = fs::try_exists(deps.join("fetched-forged").join("foo")).await?;
+
+        cached?;
+        fetched?;
+        assert!(cached_forged.is_err());
+        let err = fetched_forged.expect_err("forged signature must fail to lock");
+        assert!(format!("{:#}", anyhow::Error::from(err)).contains("signature"));
+        assert!(!forged_installed);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_download_concurrency() -> anyhow::Result<()> {
+        use crate::MemoryCache;
+
+        let manifest: Manifest = ["foo", "bar", "baz"]
+            .into_iter()
+            .map(|id| {
+                let url = format!("https://example.com/{id}.tar.gz").parse()?;
+                anyhow::Ok((id.into(), Entry::url(url)))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let dir = TempDir::new("wit-deps-lock-download-concurrency").await?;
+        let tar_gz = foo_tar_gz().await?;
+        let mut runs = Vec::default();
+        for (i, limit) in [None, Some(1)].into_iter().enumerate() {
+            let stub = Arc::new(StubFetch::new(tar_gz.clone()));
+            let mut options = LockOptions::default()
+                .with_concurrency(3)
+                .with_fs_concurrency(1)
+                .with_fetch(Arc::clone(&stub));
+            if let Some(limit) = limit {
+                options = options.with_download_concurrency(limit);
+            }
+            let lock = manifest
+                .clone()
+                .lock(
+                    None::<&Path>,
+                    dir.join(i.to_string()),
+                    None,
+                    None::<&MemoryCache>,
+                    &options,
+                )
+                .await;
+            runs.push((lock.map(|lock| lock.len()), stub.max.load(Ordering::SeqCst)));
+        }
+
+        let (locks, maxima): (Vec<_>, Vec<_>) = runs.into_iter().unzip();
+        for lock in locks {
+            assert_eq!(lock?, 3);
+        }
+        assert!(maxima[0] > 1, "downloads must be concurrent by default");
+        assert_eq!(maxima[1], 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_cache_policy() -> anyhow::Result<()> {
+        use crate::{CachePolicy, MemoryCache};
+
+        let url: Url = FOO_URL.parse()?;
+        let manifest = Manifest::from([("foo".into(), Entry::from(url.clone()))]);
+        let dir = TempDir::new("wit-deps-lock-cache-policy").await?;
+        let tar_gz = foo_tar_gz().await?;
+        let mut runs = Vec::default();
+        for (i, (policy, prefill)) in [
+            (CachePolicy::ReadWrite, false),
+            (CachePolicy::WriteOnly, true),
+            (CachePolicy::ReadOnly, false),
+            (CachePolicy::ReadOnly, true),
+            (CachePolicy::Disabled, true),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let cache = MemoryCache::default();
+            if prefill {
+                let mut w = cache.insert(&url).await?;
+                w.write_all(&tar_gz).await?;
+                w.close().await?;
+            }
+            let stub = Arc::new(StubFetch::new(tar_gz.clone()));
+            let options = LockOptions::default()
+                .with_fetch(Arc::clone(&stub))
+                .with_cache_policy(policy);
+            for j in 0..2 {
+                manifest
+                    .clone()
+                    .lock(
+                        None::<&Path>,
+                        dir.join(format!("{i}-{j}")),
+                        None,
+                        Some(&cache),
+                        &options,
+                    )
+                    .await?;
+            }
+            runs.push((stub.requested().len(), cache.get(&url).await?.is_some()));
+        }
+        assert_eq!(
+            runs,
+            [(1, true), (2, true), (2, false), (0, true), (2, true)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_cached_tree() -> anyhow::Result<()> {
+        use crate::LocalCache;
+        use tokio::fs;
+
+        let url: Url = FOO_URL.parse()?;
+        let manifest = Manifest::from([("foo".into(), Entry::from(url.clone()))]);
+
+        let dir = TempDir::new("wit-deps-lock-cached-tree").await?;
+        let deps = dir.join("deps");
+        let cache = LocalCache::from(dir.join("cache")).with_trees(true);
+        let offline = LockOptions::default().with_offline(true);
+        let mut w = cache.insert(&url).await?;
+        w.write_all(&foo_tar_gz().await?).await?;
+        w.close().await?;
+        let lock = manifest
+            .clone()
+            .lock(None::<&Path>, &deps, None, Some(&cache), &offline)
+            .await?;
+
+        // The tarball is not required to restore unpacked tree
+        fs::remove_dir_all(&deps).await?;
+        ensure!(cache.remove(&url).await?, "tarball missing in cache");
+        let restored = manifest
+            .lock(None::<&Path>, &deps, Some(&lock), Some(&cache), &offline)
+            .await?;
+        let foo = fs::read(deps.join("foo").join("foo.wit")).await?;
+        let bar = fs::read(deps.join("bar").join("bar.wit")).await?;
+
+        assert_eq!(restored, lock);
+        assert_eq!(foo, FOO_WIT);
+        assert_eq!(bar, BAR_WIT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_hooks() -> anyhow::Result<()> {
+        use crate::{digest_dir, InstallHook, MemoryCache};
+        use tokio::fs;
+
+        #[derive(Default)]
+        struct Recorder {
+            calls: Mutex<Vec<(&'static str, Identifier, LockEntrySource, PathBuf)>>,
+            fail: bool,
+        }
+
+        #[async_trait::async_trait]
+        impl InstallHook for Recorder {
+            async fn before_install(
+                &self,
+                id: &Identifier,
+                source: &LockEntrySource,
+                path: &Path,
+            ) -> anyhow::Result<()> {
+                self.calls.lock().expect("lock poisoned").push((
+                    "before",
+                    id.clone(),
+                    source.clone(),
+                    path.into(),
+                ));
+                ensure!(!self.fail, "rejected");
+                Ok(())
+            }
+
+            async fn after_install(
+                &self,
+                id: &Identifier,
+                source: &LockEntrySource,
+                path: &Path,
+            ) -> anyhow::Result<()> {
+                self.calls.lock().expect("lock poisoned").push((
+                    "after",
+                    id.clone(),
+                    source.clone(),
+                    path.into(),
+                ));
+                fs::write(path.join("hook.wit"), "package foo:hook;").await?;
+                Ok(())
+            }
+        }
+
+        let dir = TempDir::new("wit-deps-lock-hooks").await?;
+        let src = dir.join("src");
+        let deps = dir.join("deps");
+        let manifest = Manifest::from([("foo".into(), Entry::path(&src))]);
+        let recorder = Arc::new(Recorder::default());
+        let options = LockOptions::default().with_hook(Arc::clone(&recorder));
+        fs::create_dir_all(&src).await?;
+        fs::write(src.join("foo.wit"), FOO_WIT).await?;
+        let lock = manifest
+            .clone()
+            .lock(None::<&Path>, &deps, None, None::<&MemoryCache>, &options)
+            .await?;
+        let digest = digest_dir(deps.join("foo")).await?;
+        let relocked = manifest
+            .clone()
+            .lock(
+                None::<&Path>,
+                &deps,
+                Some(&lock),
+                None::<&MemoryCache>,
+                &options,
+            )
+            .await?;
+        let rejected = manifest
+            .lock(
+                None::<&Path>,
+                dir.join("rejected"),
+                None,
+                None::<&MemoryCache>,
+                &LockOptions::default().with_hook(Recorder {
+                    fail: true,
+                    ..Recorder::default()
+                }),
+            )
+            .await
+            .is_err();
+
+        let source = LockEntrySource::Path(src);
+        let path = deps.join("foo");
+        // Up-to-date dependencies are not installed again, hence hooks are only invoked once
+        assert_eq!(
+            *recorder.calls.lock().expect("lock poisoned"),
+            [
+                ("before", "foo".into(), source.clone(), path.clone()),
+                ("after", "foo".into(), source, path),
+            ]
+        );
+        // Changes made by hooks are reflected in the lock
+        assert!(lock.get("foo").is_some_and(|e| e.digest == digest));
+        assert_eq!(relocked, lock);
+        assert!(rejected, "failing hook must fail locking");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_path_snapshot() -> anyhow::Result<()> {
+        use crate::LocalCache;
+        use tokio::fs;
+
+        let dir = TempDir::new("wit-deps-lock-snapshot").await?;
+        let src = dir.join("src");
+        let deps = dir.join("deps");
+        let manifest = Manifest::from([("foo".into(), Entry::from(src.clone()))]);
+        let cache = LocalCache::from(dir.join("cache")).with_snapshots(true);
+        fs::create_dir_all(&src).await?;
+        fs::write(src.join("foo.wit"), FOO_WIT).await?;
+        let lock = manifest
+            .clone()
+            .lock(
+                None::<&Path>,
+                &deps,
+                None,
+                Some(&cache),
+                &LockOptions::default(),
+            )
+            .await?;
+
+        // Simulate an uninitialized submodule
+        fs::remove_dir_all(&deps).await?;
+        fs::remove_file(src.join("foo.wit")).await?;
+        let restored = manifest
+            .clone()
+            .lock(
+                None::<&Path>,
+                &deps,
+                Some(&lock),
+                Some(&cache),
+                &LockOptions::default(),
+            )
+            .await?;
+        let foo = fs::read(deps.join("foo").join("foo.wit")).await?;
+
+        fs::remove_dir(&src).await?;
+        let unlocked = manifest
+            .lock(
+                None::<&Path>,
+                dir.join("unlocked"),
+                None,
+                Some(&cache),
+                &LockOptions::default(),
+            )
+            .await
+            .is_err();
+
+        assert_eq!(restored, lock);
+        assert_eq!(foo, FOO_WIT);
+        assert!(
+            unlocked,
+            "missing path dependency must not be restored without a lock"
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn lock_hardlinks() -> anyhow::Result<()> {
+        use std::os::unix::fs::MetadataExt as _;
+        use tokio::fs;
+
+        let dir = TempDir::new("wit-deps-lock-hardlinks").await?;
+        let src = dir.join("src");
+        let manifest = Manifest::from([("foo".into(), Entry::from(src.clone()))]);
+        fs::create_dir_all(&src).await?;
+        fs::write(src.join("foo.wit"), FOO_WIT).await?;
+        let mut inodes = Vec::with_capacity(2);
+        let mut locks = Vec::with_capacity(2);
+        for (name, hardlinks) in [("copied", false), ("linked", true)] {
+            let deps = dir.join(name);
+            let lock = manifest
+                .clone()
+                .lock(
+                    None::<&Path>,
+                    &deps,
+                    None,
+                    None::<&crate::MemoryCache>,
+                    &LockOptions::default().with_hardlinks(hardlinks),
+                )
+                .await?;
+            locks.push(lock);
+            inodes.push(fs::metadata(deps.join("foo").join("foo.wit")).await?.ino());
+        }
+        let ino = fs::metadata(src.join("foo.wit")).await?.ino();
+
+        assert_eq!(locks[0], locks[1]);
+        assert_ne!(inodes[0], ino, "file must be copied by default");
+        assert_eq!(inodes[1], ino, "file must be hard linked");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_equivalent() -> anyhow::Result<()> {
+        use crate::{MemoryCache, MemoryVfs};
+        use tokio::fs;
+
+        const IO_WIT: &str = "package wasi:io@0.2.0;\n\ninterface error {\n  resource error;\n}\n\ninterface streams {\n  use error.{error};\n}\n";
+        const IO_FORMATTED_WIT: &str = "// Formatted\npackage wasi:io@0.2.0;\ninterface streams { use error.{error}; }\ninterface error { resource error; }\n";
+
+        let dir = TempDir::new("wit-deps-lock-equivalent").await?;
+        let deps = dir.join("deps");
+        for (id, dep, wit) in [
+            ("a", "io", IO_WIT),
+            ("b", "io", IO_FORMATTED_WIT),
+            ("c", "wasi-io", IO_FORMATTED_WIT),
+            ("d", "io", "package wasi:io@0.2.0;\ninterface error {}\n"),
+        ] {
+            let src = dir.join(id);
+            fs::create_dir_all(src.join("deps").join(dep)).await?;
+            fs::write(
+                src.join(format!("{id}.wit")),
+                format!("package test:{id};\n"),
+            )
+            .await?;
+            fs::write(src.join("deps").join(dep).join("io.wit"), wit).await?;
+        }
+        let vfs = MemoryVfs::default();
+        let options = LockOptions::default().with_concurrency(1);
+        let lock =
+            Manifest::from_iter(["a", "b", "c"].map(|id| (id.into(), Entry::from(dir.join(id)))))
+                .lock(
+                    None::<&Path>,
+                    &deps,
+                    None,
+                    None::<&MemoryCache>,
+                    &options.clone().with_vfs(vfs.clone()),
+                )
+                .await?;
+        let conflict =
+            Manifest::from_iter(["a", "d"].map(|id| (id.into(), Entry::from(dir.join(id)))))
+                .lock(
+                    None::<&Path>,
+                    &deps,
+                    None,
+                    None::<&MemoryCache>,
+                    &options.with_vfs(MemoryVfs::default()),
+                )
+                .await;
+
+        assert_eq!(
+            lock.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["a", "b", "c", "io"]
+        );
+        let io = lock.get("io").context("`io` missing")?;
+        assert_eq!(
+            io.required_by,
+            BTreeSet::from(["a".into(), "b".into(), "c".into()])
+        );
+        assert_eq!(
+            lock.get("c").context("`c` missing")?.deps,
+            BTreeSet::from(["io".into()])
+        );
+        assert_eq!(io.digest, vfs.digest(&deps.join("io")).await?);
+        assert!(vfs.read_dir(&deps.join("wasi-io")).await.is_err());
+        let Err(Error::Conflict { id, .. }) = conflict else {
+            bail!("semantically different transitive dependencies must conflict");
+        };
+        assert_eq!(id, "io");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_collisions() -> anyhow::Result<()> {
+        use crate::{MemoryCache, MemoryVfs};
+        use tokio::fs;
+
+        let dir = TempDir::new("wit-deps-lock-collisions").await?;
+        let deps = dir.join("deps");
+        for (id, dep, wit) in [
+            ("a", "io", "package wasi:io@0.2.0;\ninterface error {}\n"),
+            (
+                "b",
+                "wasi-io",
+                "package wasi:io@0.2.0;\ninterface streams {}\n",
+            ),
+        ] {
+            let src = dir.join(id);
+            fs::create_dir_all(src.join("deps").join(dep)).await?;
+            fs::write(
+                src.join(format!("{id}.wit")),
+                format!("package test:{id};\n"),
+            )
+            .await?;
+            fs::write(src.join("deps").join(dep).join("io.wit"), wit).await?;
+        }
+        let manifest =
+            Manifest::from_iter(["a", "b"].map(|id| (id.into(), Entry::from(dir.join(id)))));
+        let options = LockOptions::default().with_vfs(MemoryVfs::default());
+        let warned = manifest
+            .clone()
+            .lock(None::<&Path>, &deps, None, None::<&MemoryCache>, &options)
+            .await?;
+        let denied = manifest
+            .lock(
+                None::<&Path>,
+                &deps,
+                None,
+                None::<&MemoryCache>,
+                &options.with_deny_collisions(true),
+            )
+            .await;
+
+        assert_eq!(
+            warned.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["a", "b", "io", "wasi-io"]
+        );
+        let Err(err) = denied else {
+            bail!("colliding packages must fail locking if denied");
+        };
+        assert_eq!(
+            format!("{err:#}"),
+            "WIT package `wasi:io@0.2.0` is declared by multiple dependencies:\n  - `io` required by `a`\n  - `wasi-io` required by `b`"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_licenses() -> anyhow::Result<()> {
+        use crate::{LicensePolicy, MemoryCache, MemoryVfs};
+        use async_compression::futures::write::GzipEncoder;
+
+        let mut tar = async_tar::Builder::new(Vec::default());
+        for (path, data) in [
+            (
+                "foo-main/LICENSE-APACHE",
+                b"Apache License\nVersion 2.0, January 2004\n".as_slice(),
+            ),
+            (
+                "foo-main/LICENSE-MIT",
+                b"Permission is hereby granted, free of charge".as_slice(),
+            ),
+            ("foo-main/wit/foo.wit", FOO_WIT),
+            ("foo-main/wit/deps/bar/bar.wit", BAR_WIT),
+        ] {
+            let mut header = async_tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, path, data).await?;
+        }
+        let tar = tar.into_inner().await?;
+        let mut tar_gz = GzipEncoder::new(Vec::default());
+        tar_gz.write_all(&tar).await?;
+        tar_gz.close().await?;
+
+        let url: Url = FOO_URL.parse()?;
+        let cache = MemoryCache::from_iter([(url.clone(), tar_gz.into_inner())]);
+        let dir = TempDir::new("wit-deps-lock-licenses").await?;
+        let deps = dir.join("deps");
+        let options = LockOptions::default().with_vfs(MemoryVfs::default());
+        let lock = |manifest: Manifest, options: LockOptions| {
+            let cache = &cache;
+            let deps = &deps;
+            async move {
+                manifest
+                    .lock(None::<&Path>, deps, None, Some(cache), &options)
+                    .await
+            }
+        };
+
+        let detected = lock(
+            Manifest::from([("foo".into(), Entry::from(url.clone()))]),
+            options.clone(),
+        )
+        .await?;
+        let license = |id| {
+            detected
+                .get(id)
+                .and_then(|entry| entry.resolution.license.as_deref())
+        };
+        assert_eq!(license("foo"), Some("Apache-2.0 OR MIT"));
+        assert_eq!(license("bar"), Some("Apache-2.0 OR MIT"));
+
+        let overridden = lock(
+            Manifest::from([("foo".into(), Entry::from(url.clone()).with_license("MIT"))]),
+            options.clone(),
+        )
+        .await?;
+        assert_eq!(
+            overridden
+                .get("foo")
+                .and_then(|entry| entry.resolution.license.as_deref()),
+            Some("MIT")
+        );
+
+        let permitted = lock(
+            Manifest::from([("foo".into(), Entry::from(url.clone()))]),
+            options
+                .clone()
+                .with_license_policy(LicensePolicy::default().with_allow("MIT")),
+        )
+        .await;
+        assert!(permitted.is_ok());
+
+        let denied = lock(
+            Manifest::from([(
+                "foo".into(),
+                Entry::from(url.clone()).with_license("GPL-3.0-only"),
+            )]),
+            options
+                .clone()
+                .with_license_policy(LicensePolicy::default().with_deny("GPL-3.0-only")),
+        )
+        .await;
+        let Err(err) = denied else {
+            bail!("denied license must fail locking");
+        };
+        assert_eq!(
+            format!("{err:#}"),
+            "license `GPL-3.0-only` of `bar` is not permitted by the license policy"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn decode_layout() -> anyhow::Result<()> {
+        let manifest: Manifest = toml::from_str(
+            r#"
+layout = "versioned"
+foo = "../foo"
+"#,
+        )?;
+        assert_eq!(manifest.layout(), DepsLayout::Versioned);
+        assert_eq!(manifest.keys().collect::<Vec<_>>(), ["foo"]);
+        assert_eq!(
+            toml::from_str::<Manifest>(&toml::to_string(&manifest)?)?,
+            manifest
+        );
+
+        let manifest: Manifest = toml::from_str(r#"layout = "../layout""#)?;
+        assert_eq!(manifest.layout(), DepsLayout::Flat);
+        assert_eq!(
+            manifest.get("layout"),
+            Some(&Entry::from(PathBuf::from("../layout")))
+        );
+        assert!(!toml::to_string(&manifest)?.contains("flat"));
+        Ok(())
+    }
+
+    #[test]
+    fn decode_registry() -> anyhow::Result<()> {
+        let manifest: Manifest = toml::from_str(
+            r#"
+foo = "../foo"
+
+[registry]
+wasi = "https://github.com/WebAssembly/wasi-{name}/archive/refs/tags/v{version}.tar.gz"
+"#,
+        )?;
+        assert_eq!(
+            manifest.registry().template("wasi"),
+            Some("https://github.com/WebAssembly/wasi-{name}/archive/refs/tags/v{version}.tar.gz")
+        );
+        assert_eq!(manifest.keys().collect::<Vec<_>>(), ["foo"]);
+        assert_eq!(
+            toml::from_str::<Manifest>(&toml::to_string(&manifest)?)?,
+            manifest
+        );
+
+        let manifest: Manifest = toml::from_str(
+            r#"
+registry = { url = "https://example.com/registry.tar.gz" }
+"#,
+        )?;
+        assert!(manifest.registry().is_empty());
+        assert_eq!(manifest.keys().collect::<Vec<_>>(), ["registry"]);
+
+        let manifest: Manifest = toml::from_str(
+            r#"
+"wasi:io@0.2.0" = {}
+"wasi:clocks@0.2.0" = { sha256 = "0000000000000000000000000000000000000000000000000000000000000000" }
+"mycorp:foo@1.0.0" = { license = "MIT" }
+"mycorp:bar@1.0.0" = "https://example.com/bar.tar.gz"
+
+[registry]
+wasi = "https://github.com/WebAssembly/wasi-{name}/archive/refs/tags/v{version}.tar.gz"
+mycorp = "https://wit.mycorp.example/{name}/{version}.tar.gz"
+"#,
+        )?;
+        assert_eq!(
+            manifest.entries,
+            BTreeMap::from([
+                (
+                    "mycorp-foo-1.0.0".into(),
+                    Entry::Url {
+                        url: "https://wit.mycorp.example/foo/1.0.0.tar.gz".parse()?,
+                        sha256: None,
+                        sha512: None,
+                        blake3: None,
+                        sig: None,
+                        pubkey: None,
+                        max_age: None,
+                        license: Some("MIT".into()),
+                    }
+                ),
+                (
+                    "mycorp:bar@1.0.0".into(),
+                    Entry::from(Url::parse("https://example.com/bar.tar.gz")?)
+                ),
+                (
+                    "wasi-clocks-0.2.0".into(),
+                    Entry::Url {
+                        url: "https://github.com/WebAssembly/wasi-clocks/archive/refs/tags/v0.2.0.tar.gz"
+                            .parse()?,
+                        sha256: Some([0; 32]),
+                        sha512: None,
+                        blake3: None,
+                        sig: None,
+                        pubkey: None,
+                        max_age: None,
+                        license: None,
+                    }
+                ),
+                (
+                    "wasi-io-0.2.0".into(),
+                    Entry::from(Url::parse(
+                        "https://github.com/WebAssembly/wasi-io/archive/refs/tags/v0.2.0.tar.gz"
+                    )?)
                 ),
             ])
         );
+
+        let err = toml::from_str::<Manifest>(
+            r#"
+"foo:bar@1.0.0" = {}
+
+[registry]
+wasi = "https://example.com/{name}/{version}.tar.gz"
+"#,
+        )
+        .expect_err("package without template resolved");
+        assert!(err.to_string().contains("foo:bar@1.0.0"), "{err}");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_registry() -> anyhow::Result<()> {
+        use crate::MemoryCache;
+        use tokio::fs;
+
+        const HTTP_WIT: &[u8] =
+            b"package wasi:http@0.2.0;\ninterface types { use wasi:io/streams@0.2.0.{stream}; }\n";
+        const IO_WIT: &[u8] = b"package wasi:io@0.2.0;\ninterface streams {}\n";
+
+        let cache = MemoryCache::from_iter([
+            (
+                "https://example.com/http/0.2.0.tar.gz".parse()?,
+                tar_gz([("http-0.2.0/wit/types.wit", HTTP_WIT)]).await?,
+            ),
+            (
+                "https://example.com/io/0.2.0.tar.gz".parse()?,
+                tar_gz([("io-0.2.0/wit/streams.wit", IO_WIT)]).await?,
+            ),
+        ]);
+        let manifest: Manifest = toml::from_str(
+            r#"
+registry = { wasi = "https://example.com/{name}/{version}.tar.gz" }
+"#,
+        )?;
+        let offline = LockOptions::default().with_offline(true);
+
+        let dir = TempDir::new("wit-deps-lock-registry").await?;
+        let deps = dir.join("deps");
+        fs::create_dir_all(&dir).await?;
+        fs::write(
+            dir.join("root.wit"),
+            "package test:root;\nworld root { import wasi:http/types@0.2.0; }\n",
+        )
+        .await?;
+        let lock = manifest
+            .clone()
+            .lock(None::<&Path>, &deps, None, Some(&cache), &offline)
+            .await?;
+        let io = fs::read(deps.join("wasi-io-0.2.0").join("streams.wit")).await?;
+        let relocked = manifest
+            .clone()
+            .lock(
+                None::<&Path>,
+                &deps,
+                Some(&lock),
+                Some(&MemoryCache::default()),
+                &offline,
+            )
+            .await?;
+
+        assert_eq!(
+            lock.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["wasi-http-0.2.0", "wasi-io-0.2.0"]
+        );
+        let http = &lock["wasi-http-0.2.0"];
+        assert_eq!(http.package.as_deref(), Some("wasi:http@0.2.0"));
+        assert_eq!(
+            http.resolution.registry_record.as_deref(),
+            Some("wasi:http@0.2.0")
+        );
+        assert!(http.required_by.is_empty());
+        assert!(!http.is_direct());
+        let entry = &lock["wasi-io-0.2.0"];
+        assert_eq!(entry.package.as_deref(), Some("wasi:io@0.2.0"));
+        assert_eq!(
+            entry.required_by,
+            BTreeSet::from(["wasi-http-0.2.0".into()])
+        );
+        assert_eq!(io, IO_WIT);
+        assert!(manifest.out_of_sync(&lock).is_empty());
+        // Resolved dependencies are up-to-date and not fetched again
+        assert_eq!(relocked, lock);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_require_pinned() -> anyhow::Result<()> {
+        use crate::MemoryCache;
+
+        let manifest: Manifest = toml::from_str(&format!(
+            r#"
+require-pinned = true
+foo = "{FOO_URL}"
+bar = {{ url = "{BAR_URL}", sha256 = "{BAR_SHA256}" }}
+baz = {{ url = "{BAZ_URL}", blake3 = "{}" }}
+qux = "../qux"
+"#,
+            "00".repeat(32)
+        ))?;
+        assert!(manifest.require_pinned());
+        assert_eq!(
+            toml::from_str::<Manifest>(&toml::to_string(&manifest)?)?,
+            manifest
+        );
+        let unpinned = vec![
+            ("baz".to_string(), BAZ_URL.parse()?),
+            ("foo".to_string(), FOO_URL.parse()?),
+        ];
+        assert_eq!(manifest.unpinned(), unpinned);
+
+        let dir = TempDir::new("wit-deps-lock-require-pinned").await?;
+        let deps = dir.join("deps");
+        let offline = LockOptions::default().with_offline(true);
+        for (manifest, options) in [
+            (manifest.clone(), offline.clone()),
+            (
+                manifest.with_require_pinned(false),
+                offline.clone().with_require_pinned(true),
+            ),
+        ] {
+            let err = manifest
+                .lock(None::<&Path>, &deps, None, None::<&MemoryCache>, &options)
+                .await
+                .expect_err("unpinned entries must fail to lock");
+            let Some(Unpinned(entries)) = err.inner().downcast_ref::<Unpinned>() else {
+                bail!("unexpected error: {err:#}");
+            };
+            assert_eq!(*entries, unpinned);
+        }
+        assert!(!deps.exists());
+        assert_eq!(
+            Unpinned(unpinned).to_string(),
+            format!(
+                "the following URL dependencies are not pinned by a `sha256` or `sha512` digest:\n  - `baz` ({BAZ_URL})\n  - `foo` ({FOO_URL})"
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_require_complete() -> anyhow::Result<()> {
+        use crate::error::Missing;
+        use tokio::fs;
+
+        let dir = TempDir::new("wit-deps-lock-complete").await?;
+        let wit = dir.join("wit");
+        let deps = wit.join("deps");
+        let manifest =
+            Manifest::from_iter(["http", "io"].map(|id| (id.into(), Entry::from(dir.join(id)))));
+        for (path, src) in [
+            (
+                "wit/root.wit",
+                "package test:root;\nworld root { import wasi:io/streams@0.2.0; import foo:bar/baz; }\n",
+            ),
+            (
+                "http/types.wit",
+                "package wasi:http@0.2.0;\ninterface types { use wasi:io/streams@0.2.0.{stream}; use wasi:clocks/wall-clock@0.2.0.{datetime}; }\n",
+            ),
+            ("io/streams.wit", "package wasi:io@0.1.0;\ninterface streams {}\n"),
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().context("parent missing")?).await?;
+            fs::write(path, src).await?;
+        }
+        let lock = manifest
+            .clone()
+            .lock(
+                None::<&Path>,
+                &deps,
+                None,
+                None::<&crate::MemoryCache>,
+                &LockOptions::default(),
+            )
+            .await?;
+        let err = manifest
+            .clone()
+            .lock(
+                None::<&Path>,
+                &deps,
+                Some(&lock),
+                None::<&crate::MemoryCache>,
+                &LockOptions::default().with_require_complete(true),
+            )
+            .await
+            .err()
+            .context("incomplete dependencies locked")?;
+
+        assert_eq!(lock.keys().collect::<Vec<_>>(), ["http", "io"]);
+        let Some(Incomplete(missing)) = err.inner().downcast_ref::<Incomplete>() else {
+            panic!("unexpected error: {err:?}")
+        };
+        assert_eq!(
+            missing,
+            &[
+                Missing {
+                    package: "foo:bar".into(),
+                    root: Some(wit.clone()),
+                    used_by: vec![],
+                    expected: vec![],
+                },
+                Missing {
+                    package: "wasi:clocks@0.2.0".into(),
+                    root: None,
+                    used_by: vec!["http".into()],
+                    expected: vec!["http".into()],
+                },
+                Missing {
+                    package: "wasi:io@0.2.0".into(),
+                    root: Some(wit.clone()),
+                    used_by: vec!["http".into()],
+                    expected: vec!["io".into()],
+                },
+            ]
+        );
+        assert_eq!(
+            missing[2].to_string(),
+            format!(
+                "`wasi:io@0.2.0` used by `{}`, `http`, expected to be provided by `io`",
+                wit.display()
+            )
+        );
+        assert_eq!(
+            missing[0].to_string(),
+            format!(
+                "`foo:bar` used by `{}`, add a manifest entry providing it",
+                wit.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn lock_layout() -> anyhow::Result<()> {
+        use crate::{MemoryCache, MemoryVfs};
+        use tokio::fs;
+
+        const IO_V2_WIT: &str = "package wasi:io@0.2.0;\ninterface error {}\n";
+        const IO_V3_WIT: &str = "package wasi:io@0.3.0;\ninterface error {}\n";
+
+        let dir = TempDir::new("wit-deps-lock-layout").await?;
+        let deps = dir.join("deps");
+        let vfs = MemoryVfs::default();
+        let manifest = |layout| {
+            Manifest::from_iter(["a", "b", "io"].map(|id| (id.into(), Entry::from(dir.join(id)))))
+                .with_layout(layout)
+        };
+        let lock = |layout, lock: Option<Lock>| {
+            let options = LockOptions::default().with_vfs(vfs.clone());
+            let manifest = manifest(layout);
+            let deps = &deps;
+            async move {
+                manifest
+                    .lock(
+                        None::<&Path>,
+                        deps,
+                        lock.as_ref(),
+                        None::<&MemoryCache>,
+                        &options,
+                    )
+                    .await
+            }
+        };
+        for (id, wit) in [("a", Some(IO_V2_WIT)), ("b", Some(IO_V3_WIT)), ("io", None)] {
+            let src = dir.join(id);
+            fs::create_dir_all(&src).await?;
+            let pkg = match wit {
+                Some(wit) => {
+                    fs::create_dir_all(src.join("deps").join("io")).await?;
+                    fs::write(src.join("deps").join("io").join("io.wit"), wit).await?;
+                    format!("package test:{id};\n")
+                }
+                None => IO_V2_WIT.into(),
+            };
+            fs::write(src.join(format!("{id}.wit")), pkg).await?;
+        }
+        let versioned = lock(DepsLayout::Versioned, None).await?;
+        let package = lock(DepsLayout::Package, Some(versioned.clone())).await?;
+        let files = vfs.files();
+        let flat = lock(DepsLayout::Flat, Some(package.clone())).await?;
+
+        assert_eq!(
+            versioned.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["a", "b", "io", "wasi-io-0.3.0"]
+        );
+        let dir = |lock: &Lock, id| lock.get(id).and_then(|entry| entry.dir.clone());
+        assert_eq!(dir(&versioned, "a").as_deref(), Some("test-a"));
+        assert_eq!(dir(&versioned, "io").as_deref(), Some("wasi-io-0.2.0"));
+        assert_eq!(dir(&versioned, "wasi-io-0.3.0"), None);
+        let required = |lock: &Lock, id| lock.get(id).map(|entry| entry.deps.clone());
+        assert_eq!(required(&versioned, "a"), Some(BTreeSet::default()));
+        assert_eq!(
+            required(&versioned, "b"),
+            Some(BTreeSet::from(["wasi-io-0.3.0".into()]))
+        );
+        assert_eq!(dir(&package, "io").as_deref(), Some("wasi:io@0.2.0"));
+        assert_eq!(
+            package.keys().map(String::as_str).collect::<Vec<_>>(),
+            ["a", "b", "io", "wasi:io@0.3.0"]
+        );
+        assert_eq!(
+            files
+                .keys()
+                .map(|path| path.strip_prefix(&deps))
+                .collect::<Result<Vec<_>, _>>()?,
+            [
+                Path::new("test:a/a.wit"),
+                Path::new("test:b/b.wit"),
+                Path::new("wasi:io@0.2.0/io.wit"),
+                Path::new("wasi:io@0.3.0/io.wit"),
+            ]
+        );
+        assert_eq!(dir(&flat, "io"), None);
+        assert_eq!(
+            vfs.file(deps.join("io").join("io.wit")).as_deref(),
+            Some(IO_V2_WIT.as_bytes())
+        );
+        assert_eq!(
+            vfs.file(deps.join("wasi:io@0.3.0").join("io.wit"))
+                .as_deref(),
+            Some(IO_V3_WIT.as_bytes())
+        );
+        assert!(vfs.read_dir(&deps.join(STAGING)).await.is_err());
+        assert!(vfs.read_dir(&deps.join("test:a")).await.is_err());
         Ok(())
     }
 }
@@ -0,0 +1,88 @@
+use crate::Identifier;
+
+use std::path::PathBuf;
+
+use url::Url;
+
+/// Event reported to an [Observer] while locking dependencies
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// Dependency is up-to-date or not selected and is neither fetched nor copied
+    Skipped {
+        /// Identifier of the dependency
+        id: Identifier,
+    },
+    /// Contents of a URL dependency were found in cache
+    CacheHit {
+        /// Identifier of the dependency
+        id: Identifier,
+        /// URL of the dependency
+        url: Url,
+    },
+    /// Contents of a URL dependency were not found in cache
+    CacheMiss {
+        /// Identifier of the dependency
+        id: Identifier,
+        /// URL of the dependency
+        url: Url,
+    },
+    /// Fetch of a URL dependency from upstream started
+    FetchStarted {
+        /// Identifier of the dependency
+        id: Identifier,
+        /// URL of the dependency
+        url: Url,
+    },
+    /// Chunk of a URL dependency was downloaded
+    Downloaded {
+        /// Identifier of the dependency
+        id: Identifier,
+        /// URL of the dependency
+        url: Url,
+        /// Total number of bytes downloaded so far
+        bytes: u64,
+        /// Total number of bytes to download, if known
+        total: Option<u64>,
+    },
+    /// Fetch of a URL dependency from upstream finished
+    FetchFinished {
+        /// Identifier of the dependency
+        id: Identifier,
+        /// URL of the dependency
+        url: Url,
+        /// Total number of bytes downloaded
+        bytes: u64,
+    },
+    /// Dependency was installed into `deps` from upstream, cache or its path
+    Installed {
+        /// Identifier of the dependency
+        id: Identifier,
+        /// Directory the dependency was installed into
+        path: PathBuf,
+    },
+    /// Dependency was locked
+    Locked {
+        /// Identifier of the dependency
+        id: Identifier,
+    },
+}
+
+/// Observer of [events](Event) reported while locking dependencies, see
+/// [`LockOptions::with_observer`](crate::LockOptions::with_observer).
+///
+/// Observers are invoked synchronously from the locking pipeline and should return quickly,
+/// for example, by forwarding events over a channel.
+pub trait Observer: Send + Sync {
+    /// Handles `event`
+    fn on_event(&self, event: &Event);
+}
+
+impl<F> Observer for F
+where
+    F: Fn(&Event) + Send + Sync,
+{
+    fn on_event(&self, event: &Event) {
+        self(event);
+    }
+}
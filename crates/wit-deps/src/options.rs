@@ -0,0 +1,531 @@
+use crate::{
+    CachePolicy, DigestPolicy, Fetch, Identifier, InstallHook, LicensePolicy, LockEntrySource,
+    LockEvent, Observer, Vfs, WkgConfig,
+};
+
+use core::fmt;
+use core::num::NonZeroUsize;
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+/// Default maximum number of concurrent downloads, see
+/// [`LockOptions::with_download_concurrency`]
+const DEFAULT_DOWNLOAD_CONCURRENCY: usize = 8;
+
+/// Default maximum number of concurrent filesystem operations, see
+/// [`LockOptions::with_fs_concurrency`]
+const DEFAULT_FS_CONCURRENCY: usize = 32;
+
+/// Download permits shared by all [`LockOptions`], which do not configure a limit
+static DOWNLOADS: Semaphore = Semaphore::const_new(DEFAULT_DOWNLOAD_CONCURRENCY);
+
+/// Filesystem operation permits shared by all [`LockOptions`], which do not configure a limit
+static FS_OPERATIONS: Semaphore = Semaphore::const_new(DEFAULT_FS_CONCURRENCY);
+
+/// Limit of concurrent operations, which is shared by clones
+#[derive(Clone, Debug)]
+struct Limit {
+    permits: usize,
+    semaphore: Arc<Semaphore>,
+}
+
+impl Limit {
+    fn new(permits: usize) -> Self {
+        let permits = permits.max(1);
+        Self {
+            permits,
+            semaphore: Arc::new(Semaphore::new(permits)),
+        }
+    }
+}
+
+/// Options controlling how dependencies are locked and updated, see [lock](crate::lock()),
+/// [update](crate::update()), [`lock_path`](crate::lock_path) and [`Manifest::lock`](crate::Manifest::lock).
+///
+/// Options are constructed using [`LockOptions::default`] and the `with_*` methods, such that
+/// new options can be added without breaking existing consumers.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Default)]
+pub struct LockOptions {
+    concurrency: Option<NonZeroUsize>,
+    downloads: Option<Limit>,
+    fs_operations: Option<Limit>,
+    frozen: bool,
+    offline: bool,
+    packages: BTreeSet<Identifier>,
+    digests: DigestPolicy,
+    cache_dir: Option<PathBuf>,
+    cache_policy: CachePolicy,
+    #[cfg(feature = "check")]
+    validate: bool,
+    prune: bool,
+    deny_collisions: bool,
+    require_complete: bool,
+    require_pinned: bool,
+    hardlinks: bool,
+    licenses: LicensePolicy,
+    wkg: WkgConfig,
+    observer: Option<Arc<dyn Observer>>,
+    hooks: Vec<Arc<dyn InstallHook>>,
+    fetch: Option<Arc<dyn Fetch>>,
+    vfs: Option<Arc<dyn Vfs>>,
+    cancellation: Option<CancellationToken>,
+}
+
+impl fmt::Debug for LockOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("LockOptions");
+        s.field("concurrency", &self.concurrency)
+            .field("downloads", &self.downloads.as_ref().map(|l| l.permits))
+            .field(
+                "fs_operations",
+                &self.fs_operations.as_ref().map(|l| l.permits),
+            )
+            .field("frozen", &self.frozen)
+            .field("offline", &self.offline)
+            .field("packages", &self.packages)
+            .field("digests", &self.digests)
+            .field("cache_dir", &self.cache_dir)
+            .field("cache_policy", &self.cache_policy);
+        #[cfg(feature = "check")]
+        s.field("validate", &self.validate);
+        s.field("prune", &self.prune)
+            .field("deny_collisions", &self.deny_collisions)
+            .field("require_complete", &self.require_complete)
+            .field("require_pinned", &self.require_pinned)
+            .field("hardlinks", &self.hardlinks)
+            .field("licenses", &self.licenses)
+            .field("wkg", &self.wkg)
+            .field("observer", &self.observer.is_some())
+            .field("hooks", &self.hooks.len())
+            .field("fetch", &self.fetch.is_some())
+            .field("vfs", &self.vfs.is_some())
+            .field("cancellation", &self.cancellation)
+            .finish()
+    }
+}
+
+impl LockOptions {
+    /// Returns the options, which lock up to `concurrency` dependencies concurrently.
+    /// Dependencies are locked one at a time by default, `0` is treated as `1`.
+    #[must_use]
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        Self {
+            concurrency: NonZeroUsize::new(concurrency),
+            ..self
+        }
+    }
+
+    /// Returns the options, which download up to `concurrency` URL dependencies concurrently,
+    /// `0` is treated as `1`. The limit is shared by clones of the options, such that it applies
+    /// across all concurrent locking operations using them. If not configured, up to 8
+    /// downloads are performed concurrently within the process.
+    #[must_use]
+    pub fn with_download_concurrency(self, concurrency: usize) -> Self {
+        Self {
+            downloads: Some(Limit::new(concurrency)),
+            ..self
+        }
+    }
+
+    /// Returns the options, which perform up to `concurrency` operations on the [Vfs]
+    /// dependencies are materialized into concurrently, `0` is treated as `1`. The limit is
+    /// shared by clones of the options, such that it applies across all concurrent locking
+    /// operations using them. If not configured, up to 32 operations are performed concurrently
+    /// within the process.
+    #[must_use]
+    pub fn with_fs_concurrency(self, concurrency: usize) -> Self {
+        Self {
+            fs_operations: Some(Limit::new(concurrency)),
+            ..self
+        }
+    }
+
+    /// Returns the options, which fail instead of creating or modifying the lock if `frozen` is
+    /// `true`. Note, that `deps` may still be populated from the lock. Frozen mode is not
+    /// applicable to updates and is ignored by them.
+    #[must_use]
+    pub fn with_frozen(self, frozen: bool) -> Self {
+        Self { frozen, ..self }
+    }
+
+    /// Returns the options, which perform no network access if `offline` is `true`, in which
+    /// case dependencies are only resolved from `deps` and cache
+    #[must_use]
+    pub fn with_offline(self, offline: bool) -> Self {
+        Self { offline, ..self }
+    }
+
+    /// Returns the options, which only lock or update the dependencies with identifiers in
+    /// `packages` and their transitive dependencies, while entries of all other dependencies
+    /// are preserved. All dependencies are selected if `packages` is empty.
+    #[must_use]
+    pub fn with_packages(self, packages: impl IntoIterator<Item = Identifier>) -> Self {
+        Self {
+            packages: packages.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Returns the options, which record optional digests in the lock according to `digests`.
+    /// Optional digests are always kept up-to-date, if the existing lock already contains them.
+    #[must_use]
+    pub fn with_digests(self, digests: DigestPolicy) -> Self {
+        Self { digests, ..self }
+    }
+
+    /// Returns the options, which use `dir` as the [`LocalCache`](crate::LocalCache) directory
+    /// instead of the default one, see [`LocalCache::cache_dir`](crate::LocalCache::cache_dir)
+    #[must_use]
+    pub fn with_cache_dir(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            cache_dir: Some(dir.into()),
+            ..self
+        }
+    }
+
+    /// Returns the options, which use the cache according to `policy`. The cache is read from
+    /// and written to by default.
+    #[must_use]
+    pub fn with_cache_policy(self, policy: CachePolicy) -> Self {
+        Self {
+            cache_policy: policy,
+            ..self
+        }
+    }
+
+    /// Returns the options, which validate the WIT definitions in the parent directory of `deps`
+    /// along with all dependencies after locking if `validate` is `true`, see
+    /// [`check_path`](crate::check_path)
+    #[cfg(feature = "check")]
+    #[must_use]
+    pub fn with_validation(self, validate: bool) -> Self {
+        Self { validate, ..self }
+    }
+
+    /// Returns the options, which remove all directories within `deps`, which are not present
+    /// in the resulting lock, after locking if `prune` is `true`, see [`Lock::prune`](crate::Lock::prune)
+    #[must_use]
+    pub fn with_prune(self, prune: bool) -> Self {
+        Self { prune, ..self }
+    }
+
+    /// Returns the options, which fail locking if `deny` is `true` and multiple dependencies
+    /// within `deps` declare the same WIT package. Such collisions are only logged as warnings
+    /// by default.
+    #[must_use]
+    pub fn with_deny_collisions(self, deny: bool) -> Self {
+        Self {
+            deny_collisions: deny,
+            ..self
+        }
+    }
+
+    /// Returns the options, which fail locking if `require` is `true` and any WIT package used
+    /// by the package in the parent directory of `deps` or any locked dependency is not declared
+    /// by a locked dependency. The error lists all missing packages along with the manifest
+    /// entries expected to provide them. By default, missing packages are only detected once the
+    /// WIT definitions are resolved.
+    #[must_use]
+    pub fn with_require_complete(self, require: bool) -> Self {
+        Self {
+            require_complete: require,
+            ..self
+        }
+    }
+
+    /// Returns the options, which fail locking if `require` is `true` and any URL entry of the
+    /// manifest is not pinned by a `sha256` or `sha512` digest, before anything is fetched. The
+    /// error lists all unpinned entries. See also
+    /// [`Manifest::with_require_pinned`](crate::Manifest::with_require_pinned).
+    #[must_use]
+    pub fn with_require_pinned(self, require: bool) -> Self {
+        Self {
+            require_pinned: require,
+            ..self
+        }
+    }
+
+    /// Returns the options, which hard link WIT files of dependencies into `deps` if `hardlinks`
+    /// is `true` and the source is on the same filesystem. By default, files are cloned if
+    /// supported by the filesystem, e.g. on btrfs, XFS and APFS, and copied otherwise.
+    ///
+    /// Note, that hard linked files share their contents with path dependencies and the cache,
+    /// therefore modifying a file in `deps` modifies its source as well. This has no effect when
+    /// using a custom [Vfs].
+    #[must_use]
+    pub fn with_hardlinks(self, hardlinks: bool) -> Self {
+        Self { hardlinks, ..self }
+    }
+
+    /// Returns the options, which fail locking if the license of any locked dependency is not
+    /// permitted by `policy`. Licenses are recorded in the lock, but not restricted by default.
+    #[must_use]
+    pub fn with_license_policy(self, policy: LicensePolicy) -> Self {
+        Self {
+            licenses: policy,
+            ..self
+        }
+    }
+
+    /// Returns the options, which select [Registry](crate::Registry) templates of missing WIT
+    /// packages by the registries they are assigned to by `config`, consistently with `wkg`
+    #[must_use]
+    pub fn with_wkg_config(self, config: WkgConfig) -> Self {
+        Self {
+            wkg: config,
+            ..self
+        }
+    }
+
+    /// Returns the options, which report progress of locking to `observer`, see [`LockEvent`]
+    #[must_use]
+    pub fn with_observer(self, observer: impl Observer + 'static) -> Self {
+        Self {
+            observer: Some(Arc::new(observer)),
+            ..self
+        }
+    }
+
+    /// Returns the options, which additionally invoke `hook` before and after each dependency is
+    /// installed. Hooks are invoked in the order they were added.
+    #[must_use]
+    pub fn with_hook(mut self, hook: impl InstallHook + 'static) -> Self {
+        self.hooks.push(Arc::new(hook));
+        self
+    }
+
+    /// Returns the options, which fetch URL dependencies using `fetch` instead of the default
+    /// [`ReqwestFetch`](crate::ReqwestFetch) configured by [`ReqwestFetch::from_env`](crate::ReqwestFetch::from_env)
+    /// if the `reqwest` feature is enabled. Without it, URL dependencies can only be fetched
+    /// using a [Fetch] configured by this method.
+    #[must_use]
+    pub fn with_fetch(self, fetch: impl Fetch + 'static) -> Self {
+        Self {
+            fetch: Some(Arc::new(fetch)),
+            ..self
+        }
+    }
+
+    /// Returns the options, which materialize dependencies into `vfs` instead of the OS
+    /// filesystem, see [`MemoryVfs`](crate::MemoryVfs). Unpacked trees and snapshots are not
+    /// inserted into cache and `deps` is not rolled back on cancellation when using a custom
+    /// [Vfs], since both require `deps` to be present on disk.
+    #[must_use]
+    pub fn with_vfs(self, vfs: impl Vfs + 'static) -> Self {
+        Self {
+            vfs: Some(Arc::new(vfs)),
+            ..self
+        }
+    }
+
+    /// Returns the options, which abort locking once `token` is cancelled. In-flight downloads
+    /// are aborted and `deps` is rolled back to the state before locking started, see
+    /// [`Manifest::lock`](crate::Manifest::lock). Note, that `deps` is backed up to enable this,
+    /// which requires copying it.
+    #[must_use]
+    pub fn with_cancellation(self, token: CancellationToken) -> Self {
+        Self {
+            cancellation: Some(token),
+            ..self
+        }
+    }
+
+    /// Returns the maximum number of dependencies locked concurrently
+    #[must_use]
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.map_or(1, NonZeroUsize::get)
+    }
+
+    /// Returns the maximum number of concurrent downloads
+    #[must_use]
+    pub fn download_concurrency(&self) -> usize {
+        self.downloads
+            .as_ref()
+            .map_or(DEFAULT_DOWNLOAD_CONCURRENCY, |limit| limit.permits)
+    }
+
+    /// Returns the maximum number of concurrent filesystem operations
+    #[must_use]
+    pub fn fs_concurrency(&self) -> usize {
+        self.fs_operations
+            .as_ref()
+            .map_or(DEFAULT_FS_CONCURRENCY, |limit| limit.permits)
+    }
+
+    /// Returns the options, which only retain the settings controlling how dependencies are
+    /// fetched, i.e. the download limit, [Fetch] implementation and cancellation
+    pub(crate) fn fetch_options(&self) -> Self {
+        Self {
+            downloads: self.downloads.clone(),
+            fetch: self.fetch.clone(),
+            cancellation: self.cancellation.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Returns the semaphore limiting concurrent downloads
+    pub(crate) fn download_permits(&self) -> &Semaphore {
+        self.downloads
+            .as_ref()
+            .map_or(&DOWNLOADS, |limit| &limit.semaphore)
+    }
+
+    /// Returns the semaphore limiting concurrent filesystem operations
+    pub(crate) fn fs_permits(&self) -> &Semaphore {
+        self.fs_operations
+            .as_ref()
+            .map_or(&FS_OPERATIONS, |limit| &limit.semaphore)
+    }
+
+    /// Returns `true` if the lock must not be created or modified
+    #[must_use]
+    pub fn frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Returns `true` if no network access is performed
+    #[must_use]
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Returns the selected packages, if any
+    #[must_use]
+    pub fn packages(&self) -> Option<&BTreeSet<Identifier>> {
+        (!self.packages.is_empty()).then_some(&self.packages)
+    }
+
+    /// Returns the policy controlling which optional digests are recorded in the lock
+    #[must_use]
+    pub fn digests(&self) -> DigestPolicy {
+        self.digests
+    }
+
+    /// Returns the [`LocalCache`](crate::LocalCache) directory override, if any
+    #[must_use]
+    pub fn cache_dir(&self) -> Option<&Path> {
+        self.cache_dir.as_deref()
+    }
+
+    /// Returns the policy controlling how the cache is used
+    #[must_use]
+    pub fn cache_policy(&self) -> CachePolicy {
+        self.cache_policy
+    }
+
+    /// Returns `true` if WIT definitions are validated after locking
+    #[cfg(feature = "check")]
+    #[must_use]
+    pub fn validate(&self) -> bool {
+        self.validate
+    }
+
+    /// Returns `true` if directories not present in the lock are removed after locking
+    #[must_use]
+    pub fn prune(&self) -> bool {
+        self.prune
+    }
+
+    /// Returns `true` if multiple dependencies declaring the same WIT package fail locking
+    #[must_use]
+    pub fn deny_collisions(&self) -> bool {
+        self.deny_collisions
+    }
+
+    /// Returns `true` if WIT packages used, but not declared by any locked dependency fail
+    /// locking
+    #[must_use]
+    pub fn require_complete(&self) -> bool {
+        self.require_complete
+    }
+
+    /// Returns `true` if URL entries not pinned by a digest fail locking
+    #[must_use]
+    pub fn require_pinned(&self) -> bool {
+        self.require_pinned
+    }
+
+    /// Returns `true` if WIT files of dependencies are hard linked into `deps`
+    #[must_use]
+    pub fn hardlinks(&self) -> bool {
+        self.hardlinks
+    }
+
+    /// Returns the policy restricting licenses of locked dependencies
+    #[must_use]
+    pub fn license_policy(&self) -> &LicensePolicy {
+        &self.licenses
+    }
+
+    /// Returns the `wkg` registry configuration
+    #[must_use]
+    pub fn wkg_config(&self) -> &WkgConfig {
+        &self.wkg
+    }
+
+    /// Returns the [Fetch] implementation used to fetch URL dependencies, if overridden
+    #[must_use]
+    pub fn fetch(&self) -> Option<&dyn Fetch> {
+        self.fetch.as_deref()
+    }
+
+    /// Returns the [Vfs] dependencies are materialized into, if overridden
+    #[must_use]
+    pub fn vfs(&self) -> Option<&dyn Vfs> {
+        self.vfs.as_deref()
+    }
+
+    /// Returns the cancellation token, if any
+    #[must_use]
+    pub fn cancellation(&self) -> Option<&CancellationToken> {
+        self.cancellation.as_ref()
+    }
+
+    /// Reports the event returned by `event` to the observer, if any
+    pub(crate) fn report(&self, event: impl FnOnce() -> LockEvent) {
+        if let Some(observer) = &self.observer {
+            observer.on_event(&event());
+        }
+    }
+
+    /// Invokes [`InstallHook::before_install`] of all hooks
+    pub(crate) async fn before_install(
+        &self,
+        id: &Identifier,
+        source: &LockEntrySource,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        for hook in &self.hooks {
+            hook.before_install(id, source, path)
+                .await
+                .with_context(|| format!("pre-install hook failed for `{id}`"))?;
+        }
+        Ok(())
+    }
+
+    /// Reports [`LockEvent::Installed`] and invokes [`InstallHook::after_install`] of all hooks
+    pub(crate) async fn installed(
+        &self,
+        id: &Identifier,
+        source: &LockEntrySource,
+        path: &Path,
+    ) -> anyhow::Result<()> {
+        self.report(|| LockEvent::Installed {
+            id: id.clone(),
+            path: path.to_path_buf(),
+        });
+        for hook in &self.hooks {
+            hook.after_install(id, source, path)
+                .await
+                .with_context(|| format!("post-install hook failed for `{id}`"))?;
+        }
+        Ok(())
+    }
+}
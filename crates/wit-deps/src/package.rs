@@ -0,0 +1,226 @@
+use crate::{is_wit, Vfs};
+
+use std::collections::BTreeSet;
+use std::path::Path;
+
+use tracing::trace;
+
+/// Normalized contents of a WIT package, which are equal for packages differing only
+/// cosmetically, i.e. in comments, whitespace, split into files or order of top-level items
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Package {
+    /// Name of the package, if declared
+    pub name: Option<String>,
+    /// Sorted top-level items, excluding the package declaration, each normalized to a
+    /// sequence of tokens separated by a single space
+    items: Vec<String>,
+}
+
+/// Splits `src` into tokens, skipping whitespace and comments
+fn tokens(src: &str) -> Vec<String> {
+    let is_word = |c: char| c.is_alphanumeric() || matches!(c, '-' | '_' | '%');
+    let mut tokens = Vec::default();
+    let mut chars = src.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                // NOTE: Block comments nest in WIT
+                chars.next();
+                let mut depth = 1_usize;
+                while depth > 0 {
+                    match (chars.next(), chars.peek()) {
+                        (Some('/'), Some('*')) => {
+                            chars.next();
+                            depth += 1;
+                        }
+                        (Some('*'), Some('/')) => {
+                            chars.next();
+                            depth -= 1;
+                        }
+                        (Some(_), _) => {}
+                        (None, _) => break,
+                    }
+                }
+            }
+            c if is_word(c) => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek().filter(|&&c| is_word(c)) {
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(word);
+            }
+            c => tokens.push(c.into()),
+        }
+    }
+    tokens
+}
+
+impl Package {
+    /// Parses normalized package contents from WIT definitions in `srcs`
+    pub fn parse<'a>(srcs: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut pkg = Self::default();
+        for src in srcs {
+            let mut item = Vec::default();
+            let mut depth = 0_usize;
+            for token in tokens(src) {
+                let end = match token.as_str() {
+                    "{" => {
+                        depth += 1;
+                        false
+                    }
+                    "}" => {
+                        depth = depth.saturating_sub(1);
+                        depth == 0
+                    }
+                    ";" => depth == 0,
+                    _ => false,
+                };
+                item.push(token);
+                if end {
+                    pkg.push(item.join(" "));
+                    item.clear();
+                }
+            }
+            if !item.is_empty() {
+                pkg.push(item.join(" "));
+            }
+        }
+        pkg.items.sort();
+        pkg
+    }
+
+    /// Records top-level `item`
+    fn push(&mut self, item: String) {
+        match item
+            .strip_prefix("package ")
+            .and_then(|name| name.strip_suffix(" ;"))
+        {
+            Some(name) => self.name = Some(name.replace(' ', "")),
+            None => self.items.push(item),
+        }
+    }
+
+    /// Returns names of all packages referenced by `use`, `import`, `export` and `include`
+    /// items of the package, e.g. `wasi:io@0.2.0` for `use wasi:io/streams@0.2.0.{input-stream}`
+    pub fn uses(&self) -> BTreeSet<String> {
+        let is_word = |t: &&str| t.chars().next().is_some_and(char::is_alphanumeric);
+        let mut names = BTreeSet::default();
+        for item in &self.items {
+            let tokens: Vec<_> = item.split(' ').collect();
+            for (i, keyword) in tokens.iter().enumerate() {
+                if !matches!(*keyword, "use" | "import" | "export" | "include") {
+                    continue;
+                }
+                let [namespace, ":", name, "/", _, rest @ ..] = &tokens[i + 1..] else {
+                    continue;
+                };
+                if !is_word(namespace) || !is_word(name) {
+                    continue;
+                }
+                let mut package = format!("{namespace}:{name}");
+                if let ["@", version, rest @ ..] = rest {
+                    package.push('@');
+                    package.push_str(version);
+                    let mut rest = rest;
+                    while let [".", part, tail @ ..] = rest {
+                        if !is_word(part) {
+                            break;
+                        }
+                        package.push('.');
+                        package.push_str(part);
+                        rest = tail;
+                    }
+                }
+                names.insert(package);
+            }
+        }
+        names
+    }
+
+    /// Reads normalized package contents from WIT definitions in directory at `path` within `vfs`
+    pub async fn read(vfs: &dyn Vfs, path: &Path) -> std::io::Result<Self> {
+        let mut srcs = Vec::default();
+        for name in vfs.read_dir(path).await? {
+            if !is_wit(&name) {
+                continue;
+            }
+            let file = vfs.read(&path.join(name)).await?;
+            srcs.push(String::from_utf8_lossy(&file.contents).into_owned());
+        }
+        let pkg = Self::parse(srcs.iter().map(String::as_str));
+        trace!(name = pkg.name, "read package at `{}`", path.display());
+        Ok(pkg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let pkg = Package::parse([
+            "package wasi:io@0.2.0;\n\ninterface error {\n  resource error;\n}\n",
+            "package wasi:io@0.2.0;\n\n/// Streams\ninterface streams {\n  use error.{error};\n}\n",
+        ]);
+        assert_eq!(pkg.name.as_deref(), Some("wasi:io@0.2.0"));
+        assert_eq!(
+            pkg,
+            Package::parse([concat!(
+                "// Reordered and reformatted\n",
+                "package wasi : io @ 0.2.0 ;\n",
+                "interface streams { /* nested /* block */ comment */ use error.{error}; }\n",
+                "interface error { resource error; }\n",
+            )])
+        );
+        assert_ne!(
+            pkg,
+            Package::parse(["package wasi:io@0.2.0;\ninterface error { resource error; }\n"])
+        );
+        assert_ne!(
+            pkg,
+            Package::parse([
+                "package wasi:io@0.2.1;\ninterface error { resource error; }\n",
+                "interface streams { use error.{error}; }\n",
+            ])
+        );
+        assert!(pkg.uses().is_empty());
+    }
+
+    #[test]
+    fn uses() {
+        let pkg = Package::parse([concat!(
+            "package wasi:http@0.2.0;\n",
+            "interface types {\n",
+            "  use wasi:io/streams@0.2.0-rc-2023-11-10.{input-stream};\n",
+            "  use wasi:clocks/monotonic-clock@0.2.0.{duration};\n",
+            "  use types.{error};\n",
+            "}\n",
+            "world proxy {\n",
+            "  import wasi:random/random@0.2.0;\n",
+            "  import handler: func();\n",
+            "  include wasi:cli/imports;\n",
+            "  export wasi:http/incoming-handler@0.2.0;\n",
+            "}\n",
+        )]);
+        assert_eq!(
+            pkg.uses(),
+            BTreeSet::from([
+                "wasi:cli".into(),
+                "wasi:clocks@0.2.0".into(),
+                "wasi:http@0.2.0".into(),
+                "wasi:io@0.2.0-rc-2023-11-10".into(),
+                "wasi:random@0.2.0".into(),
+            ])
+        );
+    }
+}
@@ -0,0 +1,332 @@
+use crate::error::Missing;
+use crate::package::Package;
+use crate::{DepsLayout, Identifier, Lock, Vfs, WkgConfig};
+
+use core::iter;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Key of the URL template used for namespaces without a template of their own
+const DEFAULT_KEY: &str = "*";
+
+/// Mapping of WIT package namespaces to URL templates, which packages used by the manifest or
+/// its dependencies, but not declared by any of them, are resolved from, see
+/// [`Manifest::with_registry`](crate::Manifest::with_registry).
+///
+/// Templates may contain `{namespace}`, `{name}` and `{version}` placeholders, which are
+/// substituted by the respective parts of the missing package, e.g. `{name}` is substituted by
+/// `io` for `wasi:io@0.2.0`. The template of the `*` namespace is used for all namespaces
+/// without a template of their own.
+///
+/// Templates may also be keyed by the name of a registry, e.g. `wasi.dev`, which packages are
+/// assigned to by a [`WkgConfig`], see
+/// [`LockOptions::with_wkg_config`](crate::LockOptions::with_wkg_config). Such templates are
+/// used for namespaces without a template of their own and may contain a `{registry}`
+/// placeholder, which is substituted by the name of the registry.
+///
+/// Manifest entries keyed by a WIT package, e.g. `"wasi:io@0.2.0" = {}`, which specify neither
+/// `url` nor `path`, are resolved from the template of the namespace of the package, or the `*`
+/// template, when the manifest is decoded. They are installed like packages resolved from the
+/// registry, e.g. as `wasi-io-0.2.0`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Registry(BTreeMap<String, String>);
+
+impl Registry {
+    /// Returns the registry, which resolves packages in `namespace` from URLs given by
+    /// `template`. Use `*` as the `namespace` to set the default template or the name of a
+    /// registry to set the template of packages assigned to it.
+    #[must_use]
+    pub fn with_namespace(
+        mut self,
+        namespace: impl Into<String>,
+        template: impl Into<String>,
+    ) -> Self {
+        self.0.insert(namespace.into(), template.into());
+        self
+    }
+
+    /// Returns `true` if no templates are configured
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the URL template of `namespace`, if any
+    #[must_use]
+    pub fn template(&self, namespace: &str) -> Option<&str> {
+        self.0
+            .get(namespace)
+            .or_else(|| self.0.get(DEFAULT_KEY))
+            .map(String::as_str)
+    }
+
+    /// Returns the URL WIT package `package`, e.g. `wasi:io@0.2.0`, is resolved from, if the
+    /// namespace of it or the registry it is assigned to by `wkg` has a template. Returns [None]
+    /// if the template requires a version or registry, but `package` is not versioned or not
+    /// assigned to a registry.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the substituted template is not a valid URL
+    pub fn url(&self, package: &str, wkg: &WkgConfig) -> Result<Option<Url>, url::ParseError> {
+        let Some((namespace, name)) = package.split_once(':') else {
+            return Ok(None);
+        };
+        let (name, version) = match name.split_once('@') {
+            Some((name, version)) => (name, Some(version)),
+            None => (name, None),
+        };
+        let registry = wkg.registry(package);
+        let Some(template) = self
+            .0
+            .get(namespace)
+            .or_else(|| registry.and_then(|registry| self.0.get(registry)))
+            .or_else(|| self.0.get(DEFAULT_KEY))
+        else {
+            return Ok(None);
+        };
+        let url = template
+            .replace("{namespace}", namespace)
+            .replace("{name}", name);
+        let url = match version {
+            Some(version) => url.replace("{version}", version),
+            None if url.contains("{version}") => return Ok(None),
+            None => url,
+        };
+        let url = match registry {
+            Some(registry) => url.replace("{registry}", registry),
+            None if url.contains("{registry}") => return Ok(None),
+            None => url,
+        };
+        url.parse().map(Some)
+    }
+}
+
+/// Returns the identifier of the dependency WIT package `package` resolved from a [Registry] is
+/// locked as, e.g. `wasi-io-0.2.0`
+pub(crate) fn id(package: &str) -> Identifier {
+    DepsLayout::Versioned.dir_name(package, Some(package))
+}
+
+/// Returns the identifier of the registry record of WIT package `package` resolved from a
+/// [Registry], i.e. the package qualified by the registry it is assigned to by `wkg`, if any, e.g.
+/// `wasi.dev/wasi:io@0.2.0`
+pub(crate) fn record(package: &str, wkg: &WkgConfig) -> String {
+    match wkg.registry(package) {
+        Some(registry) => format!("{registry}/{package}"),
+        None => package.to_string(),
+    }
+}
+
+/// Returns `true` if `used` package is satisfied by `declared` package. Versions are only
+/// compared if both are versioned.
+fn satisfies(declared: &str, used: &str) -> bool {
+    match (declared.split_once('@'), used.split_once('@')) {
+        (Some(_), Some(_)) | (None, None) => declared == used,
+        (Some((declared, _)), None) => declared == used,
+        (None, Some((used, _))) => declared == used,
+    }
+}
+
+/// WIT packages declared and used by the package in a `wit` directory and locked dependencies
+#[derive(Debug, Default)]
+pub(crate) struct Usage {
+    /// Path of the `wit` directory
+    wit: PathBuf,
+    /// Names of declared packages
+    declared: BTreeSet<String>,
+    /// Names of used packages along with the locked dependencies using them
+    used: BTreeMap<String, BTreeSet<Identifier>>,
+    /// Names of packages used by the package in the `wit` directory
+    used_by_root: BTreeSet<String>,
+}
+
+impl Usage {
+    /// Reads packages declared and used by the package in the `wit` directory on disk and all
+    /// dependencies locked in `lock` and installed in `deps` within `vfs`
+    pub async fn read(
+        vfs: &dyn Vfs,
+        wit: &Path,
+        deps: &Path,
+        lock: &Lock,
+    ) -> std::io::Result<Self> {
+        let mut usage = Self {
+            wit: wit.to_path_buf(),
+            ..Self::default()
+        };
+        let paths = iter::once((None, wit.to_path_buf()))
+            .chain(lock.keys().map(|id| (Some(id), lock.path(deps, id))));
+        for (id, path) in paths {
+            let vfs = if id.is_some() { vfs } else { &crate::OsVfs };
+            let pkg = match Package::read(vfs, &path).await {
+                Ok(pkg) => pkg,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            usage.declared.extend(pkg.name.clone());
+            for name in pkg.uses() {
+                let used_by = usage.used.entry(name.clone()).or_default();
+                if let Some(id) = id {
+                    used_by.insert(id.clone());
+                } else {
+                    usage.used_by_root.insert(name);
+                }
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Returns names of used packages, which are not declared
+    pub fn missing(&self) -> impl Iterator<Item = &str> {
+        self.used
+            .keys()
+            .filter(|used| {
+                !self
+                    .declared
+                    .iter()
+                    .any(|declared| satisfies(declared, used))
+            })
+            .map(String::as_str)
+    }
+
+    /// Returns locked dependencies using `package`
+    pub fn used_by(&self, package: &str) -> BTreeSet<Identifier> {
+        self.used
+            .iter()
+            .filter(|(used, _)| satisfies(package, used))
+            .flat_map(|(_, ids)| ids.iter().cloned())
+            .collect()
+    }
+
+    /// Returns all [missing](Self::missing) packages along with the entries of `manifest`
+    /// expected to provide them given the dependencies locked in `lock`.
+    ///
+    /// Entries are expected to provide a package if they are named like it, e.g. `io`,
+    /// `wasi-io` or `wasi-io-0.2.0` for `wasi:io@0.2.0`. Otherwise, the entries, which introduced
+    /// the dependencies using the package, are expected to ship it.
+    pub fn incomplete(&self, manifest: &BTreeSet<Identifier>, lock: &Lock) -> Vec<Missing> {
+        self.missing()
+            .map(|package| {
+                let used_by = self.used_by(package);
+                let unversioned = package.split_once('@').map_or(package, |(name, _)| name);
+                let name = unversioned
+                    .split_once(':')
+                    .map_or(unversioned, |(_, name)| name);
+                let mut expected: Vec<_> = [
+                    name.to_string(),
+                    unversioned.replace(':', "-"),
+                    DepsLayout::Versioned.dir_name(package, Some(package)),
+                    DepsLayout::Package.dir_name(package, Some(package)),
+                ]
+                .into_iter()
+                .filter(|id| manifest.contains(id))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect();
+                if expected.is_empty() {
+                    expected = used_by
+                        .iter()
+                        .flat_map(|id| match lock.get(id) {
+                            Some(entry) if !manifest.contains(id) => {
+                                entry.required_by.iter().cloned().collect()
+                            }
+                            _ => vec![id.clone()],
+                        })
+                        .filter(|id| manifest.contains(id))
+                        .collect::<BTreeSet<_>>()
+                        .into_iter()
+                        .collect();
+                }
+                Missing {
+                    package: package.to_string(),
+                    root: self
+                        .used_by_root
+                        .iter()
+                        .any(|used| satisfies(package, used))
+                        .then(|| self.wit.clone()),
+                    used_by: used_by.into_iter().collect(),
+                    expected,
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url() -> anyhow::Result<()> {
+        let registry = Registry::default()
+            .with_namespace(
+                "wasi",
+                "https://github.com/WebAssembly/wasi-{name}/archive/refs/tags/v{version}.tar.gz",
+            )
+            .with_namespace("*", "https://example.com/{namespace}/{name}.tar.gz");
+        let wkg = WkgConfig::default();
+        assert_eq!(
+            registry
+                .url("wasi:io@0.2.0", &wkg)?
+                .as_ref()
+                .map(Url::as_str),
+            Some("https://github.com/WebAssembly/wasi-io/archive/refs/tags/v0.2.0.tar.gz")
+        );
+        assert_eq!(registry.url("wasi:io", &wkg)?, None);
+        assert_eq!(
+            registry
+                .url("foo:bar@1.0.0", &wkg)?
+                .as_ref()
+                .map(Url::as_str),
+            Some("https://example.com/foo/bar.tar.gz")
+        );
+        assert_eq!(Registry::default().url("wasi:io@0.2.0", &wkg)?, None);
+
+        let registry = Registry::default()
+            .with_namespace("wasi", "https://example.com/wasi/{name}.tar.gz")
+            .with_namespace("foo.dev", "https://{registry}/{namespace}/{name}.tar.gz");
+        let wkg = WkgConfig::default()
+            .with_default_registry("foo.dev")
+            .with_namespace_registry("bar", "bar.dev");
+        assert_eq!(
+            registry
+                .url("wasi:io@0.2.0", &wkg)?
+                .as_ref()
+                .map(Url::as_str),
+            Some("https://example.com/wasi/io.tar.gz")
+        );
+        assert_eq!(
+            registry
+                .url("foo:bar@1.0.0", &wkg)?
+                .as_ref()
+                .map(Url::as_str),
+            Some("https://foo.dev/foo/bar.tar.gz")
+        );
+        assert_eq!(registry.url("bar:baz@1.0.0", &wkg)?, None);
+        let registry = registry.with_namespace("*", "https://{registry}/{name}.tar.gz");
+        assert_eq!(
+            registry
+                .url("bar:baz@1.0.0", &wkg)?
+                .as_ref()
+                .map(Url::as_str),
+            Some("https://bar.dev/baz.tar.gz")
+        );
+        assert_eq!(registry.url("bar:baz@1.0.0", &WkgConfig::default())?, None);
+        assert_eq!(id("wasi:io@0.2.0"), "wasi-io-0.2.0");
+        assert_eq!(record("foo:bar@1.0.0", &wkg), "foo.dev/foo:bar@1.0.0");
+        assert_eq!(
+            record("foo:bar@1.0.0", &WkgConfig::default()),
+            "foo:bar@1.0.0"
+        );
+
+        assert!(satisfies("wasi:io@0.2.0", "wasi:io@0.2.0"));
+        assert!(satisfies("wasi:io@0.2.0", "wasi:io"));
+        assert!(!satisfies("wasi:io@0.2.0", "wasi:io@0.3.0"));
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use crate::error::InvalidManifest;
+use crate::{
+    cache, lock_decoded, lock_deps_dir, outcome, read_lock_string, read_manifest_string,
+    save_cache_counters, sticky_digest_policy, DigestPolicy, Error, Identifier, Lock, LockOptions,
+    LockOutcome, Manifest, MemoryVfs,
+};
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context as _;
+use futures::try_join;
+
+/// Result of resolving dependencies using a [`Resolver`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Resolved<T> {
+    /// Outcome of locking the dependencies
+    pub outcome: LockOutcome,
+    /// Resolved WIT packages of all locked dependencies, including transitive ones, keyed by
+    /// identifier
+    pub packages: BTreeMap<Identifier, T>,
+}
+
+/// High-level entry point for tools, which need WIT dependencies of a manifest resolved without
+/// managing locks, caches and `deps` directories themselves.
+///
+/// A resolver is constructed from a [`Manifest`] using [`Resolver::new`] or from a WIT directory
+/// using [`Resolver::from_dir`] and configured using the `with_*` methods.
+/// [`Resolver::resolve`] installs the dependencies on disk and returns their paths, while
+/// [`Resolver::resolve_sources`] returns the WIT definitions without writing them to disk.
+///
+/// Note, that the resulting lock is never written by the resolver, callers are expected to
+/// persist it if it was [updated](LockOutcome::updated).
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    manifest: Manifest,
+    at: Option<PathBuf>,
+    lock: Option<Lock>,
+    options: LockOptions,
+}
+
+impl From<Manifest> for Resolver {
+    fn from(manifest: Manifest) -> Self {
+        Self::new(manifest)
+    }
+}
+
+impl Resolver {
+    /// Returns a resolver of dependencies of `manifest`
+    #[must_use]
+    pub fn new(manifest: Manifest) -> Self {
+        Self {
+            manifest,
+            ..Self::default()
+        }
+    }
+
+    /// Returns a resolver of dependencies of the manifest at `deps.toml` within `dir` using the
+    /// lock at `deps.lock` within `dir`, if it exists. Path dependencies are resolved relative
+    /// to `dir`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifest cannot be read or decoded or if the lock cannot be decoded
+    pub async fn from_dir(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir.as_ref();
+        let manifest_path = dir.join("deps.toml");
+        let (manifest, lock) = try_join!(
+            read_manifest_string(&manifest_path),
+            read_lock_string(dir.join("deps.lock")),
+        )?;
+        let manifest = toml::from_str(&manifest).context(InvalidManifest(Some(manifest_path)))?;
+        let lock = lock
+            .as_deref()
+            .map(toml::from_str)
+            .transpose()
+            .context("failed to decode lock")?;
+        Ok(Self {
+            manifest,
+            at: Some(dir.into()),
+            lock,
+            options: LockOptions::default(),
+        })
+    }
+
+    /// Returns the resolver, which resolves path dependencies relative to `dir`
+    #[must_use]
+    pub fn with_root(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            at: Some(dir.into()),
+            ..self
+        }
+    }
+
+    /// Returns the resolver, which uses `lock` as the existing lock
+    #[must_use]
+    pub fn with_lock(self, lock: Lock) -> Self {
+        Self {
+            lock: Some(lock),
+            ..self
+        }
+    }
+
+    /// Returns the resolver, which locks dependencies according to `options`
+    #[must_use]
+    pub fn with_options(self, options: LockOptions) -> Self {
+        Self { options, ..self }
+    }
+
+    /// Returns the resolver, which only resolves the dependencies with identifiers in
+    /// `packages` and their transitive dependencies, see [`LockOptions::with_packages`]
+    #[must_use]
+    pub fn with_packages(self, packages: impl IntoIterator<Item = Identifier>) -> Self {
+        Self {
+            options: self.options.with_packages(packages),
+            ..self
+        }
+    }
+
+    /// Returns the manifest dependencies are resolved from
+    #[must_use]
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Returns the existing lock, if any
+    #[must_use]
+    pub fn lock(&self) -> Option<&Lock> {
+        self.lock.as_ref()
+    }
+
+    /// Returns the options dependencies are locked with
+    #[must_use]
+    pub fn options(&self) -> &LockOptions {
+        &self.options
+    }
+
+    /// Installs dependencies into `deps` like [`lock`](crate::lock()) and returns the directory
+    /// of each locked dependency
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if anything in the pipeline fails
+    pub async fn resolve(&self, deps: impl AsRef<Path>) -> Result<Resolved<PathBuf>, Error> {
+        let deps = deps.as_ref();
+        let _lock = lock_deps_dir(deps).await?;
+        let outcome = lock_decoded(
+            self.at.as_ref(),
+            self.manifest.clone(),
+            self.lock.clone(),
+            deps,
+            &self.options,
+        )
+        .await
+        .context("failed to resolve dependencies")?;
+        let packages = outcome
+            .lock
+            .keys()
+            .map(|id| (id.clone(), outcome.lock.path(deps, id)))
+            .collect();
+        Ok(Resolved { outcome, packages })
+    }
+
+    /// Resolves dependencies in memory and returns the contents of WIT definitions of each
+    /// locked dependency keyed by file name. Nothing is written to disk, except for the cache.
+    ///
+    /// Since dependencies are not present on disk, digests of individual files are never recorded
+    /// in the lock and options, which apply to `deps` as a whole, e.g. pruning or validation,
+    /// are not applied. Any [VFS](LockOptions::with_vfs) configured is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if anything in the pipeline fails or if a WIT definition is not valid
+    /// UTF-8
+    pub async fn resolve_sources(&self) -> Result<Resolved<BTreeMap<String, String>>, Error> {
+        let deps = Path::new("deps");
+        let vfs = MemoryVfs::default();
+        let options = self.options.clone().with_vfs(vfs.clone());
+        let old_lock = self.lock.clone().map(Lock::migrate);
+        let cache = cache(options.cache_dir(), options.offline())?;
+        let lock = self
+            .manifest
+            .clone()
+            .lock(
+                self.at.as_ref(),
+                deps,
+                old_lock.as_ref(),
+                cache.as_ref(),
+                &options,
+            )
+            .await;
+        save_cache_counters(cache.as_ref()).await;
+        let mut lock = lock.context("failed to resolve dependencies")?;
+        let policy = sticky_digest_policy(options.digests(), old_lock.as_ref());
+        lock.apply_digest_policy(
+            deps,
+            DigestPolicy {
+                files: false,
+                ..policy
+            },
+        )
+        .await?;
+
+        let mut packages: BTreeMap<Identifier, BTreeMap<String, String>> = lock
+            .keys()
+            .map(|id| (id.clone(), BTreeMap::default()))
+            .collect();
+        let ids: BTreeMap<_, _> = lock
+            .iter()
+            .map(|(id, entry)| (entry.dir.as_deref().unwrap_or(id), id))
+            .collect();
+        for (path, file) in vfs.files() {
+            let mut components = path.strip_prefix(deps).unwrap_or(&path).components();
+            let (Some(Component::Normal(id)), Some(Component::Normal(name)), None) =
+                (components.next(), components.next(), components.next())
+            else {
+                continue;
+            };
+            let (Some(dir), Some(name)) = (id.to_str(), name.to_str()) else {
+                continue;
+            };
+            let Some(sources) = ids.get(dir).and_then(|id| packages.get_mut(*id)) else {
+                continue;
+            };
+            let source = String::from_utf8(file.contents)
+                .with_context(|| format!("`{}` is not valid UTF-8", path.display()))?;
+            sources.insert(name.into(), source);
+        }
+        let outcome = outcome(old_lock, lock, options.frozen())?;
+        Ok(Resolved { outcome, packages })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    use tokio::fs;
+
+    #[tokio::test]
+    async fn resolve() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-resolver").await?;
+        let wit = dir.join("wit");
+        fs::create_dir_all(dir.join("foo").join("deps").join("bar")).await?;
+        fs::write(dir.join("foo").join("foo.wit"), "package foo:foo;").await?;
+        fs::write(
+            dir.join("foo").join("deps").join("bar").join("bar.wit"),
+            "package foo:bar;",
+        )
+        .await?;
+        fs::create_dir_all(&wit).await?;
+        fs::write(wit.join("deps.toml"), "foo = \"../foo\"\n").await?;
+
+        let resolver = Resolver::from_dir(&wit)
+            .await?
+            .with_options(LockOptions::default().with_cache_dir(dir.join("cache")));
+        let sources = resolver.resolve_sources().await?;
+        let deps_exist = wit.join("deps").exists();
+        let paths = resolver.resolve(wit.join("deps")).await?;
+        let installed = fs::read_to_string(wit.join("deps").join("bar").join("bar.wit")).await?;
+
+        assert!(!deps_exist);
+        assert!(sources.outcome.updated);
+        assert_eq!(
+            sources.packages,
+            BTreeMap::from([
+                (
+                    "bar".into(),
+                    BTreeMap::from([("bar.wit".into(), "package foo:bar;".into())])
+                ),
+                (
+                    "foo".into(),
+                    BTreeMap::from([("foo.wit".into(), "package foo:foo;".into())])
+                ),
+            ])
+        );
+        assert_eq!(paths.outcome.lock, sources.outcome.lock);
+        assert_eq!(
+            paths.packages,
+            BTreeMap::from([
+                ("bar".into(), wit.join("deps").join("bar")),
+                ("foo".into(), wit.join("deps").join("foo")),
+            ])
+        );
+        assert_eq!(installed, "package foo:bar;");
+        Ok(())
+    }
+}
@@ -0,0 +1,664 @@
+use crate::package::Package;
+use crate::{Digest, Error, Identifier, LockEntry, LockEntrySource, OsVfs};
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use serde::ser::Error as _;
+use serde::{Serialize, Serializer};
+use sha2::{Digest as _, Sha256};
+use tracing::{debug, instrument};
+
+/// Name and version of the tool recorded in the SBOM
+const TOOL: (&str, &str) = ("wit-deps", env!("CARGO_PKG_VERSION"));
+
+/// Software bill of materials format
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Format {
+    /// [CycloneDX](https://cyclonedx.org) 1.5 JSON
+    #[default]
+    CycloneDx,
+    /// [SPDX](https://spdx.dev) 2.3 JSON
+    Spdx,
+}
+
+/// Locked dependency recorded in the SBOM
+#[derive(Clone, Debug)]
+struct Component {
+    id: Identifier,
+    entry: LockEntry,
+    /// WIT package name without the version, if declared
+    package: Option<String>,
+    /// WIT package version, if declared, or the resolved upstream release tag otherwise
+    version: Option<String>,
+}
+
+impl Component {
+    fn name(&self) -> &str {
+        self.package.as_deref().unwrap_or(&self.id)
+    }
+
+    fn url(&self) -> Option<&str> {
+        match &self.entry.source {
+            Some(LockEntrySource::Url(url)) => Some(url.as_str()),
+            _ => None,
+        }
+    }
+
+    fn hashes(&self) -> impl Iterator<Item = (&'static str, String)> {
+        let Digest {
+            sha256,
+            sha512,
+            blake3,
+        } = &self.entry.digest;
+        [
+            Some(("SHA-256", hex::encode(sha256))),
+            Some(("SHA-512", hex::encode(sha512))),
+            blake3.map(|blake3| ("BLAKE3", hex::encode(blake3))),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// Returns `wit-deps` specific metadata of the component as name-value pairs
+    fn properties(&self) -> Vec<(&'static str, String)> {
+        let mut properties = vec![("id", self.id.clone())];
+        if let Some(LockEntrySource::Path(path)) = &self.entry.source {
+            properties.push(("path", path.to_string_lossy().replace('\\', "/")));
+        }
+        let resolution = &self.entry.resolution;
+        if let Some(commit) = &resolution.commit {
+            properties.push(("commit", commit.clone()));
+        }
+        if let Some(tag) = &resolution.tag {
+            properties.push(("tag", tag.clone()));
+        }
+        if let Some(record) = &resolution.registry_record {
+            properties.push(("registry-record", record.clone()));
+        }
+        if let Some(url) = &resolution.final_url {
+            properties.push(("final-url", url.to_string()));
+        }
+        properties
+    }
+}
+
+/// `CycloneDX` JSON document structure, see <https://cyclonedx.org/docs/1.5/json/>
+mod cyclonedx {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Bom<'a> {
+        #[serde(rename = "bomFormat")]
+        pub format: &'static str,
+        pub spec_version: &'static str,
+        pub version: u32,
+        pub metadata: Metadata<'a>,
+        pub components: Vec<Component<'a>>,
+        pub dependencies: Vec<Dependency<'a>>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Metadata<'a> {
+        pub timestamp: String,
+        pub tools: Tools,
+        pub component: Component<'a>,
+    }
+
+    #[derive(Serialize)]
+    pub struct Tools {
+        pub components: [Component<'static>; 1],
+    }
+
+    #[derive(Serialize)]
+    pub struct Component<'a> {
+        #[serde(rename = "type")]
+        pub kind: &'static str,
+        #[serde(rename = "bom-ref")]
+        pub bom_ref: &'a str,
+        pub name: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub version: Option<&'a str>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub licenses: Vec<License<'a>>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub hashes: Vec<Hash>,
+        #[serde(rename = "externalReferences", skip_serializing_if = "Vec::is_empty")]
+        pub external_references: Vec<ExternalReference<'a>>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub properties: Vec<Property>,
+    }
+
+    #[derive(Serialize)]
+    pub struct License<'a> {
+        pub expression: &'a str,
+    }
+
+    #[derive(Serialize)]
+    pub struct Hash {
+        pub alg: &'static str,
+        pub content: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct ExternalReference<'a> {
+        #[serde(rename = "type")]
+        pub kind: &'static str,
+        pub url: &'a str,
+    }
+
+    #[derive(Serialize)]
+    pub struct Property {
+        pub name: String,
+        pub value: String,
+    }
+
+    #[derive(Serialize)]
+    pub struct Dependency<'a> {
+        #[serde(rename = "ref")]
+        pub reference: &'a str,
+        #[serde(rename = "dependsOn")]
+        pub depends_on: Vec<&'a str>,
+    }
+}
+
+/// SPDX JSON document structure, see <https://spdx.github.io/spdx-spec/v2.3/>
+mod spdx {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Document<'a> {
+        pub spdx_version: &'static str,
+        pub data_license: &'static str,
+        #[serde(rename = "SPDXID")]
+        pub spdx_id: &'static str,
+        pub name: &'a str,
+        #[serde(rename = "documentNamespace")]
+        pub namespace: String,
+        pub creation_info: CreationInfo,
+        pub packages: Vec<Package<'a>>,
+        pub relationships: Vec<Relationship>,
+    }
+
+    #[derive(Serialize)]
+    pub struct CreationInfo {
+        pub created: String,
+        pub creators: [String; 1],
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Package<'a> {
+        #[serde(rename = "SPDXID")]
+        pub spdx_id: String,
+        pub name: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub version_info: Option<&'a str>,
+        pub download_location: &'a str,
+        pub files_analyzed: bool,
+        pub license_concluded: &'static str,
+        pub license_declared: &'a str,
+        pub copyright_text: &'static str,
+        pub checksums: Vec<Checksum>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub source_info: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Checksum {
+        pub algorithm: String,
+        #[serde(rename = "checksumValue")]
+        pub value: String,
+    }
+
+    #[derive(Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Relationship {
+        pub spdx_element_id: String,
+        #[serde(rename = "relationshipType")]
+        pub kind: &'static str,
+        pub related_spdx_element: String,
+    }
+
+    /// Returns the SPDX identifier of the package for dependency `id`, which may only
+    /// contain letters, numbers, `.` and `-`
+    pub fn package_id(id: &str) -> String {
+        let id: String = id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        format!("SPDXRef-Package-{id}")
+    }
+}
+
+/// Software bill of materials of locked dependencies returned by [`sbom_path`], which is
+/// serialized in the [`Format`] it was created with.
+///
+/// Digests recorded in the SBOM are the ones of the installed WIT definitions recorded in the
+/// lock, not those of the fetched archives.
+#[derive(Clone, Debug)]
+pub struct Sbom {
+    format: Format,
+    name: String,
+    created: SystemTime,
+    components: Vec<Component>,
+}
+
+impl Sbom {
+    /// Sets the name of the described project, defaults to `wit`
+    #[must_use]
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the creation time recorded in the SBOM, defaults to the time the SBOM was created
+    /// at. Use a fixed time, e.g. the one of the last commit, to produce reproducible SBOMs.
+    #[must_use]
+    pub fn with_created(mut self, created: SystemTime) -> Self {
+        self.created = created;
+        self
+    }
+
+    /// Returns the format the SBOM is serialized in
+    #[must_use]
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Returns direct dependencies
+    fn direct(&self) -> impl Iterator<Item = &Component> {
+        self.components
+            .iter()
+            .filter(|component| component.entry.source.is_some())
+    }
+
+    fn cyclonedx(&self, created: String) -> cyclonedx::Bom<'_> {
+        use cyclonedx::{
+            Bom, Component, Dependency, ExternalReference, Hash, License, Metadata, Property, Tools,
+        };
+
+        /// Reference of the described project, which cannot clash with dependency identifiers
+        const ROOT: &str = "wit-deps:root";
+
+        let (tool, tool_version) = TOOL;
+        let root = Component {
+            kind: "application",
+            bom_ref: ROOT,
+            name: &self.name,
+            version: None,
+            licenses: vec![],
+            hashes: vec![],
+            external_references: vec![],
+            properties: vec![],
+        };
+        let components = self
+            .components
+            .iter()
+            .map(|component| Component {
+                kind: "library",
+                bom_ref: &component.id,
+                name: component.name(),
+                version: component.version.as_deref(),
+                licenses: component
+                    .entry
+                    .resolution
+                    .license
+                    .as_deref()
+                    .map(|expression| License { expression })
+                    .into_iter()
+                    .collect(),
+                hashes: component
+                    .hashes()
+                    .map(|(alg, content)| Hash { alg, content })
+                    .collect(),
+                external_references: component
+                    .url()
+                    .map(|url| ExternalReference {
+                        kind: "distribution",
+                        url,
+                    })
+                    .into_iter()
+                    .collect(),
+                properties: component
+                    .properties()
+                    .into_iter()
+                    .map(|(name, value)| Property {
+                        name: format!("{tool}:{name}"),
+                        value,
+                    })
+                    .collect(),
+            })
+            .collect();
+        let dependencies = [Dependency {
+            reference: ROOT,
+            depends_on: self
+                .direct()
+                .map(|component| component.id.as_str())
+                .collect(),
+        }]
+        .into_iter()
+        .chain(self.components.iter().map(|component| Dependency {
+            reference: &component.id,
+            depends_on: component.entry.deps.iter().map(String::as_str).collect(),
+        }))
+        .collect();
+        Bom {
+            format: "CycloneDX",
+            spec_version: "1.5",
+            version: 1,
+            metadata: Metadata {
+                timestamp: created,
+                tools: Tools {
+                    components: [Component {
+                        kind: "application",
+                        bom_ref: tool,
+                        name: tool,
+                        version: Some(tool_version),
+                        licenses: vec![],
+                        hashes: vec![],
+                        external_references: vec![],
+                        properties: vec![],
+                    }],
+                },
+                component: root,
+            },
+            components,
+            dependencies,
+        }
+    }
+
+    fn spdx(&self, created: String) -> spdx::Document<'_> {
+        use spdx::{package_id, Checksum, CreationInfo, Document, Package, Relationship};
+
+        const DOCUMENT: &str = "SPDXRef-DOCUMENT";
+        const NOASSERTION: &str = "NOASSERTION";
+
+        // NOTE: The namespace must be unique for each document, derive it from the contents to
+        // keep the SBOM reproducible
+        let mut namespace = Sha256::new();
+        namespace.update(&self.name);
+        for component in &self.components {
+            namespace.update(&component.id);
+            namespace.update(component.entry.digest.sha256);
+        }
+        let namespace = hex::encode(namespace.finalize());
+
+        let (tool, tool_version) = TOOL;
+        let packages = self
+            .components
+            .iter()
+            .map(|component| Package {
+                spdx_id: package_id(&component.id),
+                name: component.name(),
+                version_info: component.version.as_deref(),
+                download_location: component.url().unwrap_or(NOASSERTION),
+                files_analyzed: false,
+                license_concluded: NOASSERTION,
+                license_declared: component
+                    .entry
+                    .resolution
+                    .license
+                    .as_deref()
+                    .unwrap_or(NOASSERTION),
+                copyright_text: NOASSERTION,
+                checksums: component
+                    .hashes()
+                    .map(|(algorithm, value)| Checksum {
+                        algorithm: algorithm.replace('-', ""),
+                        value,
+                    })
+                    .collect(),
+                source_info: Some(
+                    component
+                        .properties()
+                        .into_iter()
+                        .map(|(name, value)| format!("{name}: {value}"))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                ),
+            })
+            .collect();
+        let relationships = self
+            .direct()
+            .map(|component| Relationship {
+                spdx_element_id: DOCUMENT.into(),
+                kind: "DESCRIBES",
+                related_spdx_element: package_id(&component.id),
+            })
+            .chain(self.components.iter().flat_map(|component| {
+                component.entry.deps.iter().map(|dep| Relationship {
+                    spdx_element_id: package_id(&component.id),
+                    kind: "DEPENDS_ON",
+                    related_spdx_element: package_id(dep),
+                })
+            }))
+            .collect();
+        Document {
+            spdx_version: "SPDX-2.3",
+            data_license: "CC0-1.0",
+            spdx_id: DOCUMENT,
+            name: &self.name,
+            namespace: format!("https://spdx.org/spdxdocs/{}-{namespace}", self.name),
+            creation_info: CreationInfo {
+                created,
+                creators: [format!("Tool: {tool}-{tool_version}")],
+            },
+            packages,
+            relationships,
+        }
+    }
+}
+
+impl Serialize for Sbom {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let created = rfc3339(self.created).map_err(S::Error::custom)?;
+        match self.format {
+            Format::CycloneDx => self.cyclonedx(created).serialize(serializer),
+            Format::Spdx => self.spdx(created).serialize(serializer),
+        }
+    }
+}
+
+/// Formats `t` as an RFC 3339 UTC timestamp with second precision
+fn rfc3339(t: SystemTime) -> Result<String, &'static str> {
+    let secs = t
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|_| "creation time precedes UNIX epoch")?
+        .as_secs();
+    let days = i64::try_from(secs / 86400).map_err(|_| "creation time out of range")?;
+    let secs = secs % 86400;
+
+    // Converts days since UNIX epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    Ok(format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    ))
+}
+
+/// Reads the lock at `lock_path` and the WIT package declarations of locked dependencies
+/// installed in `deps` and returns a software bill of materials in `format` containing the
+/// source URL, digests, resolved version and upstream resolution of each locked dependency along
+/// with the dependency graph.
+///
+/// The version of each dependency is taken from its WIT package declaration, if versioned, or
+/// the resolved upstream release tag otherwise.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded
+#[instrument(level = "trace", skip(lock_path, deps))]
+pub async fn sbom_path(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    format: Format,
+) -> Result<Sbom, Error> {
+    let lock = crate::read_lock(lock_path).await?.migrate();
+    let deps = deps.as_ref();
+    let mut components = Vec::default();
+    for (id, entry) in lock.iter() {
+        let path = lock.path(deps, id);
+        let (package, version) = match Package::read(&OsVfs, &path).await {
+            Ok(Package {
+                name: Some(name), ..
+            }) => match name.split_once('@') {
+                Some((name, version)) => (Some(name.to_string()), Some(version.to_string())),
+                None => (Some(name), None),
+            },
+            Ok(_) => (None, None),
+            Err(e) => {
+                debug!("failed to read package at `{}`: {e}", path.display());
+                (None, None)
+            }
+        };
+        let version = version.or_else(|| entry.resolution.tag.clone());
+        components.push(Component {
+            id: id.clone(),
+            entry: entry.clone(),
+            package,
+            version,
+        });
+    }
+    Ok(Sbom {
+        format,
+        name: "wit".into(),
+        created: SystemTime::now(),
+        components,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    use std::time::Duration;
+
+    use anyhow::Context as _;
+
+    #[test]
+    fn timestamp() -> anyhow::Result<()> {
+        let t = |secs| rfc3339(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(t(0).map_err(anyhow::Error::msg)?, "1970-01-01T00:00:00Z");
+        assert_eq!(
+            t(951_782_400).map_err(anyhow::Error::msg)?,
+            "2000-02-29T00:00:00Z"
+        );
+        assert_eq!(
+            t(1_760_531_696).map_err(anyhow::Error::msg)?,
+            "2025-10-15T12:34:56Z"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn formats() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-sbom").await?;
+        let deps = dir.join("deps");
+        let lock = dir.join("deps.lock");
+        tokio::fs::create_dir_all(deps.join("io")).await?;
+        tokio::fs::write(
+            deps.join("io").join("io.wit"),
+            "package wasi:io@0.2.0;\ninterface error {}\n",
+        )
+        .await?;
+        tokio::fs::write(
+            &lock,
+            format!(
+                r#"
+[cli]
+url = "https://example.com/cli.tar.gz"
+tag = "v0.2.0"
+sha256 = "{sha256}"
+sha512 = "{sha512}"
+deps = ["io"]
+
+[io]
+sha256 = "{sha256}"
+sha512 = "{sha512}"
+"#,
+                sha256 = "00".repeat(32),
+                sha512 = "00".repeat(64),
+            ),
+        )
+        .await?;
+        let mut boms = vec![];
+        for format in [Format::CycloneDx, Format::Spdx] {
+            let sbom = sbom_path(&lock, &deps, format)
+                .await?
+                .with_name("foo")
+                .with_created(SystemTime::UNIX_EPOCH);
+            boms.push(serde_json::to_value(sbom)?);
+        }
+
+        let [cyclonedx, spdx] = boms.as_slice() else {
+            anyhow::bail!("expected two SBOMs")
+        };
+        assert_eq!(cyclonedx["metadata"]["timestamp"], "1970-01-01T00:00:00Z");
+        let components = cyclonedx["components"]
+            .as_array()
+            .context("components missing")?;
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0]["name"], "cli");
+        assert_eq!(components[0]["version"], "v0.2.0");
+        assert_eq!(
+            components[0]["externalReferences"][0]["url"],
+            "https://example.com/cli.tar.gz"
+        );
+        assert_eq!(components[1]["name"], "wasi:io");
+        assert_eq!(components[1]["version"], "0.2.0");
+        assert_eq!(components[1]["hashes"][0]["alg"], "SHA-256");
+        assert_eq!(
+            cyclonedx["dependencies"],
+            serde_json::json!([
+                { "ref": "wit-deps:root", "dependsOn": ["cli"] },
+                { "ref": "cli", "dependsOn": ["io"] },
+                { "ref": "io", "dependsOn": [] },
+            ])
+        );
+
+        assert_eq!(spdx["spdxVersion"], "SPDX-2.3");
+        assert_eq!(spdx["creationInfo"]["created"], "1970-01-01T00:00:00Z");
+        assert_eq!(spdx["packages"][0]["SPDXID"], "SPDXRef-Package-cli");
+        assert_eq!(spdx["packages"][1]["downloadLocation"], "NOASSERTION");
+        assert_eq!(spdx["packages"][1]["checksums"][0]["algorithm"], "SHA256");
+        assert_eq!(
+            spdx["relationships"],
+            serde_json::json!([
+                {
+                    "spdxElementId": "SPDXRef-DOCUMENT",
+                    "relationshipType": "DESCRIBES",
+                    "relatedSpdxElement": "SPDXRef-Package-cli",
+                },
+                {
+                    "spdxElementId": "SPDXRef-Package-cli",
+                    "relationshipType": "DEPENDS_ON",
+                    "relatedSpdxElement": "SPDXRef-Package-io",
+                },
+            ])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{fs, Error};
+
+use std::ffi::OsString;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context as _};
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+use hex::FromHex;
+use tracing::{debug, instrument};
+
+/// Returns the path of the detached signature of the lock at `lock_path`, i.e. `lock_path`
+/// with `.sig` appended
+#[must_use]
+pub fn signature_path(lock_path: impl AsRef<Path>) -> PathBuf {
+    let mut path = OsString::from(lock_path.as_ref());
+    path.push(".sig");
+    path.into()
+}
+
+async fn read_hex<T>(path: &Path, kind: &str) -> anyhow::Result<T>
+where
+    T: FromHex,
+    T::Error: fmt::Display,
+{
+    let buf = fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read {kind} at `{}`", path.display()))?;
+    FromHex::from_hex(buf.trim())
+        .map_err(|e| anyhow::anyhow!("failed to decode {kind} at `{}`: {e}", path.display()))
+}
+
+fn sign(lock: &[u8], key: &SigningKey) -> String {
+    format!("{}\n", hex::encode(key.sign(lock).to_bytes()))
+}
+
+fn verify(lock: &[u8], signature: &[u8; 64], key: &VerifyingKey) -> anyhow::Result<()> {
+    key.verify_strict(lock, &Signature::from_bytes(signature))
+        .map_err(Into::into)
+}
+
+/// Verifies the ed25519 signature `sig` of a fetched `resource` using public key `pubkey`
+pub(crate) fn verify_resource(
+    resource: &[u8],
+    sig: &[u8; 64],
+    pubkey: &[u8; 32],
+) -> anyhow::Result<()> {
+    let key = VerifyingKey::from_bytes(pubkey).context("invalid `pubkey`")?;
+    verify(resource, sig, &key).context("signature does not match `pubkey`")
+}
+
+/// Reads a hex-encoded ed25519 signing (secret) key at `path`
+///
+/// # Errors
+///
+/// Returns an error if reading or decoding the key fails
+pub async fn read_signing_key(path: impl AsRef<Path>) -> Result<SigningKey, Error> {
+    let key: [u8; 32] = read_hex(path.as_ref(), "signing key").await?;
+    Ok(SigningKey::from_bytes(&key))
+}
+
+/// Reads a hex-encoded ed25519 verifying (public) key at `path`
+///
+/// # Errors
+///
+/// Returns an error if reading or decoding the key fails
+pub async fn read_verifying_key(path: impl AsRef<Path>) -> Result<VerifyingKey, Error> {
+    let path = path.as_ref();
+    let key: [u8; 32] = read_hex(path, "verifying key").await?;
+    let key = VerifyingKey::from_bytes(&key)
+        .with_context(|| format!("invalid verifying key at `{}`", path.display()))?;
+    Ok(key)
+}
+
+/// Signs the lock at `lock_path` using `key` and writes the hex-encoded detached signature to
+/// [`signature_path`]. Returns the path of the signature.
+///
+/// # Errors
+///
+/// Returns an error if reading the lock or writing the signature fails
+#[instrument(level = "trace", skip(lock_path, key))]
+pub async fn sign_lock_path(
+    lock_path: impl AsRef<Path>,
+    key: &SigningKey,
+) -> Result<PathBuf, Error> {
+    let lock_path = lock_path.as_ref();
+    let lock = fs::read(lock_path)
+        .await
+        .with_context(|| format!("failed to read lock at `{}`", lock_path.display()))?;
+    let path = signature_path(lock_path);
+    fs::write(&path, sign(&lock, key))
+        .await
+        .with_context(|| format!("failed to write signature to `{}`", path.display()))?;
+    debug!("signed `{}`", lock_path.display());
+    Ok(path)
+}
+
+/// Verifies the detached signature at [`signature_path`] of the lock at `lock_path` using `key`
+///
+/// # Errors
+///
+/// Returns an error if the signature is missing or does not match the lock
+#[instrument(level = "trace", skip(lock_path, key))]
+pub async fn verify_lock_signature_path(
+    lock_path: impl AsRef<Path>,
+    key: &VerifyingKey,
+) -> Result<(), Error> {
+    let lock_path = lock_path.as_ref();
+    let path = signature_path(lock_path);
+    let lock = fs::read(lock_path)
+        .await
+        .with_context(|| format!("failed to read lock at `{}`", lock_path.display()))?;
+    let signature = match read_hex(&path, "signature").await {
+        Ok(signature) => signature,
+        Err(e) => {
+            return Err(e
+                .context(format!(
+                    "lock at `{}` is not signed or the signature cannot be read",
+                    lock_path.display()
+                ))
+                .into())
+        }
+    };
+    if let Err(e) = verify(&lock, &signature, key) {
+        return Err(anyhow!(
+            "signature at `{}` does not match lock at `{}`: {e}",
+            path.display(),
+            lock_path.display()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_verify() -> anyhow::Result<()> {
+        let key = SigningKey::from_bytes(&[0x42; 32]);
+        let signature = sign(b"version = 1\n", &key);
+        let signature = FromHex::from_hex(signature.trim()).map_err(|e| anyhow::anyhow!("{e}"))?;
+        verify(b"version = 1\n", &signature, &key.verifying_key())?;
+
+        let other = SigningKey::from_bytes(&[0x43; 32]);
+        assert!(verify(b"version = 1\n", &signature, &other.verifying_key()).is_err());
+        assert!(verify(b"version = 2\n", &signature, &key.verifying_key()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn signature_path() {
+        assert_eq!(
+            super::signature_path("wit/deps.lock"),
+            Path::new("wit/deps.lock.sig")
+        );
+    }
+}
@@ -0,0 +1,189 @@
+//! Stable names of spans, fields and metrics emitted while locking dependencies.
+//!
+//! The names defined in this module are part of the public API and only change in breaking
+//! releases, such that dashboards and log queries can rely on them.
+//!
+//! Every dependency is locked within a [`DEPENDENCY_SPAN`] span with an [`ID`] field, an [`URL`]
+//! field for URL dependencies and a [`CACHE`] field recording the [cache outcome](CacheOutcome),
+//! if the cache was consulted. Events with [`TIMINGS`] target record durations of [phases](phase)
+//! of locking a dependency in [`ELAPSED_US`] and the number of bytes downloaded in [`BYTES`].
+//!
+//! If the `metrics` feature is enabled, the same measurements are emitted via the [`metrics`]
+//! crate using the names defined in [`metric`] to the globally installed recorder.
+//!
+//! [`metrics`]: https://docs.rs/metrics
+
+use core::fmt;
+use core::future::Future;
+
+use std::time::Instant;
+
+use tracing::{debug, Span};
+use url::Url;
+
+/// Target of events recording durations of phases of locking a dependency within
+/// [`Manifest::lock`](crate::Manifest::lock).
+///
+/// Each duration event has a [`PHASE`] field, one of [phases](phase), and an [`ELAPSED_US`]
+/// field containing the duration in microseconds. Phases may occur multiple times per
+/// dependency. Events of all phases, except [`phase::TOTAL`], are emitted within a
+/// [`DEPENDENCY_SPAN`] span, [`phase::TOTAL`] events contain the [`ID`] field directly.
+///
+/// Once a resource is downloaded, an event without a [`PHASE`] field containing the [`URL`] and
+/// the number of [`BYTES`] downloaded is emitted within a [`DEPENDENCY_SPAN`] span.
+pub const TIMINGS: &str = "wit_deps::timings";
+
+/// Name of the span each dependency is locked within
+pub const DEPENDENCY_SPAN: &str = "dependency";
+
+/// Field containing the identifier of the dependency
+pub const ID: &str = "id";
+
+/// Field containing the URL of the dependency
+pub const URL: &str = "url";
+
+/// Field containing the [cache outcome](CacheOutcome)
+pub const CACHE: &str = "cache";
+
+/// Field containing the phase of locking a dependency, one of [phases](phase)
+pub const PHASE: &str = "phase";
+
+/// Field containing a duration in microseconds
+pub const ELAPSED_US: &str = "elapsed_us";
+
+/// Field containing the number of bytes downloaded
+pub const BYTES: &str = "bytes";
+
+/// Phases of locking a dependency
+pub mod phase {
+    /// Lookup and unpacking of cached resources and trees
+    pub const CACHE: &str = "cache";
+    /// Request of a resource, until the response headers are received
+    pub const DOWNLOAD: &str = "download";
+    /// Streaming, decompression and unpacking of a downloaded resource
+    pub const DECOMPRESS: &str = "decompress";
+    /// Copying of a path dependency
+    pub const COPY: &str = "copy";
+    /// Computation of digests
+    pub const DIGEST: &str = "digest";
+    /// Locking of the dependency as a whole
+    pub const TOTAL: &str = "total";
+}
+
+/// Names of metrics emitted if the `metrics` feature is enabled
+pub mod metric {
+    /// Histogram of durations of [phases](super::phase) in seconds with a `phase` label
+    pub const PHASE_DURATION: &str = "wit_deps_phase_duration_seconds";
+    /// Histogram of durations of locking a dependency in seconds with an `id` label and an
+    /// `outcome` label, which is either `ok` or `error`
+    pub const DEPENDENCY_DURATION: &str = "wit_deps_dependency_duration_seconds";
+    /// Counter of bytes downloaded with an `id` label
+    pub const DOWNLOADED_BYTES: &str = "wit_deps_downloaded_bytes_total";
+    /// Counter of cache lookups with an `outcome` label, one of [outcomes](super::CacheOutcome)
+    pub const CACHE_LOOKUPS: &str = "wit_deps_cache_lookups_total";
+}
+
+/// Outcome of consulting the cache for a URL dependency
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CacheOutcome {
+    /// The dependency was restored from cache
+    Hit,
+    /// The dependency was not present in cache
+    Miss,
+}
+
+impl CacheOutcome {
+    /// Returns the value of the [`CACHE`] field and `outcome` metric label
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+        }
+    }
+}
+
+impl fmt::Display for CacheOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Returns microseconds elapsed since `start`
+pub(crate) fn elapsed_us(start: Instant) -> u64 {
+    u64::try_from(start.elapsed().as_micros()).unwrap_or(u64::MAX)
+}
+
+/// Awaits `fut` and emits a [`TIMINGS`] event recording its duration as `phase`
+pub(crate) async fn timed<T>(phase: &'static str, fut: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let res = fut.await;
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(metric::PHASE_DURATION, "phase" => phase)
+        .record(start.elapsed().as_secs_f64());
+    debug!(
+        target: TIMINGS,
+        phase,
+        elapsed_us = elapsed_us(start),
+        "finished phase"
+    );
+    res
+}
+
+/// Records `outcome` of consulting the cache in the current [`DEPENDENCY_SPAN`] span
+pub(crate) fn cache(outcome: CacheOutcome) {
+    Span::current().record(CACHE, outcome.as_str());
+    #[cfg(feature = "metrics")]
+    metrics::counter!(metric::CACHE_LOOKUPS, "outcome" => outcome.as_str()).increment(1);
+}
+
+/// Emits a [`TIMINGS`] event recording `bytes` downloaded from `url` for dependency `id`
+pub(crate) fn downloaded(id: &str, url: &Url, bytes: u64) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(metric::DOWNLOADED_BYTES, "id" => id.to_string()).increment(bytes);
+    #[cfg(not(feature = "metrics"))]
+    let _ = id;
+    debug!(target: TIMINGS, %url, bytes, "downloaded resource");
+}
+
+/// Emits a [`TIMINGS`] event recording the total duration of locking dependency `id` since
+/// `start`, which either resulted in a number of transitive dependencies or failed
+pub(crate) fn locked(id: &str, start: Instant, res: Result<usize, &anyhow::Error>) {
+    let elapsed_us = elapsed_us(start);
+    #[cfg(feature = "metrics")]
+    metrics::histogram!(
+        metric::DEPENDENCY_DURATION,
+        "id" => id.to_string(),
+        "outcome" => if res.is_ok() { "ok" } else { "error" },
+    )
+    .record(start.elapsed().as_secs_f64());
+    match res {
+        Ok(deps) => debug!(
+            target: TIMINGS,
+            id,
+            phase = phase::TOTAL,
+            elapsed_us,
+            deps,
+            "locked dependency"
+        ),
+        Err(error) => debug!(
+            target: TIMINGS,
+            id,
+            phase = phase::TOTAL,
+            elapsed_us,
+            %error,
+            "failed to lock dependency"
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_outcome() {
+        assert_eq!(CacheOutcome::Hit.as_str(), "hit");
+        assert_eq!(CacheOutcome::Miss.to_string(), "miss");
+    }
+}
@@ -0,0 +1,140 @@
+use crate::fs;
+
+use core::ops::Deref;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use std::env;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use ::blocking::unblock;
+use tracing::{trace, warn};
+
+/// Number of [`TempDir`] created by this process, which makes their names unique
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Uniquely-named directory within [`env::temp_dir`], which is removed on drop unless
+/// [removed](TempDir::remove) explicitly.
+///
+/// Removal is best-effort, failures are logged and never returned, since the contents of
+/// temporary directories do not affect the result of an operation.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates a uniquely-named directory, whose name starts with `prefix`, within
+    /// [`env::temp_dir`]. This is blocking, use [`TempDir::new`] in asynchronous contexts.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created
+    pub fn create(prefix: &str) -> io::Result<Self> {
+        let pid = std::process::id();
+        loop {
+            let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("{prefix}-{pid}-{n}"));
+            // NOTE: Directories left behind by a process, which had the same PID, are skipped
+            match std::fs::create_dir(&path) {
+                Ok(()) => {
+                    trace!("created temporary directory `{}`", path.display());
+                    return Ok(Self { path });
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!(
+                            "failed to create temporary directory `{}`: {e}",
+                            path.display()
+                        ),
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Asynchronous version of [`TempDir::create`]
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created
+    pub async fn new(prefix: &str) -> io::Result<Self> {
+        let prefix = prefix.to_string();
+        unblock(move || Self::create(&prefix)).await
+    }
+
+    /// Returns the path of the directory
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Removes the directory without blocking
+    pub async fn remove(mut self) {
+        let path = mem::take(&mut self.path);
+        match fs::remove_dir_all(&path).await {
+            Ok(()) => trace!("removed temporary directory `{}`", path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!(
+                "failed to remove temporary directory `{}`: {e}",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    /// Removes the directory. This is blocking, since it is used on drop.
+    fn drop(&mut self) {
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        match std::fs::remove_dir_all(&self.path) {
+            Ok(()) => trace!("removed temporary directory `{}`", self.path.display()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => warn!(
+                "failed to remove temporary directory `{}`: {e}",
+                self.path.display()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn unique() -> anyhow::Result<()> {
+        let a = TempDir::new("wit-deps-tempdir").await?;
+        let b = TempDir::new("wit-deps-tempdir").await?;
+        assert_ne!(a.path(), b.path());
+        let (a_path, b_path) = (a.to_path_buf(), b.to_path_buf());
+        fs::write(a.join("foo.wit"), "package foo:foo;").await?;
+        assert!(fs::try_exists(a.join("foo.wit")).await?);
+
+        drop(a);
+        fs::remove_dir_all(&b_path).await?;
+        b.remove().await;
+        assert!(!fs::try_exists(&a_path).await?);
+        assert!(!fs::try_exists(&b_path).await?);
+        Ok(())
+    }
+}
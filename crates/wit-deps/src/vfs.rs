@@ -0,0 +1,407 @@
+use crate::{digest_dir, fs, is_wit, Digest, DigestWriter};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use async_trait::async_trait;
+use futures::io::sink;
+use futures::StreamExt as _;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// File stored in a [Vfs]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct File {
+    /// Contents of the file
+    pub contents: Vec<u8>,
+    /// Whether the file is executable, which is reflected in the digest of its directory
+    pub executable: bool,
+}
+
+impl From<Vec<u8>> for File {
+    fn from(contents: Vec<u8>) -> Self {
+        Self {
+            contents,
+            executable: false,
+        }
+    }
+}
+
+/// Minimal virtual filesystem dependencies are materialized into by
+/// [`Manifest::lock`](crate::Manifest::lock), see
+/// [`LockOptions::with_vfs`](crate::LockOptions::with_vfs).
+///
+/// Dependency sources, e.g. path dependencies and caches, are always read from disk.
+#[async_trait]
+pub trait Vfs: Send + Sync {
+    /// Creates directory at `path` along with all missing parents
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Removes directory at `path` along with all its contents
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Writes `file` at `path`, replacing it if it exists. The parent directory of `path` must
+    /// exist.
+    async fn write(&self, path: &Path, file: File) -> io::Result<()>;
+
+    /// Reads file at `path`
+    async fn read(&self, path: &Path) -> io::Result<File>;
+
+    /// Returns names of all entries within directory at `path`, which are not directories
+    async fn read_dir(&self, path: &Path) -> io::Result<BTreeSet<OsString>>;
+
+    /// Copies file at `src` on disk to `dst`, replacing it if it exists. The parent directory
+    /// of `dst` must exist. By default, this reads `src` and [writes](Vfs::write) it to `dst`.
+    async fn copy(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        self.write(dst, Os.read(src).await?).await
+    }
+
+    /// Computes the digest of WIT definitions within directory at `path`, which is equal to
+    /// [`digest_dir`](crate::digest_dir) of the same contents on disk
+    async fn digest(&self, path: &Path) -> io::Result<Digest> {
+        let wit = Path::new("wit");
+        let mut tar = async_tar::Builder::new(DigestWriter::from(sink()));
+        for name in self.read_dir(path).await? {
+            if !is_wit(&name) {
+                continue;
+            }
+            let File {
+                contents,
+                executable,
+            } = self.read(&path.join(&name)).await?;
+            // NOTE: This matches the header produced for files on disk in deterministic mode
+            let mut header = async_tar::Header::new_gnu();
+            header.set_mtime(0);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mode(if executable { 0o755 } else { 0o644 });
+            header.set_entry_type(async_tar::EntryType::file());
+            header.set_size(u64::try_from(contents.len()).unwrap_or(u64::MAX));
+            header.set_device_major(0)?;
+            header.set_device_minor(0)?;
+            tar.append_data(&mut header, wit.join(name), contents.as_slice())
+                .await?;
+        }
+        tar.into_inner().await.map(Into::into)
+    }
+}
+
+#[async_trait]
+impl<T: Vfs + ?Sized> Vfs for Arc<T> {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        (**self).create_dir_all(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        (**self).remove_dir_all(path).await
+    }
+
+    async fn write(&self, path: &Path, file: File) -> io::Result<()> {
+        (**self).write(path, file).await
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<File> {
+        (**self).read(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BTreeSet<OsString>> {
+        (**self).read_dir(path).await
+    }
+
+    async fn copy(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        (**self).copy(src, dst).await
+    }
+
+    async fn digest(&self, path: &Path) -> io::Result<Digest> {
+        (**self).digest(path).await
+    }
+}
+
+/// Default [Vfs] backed by the OS filesystem
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Os;
+
+#[async_trait]
+impl Vfs for Os {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path).await
+    }
+
+    async fn write(&self, path: &Path, file: File) -> io::Result<()> {
+        fs::write(path, file.contents).await?;
+        #[cfg(unix)]
+        if file.executable {
+            use std::os::unix::fs::PermissionsExt as _;
+
+            fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).await?;
+        }
+        Ok(())
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<File> {
+        let contents = fs::read(path).await?;
+        #[cfg(unix)]
+        let executable = {
+            use std::os::unix::fs::PermissionsExt as _;
+
+            fs::metadata(path).await?.permissions().mode() & 0o100 == 0o100
+        };
+        #[cfg(not(unix))]
+        let executable = false;
+        Ok(File {
+            contents,
+            executable,
+        })
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BTreeSet<OsString>> {
+        let mut entries = fs::read_dir(path).await?;
+        let mut names = BTreeSet::default();
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                names.insert(entry.file_name());
+            }
+        }
+        Ok(names)
+    }
+
+    async fn copy(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        fs::clone_file(src, dst).await
+    }
+
+    async fn digest(&self, path: &Path) -> io::Result<Digest> {
+        digest_dir(path).await
+    }
+}
+
+/// [Os] [Vfs], which hard links copied files instead of cloning them, falling back to
+/// [`Os::copy`] if that fails, e.g. if `src` and `dst` are on different filesystems,
+/// see [`LockOptions::with_hardlinks`](crate::LockOptions::with_hardlinks)
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct Linked;
+
+#[async_trait]
+impl Vfs for Linked {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        Os.create_dir_all(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        Os.remove_dir_all(path).await
+    }
+
+    async fn write(&self, path: &Path, file: File) -> io::Result<()> {
+        Os.write(path, file).await
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<File> {
+        Os.read(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BTreeSet<OsString>> {
+        Os.read_dir(path).await
+    }
+
+    async fn copy(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        match fs::remove_file(dst).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        if fs::hard_link(src, dst).await.is_ok() {
+            return Ok(());
+        }
+        Os.copy(src, dst).await
+    }
+
+    async fn digest(&self, path: &Path) -> io::Result<Digest> {
+        Os.digest(path).await
+    }
+}
+
+/// [Vfs] performing up to as many operations on the wrapped one concurrently as there are
+/// permits in the semaphore, see [`LockOptions::with_fs_concurrency`](crate::LockOptions::with_fs_concurrency)
+pub(crate) struct Limited<'a> {
+    vfs: &'a dyn Vfs,
+    permits: &'a Semaphore,
+}
+
+impl<'a> Limited<'a> {
+    pub(crate) fn new(vfs: &'a dyn Vfs, permits: &'a Semaphore) -> Self {
+        Self { vfs, permits }
+    }
+
+    async fn acquire(&self) -> io::Result<SemaphorePermit<'a>> {
+        self.permits.acquire().await.map_err(io::Error::other)
+    }
+}
+
+#[async_trait]
+impl Vfs for Limited<'_> {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let _permit = self.acquire().await?;
+        self.vfs.create_dir_all(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let _permit = self.acquire().await?;
+        self.vfs.remove_dir_all(path).await
+    }
+
+    async fn write(&self, path: &Path, file: File) -> io::Result<()> {
+        let _permit = self.acquire().await?;
+        self.vfs.write(path, file).await
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<File> {
+        let _permit = self.acquire().await?;
+        self.vfs.read(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BTreeSet<OsString>> {
+        let _permit = self.acquire().await?;
+        self.vfs.read_dir(path).await
+    }
+
+    async fn copy(&self, src: &Path, dst: &Path) -> io::Result<()> {
+        let _permit = self.acquire().await?;
+        self.vfs.copy(src, dst).await
+    }
+
+    async fn digest(&self, path: &Path) -> io::Result<Digest> {
+        let _permit = self.acquire().await?;
+        self.vfs.digest(path).await
+    }
+}
+
+#[derive(Debug, Default)]
+struct Tree {
+    dirs: BTreeSet<PathBuf>,
+    files: BTreeMap<PathBuf, File>,
+}
+
+/// In-memory [Vfs], primarily useful for testing and for tools, which need WIT definitions
+/// without writing them to disk. Clones share the same tree.
+#[derive(Clone, Debug, Default)]
+pub struct Memory(Arc<Mutex<Tree>>);
+
+impl Memory {
+    /// Returns all files in the tree keyed by path
+    #[must_use]
+    pub fn files(&self) -> BTreeMap<PathBuf, File> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .files
+            .clone()
+    }
+
+    /// Returns the contents of file at `path`, if present
+    #[must_use]
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .files
+            .get(path.as_ref())
+            .map(|file| file.contents.clone())
+    }
+}
+
+/// Returns an error of [`io::ErrorKind::NotFound`] kind for `path`
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("`{}` not found", path.display()),
+    )
+}
+
+#[async_trait]
+impl Vfs for Memory {
+    async fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut tree = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        tree.dirs.extend(path.ancestors().map(PathBuf::from));
+        Ok(())
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut tree = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if !tree.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        tree.dirs.retain(|dir| !dir.starts_with(path));
+        tree.files.retain(|file, _| !file.starts_with(path));
+        Ok(())
+    }
+
+    async fn write(&self, path: &Path, file: File) -> io::Result<()> {
+        let mut tree = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !tree.dirs.contains(parent) => {
+                Err(not_found(parent))
+            }
+            _ => {
+                tree.files.insert(path.into(), file);
+                Ok(())
+            }
+        }
+    }
+
+    async fn read(&self, path: &Path) -> io::Result<File> {
+        let tree = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        tree.files.get(path).cloned().ok_or_else(|| not_found(path))
+    }
+
+    async fn read_dir(&self, path: &Path) -> io::Result<BTreeSet<OsString>> {
+        let tree = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        if !tree.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        Ok(tree
+            .files
+            .keys()
+            .filter(|file| file.parent() == Some(path))
+            .filter_map(|file| file.file_name().map(OsString::from))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    #[tokio::test]
+    async fn digest() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-vfs-digest").await?;
+        let mem = Memory::default();
+        let files = [
+            ("foo.wit", File::from(b"package foo:foo;".to_vec())),
+            ("bar.wit", File::from(b"package foo:bar;".to_vec())),
+            ("README.md", File::from(b"# foo".to_vec())),
+        ];
+        for vfs in [&Os as &dyn Vfs, &mem] {
+            vfs.create_dir_all(&dir).await?;
+            for (name, file) in &files {
+                vfs.write(&dir.join(name), file.clone()).await?;
+            }
+        }
+        assert_eq!(Os.digest(&dir).await?, mem.digest(&dir).await?);
+        assert_eq!(mem.read_dir(&dir).await?.len(), 3);
+        mem.remove_dir_all(&dir).await?;
+        assert!(mem.files().is_empty());
+        assert_eq!(
+            mem.read_dir(&dir).await.map_err(|e| e.kind()),
+            Err(io::ErrorKind::NotFound)
+        );
+        Ok(())
+    }
+}
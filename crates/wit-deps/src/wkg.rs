@@ -0,0 +1,330 @@
+use crate::package::Package;
+use crate::{Error, OsVfs};
+
+use core::str::FromStr;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::{debug, instrument};
+
+/// Header of `wkg.lock` files, as written by `wkg`
+const LOCK_HEADER: &str =
+    "# This file is automatically generated.\n# It is not intended for manual editing.\n";
+
+/// Version of the `wkg.lock` format
+const LOCK_VERSION: u64 = 1;
+
+/// Registry mapping of `wkg`, either a registry name or a table containing it
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+enum Mapping {
+    Registry(String),
+    Custom { registry: String },
+}
+
+impl Mapping {
+    fn registry(&self) -> &str {
+        match self {
+            Self::Registry(registry) | Self::Custom { registry } => registry,
+        }
+    }
+}
+
+/// Registry configuration of [wasm-pkg-tools](https://github.com/bytecodealliance/wasm-pkg-tools)
+/// (`wkg`), i.e. the `default_registry`, `namespace_registries` and `package_registry_overrides`
+/// of a `wkg.toml` or `wkg` `config.toml` file. All other keys are ignored.
+///
+/// Registries assigned to WIT packages by the configuration select the
+/// [Registry](crate::Registry) templates packages are resolved from, see
+/// [`LockOptions::with_wkg_config`](crate::LockOptions::with_wkg_config), and are recorded in
+/// `wkg.lock` files, see [`wkg_lock_path`].
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct Config {
+    #[serde(default)]
+    default_registry: Option<String>,
+    #[serde(default)]
+    namespace_registries: BTreeMap<String, Mapping>,
+    #[serde(default)]
+    package_registry_overrides: BTreeMap<String, Mapping>,
+}
+
+impl FromStr for Config {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl Config {
+    /// Reads the configuration at `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration cannot be read or decoded
+    pub async fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read `wkg` config at `{}`", path.display()))?;
+        let config = config
+            .parse()
+            .with_context(|| format!("failed to decode `wkg` config at `{}`", path.display()))?;
+        Ok(config)
+    }
+
+    /// Returns the configuration, which assigns packages without a more specific mapping to
+    /// `registry`
+    #[must_use]
+    pub fn with_default_registry(self, registry: impl Into<String>) -> Self {
+        Self {
+            default_registry: Some(registry.into()),
+            ..self
+        }
+    }
+
+    /// Returns the configuration, which assigns packages in `namespace` to `registry`
+    #[must_use]
+    pub fn with_namespace_registry(
+        mut self,
+        namespace: impl Into<String>,
+        registry: impl Into<String>,
+    ) -> Self {
+        self.namespace_registries
+            .insert(namespace.into(), Mapping::Registry(registry.into()));
+        self
+    }
+
+    /// Returns the configuration, which assigns `package`, e.g. `wasi:io`, to `registry`
+    #[must_use]
+    pub fn with_package_registry(
+        mut self,
+        package: impl Into<String>,
+        registry: impl Into<String>,
+    ) -> Self {
+        self.package_registry_overrides
+            .insert(package.into(), Mapping::Registry(registry.into()));
+        self
+    }
+
+    /// Returns the registry WIT package `package`, e.g. `wasi:io@0.2.0`, is assigned to, if any.
+    /// Like `wkg`, package overrides take precedence over namespace mappings, which take
+    /// precedence over the default registry.
+    #[must_use]
+    pub fn registry(&self, package: &str) -> Option<&str> {
+        let package = package
+            .split_once('@')
+            .map_or(package, |(package, _)| package);
+        let namespace = package
+            .split_once(':')
+            .map_or(package, |(namespace, _)| namespace);
+        self.package_registry_overrides
+            .get(package)
+            .or_else(|| self.namespace_registries.get(namespace))
+            .map(Mapping::registry)
+            .or(self.default_registry.as_deref())
+    }
+}
+
+/// Locked version of a package in a `wkg.lock`
+#[derive(Debug, Serialize)]
+struct LockedVersion {
+    requirement: String,
+    version: String,
+    digest: String,
+}
+
+/// Locked package in a `wkg.lock`
+#[derive(Debug, Serialize)]
+struct LockedPackage {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registry: Option<String>,
+    versions: Vec<LockedVersion>,
+}
+
+/// `wkg.lock` file
+#[derive(Debug, Serialize)]
+struct LockFile {
+    version: u64,
+    packages: Vec<LockedPackage>,
+}
+
+/// Reads the lock at `lock_path` and the WIT package declarations of locked dependencies
+/// installed in `deps` and returns an equivalent `wkg.lock`, which pins each versioned package
+/// to its exact version and sha256 digest. Registries are assigned to packages by `config`.
+///
+/// Note, that digests are those of the locked dependency sources, which generally differ from
+/// digests of packages published to a registry.
+///
+/// # Errors
+///
+/// Returns an error if the lock is missing or cannot be decoded
+#[instrument(level = "trace", skip(lock_path, deps, config))]
+pub async fn wkg_lock_path(
+    lock_path: impl AsRef<Path>,
+    deps: impl AsRef<Path>,
+    config: &Config,
+) -> Result<String, Error> {
+    let lock = crate::read_lock(lock_path).await?.migrate();
+    let deps = deps.as_ref();
+    let mut packages: BTreeMap<String, Vec<LockedVersion>> = BTreeMap::default();
+    for (id, entry) in lock.iter() {
+        let path = lock.path(deps, id);
+        let name = match Package::read(&OsVfs, &path).await {
+            Ok(Package {
+                name: Some(name), ..
+            }) => name,
+            Ok(_) => {
+                debug!("`{id}` does not declare a package, skip");
+                continue;
+            }
+            Err(e) => {
+                debug!("failed to read package at `{}`: {e}", path.display());
+                continue;
+            }
+        };
+        let Some((name, version)) = name.split_once('@') else {
+            debug!("package `{name}` of `{id}` is not versioned, skip");
+            continue;
+        };
+        packages
+            .entry(name.to_string())
+            .or_default()
+            .push(LockedVersion {
+                requirement: format!("={version}"),
+                version: version.to_string(),
+                digest: format!("sha256:{}", hex::encode(entry.digest.sha256)),
+            });
+    }
+    let packages = packages
+        .into_iter()
+        .map(|(name, mut versions)| {
+            versions.sort_by_cached_key(|v| {
+                (semver::Version::parse(&v.version).ok(), v.version.clone())
+            });
+            versions.dedup_by(|a, b| a.version == b.version);
+            LockedPackage {
+                registry: config.registry(&name).map(ToString::to_string),
+                name,
+                versions,
+            }
+        })
+        .collect();
+    let lock = toml::to_string(&LockFile {
+        version: LOCK_VERSION,
+        packages,
+    })
+    .context("failed to encode `wkg.lock`")?;
+    Ok(format!("{LOCK_HEADER}{lock}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    #[test]
+    fn registry() -> anyhow::Result<()> {
+        let config: Config = r#"
+default_registry = "example.com"
+
+[namespace_registries]
+wasi = "wasi.dev"
+foo = { registry = "foo.dev", metadata = { preferredProtocol = "oci" } }
+
+[package_registry_overrides]
+"wasi:http" = "http.dev"
+
+[registry."wasi.dev".oci]
+auth = { username = "user", password = "pass" }
+"#
+        .parse()?;
+        assert_eq!(config.registry("wasi:io@0.2.0"), Some("wasi.dev"));
+        assert_eq!(config.registry("wasi:http@0.2.0"), Some("http.dev"));
+        assert_eq!(config.registry("wasi:http"), Some("http.dev"));
+        assert_eq!(config.registry("foo:bar"), Some("foo.dev"));
+        assert_eq!(config.registry("bar:baz"), Some("example.com"));
+        assert_eq!(Config::default().registry("wasi:io"), None);
+        assert_eq!(
+            Config::default()
+                .with_default_registry("example.com")
+                .with_namespace_registry("foo", "foo.dev")
+                .with_package_registry("wasi:http", "http.dev"),
+            Config {
+                default_registry: Some("example.com".into()),
+                namespace_registries: BTreeMap::from([(
+                    "foo".into(),
+                    Mapping::Registry("foo.dev".into())
+                )]),
+                package_registry_overrides: BTreeMap::from([(
+                    "wasi:http".into(),
+                    Mapping::Registry("http.dev".into())
+                )]),
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wkg_lock() -> anyhow::Result<()> {
+        let dir = TempDir::new("wit-deps-wkg-lock").await?;
+        let deps = dir.join("deps");
+        let lock = dir.join("deps.lock");
+        for (id, package) in [
+            ("io", "package wasi:io@0.2.0;"),
+            ("io-next", "package wasi:io@0.2.1;"),
+            ("clocks", "package wasi:clocks;"),
+        ] {
+            fs::create_dir_all(deps.join(id)).await?;
+            fs::write(deps.join(id).join("world.wit"), package).await?;
+        }
+        let digest = |b: u8| {
+            format!(
+                "sha256 = \"{}\"\nsha512 = \"{}\"",
+                hex::encode([b; 32]),
+                hex::encode([b; 64])
+            )
+        };
+        fs::write(
+            &lock,
+            format!(
+                "[io]\npath = \"io\"\n{}\n\n[io-next]\npath = \"io-next\"\n{}\n\n[clocks]\npath = \"clocks\"\n{}\n",
+                digest(1),
+                digest(2),
+                digest(3)
+            ),
+        )
+        .await?;
+        let config = Config::default().with_namespace_registry("wasi", "wasi.dev");
+        assert_eq!(
+            wkg_lock_path(&lock, &deps, &config).await?,
+            format!(
+                r#"{LOCK_HEADER}version = 1
+
+[[packages]]
+name = "wasi:io"
+registry = "wasi.dev"
+
+[[packages.versions]]
+requirement = "=0.2.0"
+version = "0.2.0"
+digest = "sha256:{}"
+
+[[packages.versions]]
+requirement = "=0.2.1"
+version = "0.2.1"
+digest = "sha256:{}"
+"#,
+                hex::encode([1; 32]),
+                hex::encode([2; 32]),
+            )
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,147 @@
+use crate::{fs, Error};
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context as _};
+use serde::Deserialize;
+use tracing::{debug, instrument};
+
+/// Name of the workspace configuration file at the root of a workspace
+pub const CONFIG: &str = "wit-deps.toml";
+
+/// Directory names, which are never searched for workspace members
+const IGNORED: [&str; 2] = ["node_modules", "target"];
+
+/// Workspace configuration, which is read from [`CONFIG`] at the root of a workspace
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Workspace members
+    #[serde(default)]
+    pub workspace: Workspace,
+}
+
+/// Workspace section of the workspace [Config]
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    /// Member directories relative to the workspace root
+    #[serde(default)]
+    pub members: Vec<PathBuf>,
+}
+
+/// Returns the member directories of the workspace at `root` relative to `root`, each of which
+/// contains a dependency manifest at `manifest` relative to the member directory, e.g.
+/// `wit/deps.toml`.
+///
+/// If [`CONFIG`] exists at `root`, the members listed in it are returned. Otherwise, `root` is
+/// searched recursively for directories containing `manifest`, skipping hidden directories,
+/// symbolic links, `node_modules` and `target` directories.
+///
+/// # Errors
+///
+/// Returns an error if [`CONFIG`] is invalid, a listed member does not contain `manifest` or
+/// reading a directory fails
+#[instrument(level = "trace", skip(root, manifest))]
+pub async fn workspace_members(
+    root: impl AsRef<Path>,
+    manifest: impl AsRef<Path>,
+) -> Result<Vec<PathBuf>, Error> {
+    let root = root.as_ref();
+    let manifest = manifest.as_ref();
+    let config = root.join(CONFIG);
+    match fs::read_to_string(&config).await {
+        Ok(config) => {
+            let Config {
+                workspace: Workspace { members },
+            } = toml::from_str(&config).with_context(|| format!("failed to decode `{CONFIG}`"))?;
+            for member in &members {
+                let path = root.join(member).join(manifest);
+                if !fs::try_exists(&path).await.unwrap_or(false) {
+                    return Err(anyhow!(
+                        "workspace member `{}` does not contain a manifest at `{}`",
+                        member.display(),
+                        path.display()
+                    )
+                    .into());
+                }
+            }
+            return Ok(members);
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("failed to read `{}`", config.display()))
+                .map_err(Into::into)
+        }
+    }
+
+    let mut members = vec![];
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        if fs::try_exists(root.join(&dir).join(manifest))
+            .await
+            .unwrap_or(false)
+        {
+            debug!("found workspace member `{}`", dir.display());
+            members.push(dir.clone());
+        }
+        let mut entries = fs::read_dir(root.join(&dir))
+            .await
+            .with_context(|| format!("failed to read directory `{}`", dir.display()))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .with_context(|| format!("failed to read directory entry in `{}`", dir.display()))?
+        {
+            let name = entry.file_name();
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if name.starts_with('.') || IGNORED.contains(&name) {
+                continue;
+            }
+            let ty = entry
+                .file_type()
+                .with_context(|| format!("failed to get file type of `{name}`"))?;
+            if ty.is_dir() {
+                dirs.push(dir.join(name));
+            }
+        }
+    }
+    members.sort();
+    Ok(members)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::tempdir::TempDir;
+
+    #[tokio::test]
+    async fn members() -> anyhow::Result<()> {
+        let root = TempDir::new("wit-deps-workspace").await?;
+        for dir in ["a", "b/c", ".hidden", "target", "d"] {
+            let wit = root.join(dir).join("wit");
+            fs::create_dir_all(&wit).await?;
+            if dir != "d" {
+                fs::write(wit.join("deps.toml"), "").await?;
+            }
+        }
+        assert_eq!(
+            workspace_members(&root, "wit/deps.toml").await?,
+            [PathBuf::from("a"), PathBuf::from("b/c")]
+        );
+
+        fs::write(root.join(CONFIG), "[workspace]\nmembers = [\"b/c\"]\n").await?;
+        assert_eq!(
+            workspace_members(&root, "wit/deps.toml").await?,
+            [PathBuf::from("b/c")]
+        );
+
+        fs::write(root.join(CONFIG), "[workspace]\nmembers = [\"d\"]\n").await?;
+        assert!(workspace_members(&root, "wit/deps.toml").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,290 @@
+use core::fmt::{self, Display, Write as _};
+
+use std::env;
+use std::io::IsTerminal as _;
+use std::path::Path;
+
+use wit_deps::{DigestMismatch, Error, Identifier, LockFailure};
+
+/// ANSI styles used in diagnostics
+#[derive(Clone, Copy)]
+struct Style(bool);
+
+impl Style {
+    const RED: &'static str = "\x1b[1;31m";
+    const BLUE: &'static str = "\x1b[1;34m";
+    const CYAN: &'static str = "\x1b[1;36m";
+    const BOLD: &'static str = "\x1b[1m";
+
+    fn paint(self, code: &str, s: impl Display) -> String {
+        if self.0 {
+            format!("{code}{s}\x1b[0m")
+        } else {
+            s.to_string()
+        }
+    }
+}
+
+/// Location of a dependency entry within the manifest source
+struct Span<'a> {
+    line: usize,
+    column: usize,
+    len: usize,
+    source: &'a str,
+}
+
+/// Returns the [Span] of the key defining dependency `id` within manifest `source`
+fn entry_span<'a>(source: &'a str, id: &Identifier) -> Option<Span<'a>> {
+    let keys = [
+        format!("[{id}]"),
+        format!("[\"{id}\"]"),
+        format!("\"{id}\""),
+        id.clone(),
+    ];
+    source.lines().enumerate().find_map(|(i, line)| {
+        let trimmed = line.trim_start();
+        let column = line.len() - trimmed.len();
+        keys.iter().find_map(|key| {
+            let rest = trimmed.strip_prefix(key.as_str())?;
+            let table = key.starts_with('[');
+            if !table && !rest.trim_start().starts_with('=') {
+                return None;
+            }
+            let (column, len) = if table {
+                (column + 1, key.len() - 2)
+            } else {
+                (column, key.len())
+            };
+            Some(Span {
+                line: i + 1,
+                column: column + 1,
+                len,
+                source: line,
+            })
+        })
+    })
+}
+
+/// Returns a hint on how to resolve `mismatch` of dependency `id`, if known
+fn mismatch_hint(mismatch: &DigestMismatch, id: Option<&Identifier>, manifest: &Path) -> String {
+    let DigestMismatch {
+        url,
+        algorithm,
+        locked,
+        ..
+    } = mismatch;
+    if *locked {
+        let update = id.map_or_else(
+            || "wit-deps update".into(),
+            |id| format!("wit-deps update {id}"),
+        );
+        format!(
+            "contents of `{url}` changed upstream since they were locked, e.g. because the upstream tag was moved; run `{update}` to accept the change"
+        )
+    } else {
+        let of = id.map(|id| format!(" of `{id}`")).unwrap_or_default();
+        format!(
+            "contents of `{url}` do not match the `{algorithm}` digest{of} pinned in `{}`, e.g. because the upstream tag was moved; update the digest if the change is expected",
+            manifest.display()
+        )
+    }
+}
+
+/// Returns the identifier of the dependency `err` relates to, if known
+fn dependency(err: &anyhow::Error) -> Option<&Identifier> {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<Error>())
+        .and_then(Error::id)
+        .or_else(|| {
+            err.downcast_ref::<LockFailure>()
+                .map(|LockFailure { id }| id)
+        })
+}
+
+/// Renders `err` as a diagnostic. If the error occurred while locking a dependency, the
+/// definition of the dependency in manifest `source` at `manifest` is displayed.
+/// Digest mismatches are rendered with expected and actual digests and a hint.
+fn render(
+    err: &anyhow::Error,
+    manifest: &Path,
+    source: Option<&str>,
+    style: Style,
+) -> Result<String, fmt::Error> {
+    let mut out = String::new();
+    let id = dependency(err);
+    writeln!(
+        out,
+        "{}{} {err}",
+        style.paint(Style::RED, "error"),
+        style.paint(Style::BOLD, ":")
+    )?;
+    let gutter = style.paint(Style::BLUE, "|");
+    if let Some(span) = id
+        .zip(source)
+        .and_then(|(id, source)| entry_span(source, id))
+    {
+        let width = span.line.to_string().len();
+        let pad = " ".repeat(width);
+        writeln!(
+            out,
+            "{pad}{} {}:{}:{}",
+            style.paint(Style::BLUE, "-->"),
+            manifest.display(),
+            span.line,
+            span.column
+        )?;
+        writeln!(out, "{pad} {gutter}")?;
+        writeln!(
+            out,
+            "{} {gutter} {}",
+            style.paint(Style::BLUE, span.line),
+            span.source
+        )?;
+        writeln!(
+            out,
+            "{pad} {gutter} {}{}",
+            " ".repeat(span.column - 1),
+            style.paint(Style::RED, "^".repeat(span.len))
+        )?;
+        writeln!(out, "{pad} {gutter}")?;
+    }
+    let eq = style.paint(Style::BLUE, "=");
+    for cause in err.chain().skip(1) {
+        if let Some(mismatch) = cause.downcast_ref::<DigestMismatch>() {
+            writeln!(
+                out,
+                "  {eq} {}: {} hash mismatch for `{}`",
+                style.paint(Style::BOLD, "caused by"),
+                mismatch.algorithm,
+                mismatch.url
+            )?;
+            writeln!(
+                out,
+                "  {eq} {}: {}",
+                style.paint(Style::BOLD, "expected"),
+                mismatch.expected
+            )?;
+            writeln!(
+                out,
+                "  {eq} {}: {}",
+                style.paint(Style::BOLD, "  actual"),
+                mismatch.actual
+            )?;
+            writeln!(
+                out,
+                "  {eq} {}: {}",
+                style.paint(Style::CYAN, "help"),
+                mismatch_hint(mismatch, id, manifest)
+            )?;
+        } else {
+            writeln!(
+                out,
+                "  {eq} {}: {cause}",
+                style.paint(Style::BOLD, "caused by")
+            )?;
+        }
+    }
+    Ok(out)
+}
+
+/// Writes `err` to stderr as a diagnostic referring to the manifest at `manifest`, if applicable.
+/// Output is colored if stderr is a terminal and `NO_COLOR` is not set.
+/// An error annotation is emitted as well, see [annotate]
+pub fn report(err: &anyhow::Error, manifest: &Path) {
+    let style = Style(std::io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none());
+    let source = std::fs::read_to_string(manifest).ok();
+    match render(err, manifest, source.as_deref(), style) {
+        Ok(diagnostic) => eprint!("{diagnostic}"),
+        Err(_) => eprintln!("Error: {err:?}"),
+    }
+    let id = dependency(err);
+    let mut message = format!("{err:#}");
+    if let Some(mismatch) = err.chain().find_map(|e| e.downcast_ref::<DigestMismatch>()) {
+        message.push('\n');
+        message.push_str(&mismatch_hint(mismatch, id, manifest));
+    }
+    annotate(Level::Error, manifest, id, &message);
+}
+
+/// Severity of a workflow annotation, see [annotate]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Level {
+    /// Error annotation
+    Error,
+    /// Warning annotation
+    Warning,
+}
+
+/// Escapes `s` for use as workflow command data
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escapes `s` for use as a workflow command property value
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// Returns a GitHub Actions workflow command annotating the TOML file at `path` with `message`.
+/// If the definition of `id` is found in `source`, the annotation points at it
+fn annotation(
+    level: Level,
+    path: &Path,
+    source: Option<&str>,
+    id: Option<&Identifier>,
+    message: &str,
+) -> String {
+    let command = match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+    };
+    let mut properties = format!("file={}", escape_property(&path.to_string_lossy()));
+    if let Some(span) = id
+        .zip(source)
+        .and_then(|(id, source)| entry_span(source, id))
+    {
+        let end = span.column + span.len;
+        properties = format!(
+            "{properties},line={},col={},endColumn={end}",
+            span.line, span.column
+        );
+    }
+    format!(
+        "::{command} {properties},title=wit-deps::{}",
+        escape_data(message)
+    )
+}
+
+/// Emits a GitHub Actions workflow annotation with `message` for the TOML file at `path`,
+/// pointing at the definition of `id` within it, if found.
+/// Annotations are only emitted if `GITHUB_ACTIONS` is set to `true`, in which case they are
+/// written to stderr to not interfere with results written to stdout
+pub fn annotate(level: Level, path: &Path, id: Option<&Identifier>, message: &str) {
+    if env::var_os("GITHUB_ACTIONS").is_none_or(|v| v != "true") {
+        return;
+    }
+    let source = std::fs::read_to_string(path).ok();
+    eprintln!(
+        "{}",
+        annotation(level, path, source.as_deref(), id, message)
+    );
+}
+
+/// Emits a GitHub Actions workflow annotation with `message` for `line` of the file at `path`,
+/// see [annotate]
+pub fn annotate_line(level: Level, path: &Path, line: usize, message: &str) {
+    if env::var_os("GITHUB_ACTIONS").is_none_or(|v| v != "true") {
+        return;
+    }
+    let command = match level {
+        Level::Error => "error",
+        Level::Warning => "warning",
+    };
+    eprintln!(
+        "::{command} file={},line={line},title=wit-deps::{}",
+        escape_property(&path.to_string_lossy()),
+        escape_data(message)
+    );
+}
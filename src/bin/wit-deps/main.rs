@@ -1,31 +1,182 @@
 #![warn(clippy::pedantic)]
+#![recursion_limit = "256"]
 
-use std::path::PathBuf;
+mod diagnostic;
+mod timings;
+
+use core::mem;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
 
-use anyhow::Context;
-use clap::{Parser, Subcommand};
+use anyhow::{bail, Context};
+use clap::parser::ValueSource;
+use clap::{ArgAction, Args, CommandFactory, FromArgMatches as _, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use diagnostic::{annotate, annotate_line, Level as AnnotationLevel};
+use serde::Serialize;
+use serde_json::json;
+use similar::TextDiff;
+use timings::Timings;
 use tokio::fs::File;
-use tokio::io;
-use tokio_util::compat::TokioAsyncWriteCompatExt;
+use tokio::io::{self, AsyncReadExt as _, AsyncWriteExt as _};
+use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
+use tracing::{debug, error, info, warn, Level};
+use tracing_subscriber::filter::{LevelFilter, Targets};
 use tracing_subscriber::prelude::*;
-use wit_deps::Identifier;
+use url::Url;
+use wit_deps::futures::AsyncWrite;
+use wit_deps::{
+    ArchiveLayout, CacheGcPolicy, CachePolicy, CancellationToken, DigestPolicy, Identifier,
+    LicensePolicy, LocalCache, Lock, LockDiff, LockEntryChange, LockOptions, LockOutcome,
+    OutOfSync, TempDir, WitChange, WitDiagnostic, WitLocation, WkgConfig,
+};
+
+/// Path denoting stdin when used as the manifest path and stdout when used as the lock path
+const STDIO: &str = "-";
 
+/// Exit code of `lock --check`, if dependencies were not already in-sync
+const OUT_OF_SYNC: u8 = 2;
+
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Project root directory containing the `wit` directory. Dependency output directory,
+    /// manifest and lock paths, which are not specified explicitly, are relative to it. Defaults
+    /// to the closest ancestor of the current directory containing the manifest
+    #[arg(long)]
+    dir: Option<PathBuf>,
+
     /// Dependency output directory
     #[arg(short, long, default_value = "wit/deps")]
     deps: PathBuf,
 
-    /// Dependency manifest path
+    /// Dependency manifest path. If `-`, `lock` reads the manifest from stdin. If the default
+    /// manifest does not exist, the `[package.metadata.wit-deps]` table of `Cargo.toml` is used
+    /// instead, if present. A `Cargo.toml` path always refers to that table
     #[arg(short, long, default_value = "wit/deps.toml")]
     manifest: PathBuf,
 
-    /// Dependency lock path
+    /// Dependency lock path. If `-`, `lock` writes the lock to stdout without reading an
+    /// existing one
     #[arg(short, long, default_value = "wit/deps.lock")]
     lock: PathBuf,
 
+    /// Cache directory. Defaults to `WIT_DEPS_CACHE_DIR` or the system-specific cache directory
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// How the cache is used when locking and updating
+    #[arg(long, global = true, value_enum, default_value_t)]
+    cache_policy: CacheMode,
+
+    /// Require the dependency lock to exist and be in-sync, without ever writing it
+    #[arg(long, global = true, action)]
+    frozen: bool,
+
+    /// Forbid network access, resolving dependencies only from the cache and existing dependencies
+    #[arg(long, global = true, action)]
+    offline: bool,
+
+    /// Record digests of individual WIT files in the lock
+    #[arg(long, global = true, action)]
+    file_digests: bool,
+
+    /// Record BLAKE3 digests in the lock in addition to sha256 and sha512 digests
+    #[arg(long, global = true, action)]
+    blake3: bool,
+
+    /// Record Subresource Integrity strings of sha256 and sha512 digests in the lock
+    #[arg(long, global = true, action)]
+    integrity: bool,
+
+    /// Remove directories within the dependency output directory, which are not present in the
+    /// lock, after locking
+    #[arg(long, global = true, action)]
+    prune: bool,
+
+    /// Fail instead of warning if multiple dependencies declare the same WIT package
+    #[arg(long, global = true, action)]
+    deny_collisions: bool,
+
+    /// Fail if any WIT package used by the WIT directory or a locked dependency is not declared
+    /// by a locked dependency, listing the missing packages and the manifest entries expected to
+    /// provide them
+    #[arg(long, global = true, action)]
+    require_complete: bool,
+
+    /// Fail if any URL entry of the manifest is not pinned by a `sha256` or `sha512` digest,
+    /// listing the unpinned entries
+    #[arg(long, global = true, action)]
+    require_pinned: bool,
+
+    /// Hard link WIT files of dependencies into `deps` instead of copying them. Note, that
+    /// modifying a hard linked file modifies its source, e.g. a path dependency or the cache
+    #[arg(long, global = true, action)]
+    hardlinks: bool,
+
+    /// Only permit dependencies licensed under these SPDX license identifiers. May be specified
+    /// multiple times
+    #[arg(long, global = true, action = ArgAction::Append)]
+    allow_license: Vec<String>,
+
+    /// Forbid dependencies licensed under these SPDX license identifiers. May be specified
+    /// multiple times
+    #[arg(long, global = true, action = ArgAction::Append)]
+    deny_license: Vec<String>,
+
+    /// `wkg` configuration assigning WIT packages to registries, which select the registry
+    /// templates missing packages are resolved from and are recorded by `export --format wkg-lock`. Defaults to
+    /// `wkg.toml` next to the WIT directory, if present
+    #[arg(long, global = true)]
+    wkg_config: Option<PathBuf>,
+
+    /// `wkg` configuration read from `wkg_config`
+    #[arg(skip)]
+    wkg: WkgConfig,
+
+    /// Output format of command results. Results are written to stdout, logs are always written
+    /// to stderr
+    #[arg(
+        long = "output-format",
+        id = "output_format",
+        global = true,
+        value_enum,
+        default_value_t
+    )]
+    output: Format,
+
+    /// Increase logging verbosity, `-v` enables debug and `-vv` trace logs. `RUST_LOG`, if set,
+    /// takes precedence
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Only log errors
+    #[arg(short, long, global = true, action)]
+    quiet: bool,
+
+    /// Format of the logs written to stderr. JSON logs contain one event object per line
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: Format,
+
+    /// Record durations of each phase of locking every dependency. Without a value, a summary
+    /// table is printed to stderr. With a path, a report is written to it, which is an HTML
+    /// document if the path has the `html` extension and JSON otherwise
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        num_args = 0..=1,
+        require_equals = true
+    )]
+    #[allow(clippy::option_option)]
+    timings: Option<Option<PathBuf>>,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -33,79 +184,1646 @@ struct Cli {
 #[derive(Debug, Subcommand)]
 enum Command {
     /// Lock dependencies
-    Lock {
-        /// Exit with an error code if dependencies were not already in-sync
-        #[arg(long, short, action)]
-        check: bool,
-    },
+    Lock(LockArgs),
     /// Update dependencies
-    Update,
+    Update {
+        /// Only update the specified packages (and their transitive dependencies), preserving all
+        /// other lock entries
+        packages: Vec<Identifier>,
+
+        /// Print the action, which would be taken for each dependency, without fetching or
+        /// modifying anything
+        #[arg(long, action)]
+        dry_run: bool,
+    },
+    /// List locked dependencies
+    List {
+        /// Output format, defaults to `--output-format`
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+    },
+    /// Fetch all URL dependencies in the lock into the cache without modifying the dependency
+    /// output directory
+    Fetch {
+        /// Only fetch dependencies present in the lock, without reading the manifest
+        #[arg(long, action)]
+        lock_only: bool,
+    },
+    /// Print the tree of locked dependencies, rooted at direct dependencies
+    Tree {
+        /// Output format, defaults to `--output-format`
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+    },
+    /// Print the graph of locked dependencies
+    Graph {
+        /// Output format, defaults to JSON if `--output-format json` is specified and DOT otherwise
+        #[arg(long, value_enum)]
+        format: Option<GraphFormat>,
+    },
+    /// Parse and resolve the WIT definitions including all dependencies, without fetching or
+    /// modifying anything
+    Check,
+    /// Lock dependencies and encode the WIT package along with all dependencies into a single
+    /// binary WIT package
+    Bundle {
+        /// Optional output path, if not specified, the package will be written to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write a software bill of materials of all locked dependencies to stdout
+    Sbom(SbomArgs),
+    /// Translate the lock into a format consumed by other tools and write it to stdout
+    Export {
+        /// Export format
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// Optional output path, if not specified, the export will be written to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Report WIT package dependencies of `cargo-component`, whose versions or contents drifted
+    /// from the locked dependencies, without modifying anything
+    SyncCargoComponent {
+        /// Path to the Cargo manifest, defaults to `Cargo.toml` next to the WIT directory
+        #[arg(long)]
+        cargo_manifest: Option<PathBuf>,
+    },
+    /// Remove directories within the dependency output directory, which are not present in the lock
+    Prune,
+    /// Verify that dependencies match the lock, without fetching or modifying anything
+    Verify {
+        /// Require the lock to be signed by the hex-encoded ed25519 public key at this path
+        #[arg(long)]
+        require_signature: Option<PathBuf>,
+    },
+    /// Re-download all URL dependencies in the lock, bypassing the cache, and report those, whose
+    /// upstream contents do not match the lock anymore, without modifying anything
+    Audit,
+    /// Print a unified diff between the locked WIT definitions of a dependency and the current
+    /// upstream ones, without modifying anything
+    Diff {
+        /// Dependency to compare
+        package: Identifier,
+    },
     /// Write a deterministic tar containing the `wit` subdirectory for a package to stdout
     Tar {
-        /// Package to archive
-        package: Identifier,
+        /// Package to archive, required unless `--all` is specified
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        package: Option<Identifier>,
+
+        /// Archive the whole WIT directory, i.e. the parent of the dependency output directory,
+        /// including all locked dependencies in `wit/deps`
+        #[arg(long, action)]
+        all: bool,
+
+        /// Include all transitive dependencies of the package in `wit/deps`, such that the
+        /// archive can be consumed standalone
+        #[arg(long, action, conflicts_with = "all")]
+        include_deps: bool,
 
         /// Optional output path, if not specified, the archive will be written to stdout
         #[arg(short, long)]
         output: Option<PathBuf>,
     },
+    /// Unpack a tar archive, e.g. one produced by `tar`, into the dependency output directory.
+    /// WIT definitions in the `wit` subdirectory are unpacked as the package and those in
+    /// `wit/deps` as its transitive dependencies
+    Untar(UntarArgs),
+    /// Manage the local cache
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Write a shell completion script to stdout
+    Completions {
+        /// Shell to generate the completion script for
+        shell: Shell,
+    },
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<ExitCode> {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::fmt::layer()
-                .compact()
-                .without_time()
-                .with_file(false)
-                .with_target(false)
-                .with_writer(std::io::stderr),
+#[derive(Debug, Subcommand)]
+enum CacheCommand {
+    /// Print the cache directory
+    Dir,
+    /// Print the number of entries, total size and hit/miss counters of the last run
+    Stats,
+    /// Remove the entry associated with a URL from the cache
+    Rm {
+        /// URL of the entry to remove
+        url: Url,
+    },
+    /// Evict cache entries exceeding size or age limits, least recently accessed first
+    Gc {
+        /// Maximum total size of the cache, e.g. `512M`. Defaults to `WIT_DEPS_CACHE_MAX_SIZE`
+        #[arg(long, value_parser = CacheGcPolicy::parse_size)]
+        max_size: Option<u64>,
+
+        /// Maximum duration since an entry was last accessed, e.g. `30d`. Defaults to
+        /// `WIT_DEPS_CACHE_MAX_AGE`
+        #[arg(long, value_parser = CacheGcPolicy::parse_age)]
+        max_age: Option<Duration>,
+    },
+    /// Re-hash cache entries and remove those, which do not match digests recorded on insert
+    Verify {
+        /// Move corrupted entries to the `.quarantine` directory within the cache instead of
+        /// deleting them
+        #[arg(long, action)]
+        quarantine: bool,
+    },
+    /// Write a tar archive containing the cache entries of all URL dependencies in the lock,
+    /// which can be imported on another machine to lock offline
+    Export {
+        /// Archive path
+        archive: PathBuf,
+    },
+    /// Import cache entries from a tar archive produced by `cache export`
+    Import {
+        /// Archive path
+        archive: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+enum Format {
+    /// Human-readable text
+    #[default]
+    Text,
+    /// JSON
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum CacheMode {
+    /// Restore dependencies from and insert them into the cache
+    #[default]
+    ReadWrite,
+    /// Insert dependencies into the cache, but never restore them from it
+    WriteOnly,
+    /// Restore dependencies from the cache, but never modify it
+    ReadOnly,
+    /// Do not use the cache
+    Disabled,
+}
+
+impl From<CacheMode> for CachePolicy {
+    fn from(mode: CacheMode) -> Self {
+        match mode {
+            CacheMode::ReadWrite => Self::ReadWrite,
+            CacheMode::WriteOnly => Self::WriteOnly,
+            CacheMode::ReadOnly => Self::ReadOnly,
+            CacheMode::Disabled => Self::Disabled,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum GraphFormat {
+    /// Graphviz DOT language
+    #[default]
+    Dot,
+    /// JSON
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+enum SbomFormat {
+    /// `CycloneDX` 1.5 JSON
+    #[default]
+    #[value(name = "cyclonedx")]
+    CycloneDx,
+    /// SPDX 2.3 JSON
+    Spdx,
+}
+
+impl From<SbomFormat> for wit_deps::SbomFormat {
+    fn from(format: SbomFormat) -> Self {
+        match format {
+            SbomFormat::CycloneDx => Self::CycloneDx,
+            SbomFormat::Spdx => Self::Spdx,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    /// JSON encoding of the lock
+    #[default]
+    Json,
+    /// `wkg.lock` of wasm-pkg-tools pinning all versioned WIT packages
+    WkgLock,
+    /// `[package.metadata.component.target.dependencies]` table of `cargo-component`
+    /// declaring path dependencies on all locked WIT packages
+    CargoComponent,
+}
+
+#[derive(Debug, Args)]
+struct UntarArgs {
+    /// Package to unpack the archive as
+    package: Identifier,
+
+    /// Optional input path, if not specified, the archive will be read from stdin
+    #[arg(short, long)]
+    input: Option<PathBuf>,
+
+    /// Skip entries with absolute paths, `..` components or links escaping the destination
+    /// instead of rejecting the archive
+    #[arg(long, action)]
+    lenient: bool,
+}
+
+#[derive(Debug, Args)]
+struct SbomArgs {
+    /// SBOM format
+    #[arg(long, value_enum, default_value_t)]
+    format: SbomFormat,
+    /// Name of the described project, defaults to the name of the directory containing the WIT
+    /// directory
+    #[arg(long)]
+    name: Option<String>,
+    /// Optional output path, if not specified, the SBOM will be written to stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Debug, Default, Args)]
+struct LockArgs {
+    /// Report all dependencies, which were not already in-sync, and exit with code 2 if there
+    /// are any. Other failures exit with code 1
+    #[arg(long, short, action)]
+    check: bool,
+
+    /// Only lock the specified packages (and their transitive dependencies), preserving all
+    /// other lock entries. May be specified multiple times
+    #[arg(long = "package", short)]
+    packages: Vec<Identifier>,
+
+    /// Lock multiple WIT directories, each containing a `deps.toml` manifest and `deps`
+    /// output directory, against a single shared lock. May be specified multiple times
+    #[arg(long = "root", conflicts_with = "packages")]
+    roots: Vec<PathBuf>,
+
+    /// Regenerate the lock, which may contain VCS conflict markers, from the manifest,
+    /// preserving all unchanged entries
+    #[arg(long, action, conflicts_with_all = ["check", "packages", "roots"])]
+    regenerate: bool,
+
+    /// Sign the lock using the hex-encoded ed25519 secret key at this path, writing a detached
+    /// signature next to the lock
+    #[arg(long, conflicts_with = "check")]
+    sign: Option<PathBuf>,
+
+    /// Parse and resolve the WIT definitions including all dependencies after locking
+    #[arg(long, action)]
+    validate: bool,
+
+    /// Lock all members of the workspace rooted at the current directory, which are listed in
+    /// `wit-deps.toml` or, if it does not exist, all directories containing a manifest at the
+    /// manifest path. Paths are relative to each member
+    #[arg(long, action, conflicts_with_all = ["packages", "roots", "regenerate"])]
+    workspace: bool,
+
+    /// Print the action, which would be taken for each dependency, without fetching or
+    /// modifying anything
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = ["check", "roots", "regenerate", "sign", "validate", "workspace"]
+    )]
+    dry_run: bool,
+}
+
+fn init_tracing(cli: &Cli) -> Option<Timings> {
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    let filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(level.into())
+        .from_env_lossy();
+    // NOTE: Timings are collected regardless of the log level
+    let timings = cli.timings.is_some().then(Timings::default);
+    let registry =
+        tracing_subscriber::registry().with(timings.clone().map(|timings| {
+            timings.with_filter(Targets::new().with_target("wit_deps", Level::DEBUG))
+        }));
+    match cli.log_format {
+        Format::Text => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .compact()
+                    .without_time()
+                    .with_file(false)
+                    .with_target(false)
+                    .with_writer(std::io::stderr)
+                    .with_filter(filter),
+            )
+            .init(),
+        Format::Json => registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_current_span(false)
+                    .with_span_list(false)
+                    .with_writer(std::io::stderr)
+                    .with_filter(filter),
+            )
+            .init(),
+    }
+    timings
+}
+
+/// Prints `timings` table to stderr or writes a report to `path`, if specified
+fn report_timings(timings: &Timings, path: Option<&Path>) -> anyhow::Result<()> {
+    let Some(path) = path else {
+        let table = timings.table().context("failed to format timings table")?;
+        eprint!("{table}");
+        return Ok(());
+    };
+    let report = if path.extension().is_some_and(|ext| ext == "html") {
+        timings
+            .html()
+            .context("failed to format HTML timings report")?
+    } else {
+        serde_json::to_string_pretty(&timings.json())
+            .context("failed to encode JSON timings report")?
+    };
+    std::fs::write(path, report)
+        .with_context(|| format!("failed to write timings report to `{}`", path.display()))?;
+    info!("wrote timings report to `{}`", path.display());
+    Ok(())
+}
+
+impl Cli {
+    fn json(&self) -> bool {
+        self.output == Format::Json
+    }
+
+    fn digest_policy(&self) -> DigestPolicy {
+        DigestPolicy {
+            blake3: self.blake3,
+            files: self.file_digests,
+            integrity: self.integrity,
+        }
+    }
+
+    fn license_policy(&self) -> LicensePolicy {
+        let policy = self
+            .allow_license
+            .iter()
+            .fold(LicensePolicy::default(), LicensePolicy::with_allow);
+        self.deny_license
+            .iter()
+            .fold(policy, LicensePolicy::with_deny)
+    }
+
+    fn lock_options(&self) -> LockOptions {
+        let options = LockOptions::default()
+            .with_frozen(self.frozen)
+            .with_offline(self.offline)
+            .with_digests(self.digest_policy())
+            .with_cache_policy(self.cache_policy.into())
+            .with_deny_collisions(self.deny_collisions)
+            .with_require_complete(self.require_complete)
+            .with_require_pinned(self.require_pinned)
+            .with_hardlinks(self.hardlinks)
+            .with_license_policy(self.license_policy())
+            .with_wkg_config(self.wkg.clone())
+            .with_cancellation(cancellation().clone());
+        match &self.cache_dir {
+            Some(dir) => options.with_cache_dir(dir),
+            None => options,
+        }
+    }
+}
+
+/// [`CancellationToken`] returned by [`cancellation`]
+static CANCELLATION: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Returns the [`CancellationToken`] cancelled on first Ctrl-C, which aborts locking and rolls
+/// back `deps`. The process exits immediately on second Ctrl-C.
+fn cancellation() -> &'static CancellationToken {
+    CANCELLATION.get_or_init(|| {
+        let token = CancellationToken::new();
+        tokio::spawn({
+            let token = token.clone();
+            async move {
+                if tokio::signal::ctrl_c().await.is_err() {
+                    return;
+                }
+                info!("cancelling, press Ctrl-C again to exit immediately");
+                token.cancel();
+                if tokio::signal::ctrl_c().await.is_ok() {
+                    std::process::exit(130);
+                }
+            }
+        });
+        token
+    })
+}
+
+/// Locks the dependencies of a single manifest and returns whether they were already in-sync
+/// along with the JSON report, if JSON output was requested
+async fn lock_paths(
+    cli: &Cli,
+    args: &LockArgs,
+    manifest_path: &Path,
+    lock_path: &Path,
+    deps_path: &Path,
+) -> anyhow::Result<(bool, Option<serde_json::Value>)> {
+    let LockArgs {
+        check,
+        packages,
+        roots,
+        regenerate,
+        sign,
+        validate,
+        ..
+    } = args;
+    let old_lock = if cli.json() {
+        Some(read_lock(lock_path).await)
+    } else {
+        None
+    };
+    // NOTE: Dependencies are checked before locking, which brings them in sync
+    let out_of_sync = if !*check {
+        BTreeMap::default()
+    } else if roots.is_empty() {
+        check_deps(manifest_path, lock_path, deps_path, packages).await?
+    } else {
+        check_shared_deps(roots, lock_path).await?
+    };
+    let updated = if *regenerate {
+        if cli.frozen {
+            bail!("`--frozen` cannot be used with `--regenerate`")
+        }
+        wit_deps::regenerate_path(&manifest_path, lock_path, deps_path, &cli.lock_options())
+            .await?;
+        true
+    } else if !roots.is_empty() {
+        wit_deps::lock_shared_path(
+            roots
+                .iter()
+                .map(|root| (root.join("deps.toml"), root.join("deps"))),
+            lock_path,
+            &cli.lock_options(),
         )
-        .with(
-            tracing_subscriber::EnvFilter::builder()
-                .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
-                .from_env_lossy(),
+        .await?
+        .updated
+    } else {
+        wit_deps::lock_path(
+            &manifest_path,
+            lock_path,
+            deps_path,
+            &cli.lock_options().with_packages(packages.iter().cloned()),
         )
-        .init();
+        .await?
+        .updated
+    };
+    let deps = if roots.is_empty() {
+        vec![deps_path.to_path_buf()]
+    } else {
+        roots.iter().map(|root| root.join("deps")).collect()
+    };
+    let mut pruned = BTreeSet::default();
+    if cli.prune {
+        for deps in &deps {
+            pruned.extend(prune_deps(lock_path, deps).await?);
+        }
+    }
+    let signature = match sign {
+        Some(key) => Some(sign_lock(lock_path, key).await?),
+        None => None,
+    };
+    let mut validated = vec![];
+    if *validate {
+        for deps in &deps {
+            validated.push(check_wit(deps).await?);
+        }
+    }
+    let in_sync = !updated && out_of_sync.is_empty();
+    let report = match old_lock {
+        Some(old_lock) => Some(json!({
+            "updated": updated,
+            "diff": old_lock.diff(&read_lock(lock_path).await),
+            "out_of_sync": out_of_sync,
+            "pruned": pruned,
+            "signature": signature,
+            "validated": validated,
+        })),
+        None => None,
+    };
+    Ok((in_sync, report))
+}
 
-    let Cli {
-        deps: deps_path,
-        manifest: manifest_path,
-        lock: lock_path,
-        command,
-    } = Cli::parse();
+/// Signs the lock at `lock_path` using the key at `key_path` and returns the signature path
+async fn sign_lock(lock_path: &Path, key_path: &Path) -> anyhow::Result<PathBuf> {
+    let key = wit_deps::read_signing_key(key_path).await?;
+    let path = wit_deps::sign_lock_path(lock_path, &key).await?;
+    info!(
+        "signed `{}` with public key `{}`, signature written to `{}`",
+        lock_path.display(),
+        hex::encode(key.verifying_key().as_bytes()),
+        path.display()
+    );
+    Ok(path)
+}
 
-    match command {
-        None => wit_deps::lock_path(manifest_path, lock_path, deps_path)
-            .await
-            .map(|_| ExitCode::SUCCESS),
-        Some(Command::Lock { check }) => wit_deps::lock_path(manifest_path, lock_path, deps_path)
-            .await
-            .map(|updated| {
-                if check && updated {
-                    ExitCode::FAILURE
-                } else {
-                    ExitCode::SUCCESS
-                }
-            }),
-        Some(Command::Update) => wit_deps::update_path(manifest_path, lock_path, deps_path)
+/// Returns all dependencies, which are out of sync, and logs the reason for each of them. If
+/// `packages` is not empty, only these dependencies are considered.
+async fn check_deps(
+    manifest_path: &Path,
+    lock_path: &Path,
+    deps_path: &Path,
+    packages: &[Identifier],
+) -> anyhow::Result<BTreeMap<Identifier, OutOfSync>> {
+    let mut out_of_sync = wit_deps::out_of_sync_path(manifest_path, lock_path, deps_path).await?;
+    if !packages.is_empty() {
+        out_of_sync.retain(|id, _| packages.contains(id));
+    }
+    log_out_of_sync(&out_of_sync, manifest_path, lock_path);
+    Ok(out_of_sync)
+}
+
+/// Like [`check_deps`], but for multiple `roots` sharing the lock at `lock_path`. Dependencies
+/// out of sync with a manifest are annotated on the lock, since the manifest is not known
+async fn check_shared_deps(
+    roots: &[PathBuf],
+    lock_path: &Path,
+) -> anyhow::Result<BTreeMap<Identifier, OutOfSync>> {
+    let out_of_sync = wit_deps::out_of_sync_shared_path(
+        roots
+            .iter()
+            .map(|root| (root.join("deps.toml"), root.join("deps"))),
+        lock_path,
+    )
+    .await?;
+    log_out_of_sync(&out_of_sync, lock_path, lock_path);
+    Ok(out_of_sync)
+}
+
+/// Logs and annotates the reason for each dependency in `out_of_sync`
+fn log_out_of_sync(
+    out_of_sync: &BTreeMap<Identifier, OutOfSync>,
+    manifest_path: &Path,
+    lock_path: &Path,
+) {
+    for (id, reason) in out_of_sync {
+        error!("`{id}` is out of sync: {reason}");
+        let path = match reason {
+            OutOfSync::NotLocked | OutOfSync::ManifestChanged => manifest_path,
+            OutOfSync::Removed | OutOfSync::Diverged { .. } => lock_path,
+        };
+        annotate(
+            AnnotationLevel::Error,
+            path,
+            Some(id),
+            &format!("`{id}` is out of sync: {reason}"),
+        );
+    }
+}
+
+async fn lock(cli: &Cli, args: &LockArgs) -> anyhow::Result<ExitCode> {
+    if args.dry_run {
+        return dry_run(cli, args.packages.clone(), false).await;
+    }
+    if cli.manifest == Path::new(STDIO) || cli.lock == Path::new(STDIO) {
+        return lock_stdio(cli, args).await;
+    }
+    let in_sync = if args.workspace {
+        let members = wit_deps::workspace_members(".", &cli.manifest).await?;
+        if members.is_empty() {
+            bail!(
+                "no workspace members found, create `{}` or a manifest at `{}`",
+                wit_deps::WORKSPACE_CONFIG,
+                cli.manifest.display()
+            )
+        }
+        let mut in_sync = true;
+        let mut reports = vec![];
+        for member in members {
+            info!("locking workspace member `{}`", member.display());
+            let (member_in_sync, report) = lock_paths(
+                cli,
+                args,
+                &member.join(&cli.manifest),
+                &member.join(&cli.lock),
+                &member.join(&cli.deps),
+            )
+            .await?;
+            in_sync &= member_in_sync;
+            if let Some(mut report) = report {
+                report["member"] = json!(member);
+                reports.push(report);
+            }
+        }
+        if cli.json() {
+            print_json(&json!({ "in_sync": in_sync, "members": reports }))?;
+        }
+        in_sync
+    } else {
+        let (in_sync, report) = lock_paths(cli, args, &cli.manifest, &cli.lock, &cli.deps).await?;
+        if let Some(report) = report {
+            print_json(&report)?;
+        }
+        in_sync
+    };
+    if args.check && !in_sync {
+        Ok(ExitCode::from(OUT_OF_SYNC))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Locks dependencies reading the manifest from stdin and/or writing the lock to stdout, see
+/// [`STDIO`]
+async fn lock_stdio(cli: &Cli, args: &LockArgs) -> anyhow::Result<ExitCode> {
+    if !args.roots.is_empty() || args.workspace || args.regenerate || args.sign.is_some() {
+        bail!("`--root`, `--workspace`, `--regenerate` and `--sign` cannot be used with `-` paths")
+    }
+    let (at, manifest) = if cli.manifest == Path::new(STDIO) {
+        let mut manifest = String::new();
+        io::stdin()
+            .read_to_string(&mut manifest)
             .await
-            .map(|()| ExitCode::SUCCESS),
-        Some(Command::Tar { package, output }) => {
-            wit_deps::lock_path(manifest_path, lock_path, &deps_path)
+            .context("failed to read manifest from stdin")?;
+        (None, manifest)
+    } else {
+        let manifest = wit_deps::read_manifest_path(&cli.manifest).await?;
+        (cli.manifest.parent(), manifest)
+    };
+    let stdout = cli.lock == Path::new(STDIO);
+    if stdout && cli.json() {
+        bail!("`--output-format json` cannot be used when writing the lock to stdout")
+    }
+    if stdout && (cli.frozen || args.check) {
+        bail!("`--frozen` and `--check` cannot be used when writing the lock to stdout")
+    }
+    let old_lock = if stdout {
+        None
+    } else {
+        match tokio::fs::read_to_string(&cli.lock).await {
+            Ok(lock) => Some(lock),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read lock at `{}`", cli.lock.display()))
+            }
+        }
+    };
+    let lock = wit_deps::lock(
+        at,
+        manifest,
+        old_lock.as_ref(),
+        &cli.deps,
+        &cli.lock_options()
+            .with_packages(args.packages.iter().cloned()),
+    )
+    .await?;
+    let updated = lock.is_some();
+    let lock = match lock {
+        Some(lock) if stdout => {
+            print!("{lock}");
+            lock
+        }
+        Some(lock) => {
+            tokio::fs::write(&cli.lock, &lock)
                 .await
-                .map(|_| ())?;
-            let package = deps_path.join(package);
+                .with_context(|| format!("failed to write lock at `{}`", cli.lock.display()))?;
+            lock
+        }
+        None => old_lock.unwrap_or_default(),
+    };
+    if cli.prune {
+        let lock: wit_deps::Lock = toml::from_str(&lock).context("failed to decode lock")?;
+        for id in lock.prune(&cli.deps).await? {
+            info!("removed `{id}`, which is not present in the lock");
+        }
+    }
+    if args.validate {
+        check_wit(&cli.deps).await?;
+    }
+    if cli.json() {
+        print_json(&json!({ "updated": updated }))?;
+    }
+    if args.check && updated {
+        Ok(ExitCode::from(OUT_OF_SYNC))
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Prints the action [`lock`], or [`update`] if `update` is `true`, would take for each
+/// dependency
+async fn dry_run(cli: &Cli, packages: Vec<Identifier>, update: bool) -> anyhow::Result<ExitCode> {
+    let plan = wit_deps::plan_path(
+        &cli.manifest,
+        &cli.lock,
+        &cli.deps,
+        update,
+        cli.prune,
+        packages,
+        cli.cache_dir.as_deref(),
+    )
+    .await?;
+    match cli.output {
+        Format::Text => {
+            for (id, action) in plan.actions() {
+                println!("{id}: {action}");
+            }
+        }
+        Format::Json => print_json(&json!({ "plan": plan.actions() }))?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn update(cli: &Cli, packages: Vec<Identifier>) -> anyhow::Result<ExitCode> {
+    if cli.frozen {
+        bail!("`--frozen` cannot be used with `update`")
+    }
+    // NOTE: Previous WIT definitions are snapshotted to compare them to the updated ones
+    let tmp = TempDir::new("wit-deps-update").await?;
+    let snapshot = snapshot_deps(&read_lock(&cli.lock).await, &cli.deps, &tmp);
+    if let Err(e) = &snapshot {
+        warn!("failed to snapshot dependencies, WIT changes will not be reported: {e:#}");
+    }
+    let outcome = wit_deps::update_path(
+        &cli.manifest,
+        &cli.lock,
+        &cli.deps,
+        &cli.lock_options().with_packages(packages),
+    )
+    .await;
+    let changes = match (&outcome, snapshot) {
+        (Ok(LockOutcome { lock, diff, .. }), Ok(())) => {
+            wit_changes(&tmp, &cli.deps, lock, diff).await
+        }
+        _ => BTreeMap::default(),
+    };
+    tmp.remove().await;
+    let LockOutcome { diff, .. } = outcome?;
+    let pruned = if cli.prune {
+        prune_deps(&cli.lock, &cli.deps).await?
+    } else {
+        BTreeSet::default()
+    };
+    if cli.json() {
+        print_json(&json!({
+            "updated": true,
+            "diff": diff,
+            "changes": changes,
+            "pruned": pruned,
+        }))?;
+    } else {
+        for (id, changes) in &changes {
+            let breaking = changes.iter().filter(|change| change.breaking).count();
+            println!(
+                "`{id}`: {} WIT change(s), {breaking} breaking",
+                changes.len()
+            );
+            for change in changes {
+                println!("  {change}");
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Copies WIT definitions of all dependencies locked in `lock` from `deps` into subdirectories
+/// of `dst` named by their identifiers
+fn snapshot_deps(lock: &Lock, deps: &Path, dst: &Path) -> anyhow::Result<()> {
+    for id in lock.keys() {
+        let src = lock.path(deps, id);
+        let dst = dst.join(id);
+        std::fs::create_dir_all(&dst)
+            .with_context(|| format!("failed to create `{}`", dst.display()))?;
+        let entries = match std::fs::read_dir(&src) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to read `{}`", src.display())),
+        };
+        for e in entries {
+            let path = e?.path();
+            if path.extension().is_some_and(|ext| ext == "wit") {
+                if let Some(name) = path.file_name() {
+                    std::fs::copy(&path, dst.join(name))
+                        .with_context(|| format!("failed to copy `{}`", path.display()))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns changes of WIT definitions of all dependencies, whose contents changed according to
+/// `diff`, compared to their previous definitions snapshotted in `old`, see [`snapshot_deps`]
+async fn wit_changes(
+    old: &Path,
+    deps: &Path,
+    lock: &Lock,
+    diff: &LockDiff,
+) -> BTreeMap<Identifier, Vec<WitChange>> {
+    let mut changes = BTreeMap::default();
+    for (id, LockEntryChange { old: entry, new }) in &diff.changed {
+        if entry.digest.sha256 == new.digest.sha256 {
+            continue;
+        }
+        match wit_deps::wit_changes_path(old.join(id), lock.path(deps, id)).await {
+            Ok(entry_changes) if entry_changes.is_empty() => {}
+            Ok(entry_changes) => {
+                changes.insert(id.clone(), entry_changes);
+            }
+            Err(e) => warn!("failed to compare WIT definitions of `{id}`: {e:#}"),
+        }
+    }
+    changes
+}
+
+/// Parses the command line and resolves the dependency output directory, manifest and lock
+/// paths, which are not specified explicitly, relative to the project root, see [`Cli::dir`]
+fn parse_cli() -> anyhow::Result<Cli> {
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // NOTE: Workspace members are relative to the current directory
+    let workspace = matches!(
+        &cli.command,
+        Some(Command::Lock(LockArgs {
+            workspace: true,
+            ..
+        }))
+    );
+    let default_manifest = matches.value_source("manifest") == Some(ValueSource::DefaultValue);
+    // NOTE: The default manifest may also be contained in `Cargo.toml` next to `wit`
+    let manifest_exists = |dir: &Path| {
+        let manifest = dir.join(&cli.manifest);
+        manifest.exists()
+            || manifest
+                .parent()
+                .is_some_and(|wit| wit_deps::manifest_path(wit).exists())
+    };
+    let root = match &cli.dir {
+        Some(dir) => Some(dir.clone()),
+        None if workspace => None,
+        None if default_manifest && !manifest_exists(Path::new("")) => {
+            let cwd = env::current_dir().context("failed to get current directory")?;
+            cwd.ancestors()
+                .skip(1)
+                .find(|dir| manifest_exists(dir))
+                .map(Path::to_path_buf)
+        }
+        None => None,
+    };
+    if let Some(root) = root {
+        if matches.value_source("deps") == Some(ValueSource::DefaultValue) {
+            cli.deps = root.join(&cli.deps);
+        }
+        if matches.value_source("manifest") == Some(ValueSource::DefaultValue) {
+            cli.manifest = root.join(&cli.manifest);
+        }
+        if matches.value_source("lock") == Some(ValueSource::DefaultValue) {
+            cli.lock = root.join(&cli.lock);
+        }
+    }
+    if default_manifest && !workspace {
+        if let Some(wit) = cli.manifest.parent() {
+            cli.manifest = wit_deps::manifest_path(wit);
+        }
+    }
+    Ok(cli)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut cli = match parse_cli() {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let timings = init_tracing(&cli);
+    debug!(manifest = %cli.manifest.display(), lock = %cli.lock.display(), deps = %cli.deps.display(), "resolved paths");
+
+    let code = match run(&mut cli).await {
+        Ok(code) => code,
+        Err(e) => {
+            diagnostic::report(&e, &cli.manifest);
+            if CANCELLATION
+                .get()
+                .is_some_and(CancellationToken::is_cancelled)
+            {
+                ExitCode::from(130)
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+    };
+    if let (Some(timings), Some(path)) = (timings, &cli.timings) {
+        if let Err(e) = report_timings(&timings, path.as_deref()) {
+            diagnostic::report(&e, &cli.manifest);
+            return ExitCode::FAILURE;
+        }
+    }
+    code
+}
+
+async fn run(cli: &mut Cli) -> anyhow::Result<ExitCode> {
+    cli.wkg = read_wkg_config(cli).await?;
+    match cli.command.take() {
+        None => lock(cli, &LockArgs::default()).await,
+        Some(Command::Lock(args)) => lock(cli, &args).await,
+        Some(Command::Update {
+            packages,
+            dry_run: true,
+        }) => dry_run(cli, packages, true).await,
+        Some(Command::Update { packages, .. }) => update(cli, packages).await,
+        Some(Command::List { format }) => {
+            print_list(&cli.lock, &cli.deps, format.unwrap_or(cli.output)).await
+        }
+        Some(Command::Fetch { lock_only }) => fetch(cli, lock_only).await,
+        Some(Command::Tree { format }) => print_tree(&cli.lock, format.unwrap_or(cli.output)).await,
+        Some(Command::Graph { format }) => {
+            let format = format.unwrap_or(match cli.output {
+                Format::Text => GraphFormat::Dot,
+                Format::Json => GraphFormat::Json,
+            });
+            print_graph(&cli.lock, format).await
+        }
+        Some(Command::Check) => {
+            let package = check_wit(&cli.deps).await?;
+            if cli.json() {
+                print_json(&json!({ "package": package }))?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Bundle { output }) => bundle(cli, output).await,
+        Some(Command::Prune) => {
+            let removed = prune_deps(&cli.lock, &cli.deps).await?;
+            if cli.json() {
+                print_json(&json!({ "removed": removed }))?;
+            }
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(Command::Verify { require_signature }) => {
+            if let Some(key) = require_signature {
+                let key = wit_deps::read_verifying_key(key).await?;
+                wit_deps::verify_lock_signature_path(&cli.lock, &key).await?;
+            }
+            verify_deps(&cli.lock, &cli.deps, cli.output).await
+        }
+        Some(Command::Sbom(args)) => sbom(cli, args).await,
+        Some(Command::Export { format, output }) => export(cli, format, output).await,
+        Some(Command::SyncCargoComponent { cargo_manifest }) => {
+            sync_cargo_component(cli, cargo_manifest).await
+        }
+        Some(Command::Audit) => audit(cli).await,
+        Some(Command::Diff { package }) => diff(cli, &package).await,
+        Some(Command::Tar {
+            package,
+            all,
+            include_deps,
+            output,
+        }) => {
+            // Only the result of archiving is reported, not the result of locking
+            let format = mem::take(&mut cli.output);
+            lock(cli, &LockArgs::default()).await?;
             if let Some(output) = output {
-                let output = File::create(&output).await.with_context(|| {
+                let file = File::create(&output).await.with_context(|| {
                     format!("failed to create output path `{}`", output.display())
                 })?;
-                wit_deps::tar(package, output.compat_write()).await?;
+                tar(
+                    cli,
+                    package.as_ref(),
+                    all,
+                    include_deps,
+                    file.compat_write(),
+                )
+                .await?;
+                if format == Format::Json {
+                    print_json(&json!({ "package": package, "archive": output }))?;
+                }
             } else {
-                wit_deps::tar(package, io::stdout().compat_write()).await?;
+                tar(
+                    cli,
+                    package.as_ref(),
+                    all,
+                    include_deps,
+                    io::stdout().compat_write(),
+                )
+                .await?;
             }
             Ok(ExitCode::SUCCESS)
         }
+        Some(Command::Untar(args)) => untar(cli, args).await,
+        Some(Command::Cache { command }) => cache(cli, command).await,
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                env!("CARGO_BIN_NAME"),
+                &mut std::io::stdout(),
+            );
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Locks dependencies and writes the bundled WIT package to `output` or stdout
+async fn bundle(cli: &mut Cli, output: Option<PathBuf>) -> anyhow::Result<ExitCode> {
+    // Only the result of bundling is reported, not the result of locking
+    let format = mem::take(&mut cli.output);
+    lock(cli, &LockArgs::default()).await?;
+    let wasm = bundle_wit(&cli.deps).await?;
+    if let Some(output) = output {
+        tokio::fs::write(&output, &wasm)
+            .await
+            .with_context(|| format!("failed to write bundle to `{}`", output.display()))?;
+        if format == Format::Json {
+            print_json(&json!({ "bundle": output, "size": wasm.len() }))?;
+        }
+    } else {
+        io::stdout()
+            .write_all(&wasm)
+            .await
+            .context("failed to write bundle to stdout")?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn sbom(
+    cli: &Cli,
+    SbomArgs {
+        format,
+        name,
+        output,
+    }: SbomArgs,
+) -> anyhow::Result<ExitCode> {
+    let name = name.or_else(|| {
+        let wit = std::fs::canonicalize(wit_dir(&cli.deps)).ok()?;
+        let name = wit.parent()?.file_name()?;
+        Some(name.to_string_lossy().into_owned())
+    });
+    let mut sbom = wit_deps::sbom_path(&cli.lock, &cli.deps, format.into()).await?;
+    if let Some(name) = name {
+        sbom = sbom.with_name(name);
+    }
+    // NOTE: See https://reproducible-builds.org/specs/source-date-epoch/
+    if let Some(epoch) = env::var("SOURCE_DATE_EPOCH").ok().filter(|v| !v.is_empty()) {
+        let epoch = epoch
+            .parse()
+            .with_context(|| format!("invalid `SOURCE_DATE_EPOCH` value `{epoch}`"))?;
+        sbom = sbom.with_created(SystemTime::UNIX_EPOCH + Duration::from_secs(epoch));
+    }
+    let mut sbom = serde_json::to_string_pretty(&sbom).context("failed to encode SBOM")?;
+    sbom.push('\n');
+    if let Some(output) = output {
+        tokio::fs::write(&output, &sbom)
+            .await
+            .with_context(|| format!("failed to write SBOM to `{}`", output.display()))?;
+        if cli.json() {
+            print_json(&json!({ "sbom": output }))?;
+        }
+    } else {
+        io::stdout()
+            .write_all(sbom.as_bytes())
+            .await
+            .context("failed to write SBOM to stdout")?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Reads the `wkg` configuration at `--wkg-config` or, if not specified, `wkg.toml` next to the
+/// WIT directory, if present
+async fn read_wkg_config(cli: &Cli) -> anyhow::Result<WkgConfig> {
+    if let Some(path) = &cli.wkg_config {
+        return Ok(WkgConfig::read(path).await?);
+    }
+    let path = wit_dir(&cli.deps)
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join("wkg.toml");
+    if !tokio::fs::try_exists(&path).await.unwrap_or_default() {
+        return Ok(WkgConfig::default());
+    }
+    debug!("read `wkg` config at `{}`", path.display());
+    Ok(WkgConfig::read(path).await?)
+}
+
+async fn export(
+    cli: &Cli,
+    format: ExportFormat,
+    output: Option<PathBuf>,
+) -> anyhow::Result<ExitCode> {
+    let export = match format {
+        ExportFormat::Json => {
+            let lock = tokio::fs::read_to_string(&cli.lock)
+                .await
+                .with_context(|| format!("failed to read lock at `{}`", cli.lock.display()))?;
+            let lock: wit_deps::Lock = toml::from_str(&lock).context("failed to decode lock")?;
+            let mut lock = serde_json::to_string_pretty(&lock).context("failed to encode lock")?;
+            lock.push('\n');
+            lock
+        }
+        ExportFormat::WkgLock => wit_deps::wkg_lock_path(&cli.lock, &cli.deps, &cli.wkg).await?,
+        ExportFormat::CargoComponent => {
+            let root = wit_dir(&cli.deps).parent().unwrap_or_else(|| Path::new(""));
+            wit_deps::cargo_component_dependencies_path(&cli.lock, &cli.deps, root).await?
+        }
+    };
+    if let Some(output) = output {
+        tokio::fs::write(&output, &export)
+            .await
+            .with_context(|| format!("failed to write export to `{}`", output.display()))?;
+        if cli.json() {
+            print_json(&json!({ "export": output }))?;
+        }
+    } else {
+        io::stdout()
+            .write_all(export.as_bytes())
+            .await
+            .context("failed to write export to stdout")?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn sync_cargo_component(
+    cli: &Cli,
+    cargo_manifest: Option<PathBuf>,
+) -> anyhow::Result<ExitCode> {
+    let cargo_manifest = cargo_manifest.unwrap_or_else(|| {
+        wit_dir(&cli.deps)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join("Cargo.toml")
+    });
+    let drift = wit_deps::cargo_component_drift_path(&cargo_manifest, &cli.lock, &cli.deps).await?;
+    for (name, reason) in &drift {
+        error!("`cargo-component` dependency `{name}` drifted: {reason}");
+        annotate(
+            AnnotationLevel::Error,
+            &cargo_manifest,
+            None,
+            &format!("`cargo-component` dependency `{name}` drifted: {reason}"),
+        );
+    }
+    if cli.json() {
+        print_json(&json!({ "in_sync": drift.is_empty(), "drift": drift }))?;
+    }
+    if drift.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(OUT_OF_SYNC))
+    }
+}
+
+async fn audit(cli: &Cli) -> anyhow::Result<ExitCode> {
+    if cli.offline {
+        bail!("`--offline` cannot be used with `audit`");
+    }
+    let statuses = wit_deps::audit_path(&cli.lock, &cli.lock_options()).await?;
+    let mut changed = false;
+    for (id, status) in &statuses {
+        if *status == wit_deps::LockAuditStatus::Unchanged {
+            info!("`{id}`: {status}");
+        } else {
+            error!("`{id}`: {status}");
+            let level = if matches!(status, wit_deps::LockAuditStatus::Failed { .. }) {
+                AnnotationLevel::Warning
+            } else {
+                AnnotationLevel::Error
+            };
+            annotate(level, &cli.lock, Some(id), &format!("`{id}`: {status}"));
+            changed = true;
+        }
+    }
+    if cli.json() {
+        print_json(&json!({ "audit": statuses }))?;
+    }
+    if changed {
+        Ok(ExitCode::FAILURE)
+    } else {
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+async fn diff(cli: &Cli, id: &Identifier) -> anyhow::Result<ExitCode> {
+    if cli.offline {
+        bail!("`--offline` cannot be used with `diff`");
+    }
+    let tmp = TempDir::new("wit-deps-diff").await?;
+    let upstream = wit_deps::upstream_path(&cli.lock, id, &tmp).await?;
+    let path = read_lock(&cli.lock).await.path(&cli.deps, id);
+    let diffs = diff_dirs(&path, &upstream, id)?;
+    tmp.remove().await;
+    if diffs.is_empty() {
+        info!("`{id}` is up-to-date with upstream");
+    }
+    if cli.json() {
+        print_json(&json!({ "diff": diffs }))?;
+    } else {
+        for diff in diffs.values() {
+            print!("{diff}");
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Returns unified diffs of all files, which differ between `old` and `new` directories, keyed
+/// by path relative to the directories. Paths in diff headers are prefixed by `prefix`
+fn diff_dirs(
+    old: &Path,
+    new: &Path,
+    prefix: &Identifier,
+) -> anyhow::Result<BTreeMap<PathBuf, String>> {
+    let old_files = dir_files(old)?;
+    let new_files = dir_files(new)?;
+    let mut diffs = BTreeMap::default();
+    for path in old_files.union(&new_files) {
+        let read = |dir: &Path, files: &BTreeSet<PathBuf>| {
+            if files.contains(path) {
+                let path = dir.join(path);
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read `{}`", path.display()))
+                    .map(Some)
+            } else {
+                Ok(None)
+            }
+        };
+        let old_text = read(old, &old_files)?;
+        let new_text = read(new, &new_files)?;
+        if old_text == new_text {
+            continue;
+        }
+        let name = Path::new(prefix).join(path);
+        let header = |text: &Option<String>, side: &str| {
+            if text.is_some() {
+                format!("{side}/{}", name.display())
+            } else {
+                "/dev/null".into()
+            }
+        };
+        let diff = TextDiff::from_lines(
+            old_text.as_deref().unwrap_or_default(),
+            new_text.as_deref().unwrap_or_default(),
+        )
+        .unified_diff()
+        .header(&header(&old_text, "a"), &header(&new_text, "b"))
+        .to_string();
+        diffs.insert(path.clone(), diff);
+    }
+    Ok(diffs)
+}
+
+/// Returns paths of all files within `dir` relative to `dir`. Missing directories are treated as
+/// empty
+fn dir_files(dir: &Path) -> anyhow::Result<BTreeSet<PathBuf>> {
+    let mut files = BTreeSet::default();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(sub) = dirs.pop() {
+        let path = dir.join(&sub);
+        let entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && sub.as_os_str().is_empty() => {
+                return Ok(files)
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read `{}`", path.display()))
+            }
+        };
+        for entry in entries {
+            let entry =
+                entry.with_context(|| format!("failed to read entry in `{}`", path.display()))?;
+            let sub = sub.join(entry.file_name());
+            if entry.file_type()?.is_dir() {
+                dirs.push(sub);
+            } else {
+                files.insert(sub);
+            }
+        }
+    }
+    Ok(files)
+}
+
+async fn fetch(cli: &Cli, lock_only: bool) -> anyhow::Result<ExitCode> {
+    if cli.offline {
+        bail!("`--offline` cannot be used with `fetch`");
+    }
+    let manifest = (!lock_only).then_some(&cli.manifest);
+    let fetched = wit_deps::fetch_path(&cli.lock, manifest, cli.cache_dir.as_deref()).await?;
+    info!("fetched {fetched} dependencies");
+    if cli.json() {
+        print_json(&json!({ "fetched": fetched }))?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn cache(cli: &Cli, command: CacheCommand) -> anyhow::Result<ExitCode> {
+    let Some(cache) = cli
+        .cache_dir
+        .as_deref()
+        .map(LocalCache::from)
+        .or_else(LocalCache::cache_dir)
+    else {
+        bail!("failed to determine cache directory")
+    };
+    match command {
+        CacheCommand::Dir if cli.json() => {
+            print_json(&json!({ "directory": cache.to_string() }))?;
+        }
+        CacheCommand::Dir => println!("{cache}"),
+        CacheCommand::Stats => {
+            let stats = cache.stats().await?;
+            if cli.json() {
+                print_json(&json!({ "directory": cache.to_string(), "stats": stats }))?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            let wit_deps::CacheStats {
+                entries,
+                trees,
+                size,
+                last_run,
+            } = stats;
+            println!("directory: {cache}");
+            println!("entries: {entries}");
+            if trees > 0 {
+                println!("unpacked trees: {trees}");
+            }
+            println!("size: {size} bytes");
+            if let Some(wit_deps::CacheCounters { hits, misses }) = last_run {
+                println!("last run: {hits} hits, {misses} misses");
+            }
+        }
+        CacheCommand::Rm { url } => {
+            if !cache.remove(&url).await? {
+                bail!("`{url}` is not present in cache")
+            }
+            info!("removed `{url}` from cache");
+            if cli.json() {
+                print_json(&json!({ "removed": url }))?;
+            }
+        }
+        CacheCommand::Gc { max_size, max_age } => {
+            let policy = CacheGcPolicy { max_size, max_age }.or(CacheGcPolicy::from_env()?);
+            if policy.is_empty() {
+                bail!("no cache limits specified, use `--max-size` or `--max-age`")
+            }
+            let stats = cache.gc(policy).await?;
+            let wit_deps::CacheGcStats {
+                removed,
+                freed,
+                size,
+            } = stats;
+            info!("removed {removed} cache entries ({freed} bytes), {size} bytes remaining in `{cache}`");
+            if cli.json() {
+                print_json(&stats)?;
+            }
+        }
+        CacheCommand::Verify { quarantine } => {
+            let stats = cache.verify(quarantine).await?;
+            info!(
+                "verified {} cache entries, removed {} corrupted entries, {} entries have no recorded digest",
+                stats.valid,
+                stats.corrupted.len(),
+                stats.unknown,
+            );
+            if cli.json() {
+                print_json(&stats)?;
+            }
+        }
+        CacheCommand::Export { archive } => {
+            let lock = tokio::fs::read_to_string(&cli.lock)
+                .await
+                .with_context(|| format!("failed to read lock at `{}`", cli.lock.display()))?;
+            let lock: wit_deps::Lock = toml::from_str(&lock).context("failed to decode lock")?;
+            let file = File::create(&archive)
+                .await
+                .with_context(|| format!("failed to create `{}`", archive.display()))?;
+            let n = cache.export(&lock, file.compat_write()).await?;
+            info!("exported {n} cache entries to `{}`", archive.display());
+            if cli.json() {
+                print_json(&json!({ "exported": n, "archive": archive }))?;
+            }
+        }
+        CacheCommand::Import { archive } => {
+            let file = File::open(&archive)
+                .await
+                .with_context(|| format!("failed to open `{}`", archive.display()))?;
+            let n = cache.import(file.compat()).await?;
+            info!("imported {n} cache entries into `{cache}`");
+            if cli.json() {
+                print_json(&json!({ "imported": n }))?;
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn tar<T>(
+    cli: &Cli,
+    package: Option<&Identifier>,
+    all: bool,
+    include_deps: bool,
+    dst: T,
+) -> anyhow::Result<T>
+where
+    T: AsyncWrite + Sync + Send + Unpin,
+{
+    let layout = ArchiveLayout::default();
+    match package {
+        _ if all => Ok(wit_deps::tar_all_path(&cli.lock, &cli.deps, dst, &layout).await?),
+        Some(package) if include_deps => {
+            Ok(wit_deps::tar_package_path(&cli.lock, &cli.deps, package, dst, &layout).await?)
+        }
+        Some(package) => {
+            let path = read_lock(&cli.lock).await.path(&cli.deps, package);
+            Ok(wit_deps::tar(path, dst).await?)
+        }
+        None => bail!("either a package or `--all` must be specified"),
+    }
+}
+
+async fn untar(
+    cli: &Cli,
+    UntarArgs {
+        package,
+        input,
+        lenient,
+    }: UntarArgs,
+) -> anyhow::Result<ExitCode> {
+    let dst = cli.deps.join(&package);
+    let layout = ArchiveLayout::default().with_strict(!lenient);
+    let deps = if let Some(input) = input {
+        let file = File::open(&input)
+            .await
+            .with_context(|| format!("failed to open input path `{}`", input.display()))?;
+        wit_deps::untar(file.compat(), &dst, &BTreeSet::default(), &layout).await
+    } else {
+        wit_deps::untar(io::stdin().compat(), &dst, &BTreeSet::default(), &layout).await
+    }
+    .with_context(|| format!("failed to unpack `{package}` to `{}`", dst.display()))?;
+    info!("unpacked `{package}` to `{}`", dst.display());
+    let deps: BTreeMap<_, _> = deps.into_iter().collect();
+    for (id, path) in &deps {
+        info!("unpacked `{id}` to `{}`", path.display());
+    }
+    if cli.json() {
+        print_json(&json!({ "package": package, "path": dst, "deps": deps }))?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Writes `value` encoded as pretty-printed JSON to stdout
+fn print_json(value: &impl Serialize) -> anyhow::Result<()> {
+    let value = serde_json::to_string_pretty(value).context("failed to encode JSON output")?;
+    println!("{value}");
+    Ok(())
+}
+
+/// Reads the lock at `path`. Missing or undecodable locks, e.g. ones containing VCS conflict
+/// markers, are treated as empty
+async fn read_lock(path: &Path) -> wit_deps::Lock {
+    tokio::fs::read_to_string(path)
+        .await
+        .ok()
+        .and_then(|lock| toml::from_str(&lock).ok())
+        .unwrap_or_default()
+}
+
+async fn print_list(
+    lock_path: &Path,
+    deps_path: &Path,
+    format: Format,
+) -> anyhow::Result<ExitCode> {
+    let entries = wit_deps::list_path(lock_path, deps_path).await?;
+    match format {
+        Format::Text => {
+            for wit_deps::LockListEntry {
+                id, source, path, ..
+            } in entries
+            {
+                match source {
+                    Some(source) => println!("{id} {source} {}", path.display()),
+                    None => println!("{id} (transitive) {}", path.display()),
+                }
+            }
+        }
+        Format::Json => print_json(&entries)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn print_tree(lock_path: &Path, format: Format) -> anyhow::Result<ExitCode> {
+    let tree = wit_deps::tree_path(lock_path).await?;
+    match format {
+        Format::Text => {
+            for node in tree {
+                println!("{node}");
+            }
+        }
+        Format::Json => print_json(&tree)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn print_graph(lock_path: &Path, format: GraphFormat) -> anyhow::Result<ExitCode> {
+    let graph = wit_deps::graph_path(lock_path).await?;
+    match format {
+        GraphFormat::Dot => println!("{}", graph.to_dot()),
+        GraphFormat::Json => print_json(&graph)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Returns the parent directory of `deps_path`, which is where `wit-parser` expects the `deps`
+/// directory to be located
+fn wit_dir(deps_path: &Path) -> &Path {
+    deps_path
+        .parent()
+        .filter(|wit| !wit.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Annotates each diagnostic of `err`, if it is a validation error of WIT definitions in `wit`
+fn annotate_validation(err: &wit_deps::Error, wit: &Path) {
+    let wit_deps::Error::Validation { diagnostics, .. } = err else {
+        return;
+    };
+    for diagnostic in diagnostics {
+        let message = diagnostic.to_string();
+        match diagnostic {
+            WitDiagnostic::MissingPackage {
+                location: Some(WitLocation { path, line }),
+                ..
+            } => annotate_line(AnnotationLevel::Error, path, *line, &message),
+            _ => annotate(
+                AnnotationLevel::Error,
+                &wit.join("deps.toml"),
+                diagnostic.id(),
+                &message,
+            ),
+        }
+    }
+}
+
+/// Validates the WIT definitions in the [WIT directory](wit_dir) of `deps_path` and returns the
+/// package name
+async fn check_wit(deps_path: &Path) -> anyhow::Result<String> {
+    let wit = wit_dir(deps_path);
+    let name = wit_deps::check_path(wit).await.inspect_err(|err| {
+        annotate_validation(err, wit);
+    })?;
+    info!("validated `{name}` in `{}`", wit.display());
+    Ok(name)
+}
+
+/// Encodes the WIT package in the [WIT directory](wit_dir) of `deps_path` along with all
+/// dependencies into a binary WIT package
+async fn bundle_wit(deps_path: &Path) -> anyhow::Result<Vec<u8>> {
+    let wit = wit_dir(deps_path);
+    let wasm = wit_deps::bundle_path(wit).await.inspect_err(|err| {
+        annotate_validation(err, wit);
+    })?;
+    info!("bundled `{}` into {} bytes", wit.display(), wasm.len());
+    Ok(wasm)
+}
+
+async fn verify_deps(
+    lock_path: &Path,
+    deps_path: &Path,
+    format: Format,
+) -> anyhow::Result<ExitCode> {
+    let divergences = wit_deps::verify_path(lock_path, deps_path).await?;
+    for (id, divergence) in &divergences {
+        annotate(
+            AnnotationLevel::Error,
+            lock_path,
+            Some(id),
+            &format!("`{id}` diverges from the lock: {divergence}"),
+        );
+    }
+    match format {
+        Format::Text => {
+            for (id, divergence) in &divergences {
+                error!("`{id}` diverges from the lock: {divergence}");
+            }
+        }
+        Format::Json => print_json(&json!({ "divergences": divergences }))?,
+    }
+    if divergences.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+async fn prune_deps(lock_path: &Path, deps_path: &Path) -> anyhow::Result<BTreeSet<Identifier>> {
+    let removed = wit_deps::prune_path(lock_path, deps_path).await?;
+    for id in &removed {
+        info!("removed `{id}`, which is not present in the lock");
     }
+    Ok(removed)
 }
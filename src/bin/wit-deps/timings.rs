@@ -0,0 +1,227 @@
+use core::fmt::{self, Write as _};
+use core::time::Duration;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+use wit_deps::telemetry::{self, phase};
+
+/// Phases of locking a dependency in the order they are reported in
+const PHASES: [&str; 6] = [
+    phase::CACHE,
+    phase::DOWNLOAD,
+    phase::DECOMPRESS,
+    phase::COPY,
+    phase::DIGEST,
+    phase::TOTAL,
+];
+
+/// Colors of [PHASES] in the HTML report, `total` is not drawn
+const COLORS: [&str; 5] = ["#8dd3c7", "#80b1d3", "#fdb462", "#b3de69", "#bebada"];
+
+/// Durations in microseconds of each phase of locking a dependency keyed by phase name
+type Phases = BTreeMap<String, u64>;
+
+/// [Layer] collecting durations of phases of locking each dependency from
+/// [`telemetry::TIMINGS`] events
+#[derive(Clone, Debug, Default)]
+pub struct Timings(Arc<Mutex<BTreeMap<String, Phases>>>);
+
+/// Identifier of the dependency a `dependency` span is locking
+struct DependencyId(String);
+
+/// Visitor collecting the fields of [`telemetry::TIMINGS`] events and `dependency` spans
+#[derive(Default)]
+struct Fields {
+    id: Option<String>,
+    phase: Option<String>,
+    elapsed_us: Option<u64>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            telemetry::ID => self.id = Some(value.into()),
+            telemetry::PHASE => self.phase = Some(value.into()),
+            _ => {}
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        if field.name() == telemetry::ELAPSED_US {
+            self.elapsed_us = Some(value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == telemetry::ID {
+            self.id = Some(format!("{value:?}"));
+        }
+    }
+}
+
+impl<S> Layer<S> for Timings
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if attrs.metadata().name() != telemetry::DEPENDENCY_SPAN {
+            return;
+        }
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let (Some(span), Some(id)) = (ctx.span(id), fields.id) {
+            span.extensions_mut().insert(DependencyId(id));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if event.metadata().target() != telemetry::TIMINGS {
+            return;
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let (Some(phase), Some(elapsed_us)) = (fields.phase, fields.elapsed_us) else {
+            return;
+        };
+        let id = fields.id.or_else(|| {
+            ctx.event_scope(event)?.find_map(|span| {
+                span.extensions()
+                    .get::<DependencyId>()
+                    .map(|DependencyId(id)| id.clone())
+            })
+        });
+        let Some(id) = id else {
+            return;
+        };
+        if let Ok(mut timings) = self.0.lock() {
+            *timings.entry(id).or_default().entry(phase).or_default() += elapsed_us;
+        }
+    }
+}
+
+/// Formats a duration of `us` microseconds
+fn duration(us: u64) -> String {
+    format!("{:.1?}", Duration::from_micros(us))
+}
+
+/// Escapes `s` for use in HTML
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+impl Timings {
+    fn snapshot(&self) -> BTreeMap<String, Phases> {
+        self.0
+            .lock()
+            .map(|timings| timings.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns a human-readable table of collected timings
+    pub fn table(&self) -> Result<String, fmt::Error> {
+        let timings = self.snapshot();
+        let width = timings
+            .keys()
+            .map(String::len)
+            .chain(["dependency".len()])
+            .max()
+            .unwrap_or_default();
+        let mut out = String::new();
+        write!(out, "{:width$}", "dependency")?;
+        for phase in PHASES {
+            write!(out, " {phase:>10}")?;
+        }
+        writeln!(out)?;
+        for (id, phases) in &timings {
+            write!(out, "{id:width$}")?;
+            for phase in PHASES {
+                let value = phases
+                    .get(phase)
+                    .map_or_else(|| "-".into(), |us| duration(*us));
+                write!(out, " {value:>10}")?;
+            }
+            writeln!(out)?;
+        }
+        Ok(out)
+    }
+
+    /// Returns a JSON report of collected timings
+    pub fn json(&self) -> serde_json::Value {
+        json!({ "unit": "us", "dependencies": self.snapshot() })
+    }
+
+    /// Returns an HTML report of collected timings
+    pub fn html(&self) -> Result<String, fmt::Error> {
+        let timings = self.snapshot();
+        let max = timings
+            .values()
+            .filter_map(|phases| phases.get(phase::TOTAL))
+            .copied()
+            .max()
+            .unwrap_or_default()
+            .max(1);
+        let mut out = String::new();
+        writeln!(
+            out,
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>wit-deps timings</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+th, td {{ padding: 2px 8px; text-align: right; }}
+th:first-child, td:first-child {{ text-align: left; }}
+.bar {{ display: flex; width: 400px; height: 1em; }}
+</style>
+</head>
+<body>
+<h1>wit-deps timings</h1>
+<table>
+<tr><th>dependency</th>"#
+        )?;
+        for (phase, color) in PHASES.iter().zip(COLORS.iter().map(Some).chain([None])) {
+            match color {
+                Some(color) => write!(out, r#"<th style="color: {color}">{phase}</th>"#)?,
+                None => write!(out, "<th>{phase}</th>")?,
+            }
+        }
+        writeln!(out, "<th></th></tr>")?;
+        for (id, phases) in &timings {
+            write!(out, "<tr><td>{}</td>", escape(id))?;
+            for phase in PHASES {
+                let value = phases
+                    .get(phase)
+                    .map_or_else(|| "-".into(), |us| duration(*us));
+                write!(out, "<td>{value}</td>")?;
+            }
+            write!(out, r#"<td><div class="bar">"#)?;
+            for (phase, color) in PHASES.iter().zip(COLORS) {
+                if let Some(us) = phases.get(*phase) {
+                    let permille = us.saturating_mul(1000) / max;
+                    write!(
+                        out,
+                        r#"<div title="{phase}" style="width: {}.{}%; background: {color}"></div>"#,
+                        permille / 10,
+                        permille % 10
+                    )?;
+                }
+            }
+            writeln!(out, "</div></td></tr>")?;
+        }
+        writeln!(out, "</table>\n</body>\n</html>")?;
+        Ok(out)
+    }
+}
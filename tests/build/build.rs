@@ -15,11 +15,7 @@ fn main() -> anyhow::Result<()> {
         )
         .init();
 
-    wit_deps::lock_sync!().context("failed to lock root WIT dependencies")?;
-
-    println!("cargo:rerun-if-changed=wit/deps");
-    println!("cargo:rerun-if-changed=wit/deps.lock");
-    println!("cargo:rerun-if-changed=wit/deps.toml");
+    wit_deps::build::lock().context("failed to lock root WIT dependencies")?;
 
     Ok(())
 }
@@ -16,14 +16,8 @@ fn main() -> anyhow::Result<()> {
         .init();
 
     // NOTE: Root WIT definitions are a dependency of this crate, so those have to be locked first
-    wit_deps::lock_sync!("../wit").context("failed to lock root WIT dependencies")?;
-    wit_deps::lock_sync!().context("failed to lock own WIT dependencies")?;
-
-    println!("cargo:rerun-if-changed=wit/deps");
-    println!("cargo:rerun-if-changed=wit/deps.lock");
-    println!("cargo:rerun-if-changed=wit/deps.toml");
-
-    println!("cargo:rerun-if-changed=../wit");
+    wit_deps::build::lock_dir("../wit").context("failed to lock root WIT dependencies")?;
+    wit_deps::build::lock().context("failed to lock own WIT dependencies")?;
 
     Ok(())
 }